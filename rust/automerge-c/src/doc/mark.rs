@@ -113,6 +113,26 @@ pub unsafe extern "C" fn AMmarkName(mark: *const AMmark) -> AMbyteSpan {
     Default::default()
 }
 
+/// \memberof AMmark
+/// \brief Gets the instance ID of a mark, if it's an instance of a multi-instance mark.
+///
+/// \param[in] mark A pointer to an `AMmark` struct.
+/// \return A UTF-8 string view as an `AMbyteSpan` struct, `(AMbyteSpan){NULL, 0}` if the mark
+///         isn't an instance.
+/// \pre \p mark `!= NULL`
+/// \post `(`\p mark `== NULL) -> (AMbyteSpan){NULL, 0}`
+/// \internal
+///
+/// # Safety
+/// mark must be a valid pointer to an AMmark
+#[no_mangle]
+pub unsafe extern "C" fn AMmarkInstanceId(mark: *const AMmark) -> AMbyteSpan {
+    if let Some(id) = mark.as_ref().and_then(|mark| mark.as_ref().instance_id()) {
+        return id.as_bytes().into();
+    }
+    Default::default()
+}
+
 /// \memberof AMmark
 /// \brief Gets the value of a mark.
 ///
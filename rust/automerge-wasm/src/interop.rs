@@ -406,6 +406,7 @@ impl TryFrom<JS> for am::sync::State {
                             "message-v1" => caps.push(am::sync::Capability::MessageV1),
                             "message-v2" => caps.push(am::sync::Capability::MessageV2),
                             "supports-sync-reset" => caps.push(am::sync::Capability::SyncReset),
+                            "ephemeral" => caps.push(am::sync::Capability::Ephemeral),
                             _ => {}
                         }
                     }
@@ -430,6 +431,8 @@ impl TryFrom<JS> for am::sync::State {
             read_only,
             peer_read_only,
             needs_reset: false,
+            max_message_size: None,
+            load_limits: am::LoadLimits::unlimited(),
         })
     }
 }
@@ -620,10 +623,12 @@ impl From<&[am::sync::Capability]> for AR {
     fn from(value: &[am::sync::Capability]) -> Self {
         AR(value
             .iter()
-            .map(|c| match c {
-                automerge::sync::Capability::MessageV1 => JsValue::from_str("message-v1"),
-                automerge::sync::Capability::MessageV2 => JsValue::from_str("message-v2"),
-                automerge::sync::Capability::SyncReset => JsValue::from_str("sync-reset"),
+            .filter_map(|c| match c {
+                automerge::sync::Capability::MessageV1 => Some(JsValue::from_str("message-v1")),
+                automerge::sync::Capability::MessageV2 => Some(JsValue::from_str("message-v2")),
+                automerge::sync::Capability::SyncReset => Some(JsValue::from_str("sync-reset")),
+                automerge::sync::Capability::Ephemeral => Some(JsValue::from_str("ephemeral")),
+                _ => None,
             })
             .collect())
     }
@@ -1867,6 +1872,9 @@ fn export_patch(
             for m in marks.iter() {
                 let mark = Object::new();
                 js_set(&mark, "name", m.name())?;
+                if let Some(id) = m.instance_id() {
+                    js_set(&mark, "instanceId", id)?;
+                }
                 js_set(&mark, "value", &alloc(&m.value().into()).1)?;
                 js_set(&mark, "start", m.start as i32)?;
                 js_set(&mark, "end", m.end as i32)?;
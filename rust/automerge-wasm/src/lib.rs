@@ -282,6 +282,7 @@ export type ConflictPatch = {
 
 export type Mark = {
   name: string;
+  instanceId?: string;
   value: ScalarValue;
   start: number;
   end: number;
@@ -1727,6 +1728,9 @@ impl Automerge {
             let mark = Object::new();
             let (_datatype, value) = alloc(&m.value().clone().into());
             js_set(&mark, "name", m.name())?;
+            if let Some(id) = m.instance_id() {
+                js_set(&mark, "instanceId", id)?;
+            }
             js_set(&mark, "value", value)?;
             js_set(&mark, "start", m.start as i32)?;
             js_set(&mark, "end", m.end as i32)?;
@@ -1,7 +1,7 @@
 #![no_main]
 
 use sha2::{Sha256, Digest};
-use automerge::{Automerge};
+use automerge::{Automerge, LoadLimits, LoadOptions};
 use libfuzzer_sys::arbitrary::{Arbitrary, Result, Unstructured};
 use libfuzzer_sys::fuzz_target;
 
@@ -32,6 +32,18 @@ impl<'a> Arbitrary<'a> for DocumentChunk
     }
 }
 
+// Small enough that anything over them would show up as excessive memory use when fuzzing
+fn limits() -> LoadLimits {
+    LoadLimits {
+        max_ops: Some(100_000),
+        max_actors: Some(1_000),
+        max_changes: Some(10_000),
+        max_column_size: Some(1024 * 1024),
+        max_depth: Some(100),
+    }
+}
+
 fuzz_target!(|doc: DocumentChunk| {
     Automerge::load(&doc.bytes);
+    let _ = Automerge::load_with_options(&doc.bytes, LoadOptions::new().limits(limits()));
 });
@@ -1,12 +1,13 @@
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
 
 use crate::automerge::SaveOptions;
 use crate::clock::Clock;
-use crate::cursor::{CursorPosition, MoveCursor};
+use crate::cursor::{CursorPosition, CursorRange, MoveCursor};
 use crate::exid::ExId;
 use crate::iter::{DiffIter, DocIter, Keys, ListRange, MapRange, Span, Spans, Values};
+use crate::line_index::LineIndex;
 use crate::marks::UpdateSpansConfig;
-use crate::marks::{ExpandMark, Mark, MarkSet};
+use crate::marks::{ExpandMark, Mark, MarkDefinition, MarkSchema, MarkSet};
 use crate::op_set2::{ChangeMetadata, Parents};
 use crate::patches::PatchLog;
 use crate::sync::SyncDoc;
@@ -89,6 +90,23 @@ impl AutoCommit {
         AutoCommit::default()
     }
 
+    /// Change the units text is measured in, see [`Automerge::set_text_encoding()`]
+    ///
+    /// This closes the open transaction. Patches recorded for [`Self::diff_incremental()`] before
+    /// the change are calculated again in the new units.
+    pub fn set_text_encoding(&mut self, text_encoding: TextEncoding) {
+        self.ensure_transaction_closed();
+        if text_encoding == self.doc.text_encoding() {
+            return;
+        }
+        self.doc.set_text_encoding(text_encoding);
+        // the recorded patches have indexes in the old encoding, so fall back to diffing the
+        // document until the diff cursor is next updated
+        self.patch_log.truncate();
+        self.patch_log.set_active(false);
+        self.diff_cache = None;
+    }
+
     pub fn diff_opset(&self, other: &AutoCommit) -> Result<(), AutomergeError> {
         self.doc.diff_opset(&other.doc)
     }
@@ -176,6 +194,23 @@ impl AutoCommit {
         })
     }
 
+    /// Load a document from `reader`, see [`Automerge::load_from_reader()`]
+    pub fn load_from_reader<R: std::io::Read>(
+        reader: R,
+        options: LoadOptions<'_>,
+    ) -> Result<Self, AutomergeError> {
+        let doc = Automerge::load_from_reader(reader, options)?;
+        Ok(Self {
+            doc,
+            transaction: None,
+            patch_log: PatchLog::inactive(),
+            diff_cursor: Vec::new(),
+            diff_cache: None,
+            save_cursor: Vec::new(),
+            isolation: None,
+        })
+    }
+
     /// Erases the diff cursor created by [`Self::update_diff_cursor()`] and no
     /// longer indexes changes to the document.
     pub fn reset_diff_cursor(&mut self) {
@@ -499,14 +534,35 @@ impl AutoCommit {
         self.save_with_options(SaveOptions::default())
     }
 
+    /// Save the entirety of this document in a compact form.
+    ///
+    /// # Panics
+    ///
+    /// If `options.codec` isn't compiled in, see [`Automerge::try_save_with_options()`].
     pub fn save_with_options(&mut self, options: SaveOptions) -> Vec<u8> {
+        match self.try_save_with_options(options) {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Save the entirety of this document in a compact form.
+    ///
+    /// # Errors
+    ///
+    /// See [`Automerge::try_save_with_options()`].
+    pub fn try_save_with_options(
+        &mut self,
+        options: SaveOptions,
+    ) -> Result<Vec<u8>, AutomergeError> {
+        options.codec.check_available()?;
         self.ensure_transaction_closed();
         self.doc.remove_unused_actors(true);
-        let bytes = self.doc.save_with_options(options);
+        let bytes = self.doc.try_save_with_options(options)?;
         if !bytes.is_empty() {
             self.save_cursor = self.doc.get_heads()
         }
-        bytes
+        Ok(bytes)
     }
 
     /// Save the document and attempt to load it before returning - slow!
@@ -544,7 +600,7 @@ impl AutoCommit {
     /// Save this document, but don't run it through DEFLATE afterwards
     pub fn save_nocompress(&mut self) -> Vec<u8> {
         self.save_with_options(SaveOptions {
-            deflate: false,
+            codec: crate::Codec::None,
             ..Default::default()
         })
     }
@@ -907,6 +963,21 @@ impl ReadDoc for AutoCommit {
             .get_marks_for(obj.as_ref(), index, self.get_scope(heads))
     }
 
+    fn mark_instances<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        kind: &str,
+    ) -> Result<Vec<Mark>, AutomergeError> {
+        self.doc
+            .mark_instances_for(obj.as_ref(), kind, self.get_scope(None))
+    }
+
+    fn mark_schema(&self, heads: Option<&[ChangeHash]>) -> Result<MarkSchema, AutomergeError> {
+        Ok(self
+            .doc
+            .mark_schema_or_default(self.get_scope(heads).as_ref()))
+    }
+
     fn text<O: AsRef<ExId>>(&self, obj: O) -> Result<String, AutomergeError> {
         self.doc.text_for(obj.as_ref(), self.get_scope(None))
     }
@@ -932,6 +1003,19 @@ impl ReadDoc for AutoCommit {
             .spans_for(obj.as_ref(), self.get_scope(Some(heads)))
     }
 
+    fn line_index<O: AsRef<ExId>>(&self, obj: O) -> Result<LineIndex<'_>, AutomergeError> {
+        self.doc.line_index_for(obj.as_ref(), self.get_scope(None))
+    }
+
+    fn line_index_at<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        heads: &[ChangeHash],
+    ) -> Result<LineIndex<'_>, AutomergeError> {
+        self.doc
+            .line_index_for(obj.as_ref(), self.get_scope(Some(heads)))
+    }
+
     fn get_cursor<O: AsRef<ExId>, I: Into<CursorPosition>>(
         &self,
         obj: O,
@@ -971,6 +1055,53 @@ impl ReadDoc for AutoCommit {
             .get_cursor_position_for(obj.as_ref(), cursor, self.get_scope(at))
     }
 
+    fn get_cursor_positions<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        cursors: &[Cursor],
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Vec<usize>, AutomergeError> {
+        self.doc
+            .get_cursor_positions_for(obj.as_ref(), cursors, self.get_scope(at))
+    }
+
+    fn get_cursor_range_position<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        range: &CursorRange,
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Range<usize>, AutomergeError> {
+        self.doc
+            .get_cursor_range_position_for(obj.as_ref(), range, self.get_scope(at))
+    }
+
+    fn get_cursor_range_positions<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        ranges: &[CursorRange],
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Vec<Range<usize>>, AutomergeError> {
+        self.doc
+            .get_cursor_range_positions_for(obj.as_ref(), ranges, self.get_scope(at))
+    }
+
+    fn transform_indices<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        indices: &[usize],
+        from_heads: &[ChangeHash],
+        to_heads: &[ChangeHash],
+        bias: MoveCursor,
+    ) -> Result<Vec<usize>, AutomergeError> {
+        self.doc.transform_indices_for(
+            obj.as_ref(),
+            indices,
+            self.get_scope(Some(from_heads)),
+            self.get_scope(Some(to_heads)),
+            bias,
+        )
+    }
+
     fn hydrate<O: AsRef<ExId>>(
         &self,
         obj: O,
@@ -1197,6 +1328,16 @@ impl Transactable for AutoCommit {
         }
     }
 
+    fn define_mark(
+        &mut self,
+        name: &str,
+        definition: &MarkDefinition,
+    ) -> Result<(), AutomergeError> {
+        self.ensure_transaction_open();
+        let (patch_log, tx) = self.transaction.as_mut().unwrap();
+        tx.define_mark(&mut self.doc, patch_log, name, definition)
+    }
+
     fn update_text<S: AsRef<str>>(
         &mut self,
         obj: &ExId,
@@ -1309,6 +1450,10 @@ impl SyncDoc for SyncWrapper<'_> {
             .doc
             .receive_sync_message_log_patches(sync_state, message, patch_log)
     }
+
+    fn sync_progress(&self, sync_state: &sync::State) -> Option<sync::Progress> {
+        self.inner.doc.sync_progress(sync_state)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1,9 +1,11 @@
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::HashSet;
 use std::env;
 use std::fmt::Debug;
+use std::io::Read;
 use std::num::NonZeroU64;
-use std::ops::RangeBounds;
+use std::ops::{ControlFlow, Range, RangeBounds};
+use std::sync::Arc;
 
 use itertools::Itertools;
 
@@ -16,13 +18,22 @@ pub(crate) use crate::read::ReadDoc;
 
 use crate::change_graph::ChangeGraph;
 use crate::change_queue::ChangeQueue;
-use crate::cursor::{CursorPosition, MoveCursor, OpCursor};
+use crate::cursor::{
+    CursorPosition, CursorRange, CursorResolution, MoveCursor, OpCursor, RangeCollapse,
+};
 use crate::exid::ExId;
 use crate::iter::{DiffIter, DocIter, Keys, ListRange, MapRange, Spans, Values};
-use crate::marks::{Mark, MarkAccumulator, MarkSet};
+use crate::line_index::LineIndex;
+use crate::marks::{
+    split_mark_name, CachedMarkSchema, Mark, MarkAccumulator, MarkSchema, MarkSet, MARK_SCHEMA_KEY,
+};
+use crate::op_set2::op_set::FoundOpId;
 use crate::patches::{Patch, PatchLog};
 use crate::storage::document::ReconstructError;
-use crate::storage::{self, change, load, Bundle, CompressConfig, Document, VerificationMode};
+use crate::storage::{
+    self, change, load, Bundle, Codec, CompressConfig, Document, LimitExceeded, LoadLimits,
+    LoadPhase, LoadProgress, VerificationMode,
+};
 use crate::transaction::{
     self, CommitOptions, Failure, OwnedTransaction, Success, Transactable, Transaction,
     TransactionArgs,
@@ -33,7 +44,17 @@ use crate::hydrate;
 use crate::types::{ActorId, ChangeHash, ObjId, ObjMeta, OpId, SequenceType, TextEncoding, Value};
 use crate::{AutomergeError, Change, Cursor, Fragment, ObjType, Prop};
 
+/// The number of changes [`Automerge::load_from_reader()`] collects before applying them
+const STREAMING_LOAD_BATCH: usize = 1024;
+
+/// The number of loaded changes applied between progress reports
+const APPLY_LOADED_BATCH: usize = 1024;
+
 pub(crate) mod current_state;
+mod lazy;
+mod repair;
+pub use lazy::{LazyChange, LazyDocument};
+pub use repair::{RepairIssue, RepairReport};
 
 // FIXME
 //#[cfg(test)]
@@ -93,6 +114,8 @@ pub struct LoadOptions<'a> {
     string_migration: StringMigration,
     patch_log: Option<&'a mut PatchLog>,
     text_encoding: TextEncoding,
+    limits: LoadLimits,
+    progress: load::Progress<'a>,
 }
 
 impl<'a> LoadOptions<'a> {
@@ -160,6 +183,48 @@ impl<'a> LoadOptions<'a> {
             ..self
         }
     }
+
+    /// Limits on the size of the loaded document, use these when loading untrusted data
+    ///
+    /// Exceeding a limit fails the load with [`AutomergeError::LimitExceeded`], whatever
+    /// [`Self::on_partial_load()`] is set to. The default is [`LoadLimits::unlimited()`]
+    pub fn limits(self, limits: LoadLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// A callback which is told how far through the load we are
+    ///
+    /// The callback is called with the current [`LoadPhase`] and the fraction of that phase
+    /// which is complete. Returning [`ControlFlow::Break`] stops the load, which then fails with
+    /// [`AutomergeError::LoadCancelled`], whatever [`Self::on_partial_load()`] is set to.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::ops::ControlFlow;
+    /// # use automerge::{transaction::Transactable, AutoCommit, Automerge, AutomergeError, LoadOptions};
+    /// let mut doc = AutoCommit::new();
+    /// doc.put(automerge::ROOT, "key", "value").unwrap();
+    /// let saved = doc.save();
+    ///
+    /// let mut phases = Vec::new();
+    /// let on_progress = |p: automerge::LoadProgress| {
+    ///     phases.push(p.phase);
+    ///     ControlFlow::Continue(())
+    /// };
+    /// Automerge::load_with_options(&saved, LoadOptions::new().progress(on_progress)).unwrap();
+    /// assert!(!phases.is_empty());
+    ///
+    /// let cancel = |_| ControlFlow::Break(());
+    /// let result = Automerge::load_with_options(&saved, LoadOptions::new().progress(cancel));
+    /// assert!(matches!(result, Err(AutomergeError::LoadCancelled(_))));
+    /// ```
+    pub fn progress<F: FnMut(LoadProgress) -> ControlFlow<()> + 'a>(self, callback: F) -> Self {
+        Self {
+            progress: load::Progress::new(callback),
+            ..self
+        }
+    }
 }
 
 impl std::default::Default for LoadOptions<'static> {
@@ -170,6 +235,8 @@ impl std::default::Default for LoadOptions<'static> {
             patch_log: None,
             string_migration: StringMigration::NoMigration,
             text_encoding: TextEncoding::platform_default(),
+            limits: LoadLimits::unlimited(),
+            progress: load::Progress::none(),
         }
     }
 }
@@ -794,6 +861,7 @@ impl Automerge {
     /// Best-effort rescue for documents which fail strict loading.
     ///
     /// This returns only the current hydrated value and does not preserve the original change graph.
+    /// Use [`Self::repair()`] to keep the history.
     pub fn rescue(data: &[u8]) -> Result<hydrate::Value, AutomergeError> {
         Ok(Self::load_with_options_and_mark_validation(
             data,
@@ -805,28 +873,147 @@ impl Automerge {
 
     fn load_with_options_and_mark_validation(
         data: &[u8],
-        options: LoadOptions<'_>,
+        mut options: LoadOptions<'_>,
         mark_order: load::MarkOrderValidation,
     ) -> Result<Self, AutomergeError> {
         if data.is_empty() {
             tracing::trace!("no data, initializing empty document");
             return Ok(Self::new());
         }
+        options.progress.set_total_bytes(data.len());
+        let (remaining, mut am, first_chunk_was_doc, mut changes) =
+            Self::load_first_chunk(storage::parse::Input::new(data), &mut options, mark_order)?;
+        tracing::trace!("loading change chunks");
+        match load::load_changes(
+            remaining.reset(),
+            options.text_encoding,
+            &am.change_graph,
+            mark_order,
+            &options.limits,
+            &mut options.progress,
+        ) {
+            load::LoadedChanges::Complete(c) => {
+                changes.extend(c);
+                am.apply_loaded_changes(changes, &mut options)?;
+                // Only allow missing deps if the first chunk was a document chunk
+                // See https://github.com/automerge/automerge/pull/599#issuecomment-1549667472
+                if !am.queue.is_empty()
+                    && !first_chunk_was_doc
+                    && options.on_partial_load == OnPartialLoad::Error
+                {
+                    return Err(AutomergeError::MissingDeps);
+                }
+            }
+            load::LoadedChanges::Partial { error, .. } => {
+                if options.on_partial_load == OnPartialLoad::Error || error.is_fatal() {
+                    return Err(error.into());
+                }
+            }
+        }
+        am.finish_load(options)
+    }
+
+    /// Load a document from `reader`, parsing each chunk as it arrives
+    ///
+    /// This accepts the same data as [`Self::load_with_options()`] but doesn't need the whole
+    /// input in memory at once. Chunks are read one at a time, along with a bounded number of
+    /// changes waiting to be applied, so peak memory use is close to the size of the loaded
+    /// document plus its largest chunk.
+    ///
+    /// Document chunks, such as the output of [`Self::save()`], are read a column at a time and
+    /// each compressed column is decompressed as soon as it has been read, so the compressed
+    /// input is never in memory as a whole. The decompressed columns are, until the document has
+    /// been built from them. Change chunks are read in full.
+    pub fn load_from_reader<R: Read>(
+        reader: R,
+        mut options: LoadOptions<'_>,
+    ) -> Result<Self, AutomergeError> {
+        let mark_order = load::MarkOrderValidation::Validate;
+        let mut reader = load::ChunkReader::new(reader, options.limits.max_column_size);
+        let Some(first) = reader.next_chunk()? else {
+            tracing::trace!("no data, initializing empty document");
+            return Ok(Self::new());
+        };
+        let (_, mut am, first_chunk_was_doc, mut changes) =
+            Self::load_first_chunk(storage::parse::Input::new(&first), &mut options, mark_order)?;
+        drop(first);
+
+        tracing::trace!("loading change chunks");
+        loop {
+            let chunk = match reader.next_chunk() {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(error) => {
+                    if options.on_partial_load == OnPartialLoad::Error {
+                        return Err(error.into());
+                    }
+                    tracing::warn!(err=?error, "partial load");
+                    break;
+                }
+            };
+            match load::load_changes(
+                storage::parse::Input::new(&chunk),
+                options.text_encoding,
+                &am.change_graph,
+                mark_order,
+                &options.limits,
+                &mut options.progress,
+            ) {
+                load::LoadedChanges::Complete(c) => changes.extend(c),
+                load::LoadedChanges::Partial { error, loaded, .. } => {
+                    if options.on_partial_load == OnPartialLoad::Error || error.is_fatal() {
+                        return Err(error.into());
+                    }
+                    tracing::warn!(err=?error, "partial load");
+                    changes.extend(loaded);
+                    break;
+                }
+            }
+            if changes.len() >= STREAMING_LOAD_BATCH {
+                am.apply_loaded_changes(std::mem::take(&mut changes), &mut options)?;
+            }
+        }
+        am.apply_loaded_changes(changes, &mut options)?;
+        if !am.queue.is_empty()
+            && !first_chunk_was_doc
+            && options.on_partial_load == OnPartialLoad::Error
+        {
+            return Err(AutomergeError::MissingDeps);
+        }
+        am.finish_load(options)
+    }
+
+    /// Parse the first chunk of `input`, which determines how we load the rest
+    ///
+    /// Returns the remaining input, the document, whether the first chunk was a document chunk
+    /// and any changes from the first chunk which still need applying.
+    fn load_first_chunk<'a>(
+        input: storage::parse::Input<'a>,
+        options: &mut LoadOptions<'_>,
+        mark_order: load::MarkOrderValidation,
+    ) -> Result<(storage::parse::Input<'a>, Self, bool, Vec<Change>), AutomergeError> {
         tracing::trace!("loading first chunk");
-        let (remaining, first_chunk) = storage::Chunk::parse(storage::parse::Input::new(data))
-            .map_err(|e| load::Error::Parse(Box::new(e)))?;
+        let (remaining, first_chunk) =
+            load::parse_chunk(input, &options.limits, &mut options.progress)?;
         if !first_chunk.checksum_valid() {
             return Err(load::Error::BadChecksum.into());
         }
 
         let mut changes = vec![];
         let mut first_chunk_was_doc = false;
-        let mut am = match first_chunk {
+        let am = match first_chunk {
             storage::Chunk::Document(d) => {
                 tracing::trace!("first chunk is document chunk, inflating");
                 first_chunk_was_doc = true;
-                match d.reconstruct(options.verification_mode, options.text_encoding) {
+                match d.reconstruct(
+                    options.verification_mode,
+                    options.text_encoding,
+                    &options.limits,
+                    &mut options.progress,
+                ) {
                     Ok(doc) => doc,
+                    Err(ReconstructError::LimitExceeded(e)) => return Err(e.into()),
+                    Err(ReconstructError::Cancelled(e)) => return Err(e.into()),
                     Err(ReconstructError::InvalidMarkOrderDoc {
                         doc,
                         error_message: _,
@@ -864,39 +1051,19 @@ impl Automerge {
                 Self::new_with_encoding(options.text_encoding)
             }
         };
-        tracing::trace!("loading change chunks");
-        match load::load_changes(
-            remaining.reset(),
-            options.text_encoding,
-            &am.change_graph,
-            mark_order,
-        ) {
-            load::LoadedChanges::Complete(c) => {
-                am.apply_changes(changes.into_iter().chain(c))?;
-                // Only allow missing deps if the first chunk was a document chunk
-                // See https://github.com/automerge/automerge/pull/599#issuecomment-1549667472
-                if !am.queue.is_empty()
-                    && !first_chunk_was_doc
-                    && options.on_partial_load == OnPartialLoad::Error
-                {
-                    return Err(AutomergeError::MissingDeps);
-                }
-            }
-            load::LoadedChanges::Partial { error, .. } => {
-                if options.on_partial_load == OnPartialLoad::Error {
-                    return Err(error.into());
-                }
-            }
-        }
+        Ok((remaining, am, first_chunk_was_doc, changes))
+    }
+
+    fn finish_load(mut self, options: LoadOptions<'_>) -> Result<Self, AutomergeError> {
         if let StringMigration::ConvertToText = options.string_migration {
-            am.convert_scalar_strings_to_text()?;
+            self.convert_scalar_strings_to_text()?;
         }
         if let Some(patch_log) = options.patch_log {
             if patch_log.is_active() {
-                am.log_current_state(ObjMeta::root(), patch_log, true);
+                self.log_current_state(ObjMeta::root(), patch_log, true);
             }
         }
-        Ok(am)
+        Ok(self)
     }
 
     /// Create the patches from a [`PatchLog`]
@@ -934,6 +1101,15 @@ impl Automerge {
         &mut self,
         data: &[u8],
         patch_log: &mut PatchLog,
+    ) -> Result<usize, AutomergeError> {
+        self.load_incremental_with_limits(data, patch_log, &LoadLimits::unlimited())
+    }
+
+    pub(crate) fn load_incremental_with_limits(
+        &mut self,
+        data: &[u8],
+        patch_log: &mut PatchLog,
+        limits: &LoadLimits,
     ) -> Result<usize, AutomergeError> {
         if self.is_empty() {
             let mut doc = Self::load_with_options(
@@ -941,7 +1117,8 @@ impl Automerge {
                 LoadOptions::new()
                     .text_encoding(self.text_encoding())
                     .on_partial_load(OnPartialLoad::Ignore)
-                    .verification_mode(VerificationMode::Check),
+                    .verification_mode(VerificationMode::Check)
+                    .limits(*limits),
             )?;
             doc = doc.with_actor(self.actor_id().clone());
             if patch_log.is_active() {
@@ -955,13 +1132,19 @@ impl Automerge {
             self.text_encoding(),
             &self.change_graph,
             load::MarkOrderValidation::Validate,
+            limits,
+            &mut load::Progress::none(),
         ) {
             load::LoadedChanges::Complete(c) => c,
+            load::LoadedChanges::Partial { error, .. } if error.is_fatal() => {
+                return Err(error.into());
+            }
             load::LoadedChanges::Partial { error, loaded, .. } => {
                 tracing::warn!(successful_chunks=loaded.len(), err=?error, "partial load");
                 loaded
             }
         };
+        self.check_limits(&changes, limits)?;
         let start = self.ops.len();
         self.apply_changes_log_patches(changes, patch_log)?;
         let delta = self.ops.len() - start;
@@ -986,6 +1169,44 @@ impl Automerge {
             .unwrap_or(0)
     }
 
+    /// Check that applying `changes` wouldn't take the document over `limits`
+    fn check_limits(&self, changes: &[Change], limits: &LoadLimits) -> Result<(), LimitExceeded> {
+        limits.check_changes(
+            changes,
+            &self.change_graph,
+            &self.queue,
+            self.ops.len(),
+            &self.ops.actors,
+        )?;
+        limits.check_change_depth(changes.iter().chain(self.queue.iter()), &self.ops)
+    }
+
+    /// Apply the changes collected while loading, reporting progress to `options`
+    ///
+    /// The changes are applied in batches so that progress is reported, and the load can be
+    /// cancelled, between them. Changes whose dependencies are in a later batch wait in the queue.
+    fn apply_loaded_changes(
+        &mut self,
+        changes: Vec<Change>,
+        options: &mut LoadOptions<'_>,
+    ) -> Result<(), AutomergeError> {
+        self.check_limits(&changes, &options.limits)?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        options.progress.report(LoadPhase::Reconstruct, Some(0.0))?;
+        let total = changes.len();
+        let mut applied = 0;
+        for batch in changes.chunks(APPLY_LOADED_BATCH) {
+            self.apply_changes(batch.iter().cloned())?;
+            applied += batch.len();
+            options
+                .progress
+                .report(LoadPhase::Reconstruct, Some(applied as f64 / total as f64))?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn has_actor_seq(&self, change: &Change) -> bool {
         self.seq_for_actor(change.actor_id()) >= change.seq()
     }
@@ -1046,7 +1267,26 @@ impl Automerge {
     }
 
     /// Save the entirety of this document in a compact form.
+    ///
+    /// # Panics
+    ///
+    /// If `options.codec` isn't compiled in, use [`Self::try_save_with_options()`] to get an error
+    /// instead.
     pub fn save_with_options(&self, options: SaveOptions) -> Vec<u8> {
+        match self.try_save_with_options(options) {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Save the entirety of this document in a compact form.
+    ///
+    /// # Errors
+    ///
+    /// [`AutomergeError::CodecNotCompiledIn`] if `options.codec` needs a cargo feature which isn't
+    /// enabled, see [`Codec`].
+    pub fn try_save_with_options(&self, options: SaveOptions) -> Result<Vec<u8>, AutomergeError> {
+        options.codec.check_available()?;
         self.assert_no_unused_actors(true);
 
         let doc = Document::new(&self.ops, &self.change_graph, options.compress());
@@ -1057,7 +1297,7 @@ impl Automerge {
                 bytes.extend(orphaned.raw_bytes());
             }
         }
-        bytes
+        Ok(bytes)
     }
 
     #[cfg(test)]
@@ -1080,7 +1320,7 @@ impl Automerge {
     /// Save this document, but don't run it through `DEFLATE` afterwards
     pub fn save_nocompress(&self) -> Vec<u8> {
         self.save_with_options(SaveOptions {
-            deflate: false,
+            codec: Codec::None,
             ..Default::default()
         })
     }
@@ -1100,25 +1340,6 @@ impl Automerge {
         bytes
     }
 
-    /// Filter the changes down to those that are not transitive dependencies of the heads.
-    ///
-    /// Thus a graph with these heads has not seen the remaining changes.
-    pub(crate) fn filter_changes(
-        &self,
-        heads: &[ChangeHash],
-        changes: &mut BTreeSet<ChangeHash>,
-    ) -> Result<(), AutomergeError> {
-        let heads = heads
-            .iter()
-            .filter(|hash| self.has_change(hash))
-            .copied()
-            .collect::<Vec<_>>();
-
-        self.change_graph.remove_ancestors(changes, &heads);
-
-        Ok(())
-    }
-
     /// Get the last change this actor made to the document.
     pub fn get_last_local_change(&self) -> Option<Change> {
         let actor = self.get_actor_index()?;
@@ -1463,6 +1684,16 @@ impl Automerge {
         &self,
         obj: &ExId,
         clock: Option<Clock>,
+    ) -> Result<Vec<Mark>, AutomergeError> {
+        self.calculate_marks_matching(obj, clock, |_| true)
+    }
+
+    /// The marks of `obj` whose names satisfy `keep`, without accumulating any others
+    fn calculate_marks_matching<F: Fn(&str) -> bool>(
+        &self,
+        obj: &ExId,
+        clock: Option<Clock>,
+        keep: F,
     ) -> Result<Vec<Mark>, AutomergeError> {
         let obj = self.exid_to_obj(obj.as_ref())?;
 
@@ -1476,16 +1707,20 @@ impl Automerge {
         // indexes — no op materialization (the text index carries text
         // widths, so lists still take the walk below)
         if clock.is_none() && seq_type == SequenceType::Text {
-            let fast = self.ops().calculate_marks_fast(&obj.id);
+            let fast = self.ops().calculate_marks_fast(&obj.id, &keep);
             #[cfg(feature = "slow_path_assertions")]
             {
-                let slow = self.calculate_marks_slow(&obj, None, seq_type);
+                let mut slow = self.calculate_marks_slow(&obj, None, seq_type);
+                slow.retain(|mark| keep(&mark.stored_name()));
                 assert_eq!(fast, slow, "indexed marks != walked marks");
             }
             return Ok(fast);
         }
 
-        Ok(self.calculate_marks_slow(&obj, clock, seq_type))
+        // each name accumulates independently, so filtering afterwards gives the same marks
+        let mut marks = self.calculate_marks_slow(&obj, clock, seq_type);
+        marks.retain(|mark| keep(&mark.stored_name()));
+        Ok(marks)
     }
 
     fn calculate_marks_slow(
@@ -1555,7 +1790,14 @@ impl Automerge {
     pub(crate) fn keys_for(&self, obj: &ExId, clock: Option<Clock>) -> Keys<'_> {
         self.exid_to_obj(obj)
             .ok()
-            .map(|obj| self.ops.keys(&obj.id, clock))
+            .map(|obj| {
+                let keys = self.ops.keys(&obj.id, clock);
+                if obj.id.is_root() {
+                    keys.without_key(MARK_SCHEMA_KEY)
+                } else {
+                    keys
+                }
+            })
             .unwrap_or_default()
     }
 
@@ -1574,7 +1816,14 @@ impl Automerge {
     ) -> MapRange<'a> {
         self.exid_to_obj(obj)
             .ok()
-            .map(|obj| self.ops.map_range(&obj.id, range, clock))
+            .map(|obj| {
+                let map_range = self.ops.map_range(&obj.id, range, clock);
+                if obj.id.is_root() {
+                    map_range.without_key(MARK_SCHEMA_KEY)
+                } else {
+                    map_range
+                }
+            })
             .unwrap_or_default()
     }
 
@@ -1593,14 +1842,26 @@ impl Automerge {
     pub(crate) fn values_for(&self, obj: &ExId, clock: Option<Clock>) -> Values<'_> {
         self.exid_to_obj(obj)
             .ok()
-            .map(|obj| Values::new(&self.ops, self.ops.top_ops(&obj.id, clock.clone()), clock))
+            .map(|obj| {
+                let values =
+                    Values::new(&self.ops, self.ops.top_ops(&obj.id, clock.clone()), clock);
+                if obj.id.is_root() {
+                    values.without_key(MARK_SCHEMA_KEY)
+                } else {
+                    values
+                }
+            })
             .unwrap_or_default()
     }
 
     pub(crate) fn length_for(&self, obj: &ExId, clock: Option<Clock>) -> usize {
         // FIXME - is doc.length() for a text always the string length?
         self.exid_to_obj(obj)
-            .map(|obj| self.ops.seq_length(&obj.id, self.text_encoding(), clock))
+            .map(|obj| {
+                // The mark schema isn't part of the content of the document
+                let schema = obj.id.is_root() && self.has_mark_schema_key(clock.as_ref());
+                self.ops.seq_length(&obj.id, self.text_encoding(), clock) - usize::from(schema)
+            })
             .unwrap_or(0)
     }
 
@@ -1619,7 +1880,102 @@ impl Automerge {
         clock: Option<Clock>,
     ) -> Result<Spans<'_>, AutomergeError> {
         let obj = self.exid_to_obj(obj)?;
-        Ok(Spans::new(self.ops.spans(&obj.id, clock)))
+        let schema = self.mark_schema_for(clock.as_ref());
+        Ok(Spans::new(self.ops.spans(&obj.id, clock)).with_schema(schema))
+    }
+
+    pub(crate) fn line_index_for(
+        &self,
+        obj: &ExId,
+        clock: Option<Clock>,
+    ) -> Result<LineIndex<'_>, AutomergeError> {
+        let obj = self.exid_to_obj(obj)?;
+        if obj.typ != ObjType::Text {
+            return Err(AutomergeError::InvalidOp(obj.typ));
+        }
+        let encoding = self.text_encoding();
+        Ok(match clock {
+            None => LineIndex::current(&self.ops, obj.id, encoding),
+            Some(clock) => LineIndex::snapshot(self.ops.text(&obj.id, Some(clock)), encoding),
+        })
+    }
+
+    /// The mark schema of the document, `None` if it doesn't declare any marks
+    ///
+    /// The schema of the current state is parsed once and kept in the op set until one of the
+    /// objects it was parsed from changes.
+    pub(crate) fn mark_schema_for(&self, clock: Option<&Clock>) -> Option<Arc<MarkSchema>> {
+        match clock {
+            None => self
+                .ops
+                .mark_schema
+                .get_or_init(|| self.read_mark_schema(None)),
+            Some(_) => self.read_mark_schema(clock).schema,
+        }
+    }
+
+    pub(crate) fn mark_schema_or_default(&self, clock: Option<&Clock>) -> MarkSchema {
+        self.mark_schema_for(clock)
+            .map(|schema| schema.as_ref().clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the root map has a value under [`MARK_SCHEMA_KEY`], which the cached schema of the
+    /// current state records
+    fn has_mark_schema_key(&self, clock: Option<&Clock>) -> bool {
+        match clock {
+            None => {
+                self.ops
+                    .mark_schema
+                    .get_or_init_cached(|| self.read_mark_schema(None))
+                    .declared
+            }
+            Some(_) => !self.mark_schema_maps(clock).is_empty(),
+        }
+    }
+
+    /// The values under [`MARK_SCHEMA_KEY`] in the root map, which [`ReadDoc::get_all()`] hides,
+    /// with the winning value last
+    pub(crate) fn mark_schema_maps(&self, clock: Option<&Clock>) -> Vec<(Value<'_>, ExId)> {
+        self.ops
+            .seek_ops_by_map_key(&ObjId::root(), MARK_SCHEMA_KEY, clock)
+            .ops
+            .into_iter()
+            .map(|op| op.tagged_value(self.ops()))
+            .collect()
+    }
+
+    /// Parse the definitions in every map under [`MARK_SCHEMA_KEY`]
+    ///
+    /// Peers which define marks before they have seen each other's schema each create a map, so
+    /// the definitions in all of them apply. Where they define the same mark the definition in the
+    /// winning map is used.
+    fn read_mark_schema(&self, clock: Option<&Clock>) -> CachedMarkSchema {
+        let mut schema = MarkSchema::default();
+        let mut objects = HashSet::new();
+        let maps = self.mark_schema_maps(clock);
+        let declared = !maps.is_empty();
+        // Conflicting values are in ascending order so the winning map is merged last
+        for (value, id) in maps {
+            let (Value::Object(ObjType::Map), Ok(obj)) = (value, self.exid_to_obj(&id)) else {
+                continue;
+            };
+            objects.insert(obj.id);
+            objects.extend(
+                self.ops
+                    .map_range(&obj.id, .., clock.cloned())
+                    .filter(|item| item.value.is_object())
+                    .map(|item| ObjId(item.op_id())),
+            );
+            if let hydrate::Value::Map(map) = self.hydrate_map(&obj.id, clock) {
+                schema.merge_hydrate(&map);
+            }
+        }
+        CachedMarkSchema {
+            schema: (!schema.is_empty()).then(|| Arc::new(schema)),
+            objects,
+            declared,
+        }
     }
 
     pub(crate) fn get_cursor_for(
@@ -1656,84 +2012,277 @@ impl Automerge {
         cursor: &Cursor,
         clock: Option<Clock>,
     ) -> Result<usize, AutomergeError> {
-        match cursor {
-            Cursor::Start => Ok(0),
-            Cursor::End => Ok(self.length_for(obj, clock)),
-            Cursor::Op(op) => {
-                let obj_meta = self.exid_to_obj(obj)?;
-
-                let Some(seq_type) = obj_meta.typ.as_sequence_type() else {
-                    return Err(AutomergeError::InvalidCursor(cursor.clone()));
+        Ok(self.resolve_cursor_for(obj, cursor, clock)?.position())
+    }
+
+    pub(crate) fn get_cursor_positions_for(
+        &self,
+        obj: &ExId,
+        cursors: &[Cursor],
+        clock: Option<Clock>,
+    ) -> Result<Vec<usize>, AutomergeError> {
+        Ok(self
+            .resolve_cursors_for(obj, cursors, clock)?
+            .into_iter()
+            .map(|resolved| resolved.position())
+            .collect())
+    }
+
+    pub(crate) fn get_cursor_range_position_for(
+        &self,
+        obj: &ExId,
+        range: &CursorRange,
+        clock: Option<Clock>,
+    ) -> Result<Range<usize>, AutomergeError> {
+        let mut ranges =
+            self.get_cursor_range_positions_for(obj, std::slice::from_ref(range), clock)?;
+        Ok(ranges.remove(0))
+    }
+
+    pub(crate) fn get_cursor_range_positions_for(
+        &self,
+        obj: &ExId,
+        ranges: &[CursorRange],
+        clock: Option<Clock>,
+    ) -> Result<Vec<Range<usize>>, AutomergeError> {
+        let cursors = ranges
+            .iter()
+            .flat_map(|range| std::iter::once(&range.start).chain(&range.end).cloned())
+            .collect::<Vec<_>>();
+        let mut resolved = self.resolve_cursors_for(obj, &cursors, clock)?.into_iter();
+        Ok(ranges
+            .iter()
+            .map(|range| {
+                let start = resolved.next().expect("every range has a start cursor");
+                let end = range.end.as_ref().and_then(|_| resolved.next());
+                // An empty range is a single position
+                let Some(end) = end else {
+                    let position = start.position();
+                    return position..position;
+                };
+                let start = start.range_start();
+                let end = end.range_end();
+                if end < start {
+                    match range.collapse {
+                        RangeCollapse::Start => start..start,
+                        RangeCollapse::End => end..end,
+                    }
+                } else {
+                    start..end
+                }
+            })
+            .collect())
+    }
+
+    pub(crate) fn transform_indices_for(
+        &self,
+        obj: &ExId,
+        indices: &[usize],
+        from: Option<Clock>,
+        to: Option<Clock>,
+        bias: MoveCursor,
+    ) -> Result<Vec<usize>, AutomergeError> {
+        let obj_meta = self.exid_to_obj(obj)?;
+        let Some(seq_type) = obj_meta.typ.as_sequence_type() else {
+            return Err(AutomergeError::InvalidOp(obj_meta.typ));
+        };
+        let from_length = self.length_for(obj, from.clone());
+
+        // The element each index sticks to, `None` for the start or the end of the sequence
+        let anchors = indices
+            .iter()
+            .map(|&index| {
+                if index > from_length {
+                    return Err(AutomergeError::InvalidIndex(index));
+                }
+                let anchor = match bias {
+                    MoveCursor::After => (index < from_length).then_some(index),
+                    MoveCursor::Before => index.checked_sub(1),
                 };
+                anchor
+                    .map(|i| {
+                        self.ops
+                            .seek_ops_by_index(&obj_meta.id, i, seq_type, from.as_ref())
+                            .ops
+                            .last()
+                            .map(|op| op.id)
+                            .ok_or(AutomergeError::InvalidIndex(index))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-                let opid = self.op_cursor_to_opid(op, clock.as_ref())?;
+        let opids = anchors.iter().flatten().copied().collect::<Vec<_>>();
+        let mut found = self
+            .ops
+            .seek_list_opids(&obj_meta.id, &opids, seq_type, to.as_ref())
+            .into_iter();
+        let mut to_length = None;
+        let mut transformed = Vec::with_capacity(indices.len());
+        for (&index, anchor) in indices.iter().zip(&anchors) {
+            transformed.push(match (anchor, &bias) {
+                (None, MoveCursor::Before) => 0,
+                (None, MoveCursor::After) => {
+                    *to_length.get_or_insert_with(|| self.length_for(obj, to.clone()))
+                }
+                (Some(_), _) => {
+                    let found = found
+                        .next()
+                        .flatten()
+                        .ok_or(AutomergeError::InvalidIndex(index))?;
+                    let resolved =
+                        self.cursor_resolution(&obj_meta.id, seq_type, &bias, found, to.as_ref());
+                    match bias {
+                        MoveCursor::After => resolved.position(),
+                        MoveCursor::Before => resolved.range_end(),
+                    }
+                }
+            });
+        }
+        Ok(transformed)
+    }
 
-                let found = self
-                    .ops
-                    .seek_list_opid(&obj_meta.id, opid, seq_type, clock.as_ref())
-                    .ok_or_else(|| AutomergeError::InvalidCursor(cursor.clone()))?;
-
-                match op.move_cursor {
-                    // `MoveCursor::After` mimics the original behavior of cursors.
-                    //
-                    // The original behavior was to just return the `FoundOpId::index` found by
-                    // `OpSetInternal::seek_list_opid()`.
-                    //
-                    // This index always corresponds to the:
-                    // - index of the item itself (if it's visible at `clock`)
-                    // - next index of visible item that **was also visible at the time of cursor creation**
-                    //   (if the item is not visible at `clock`).
-                    // - or `sequence.length` if none of the next items are visible at `clock`.
-                    MoveCursor::After => Ok(found.index),
-                    MoveCursor::Before => {
-                        // `MoveCursor::Before` behaves like `MoveCursor::After` but in the opposite direction:
-                        //
-                        // - if the item is visible at `clock`, just return its index
-                        // - if the item isn't visible at `clock`, find the index of the **previous** item
-                        //   that's visible at `clock` that was also visible at the time of cursor creation.
-                        // - if none of the previous items are visible (or the index of the original item is 0),
-                        //   our index is `0`.
-                        if found.visible || found.index == 0 {
-                            Ok(found.index)
-                        } else {
-                            // FIXME: this should probably be an `OpSet` query
-                            // also this implementation is likely very inefficient
-
-                            // current implementation walks upwards through `key` of op pointed to by cursor
-                            // and checks if `key` is visible by using `seek_list_opid()`.
-
-                            let mut key = found
-                                .op.key.elemid()
-                                .expect("failed to retrieve initial cursor op key for MoveCursor::Before")
-                                .0;
-
-                            loop {
-                                let f = self.ops.seek_list_opid(
-                                    &obj_meta.id,
-                                    key,
-                                    seq_type,
-                                    clock.as_ref(),
-                                );
-
-                                match f {
-                                    Some(f) => {
-                                        if f.visible {
-                                            return Ok(f.index);
-                                        }
-
-                                        key = f
-                                            .op
-                                            .key
-                                            .elemid()
-                                            .expect(
-                                                "failed to retrieve op key in MoveCursor::Before",
-                                            )
-                                            .0;
-                                    }
-                                    // reached when we've gone before the beginning of the sequence
-                                    None => break Ok(0),
+    fn resolve_cursor_for(
+        &self,
+        obj: &ExId,
+        cursor: &Cursor,
+        clock: Option<Clock>,
+    ) -> Result<CursorResolution, AutomergeError> {
+        let resolved = self.resolve_cursors_for(obj, std::slice::from_ref(cursor), clock)?;
+        Ok(resolved[0])
+    }
+
+    /// Resolve all of `cursors` with one lookup of their ops in the sequence
+    fn resolve_cursors_for(
+        &self,
+        obj: &ExId,
+        cursors: &[Cursor],
+        clock: Option<Clock>,
+    ) -> Result<Vec<CursorResolution>, AutomergeError> {
+        let mut length = None;
+        let mut length = || *length.get_or_insert_with(|| self.length_for(obj, clock.clone()));
+        let Some(first_op) = cursors.iter().find(|c| matches!(c, Cursor::Op(_))) else {
+            return Ok(cursors
+                .iter()
+                .map(|cursor| match cursor {
+                    Cursor::End => CursorResolution::Boundary(length()),
+                    _ => CursorResolution::Boundary(0),
+                })
+                .collect());
+        };
+
+        let obj_meta = self.exid_to_obj(obj)?;
+        let Some(seq_type) = obj_meta.typ.as_sequence_type() else {
+            return Err(AutomergeError::InvalidCursor(first_op.clone()));
+        };
+        let opids = cursors
+            .iter()
+            .filter_map(|cursor| match cursor {
+                Cursor::Op(op) => Some(self.op_cursor_to_opid(op, clock.as_ref())),
+                _ => None,
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut found = self
+            .ops
+            .seek_list_opids(&obj_meta.id, &opids, seq_type, clock.as_ref())
+            .into_iter();
+
+        let mut resolved = Vec::with_capacity(cursors.len());
+        for cursor in cursors {
+            resolved.push(match cursor {
+                Cursor::Start => CursorResolution::Boundary(0),
+                Cursor::End => CursorResolution::Boundary(length()),
+                Cursor::Op(op) => {
+                    let found = found
+                        .next()
+                        .flatten()
+                        .ok_or_else(|| AutomergeError::InvalidCursor(cursor.clone()))?;
+                    self.cursor_resolution(
+                        &obj_meta.id,
+                        seq_type,
+                        &op.move_cursor,
+                        found,
+                        clock.as_ref(),
+                    )
+                }
+            });
+        }
+        Ok(resolved)
+    }
+
+    fn cursor_resolution(
+        &self,
+        obj: &ObjId,
+        seq_type: SequenceType,
+        move_cursor: &MoveCursor,
+        found: FoundOpId<'_>,
+        clock: Option<&Clock>,
+    ) -> CursorResolution {
+        match move_cursor {
+            // `MoveCursor::After` mimics the original behavior of cursors.
+            //
+            // The original behavior was to just return the `FoundOpId::index` found by
+            // `OpSetInternal::seek_list_opid()`.
+            //
+            // This index always corresponds to the:
+            // - index of the item itself (if it's visible at `clock`)
+            // - next index of visible item that **was also visible at the time of cursor creation**
+            //   (if the item is not visible at `clock`).
+            // - or `sequence.length` if none of the next items are visible at `clock`.
+            MoveCursor::After if found.visible => CursorResolution::Element {
+                index: found.index,
+                width: found.op.width(seq_type, self.text_encoding()),
+            },
+            MoveCursor::After => CursorResolution::Boundary(found.index),
+            MoveCursor::Before => {
+                // `MoveCursor::Before` behaves like `MoveCursor::After` but in the opposite direction:
+                //
+                // - if the item is visible at `clock`, just return its index
+                // - if the item isn't visible at `clock`, find the index of the **previous** item
+                //   that's visible at `clock` that was also visible at the time of cursor creation.
+                // - if none of the previous items are visible (or the index of the original item is 0),
+                //   our index is `0`.
+                if found.visible {
+                    CursorResolution::Element {
+                        index: found.index,
+                        width: found.op.width(seq_type, self.text_encoding()),
+                    }
+                } else if found.index == 0 {
+                    CursorResolution::Boundary(0)
+                } else {
+                    // FIXME: this should probably be an `OpSet` query
+                    // also this implementation is likely very inefficient
+
+                    // current implementation walks upwards through `key` of op pointed to by cursor
+                    // and checks if `key` is visible by using `seek_list_opids()`, which also finds
+                    // keys with nothing visible after them.
+
+                    let mut key = found
+                        .op
+                        .key
+                        .elemid()
+                        .expect("failed to retrieve initial cursor op key for MoveCursor::Before")
+                        .0;
+
+                    loop {
+                        let found = self.ops.seek_list_opids(obj, &[key], seq_type, clock);
+                        match found.into_iter().next().flatten() {
+                            Some(f) => {
+                                if f.visible {
+                                    return CursorResolution::Previous {
+                                        index: f.index,
+                                        width: f.op.width(seq_type, self.text_encoding()),
+                                    };
                                 }
+
+                                key =
+                                    f.op.key
+                                        .elemid()
+                                        .expect("failed to retrieve op key in MoveCursor::Before")
+                                        .0;
                             }
+                            // reached when we've gone before the beginning of the sequence
+                            None => break CursorResolution::Boundary(0),
                         }
                     }
                 }
@@ -1746,7 +2295,37 @@ impl Automerge {
         obj: &ExId,
         clock: Option<Clock>,
     ) -> Result<Vec<Mark>, AutomergeError> {
-        self.calculate_marks(obj, clock)
+        let schema = self.mark_schema_for(clock.as_ref());
+        let marks = self.calculate_marks(obj, clock)?;
+        Ok(match schema {
+            Some(schema) => schema.resolve_marks(marks),
+            None => marks,
+        })
+    }
+
+    pub(crate) fn mark_instances_for(
+        &self,
+        obj: &ExId,
+        kind: &str,
+        clock: Option<Clock>,
+    ) -> Result<Vec<Mark>, AutomergeError> {
+        let is_instance = |name: &str| matches!(split_mark_name(name), (k, Some(_)) if k == kind);
+        let schema = self.mark_schema_for(clock.as_ref());
+        let marks = match &schema {
+            // an exclusive mark of any kind can hide instances, so resolving needs every mark
+            Some(schema) if schema.has_exclusive() => {
+                let marks = self.calculate_marks(obj, clock)?;
+                schema.resolve_marks(marks)
+            }
+            Some(schema) => {
+                schema.resolve_marks(self.calculate_marks_matching(obj, clock, is_instance)?)
+            }
+            None => self.calculate_marks_matching(obj, clock, is_instance)?,
+        };
+        Ok(marks
+            .into_iter()
+            .filter(|mark| mark.name == kind && mark.instance_id.is_some())
+            .collect())
     }
 
     pub(crate) fn get_for(
@@ -1757,6 +2336,8 @@ impl Automerge {
     ) -> Result<Option<(Value<'_>, ExId)>, AutomergeError> {
         let obj = self.exid_to_obj(obj)?;
         let op = match (obj.typ, prop) {
+            // The mark schema isn't part of the content of the document
+            (ObjType::Map, Prop::Map(key)) if obj.id.is_root() && key == MARK_SCHEMA_KEY => None,
             (ObjType::Map | ObjType::Table, Prop::Map(key)) => self
                 .ops
                 .seek_ops_by_map_key(&obj.id, &key, clock.as_ref())
@@ -1790,6 +2371,10 @@ impl Automerge {
         let prop = prop.into();
         let obj = self.exid_to_obj(obj.as_ref())?;
         let values = match (obj.typ, prop) {
+            // The mark schema isn't part of the content of the document
+            (ObjType::Map, Prop::Map(key)) if obj.id.is_root() && key == MARK_SCHEMA_KEY => {
+                vec![]
+            }
             (ObjType::Map | ObjType::Table, Prop::Map(key)) => self
                 .ops
                 .seek_ops_by_map_key(&obj.id, &key, clock.as_ref())
@@ -1826,11 +2411,13 @@ impl Automerge {
         clock: Option<Clock>,
     ) -> Result<MarkSet, AutomergeError> {
         let obj = self.exid_to_obj(obj.as_ref())?;
+        let schema = self.mark_schema_for(clock.as_ref());
         let mut iter = self.ops.top_ops(&obj.id, clock).marks();
         iter.nth(index);
-        match iter.get_marks() {
-            Some(arc) => Ok(arc.as_ref().clone().without_unmarks()),
-            None => Ok(MarkSet::default()),
+        match (iter.get_marks(), schema) {
+            (Some(arc), Some(schema)) => Ok(schema.resolve(arc.as_ref())),
+            (Some(arc), None) => Ok(arc.as_ref().clone().without_unmarks()),
+            (None, _) => Ok(MarkSet::default()),
         }
     }
 
@@ -1902,36 +2489,30 @@ impl Automerge {
         &self,
         start: impl Iterator<Item = ChangeHash>,
     ) -> Vec<ChangeHash> {
-        let queued_changes = self
-            .queue
-            .iter()
-            .map(|change| (change.hash(), change))
-            .collect::<HashMap<_, _>>();
-
-        let mut missing = HashSet::new();
-        let mut seen = HashSet::new();
-        let mut stack = start.collect::<Vec<_>>();
-
-        while let Some(hash) = stack.pop() {
-            if self.has_change(&hash) || !seen.insert(hash) {
-                continue;
-            }
-
-            if let Some(change) = queued_changes.get(&hash) {
-                stack.extend(change.deps().iter().copied());
-            } else {
-                missing.insert(hash);
-            }
-        }
-
-        let mut missing = missing.into_iter().collect::<Vec<_>>();
-        missing.sort();
-        missing
+        self.queue.missing_deps_from(&self.change_graph, start)
     }
 
     pub fn text_encoding(&self) -> TextEncoding {
         self.ops.text_encoding
     }
+
+    /// Change the units text is measured in
+    ///
+    /// Every index, length, patch, mark and cursor position into a text object from now on uses
+    /// `text_encoding`, as if the document had been loaded with
+    /// [`LoadOptions::text_encoding()`]. This recalculates the width of every character in the
+    /// document, so it takes time proportional to the size of the document rather than to the
+    /// amount of text.
+    ///
+    /// Text inserted while counting grapheme clusters is stored a cluster at a time and other
+    /// text a code point at a time, so as when loading a document, a cluster which was inserted
+    /// as several code points counts as several clusters.
+    ///
+    /// Positions obtained before the change, including the patches a [`PatchLog`] recorded
+    /// before it, are in the old units and must not be used with the new ones.
+    pub fn set_text_encoding(&mut self, text_encoding: TextEncoding) {
+        self.ops.set_text_encoding(text_encoding);
+    }
 }
 
 impl ReadDoc for Automerge {
@@ -2030,6 +2611,19 @@ impl ReadDoc for Automerge {
         self.spans_for(obj.as_ref(), clock)
     }
 
+    fn line_index<O: AsRef<ExId>>(&self, obj: O) -> Result<LineIndex<'_>, AutomergeError> {
+        self.line_index_for(obj.as_ref(), None)
+    }
+
+    fn line_index_at<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        heads: &[ChangeHash],
+    ) -> Result<LineIndex<'_>, AutomergeError> {
+        let clock = self.clock_at(heads);
+        self.line_index_for(obj.as_ref(), clock)
+    }
+
     fn get_cursor<O: AsRef<ExId>, I: Into<CursorPosition>>(
         &self,
         obj: O,
@@ -2061,6 +2655,49 @@ impl ReadDoc for Automerge {
         self.get_cursor_position_for(obj.as_ref(), cursor, clock)
     }
 
+    fn get_cursor_positions<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        cursors: &[Cursor],
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Vec<usize>, AutomergeError> {
+        let clock = at.and_then(|heads| self.clock_at(heads));
+        self.get_cursor_positions_for(obj.as_ref(), cursors, clock)
+    }
+
+    fn get_cursor_range_position<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        range: &CursorRange,
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Range<usize>, AutomergeError> {
+        let clock = at.and_then(|heads| self.clock_at(heads));
+        self.get_cursor_range_position_for(obj.as_ref(), range, clock)
+    }
+
+    fn get_cursor_range_positions<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        ranges: &[CursorRange],
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Vec<Range<usize>>, AutomergeError> {
+        let clock = at.and_then(|heads| self.clock_at(heads));
+        self.get_cursor_range_positions_for(obj.as_ref(), ranges, clock)
+    }
+
+    fn transform_indices<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        indices: &[usize],
+        from_heads: &[ChangeHash],
+        to_heads: &[ChangeHash],
+        bias: MoveCursor,
+    ) -> Result<Vec<usize>, AutomergeError> {
+        let from = self.clock_at(from_heads);
+        let to = self.clock_at(to_heads);
+        self.transform_indices_for(obj.as_ref(), indices, from, to, bias)
+    }
+
     fn text_at<O: AsRef<ExId>>(
         &self,
         obj: O,
@@ -2083,6 +2720,19 @@ impl ReadDoc for Automerge {
         self.marks_for(obj.as_ref(), clock)
     }
 
+    fn mark_instances<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        kind: &str,
+    ) -> Result<Vec<Mark>, AutomergeError> {
+        self.mark_instances_for(obj.as_ref(), kind, None)
+    }
+
+    fn mark_schema(&self, heads: Option<&[ChangeHash]>) -> Result<MarkSchema, AutomergeError> {
+        let clock = heads.and_then(|h| self.clock_at(h));
+        Ok(self.mark_schema_or_default(clock.as_ref()))
+    }
+
     fn hydrate<O: AsRef<ExId>>(
         &self,
         obj: O,
@@ -2192,18 +2842,20 @@ impl Default for Automerge {
 /// Options to pass to [`Automerge::save_with_options()`] and [`crate::AutoCommit::save_with_options()`]
 #[derive(Debug)]
 pub struct SaveOptions {
-    /// Whether to apply DEFLATE compression to the RLE encoded columns in the document
-    pub deflate: bool,
     /// Whether to save changes which we do not have the dependencies for
     pub retain_orphans: bool,
+    /// The codec to compress the RLE encoded columns in the document with, [`Codec::None`] to
+    /// leave them uncompressed
+    ///
+    /// The default is [`Codec::Deflate`], see [`Codec`] for the compatibility of the others.
+    pub codec: Codec,
 }
 
 impl SaveOptions {
     fn compress(&self) -> CompressConfig {
-        if self.deflate {
-            CompressConfig::Threshold(change::DEFLATE_MIN_SIZE)
-        } else {
-            CompressConfig::None
+        match self.codec {
+            Codec::None => CompressConfig::None,
+            codec => CompressConfig::Threshold(change::DEFLATE_MIN_SIZE, codec),
         }
     }
 }
@@ -2211,8 +2863,8 @@ impl SaveOptions {
 impl std::default::Default for SaveOptions {
     fn default() -> Self {
         Self {
-            deflate: true,
             retain_orphans: true,
+            codec: Codec::Deflate,
         }
     }
 }
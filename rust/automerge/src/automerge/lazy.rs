@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::change_graph::{ChangeGraphCols, ChangeIter};
+use crate::exid::ExId;
+use crate::op_set2::types::Action;
+use crate::op_set2::{OpSet, ScalarValue};
+use crate::storage::document::{Rebuilt, ReconstructError};
+use crate::storage::load::Progress;
+use crate::storage::{self, load, LoadLimits, VerificationMode};
+use crate::types::{ActorId, ChangeHash, ObjId, OpId, Value};
+use crate::{Automerge, AutomergeError, ObjType, Prop, ReadDoc};
+
+use super::{LoadOptions, StringMigration};
+
+/// A document which has been parsed but not yet reconstructed
+///
+/// Loading an [`Automerge`] builds indexes over every op in the document and rebuilds every
+/// change to check the heads, which dominates the time it takes to load a large document. A
+/// [`LazyDocument`] skips those two steps, which makes the heads, the change metadata and the
+/// types of the objects in the document available straight away, and answers [`Self::get()`]
+/// and [`Self::get_all()`] on maps by scanning the sorted object and key columns for that key.
+/// Everything else goes through [`Self::doc()`], which reconstructs the document the first time
+/// it's called.
+///
+/// Every column is still decompressed and decoded when the document is loaded, only the indexes
+/// and the changes are deferred. So loading lazily saves time but not memory: the input isn't
+/// kept, the decoded columns are what the document is reconstructed from and they become part of
+/// the reconstructed document, so once it's built the memory used is the same as for a document
+/// loaded with [`Automerge::load()`]. This is a separate type rather than a mode of
+/// [`LoadOptions`] because an [`Automerge`] always has its indexes.
+///
+/// Only input which is a single document chunk, such as the output of [`Automerge::save()`],
+/// is loaded lazily. Anything else, or options which need the whole document (a
+/// [`LoadOptions::patch_log()`] or a [`LoadOptions::migrate_strings()`]), is reconstructed
+/// straight away.
+///
+/// Because the changes aren't rebuilt until the document is reconstructed, a document whose
+/// heads don't match its ops loads without an error. The error is returned by [`Self::doc()`]
+/// instead, when [`LoadOptions::verification_mode()`] is [`VerificationMode::Check`].
+///
+/// ## Example
+///
+/// ```
+/// # use automerge::{transaction::Transactable, AutoCommit, LazyDocument, ObjType, ReadDoc, ROOT};
+/// let mut doc = AutoCommit::new();
+/// doc.put(ROOT, "title", "hello").unwrap();
+/// let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+/// doc.splice_text(&text, 0, 0, "some text").unwrap();
+/// let saved = doc.save();
+///
+/// let mut lazy = LazyDocument::load(&saved).unwrap();
+/// assert_eq!(lazy.get_heads(), doc.get_heads());
+/// assert_eq!(lazy.object_type(&text).unwrap(), ObjType::Text);
+/// let (title, _) = lazy.get(ROOT, "title").unwrap().unwrap();
+/// assert_eq!(title.to_str(), Some("hello"));
+/// assert!(!lazy.is_reconstructed());
+///
+/// assert_eq!(lazy.doc().unwrap().text(&text).unwrap(), "some text");
+/// assert!(lazy.is_reconstructed());
+/// ```
+#[derive(Debug)]
+pub struct LazyDocument {
+    pending: Option<Box<Pending>>,
+    doc: Option<Automerge>,
+}
+
+/// The decoded columns of a document chunk which hasn't been reconstructed
+#[derive(Debug)]
+struct Pending {
+    verification_mode: VerificationMode,
+    limits: LoadLimits,
+    heads: Vec<ChangeHash>,
+    /// The op columns, without the indexes which [`Automerge`] builds over them
+    ops: OpSet,
+    changes: ChangeGraphCols,
+    objects: HashMap<ObjId, ObjType>,
+}
+
+/// The metadata of a change in a [`LazyDocument`], from [`LazyDocument::changes()`]
+///
+/// The hashes of changes are only known once the document is reconstructed, so dependencies are
+/// given as indexes into [`LazyDocument::changes()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyChange<'a> {
+    pub actor: &'a ActorId,
+    pub seq: u64,
+    pub start_op: u64,
+    pub max_op: u64,
+    pub timestamp: i64,
+    pub message: Option<Cow<'a, str>>,
+    pub extra: Cow<'a, [u8]>,
+    /// The indexes of the dependencies of this change in [`LazyDocument::changes()`]
+    pub deps: Vec<usize>,
+}
+
+impl LazyDocument {
+    /// Load a document lazily, see [`LazyDocument`]
+    pub fn load(data: &[u8]) -> Result<Self, AutomergeError> {
+        Self::load_with_options(data, LoadOptions::new())
+    }
+
+    /// Load a document lazily with `options`, see [`LazyDocument`]
+    ///
+    /// The options are used again when the document is reconstructed, apart from
+    /// [`LoadOptions::progress()`] which is only told about decoding the columns.
+    pub fn load_with_options(
+        data: &[u8],
+        mut options: LoadOptions<'_>,
+    ) -> Result<Self, AutomergeError> {
+        if options.patch_log.is_some()
+            || matches!(options.string_migration, StringMigration::ConvertToText)
+        {
+            return Automerge::load_with_options(data, options).map(Self::from);
+        }
+        let input = storage::parse::Input::new(data);
+        let (remaining, chunk) = load::parse_chunk(input, &options.limits, &mut options.progress)?;
+        let document = match chunk {
+            storage::Chunk::Document(d) if remaining.is_empty() => d,
+            _ => return Automerge::load_with_options(data, options).map(Self::from),
+        };
+        if !document.checksum_valid() {
+            return Err(load::Error::BadChecksum.into());
+        }
+        let (ops, changes) = document
+            .load_columns(options.text_encoding, &options.limits)
+            .map_err(reconstruct_error)?;
+        let objects = object_types(&ops);
+        let pending = Pending {
+            verification_mode: options.verification_mode,
+            limits: options.limits,
+            heads: document.heads().to_vec(),
+            ops,
+            changes,
+            objects,
+        };
+        Ok(Self {
+            pending: Some(Box::new(pending)),
+            doc: None,
+        })
+    }
+
+    /// Whether the document has been reconstructed, either by [`Self::doc()`] or because it
+    /// couldn't be loaded lazily
+    pub fn is_reconstructed(&self) -> bool {
+        self.doc.is_some()
+    }
+
+    /// The reconstructed document, reconstructing it if this is the first call
+    ///
+    /// If reconstructing fails the decoded columns are kept, so the lazy reads still work.
+    pub fn doc(&mut self) -> Result<&Automerge, AutomergeError> {
+        if self.doc.is_none() {
+            let rebuilt = self
+                .pending
+                .as_ref()
+                .expect("a lazy document is either pending or reconstructed")
+                .rebuild()?;
+            let pending = self.pending.take().unwrap();
+            self.doc = Some(rebuilt.assemble(pending.ops, pending.changes));
+        }
+        Ok(self.doc.as_ref().unwrap())
+    }
+
+    /// The reconstructed document, reconstructing it if that hasn't been done yet
+    pub fn into_doc(mut self) -> Result<Automerge, AutomergeError> {
+        self.doc()?;
+        Ok(self.doc.unwrap())
+    }
+
+    pub fn get_heads(&self) -> Vec<ChangeHash> {
+        match self.state() {
+            State::Pending(p) => p.heads.clone(),
+            State::Reconstructed(doc) => doc.get_heads(),
+        }
+    }
+
+    /// The actors which have made changes to the document, in the order used by
+    /// [`LazyChange`]s
+    pub fn actors(&self) -> &[ActorId] {
+        match self.state() {
+            State::Pending(p) => &p.ops.actors,
+            State::Reconstructed(doc) => &doc.ops.actors,
+        }
+    }
+
+    /// The metadata of every change in the document, in the order they were saved in
+    pub fn changes(&self) -> impl Iterator<Item = LazyChange<'_>> + '_ {
+        let (iter, actors) = match self.state() {
+            State::Pending(p) => (p.changes.iter(), &p.ops.actors),
+            State::Reconstructed(doc) => (doc.change_graph.iter(), &doc.ops.actors),
+        };
+        LazyChanges { iter, actors }
+    }
+
+    /// The type of the object `obj`
+    pub fn object_type<O: AsRef<ExId>>(&self, obj: O) -> Result<ObjType, AutomergeError> {
+        let p = match self.state() {
+            State::Pending(p) => p,
+            State::Reconstructed(doc) => return doc.object_type(obj),
+        };
+        let obj = obj.as_ref();
+        let id = p.exid_to_obj(obj)?;
+        if id.is_root() {
+            return Ok(ObjType::Map);
+        }
+        p.objects
+            .get(&id)
+            .copied()
+            .ok_or_else(|| AutomergeError::InvalidObjId(obj.to_string()))
+    }
+
+    /// Get the value of `prop` in `obj`, as [`ReadDoc::get()`]
+    ///
+    /// This doesn't reconstruct the document when `obj` is a map.
+    pub fn get<O: AsRef<ExId>, P: Into<Prop>>(
+        &mut self,
+        obj: O,
+        prop: P,
+    ) -> Result<Option<(Value<'_>, ExId)>, AutomergeError> {
+        Ok(self.get_all(obj, prop)?.into_iter().next_back())
+    }
+
+    /// Get the conflicting values of `prop` in `obj`, as [`ReadDoc::get_all()`]
+    ///
+    /// This doesn't reconstruct the document when `obj` is a map.
+    pub fn get_all<O: AsRef<ExId>, P: Into<Prop>>(
+        &mut self,
+        obj: O,
+        prop: P,
+    ) -> Result<Vec<(Value<'_>, ExId)>, AutomergeError> {
+        let obj = obj.as_ref();
+        let prop = prop.into();
+        if let (false, Prop::Map(key)) = (self.is_reconstructed(), &prop) {
+            if matches!(self.object_type(obj)?, ObjType::Map | ObjType::Table) {
+                let p = self.pending.as_ref().unwrap();
+                return Ok(p.get_all_map(p.exid_to_obj(obj)?, key));
+            }
+        }
+        self.doc()?.get_all(obj, prop)
+    }
+
+    fn state(&self) -> State<'_> {
+        match (&self.doc, &self.pending) {
+            (Some(doc), _) => State::Reconstructed(doc),
+            (None, Some(pending)) => State::Pending(pending),
+            (None, None) => unreachable!("a lazy document is either pending or reconstructed"),
+        }
+    }
+}
+
+impl From<Automerge> for LazyDocument {
+    fn from(doc: Automerge) -> Self {
+        Self {
+            pending: None,
+            doc: Some(doc),
+        }
+    }
+}
+
+enum State<'a> {
+    Pending(&'a Pending),
+    Reconstructed(&'a Automerge),
+}
+
+impl Pending {
+    /// Rebuild the changes and indexes, which is everything in reconstructing the document which
+    /// can fail
+    fn rebuild(&self) -> Result<Rebuilt, AutomergeError> {
+        let mut rebuilt = Rebuilt::new(
+            &self.ops,
+            &self.changes,
+            &self.heads,
+            self.verification_mode,
+            &self.limits,
+            &mut Progress::none(),
+        )
+        .map_err(reconstruct_error)?;
+        if let Some(error_message) = rebuilt.take_mark_order_error() {
+            return Err(reconstruct_error(
+                ReconstructError::InvalidMarkOrderChanges {
+                    changes: rebuilt.changes().to_vec(),
+                    error_message,
+                },
+            ));
+        }
+        Ok(rebuilt)
+    }
+
+    fn exid_to_obj(&self, id: &ExId) -> Result<ObjId, AutomergeError> {
+        match id {
+            ExId::Root => Ok(ObjId::root()),
+            ExId::Id(ctr, actor, idx) => {
+                let idx = if self.ops.get_actor_safe(*idx) == Some(actor) {
+                    *idx
+                } else {
+                    self.ops
+                        .lookup_actor(actor)
+                        .ok_or_else(|| AutomergeError::InvalidObjId(id.to_string()))?
+                };
+                Ok(ObjId(OpId::new(*ctr, idx)))
+            }
+        }
+    }
+
+    /// The visible values of `key` in the map `obj`
+    ///
+    /// All the ops for a key in a map, including increments of counters, are next to each other
+    /// so we can work out which are visible without the indexes which [`Automerge`] builds.
+    fn get_all_map(&self, obj: ObjId, key: &str) -> Vec<(Value<'_>, ExId)> {
+        let range = self.ops.prop_range(&obj, key);
+        let ops = self.ops.iter_range(&range).collect::<Vec<_>>();
+        let incs = ops
+            .iter()
+            .filter_map(|op| Some((op.id, op.get_increment_value()?)))
+            .collect::<HashMap<_, _>>();
+        ops.into_iter()
+            .filter(|op| op.action != Action::Increment)
+            .filter_map(|mut op| {
+                let mut inc = 0;
+                for succ in op.succ() {
+                    inc += incs.get(&succ)?;
+                }
+                if let ScalarValue::Counter(n) = op.value {
+                    op.value = ScalarValue::Counter(n + inc);
+                }
+                Some(op.tagged_value(&self.ops))
+            })
+            .collect()
+    }
+}
+
+fn reconstruct_error(e: ReconstructError) -> AutomergeError {
+    match e {
+        ReconstructError::LimitExceeded(e) => e.into(),
+        ReconstructError::Cancelled(e) => e.into(),
+        e => load::Error::InflateDocument(Box::new(e)).into(),
+    }
+}
+
+/// The type of every object created in `ops`
+fn object_types(ops: &OpSet) -> HashMap<ObjId, ObjType> {
+    let range = 0..ops.len();
+    ops.action_iter_range(&range)
+        .zip(ops.id_iter_range(&range))
+        .filter_map(|(action, id)| {
+            let typ = match action {
+                Action::MakeMap => ObjType::Map,
+                Action::MakeList => ObjType::List,
+                Action::MakeText => ObjType::Text,
+                Action::MakeTable => ObjType::Table,
+                _ => return None,
+            };
+            Some((ObjId(id), typ))
+        })
+        .collect()
+}
+
+struct LazyChanges<'a> {
+    iter: ChangeIter<'a>,
+    actors: &'a [ActorId],
+}
+
+impl<'a> Iterator for LazyChanges<'a> {
+    type Item = LazyChange<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let meta = self.iter.next()?;
+        Some(LazyChange {
+            actor: &self.actors[meta.actor],
+            seq: meta.seq,
+            start_op: meta.start_op,
+            max_op: meta.max_op,
+            timestamp: meta.timestamp,
+            message: meta.message,
+            extra: meta.extra,
+            deps: meta.deps.into_iter().map(|d| d as usize).collect(),
+        })
+    }
+}
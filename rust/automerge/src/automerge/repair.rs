@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::change_queue::ChangeQueue;
+use crate::storage::document::ReconstructError;
+use crate::storage::{self, load, Bundle, LoadLimits, VerificationMode, MAGIC_BYTES};
+use crate::types::{ChangeHash, TextEncoding};
+use crate::{Automerge, Change};
+
+/// What [`Automerge::repair()`] found wrong with its input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepairIssue {
+    /// Bytes which aren't part of any chunk which could be parsed
+    Unreadable { range: Range<usize> },
+    /// A change chunk whose checksum doesn't match its contents, the change was dropped
+    BadChangeChecksum { range: Range<usize> },
+    /// A document chunk whose checksum doesn't match its contents, the document was still
+    /// reconstructed so its ops may not be what was saved
+    BadDocumentChecksum { range: Range<usize> },
+    /// A document chunk whose heads don't match the heads of the changes reconstructed from it
+    MismatchedHeads {
+        range: Range<usize>,
+        expected: Vec<ChangeHash>,
+        derived: Vec<ChangeHash>,
+    },
+    /// A chunk which was parsed but whose changes couldn't be recovered
+    Unrecoverable { range: Range<usize>, error: String },
+    /// A change which was recovered but couldn't be applied to the document
+    NotApplied { hash: ChangeHash, error: String },
+}
+
+/// The result of [`Automerge::repair()`]
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// The number of changes recovered from the input, including those in [`Self::quarantined`]
+    pub recovered_changes: usize,
+    /// Changes whose dependencies aren't in the input, which were left out of the document
+    pub quarantined: Vec<Change>,
+    /// Everything in the input which was damaged, in the order it was found
+    pub issues: Vec<RepairIssue>,
+}
+
+impl RepairReport {
+    /// Whether the input loaded without any problems
+    pub fn is_clean(&self) -> bool {
+        self.quarantined.is_empty() && self.issues.is_empty()
+    }
+}
+
+impl Automerge {
+    /// Recover as much as possible of a document from damaged data
+    ///
+    /// Unlike [`Self::rescue()`] this keeps the history of the document. Every chunk in `data`
+    /// whose checksum is valid is recovered, skipping over damaged bytes to the next chunk.
+    /// Document chunks are reconstructed even if their checksum is invalid, and the heads of the
+    /// changes reconstructed from each one are checked against the heads it stores. Changes
+    /// whose dependencies are missing are left out of the document and returned in
+    /// [`RepairReport::quarantined`], so they can be applied if the missing changes turn up.
+    ///
+    /// This never fails, in the worst case it returns an empty document and a report of why.
+    pub fn repair(data: &[u8]) -> (Self, RepairReport) {
+        let mut report = RepairReport::default();
+        let mut base = None;
+        let mut changes = Vec::new();
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let issues_before = report.issues.len();
+            let input = storage::parse::Input::new(&data[pos..]);
+            let end = match storage::Chunk::parse(input) {
+                Ok((remaining, chunk)) => {
+                    let end = data.len() - remaining.bytes().len();
+                    let recovered =
+                        recover_chunk(chunk, pos..end, &mut base, &mut changes, &mut report.issues);
+                    recovered.then_some(end)
+                }
+                Err(_) => None,
+            };
+            pos = match end {
+                Some(end) => end,
+                None => {
+                    // Skip to the next thing which looks like a chunk, a damaged length could make
+                    // this chunk appear to cover the chunks after it
+                    let next = next_magic(data, pos + 1);
+                    // Unless the chunk has already been reported
+                    if report.issues.len() == issues_before {
+                        match report.issues.last_mut() {
+                            Some(RepairIssue::Unreadable { range }) if range.end == pos => {
+                                range.end = next
+                            }
+                            _ => report
+                                .issues
+                                .push(RepairIssue::Unreadable { range: pos..next }),
+                        }
+                    }
+                    next
+                }
+            };
+        }
+
+        let mut doc = base.unwrap_or_else(Self::new);
+        // The same change can be in several chunks, or in the base document and a later chunk
+        let new_changes = changes
+            .iter()
+            .map(Change::hash)
+            .filter(|hash| !doc.has_change(hash))
+            .collect::<HashSet<_>>();
+        report.recovered_changes = doc.change_graph.len() + new_changes.len();
+        if let Err(e) = doc.apply_changes(changes.iter().cloned()) {
+            tracing::warn!(err=?e, "failed to apply recovered changes, applying them one by one");
+            for change in changes {
+                let hash = change.hash();
+                if let Err(e) = doc.apply_changes([change]) {
+                    report.issues.push(RepairIssue::NotApplied {
+                        hash,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        let queue = std::mem::replace(&mut doc.queue, ChangeQueue::new());
+        report.quarantined = queue.iter().cloned().collect();
+        (doc, report)
+    }
+}
+
+/// Recover the changes in one chunk, returns false if the chunk should be skipped over byte by
+/// byte rather than as a whole
+fn recover_chunk(
+    chunk: storage::Chunk<'_>,
+    range: Range<usize>,
+    base: &mut Option<Automerge>,
+    changes: &mut Vec<Change>,
+    issues: &mut Vec<RepairIssue>,
+) -> bool {
+    let checksum_valid = chunk.checksum_valid();
+    match chunk {
+        storage::Chunk::Document(d) => {
+            if !checksum_valid {
+                issues.push(RepairIssue::BadDocumentChecksum {
+                    range: range.clone(),
+                });
+            }
+            match d.reconstruct(
+                VerificationMode::Check,
+                TextEncoding::platform_default(),
+                &LoadLimits::unlimited(),
+                &mut load::Progress::none(),
+            ) {
+                Ok(doc) => match base {
+                    None => *base = Some(doc),
+                    Some(_) => changes.extend(doc.get_changes(&[])),
+                },
+                Err(ReconstructError::InvalidMarkOrderDoc { doc, .. }) => match base {
+                    None => *base = Some(*doc),
+                    Some(_) => changes.extend(doc.get_changes(&[])),
+                },
+                // The changes are rebuilt from the ops so they're still usable, but the heads of
+                // a document built from the chunk would be the stored ones
+                Err(ReconstructError::MismatchingHeads(mismatch)) => {
+                    issues.push(RepairIssue::MismatchedHeads {
+                        range,
+                        expected: mismatch.expected_heads.into_iter().collect(),
+                        derived: mismatch.derived_heads.into_iter().collect(),
+                    });
+                    changes.extend(mismatch.changes);
+                }
+                Err(e) => {
+                    issues.push(RepairIssue::Unrecoverable {
+                        range,
+                        error: e.to_string(),
+                    });
+                    return checksum_valid;
+                }
+            }
+            true
+        }
+        _ if !checksum_valid => {
+            issues.push(RepairIssue::BadChangeChecksum { range });
+            false
+        }
+        storage::Chunk::Change(c) => match Change::new_from_unverified(c.into_owned(), None) {
+            Ok(change) => {
+                changes.push(change);
+                true
+            }
+            Err(e) => {
+                issues.push(RepairIssue::Unrecoverable {
+                    range,
+                    error: e.to_string(),
+                });
+                false
+            }
+        },
+        storage::Chunk::CompressedChange(c, compressed) => {
+            match Change::new_from_unverified(c.into_owned(), Some(compressed.into_owned())) {
+                Ok(change) => {
+                    changes.push(change);
+                    true
+                }
+                Err(e) => {
+                    issues.push(RepairIssue::Unrecoverable {
+                        range,
+                        error: e.to_string(),
+                    });
+                    false
+                }
+            }
+        }
+        storage::Chunk::Bundle(b) => {
+            let bundle_changes = Bundle::new_from_unverified(b.into_owned())
+                .map_err(|e| e.to_string())
+                .and_then(|bundle| bundle.to_changes().map_err(|e| e.to_string()));
+            match bundle_changes {
+                Ok(bundle_changes) => {
+                    changes.extend(bundle_changes);
+                    true
+                }
+                Err(error) => {
+                    issues.push(RepairIssue::Unrecoverable { range, error });
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// The offset of the next magic bytes at or after `from`, or the end of `data`
+fn next_magic(data: &[u8], from: usize) -> usize {
+    data.get(from..)
+        .and_then(|rest| {
+            rest.windows(MAGIC_BYTES.len())
+                .position(|window| window == MAGIC_BYTES)
+        })
+        .map(|i| from + i)
+        .unwrap_or(data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::Transactable, AutoCommit, ReadDoc, ROOT};
+
+    #[test]
+    fn repair_clean_document() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let (repaired, report) = Automerge::repair(&doc.save());
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(repaired.get_heads(), doc.get_heads());
+    }
+
+    #[test]
+    fn repair_skips_damaged_change_chunks() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let mut data = doc.save();
+        let snapshot_heads = doc.get_heads();
+        let mut other = doc.fork();
+
+        doc.put(ROOT, "b", 2).unwrap();
+        let first = doc.save_incremental();
+        doc.put(ROOT, "c", 3).unwrap();
+        let second = doc.save_incremental();
+        other.put(ROOT, "d", 4).unwrap();
+        let independent = other.save_after(&snapshot_heads);
+
+        // Damage the first incremental change, which the second depends on
+        let damaged_at = data.len() + first.len() - 1;
+        data.extend(&first);
+        data.extend(&second);
+        data.extend(&independent);
+        data[damaged_at] ^= 0xff;
+        // Put some garbage at the end
+        data.extend([1, 2, 3]);
+
+        let (repaired, report) = Automerge::repair(&data);
+        assert_eq!(report.recovered_changes, 3);
+        assert_eq!(report.quarantined.len(), 1);
+        assert_eq!(report.quarantined[0].hash(), doc.get_heads()[0]);
+        assert_eq!(report.issues.len(), 2, "{:?}", report.issues);
+        assert!(matches!(
+            report.issues[0],
+            RepairIssue::BadChangeChecksum { .. }
+        ));
+        assert_eq!(
+            report.issues[1],
+            RepairIssue::Unreadable {
+                range: data.len() - 3..data.len()
+            }
+        );
+        assert_eq!(repaired.get_heads(), other.get_heads());
+        assert!(repaired.get(ROOT, "b").unwrap().is_none());
+        assert!(repaired.get(ROOT, "d").unwrap().is_some());
+    }
+
+    #[test]
+    fn repair_counts_each_change_once() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let mut data = doc.save();
+        doc.put(ROOT, "b", 2).unwrap();
+        let incremental = doc.save_incremental();
+        // The snapshot twice, the incremental change twice and a snapshot which contains it
+        data.extend(data.clone());
+        data.extend(&incremental);
+        data.extend(&incremental);
+        data.extend(doc.save());
+
+        let (repaired, report) = Automerge::repair(&data);
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(report.recovered_changes, 2);
+        assert_eq!(repaired.get_heads(), doc.get_heads());
+    }
+
+    #[test]
+    fn repair_reports_mismatched_heads() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let mut data = doc.save_nocompress();
+        // Damage the heads stored in the document chunk
+        let head = doc.get_heads()[0];
+        let at = data.windows(32).position(|w| w == head.as_bytes()).unwrap();
+        data[at + 31] ^= 0xff;
+
+        let (repaired, report) = Automerge::repair(&data);
+        assert_eq!(repaired.get_heads(), doc.get_heads());
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, RepairIssue::BadDocumentChecksum { .. })));
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, RepairIssue::MismatchedHeads { .. })));
+    }
+}
@@ -46,6 +46,7 @@ pub(crate) struct ChangeGraph {
     fragments: Vec<FragmentNode>,
 }
 
+#[derive(Debug)]
 pub(crate) struct ChangeGraphCols(ChangeGraph);
 
 const CACHE_STEP: u32 = 16;
@@ -335,6 +336,12 @@ impl ChangeGraph {
         self.nodes_by_hash.contains_key(hash)
     }
 
+    /// The number of ops in the change with the given hash
+    pub(crate) fn num_ops(&self, hash: &ChangeHash) -> Option<u64> {
+        let node_idx = self.nodes_by_hash.get(hash)?;
+        self.num_ops.get(node_idx.0 as usize)
+    }
+
     pub(crate) fn get_bundle_metadata<I>(
         &self,
         hashes: I,
@@ -63,6 +63,10 @@ impl ChangeQueue {
         self.changes.is_empty()
     }
 
+    pub(crate) fn len(&self) -> usize {
+        self.changes.len()
+    }
+
     /// O(1) check whether a change with this hash is in the queue.
     pub(crate) fn has_hash(&self, hash: &ChangeHash) -> bool {
         self.hashes.contains(hash)
@@ -184,4 +188,38 @@ impl ChangeQueue {
 
         topo
     }
+
+    /// The first hash on each path back from `start` which is neither in `change_graph` nor
+    /// queued, traversing through the dependencies of queued changes on the way.
+    pub(crate) fn missing_deps_from(
+        &self,
+        change_graph: &ChangeGraph,
+        start: impl Iterator<Item = ChangeHash>,
+    ) -> Vec<ChangeHash> {
+        let queued_changes = self
+            .changes
+            .iter()
+            .map(|change| (change.hash(), change))
+            .collect::<HashMap<_, _>>();
+
+        let mut missing = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut stack = start.collect::<Vec<_>>();
+
+        while let Some(hash) = stack.pop() {
+            if change_graph.has_change(&hash) || !seen.insert(hash) {
+                continue;
+            }
+
+            if let Some(change) = queued_changes.get(&hash) {
+                stack.extend(change.deps().iter().copied());
+            } else {
+                missing.insert(hash);
+            }
+        }
+
+        let mut missing = missing.into_iter().collect::<Vec<_>>();
+        missing.sort();
+        missing
+    }
 }
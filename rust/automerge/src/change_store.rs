@@ -0,0 +1,413 @@
+use crate::{
+    change_graph::ChangeGraph,
+    change_queue::{ChangeBatch, ChangeQueue},
+    patches::PatchLog,
+    storage::{self, load},
+    sync::{self, SyncDoc, SyncStore},
+    ActorId, Automerge, AutomergeError, Change, ChangeHash, LoadLimits, TextEncoding,
+};
+
+/// A store of changes which can take part in the sync protocol without materializing the document
+///
+/// An [`Automerge`] keeps both the history of a document and its current state, and applying
+/// changes means merging their ops into that state. A `ChangeStore` only keeps the changes
+/// themselves and the graph of their dependencies, which is all the sync protocol needs. This
+/// makes it a cheap way for a server to relay documents between peers: receiving a change is a
+/// matter of checking its dependencies and storing its bytes.
+///
+/// The current state of the document is only computed when asked for, with [`Self::save()`] or
+/// [`Self::to_automerge()`].
+///
+/// ## Example
+///
+/// ```
+/// use automerge::{transaction::Transactable, sync::{self, SyncDoc}, AutoCommit, ChangeStore, ReadDoc};
+/// # fn main() -> Result<(), automerge::AutomergeError> {
+/// let mut doc = AutoCommit::new();
+/// doc.put(automerge::ROOT, "key", "value")?;
+///
+/// let mut store = ChangeStore::new();
+/// let mut doc_state = sync::State::new();
+/// let mut store_state = sync::State::new();
+/// loop {
+///     let to_store = doc.sync().generate_sync_message(&mut doc_state);
+///     if let Some(message) = to_store.clone() {
+///         store.receive_sync_message(&mut store_state, message)?;
+///     }
+///     let to_doc = store.generate_sync_message(&mut store_state);
+///     if let Some(message) = to_doc.clone() {
+///         doc.sync().receive_sync_message(&mut doc_state, message)?;
+///     }
+///     if to_store.is_none() && to_doc.is_none() {
+///         break;
+///     }
+/// }
+///
+/// assert_eq!(store.get_heads(), doc.get_heads());
+/// let loaded = store.to_automerge()?;
+/// assert_eq!(loaded.get(automerge::ROOT, "key")?.unwrap().0.to_str(), Some("value"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChangeStore {
+    /// The changes which have been added to the change graph, in the same order
+    changes: Vec<Change>,
+    change_graph: ChangeGraph,
+    /// Sorted, the index of an actor here is its index in the change graph
+    actors: Vec<ActorId>,
+    /// Changes whose dependencies we don't have yet
+    queue: ChangeQueue,
+    num_ops: usize,
+}
+
+impl ChangeStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self {
+            changes: Vec::new(),
+            change_graph: ChangeGraph::new(0),
+            actors: Vec::new(),
+            queue: ChangeQueue::new(),
+            num_ops: 0,
+        }
+    }
+
+    /// Load a store from the output of [`Automerge::save()`], [`Self::save()`] or any
+    /// concatenation of change chunks
+    pub fn load(data: &[u8]) -> Result<Self, AutomergeError> {
+        let mut store = Self::new();
+        store.load_incremental(data)?;
+        Ok(store)
+    }
+
+    /// Load the changes in `data`, which can be anything [`Automerge::load_incremental()`] accepts
+    ///
+    /// The return value is the number of changes which were added to the store.
+    pub fn load_incremental(&mut self, data: &[u8]) -> Result<usize, AutomergeError> {
+        self.load_incremental_with_limits(data, &LoadLimits::unlimited())
+    }
+
+    /// Like [`Self::load_incremental()`] but fail without adding anything if the store would
+    /// exceed `limits`
+    ///
+    /// A `ChangeStore` doesn't build the document, so [`LoadLimits::max_depth`] isn't checked.
+    pub fn load_incremental_with_limits(
+        &mut self,
+        data: &[u8],
+        limits: &LoadLimits,
+    ) -> Result<usize, AutomergeError> {
+        let changes = match load::load_changes(
+            storage::parse::Input::new(data),
+            TextEncoding::platform_default(),
+            &self.change_graph,
+            load::MarkOrderValidation::Validate,
+            limits,
+            &mut load::Progress::none(),
+        ) {
+            load::LoadedChanges::Complete(c) => c,
+            load::LoadedChanges::Partial { error, .. } if error.is_fatal() => {
+                return Err(error.into());
+            }
+            load::LoadedChanges::Partial { error, loaded, .. } => {
+                tracing::warn!(successful_chunks=loaded.len(), err=?error, "partial load");
+                loaded
+            }
+        };
+        limits.check_changes(
+            &changes,
+            &self.change_graph,
+            &self.queue,
+            self.num_ops,
+            &self.actors,
+        )?;
+        let start = self.changes.len();
+        self.apply_changes(changes)?;
+        Ok(self.changes.len() - start)
+    }
+
+    /// Add changes to the store
+    ///
+    /// Changes which are already in the store are ignored. Changes whose dependencies are not in
+    /// the store are held back until the dependencies arrive, see [`Self::get_missing_deps()`].
+    pub fn apply_changes(
+        &mut self,
+        changes: impl IntoIterator<Item = Change>,
+    ) -> Result<(), AutomergeError> {
+        let mut batch = ChangeBatch::new();
+        let changes = changes.into_iter().filter(|c| {
+            let hash = c.hash();
+            !(self.change_graph.has_change(&hash) || self.queue.has_hash(&hash))
+        });
+        for c in changes {
+            if self.has_actor_seq(&c) {
+                self.queue
+                    .remove_actor_branch_from(c.actor_id(), c.seq().saturating_add(1));
+                return Err(AutomergeError::DuplicateSeqNumber(
+                    c.seq(),
+                    c.actor_id().clone(),
+                ));
+            }
+            if self.queue.has_actor_seq(&c) {
+                return Err(AutomergeError::DuplicateSeqNumber(
+                    c.seq(),
+                    c.actor_id().clone(),
+                ));
+            }
+            batch.push(c)?;
+        }
+
+        self.queue.extend(batch);
+
+        for change in self.queue.pop_topo_sorted_ready(&self.change_graph) {
+            self.insert_change(change);
+        }
+        Ok(())
+    }
+
+    fn insert_change(&mut self, change: Change) {
+        let actor = match self.actors.binary_search(change.actor_id()) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.actors.insert(idx, change.actor_id().clone());
+                self.change_graph.insert_actor(idx);
+                idx
+            }
+        };
+        self.change_graph
+            .add_change(&change, actor)
+            .expect("Change's deps should already be in the store");
+        self.num_ops += change.len();
+        self.changes.push(change);
+    }
+
+    fn has_actor_seq(&self, change: &Change) -> bool {
+        let seq = self
+            .actors
+            .binary_search(change.actor_id())
+            .map(|idx| self.change_graph.seq_for_actor(idx))
+            .unwrap_or(0);
+        seq >= change.seq()
+    }
+
+    /// The number of changes in the store, not counting those waiting for their dependencies
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Whether there are no changes in the store
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Get the heads of the document
+    pub fn get_heads(&self) -> Vec<ChangeHash> {
+        self.change_graph.heads().collect()
+    }
+
+    /// Get the changes since `have_deps`, in causal order
+    pub fn get_changes(&self, have_deps: &[ChangeHash]) -> Vec<&Change> {
+        self.change_graph
+            .get_hashes(have_deps)
+            .iter()
+            .filter_map(|hash| self.get_change_by_hash(hash))
+            .collect()
+    }
+
+    /// Get a change by its hash
+    pub fn get_change_by_hash(&self, hash: &ChangeHash) -> Option<&Change> {
+        let idx = self.change_graph.hash_to_index(hash)?;
+        self.changes.get(idx)
+    }
+
+    /// Get the hashes of the changes we need before we can add the queued changes and reach
+    /// `heads`
+    pub fn get_missing_deps(&self, heads: &[ChangeHash]) -> Vec<ChangeHash> {
+        let queued = self.queue.iter().map(|change| change.hash());
+        self.queue
+            .missing_deps_from(&self.change_graph, queued.chain(heads.iter().copied()))
+    }
+
+    /// Build the document these changes describe
+    pub fn to_automerge(&self) -> Result<Automerge, AutomergeError> {
+        let mut doc = Automerge::new();
+        doc.apply_changes(self.changes.iter().cloned())?;
+        Ok(doc)
+    }
+
+    /// Save the document these changes describe in the same compact format as
+    /// [`Automerge::save()`]
+    ///
+    /// This builds the document with [`Self::to_automerge()`] so it is as expensive as loading the
+    /// changes into an [`Automerge`].
+    pub fn save(&self) -> Result<Vec<u8>, AutomergeError> {
+        Ok(self.to_automerge()?.save())
+    }
+}
+
+impl Default for ChangeStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyncStore for ChangeStore {
+    fn change_graph(&self) -> &ChangeGraph {
+        &self.change_graph
+    }
+
+    fn missing_deps(&self, start: impl Iterator<Item = ChangeHash>) -> Vec<ChangeHash> {
+        self.queue.missing_deps_from(&self.change_graph, start)
+    }
+
+    fn changes_by_hashes(&self, hashes: Vec<ChangeHash>) -> Result<Vec<Change>, AutomergeError> {
+        hashes
+            .into_iter()
+            .map(|hash| {
+                self.get_change_by_hash(&hash)
+                    .cloned()
+                    .ok_or(AutomergeError::MissingHash(hash))
+            })
+            .collect()
+    }
+
+    fn save_whole_doc(&self) -> Option<Vec<u8>> {
+        // Building the document is the expensive thing we are trying to avoid, peers which have
+        // nothing get the individual changes instead
+        None
+    }
+
+    fn load_sync_changes(
+        &mut self,
+        data: &[u8],
+        limits: &LoadLimits,
+        _patch_log: &mut PatchLog,
+    ) -> Result<(), AutomergeError> {
+        self.load_incremental_with_limits(data, limits)?;
+        Ok(())
+    }
+
+    fn num_ops(&self) -> usize {
+        self.num_ops
+    }
+}
+
+/// A `ChangeStore` has no document state to patch, so the `_log_patches` variant is the same as
+/// [`SyncDoc::receive_sync_message()`]
+impl SyncDoc for ChangeStore {
+    fn generate_sync_message(&self, sync_state: &mut sync::State) -> Option<sync::Message> {
+        self.generate_sync_message_inner(sync_state)
+    }
+
+    fn receive_sync_message(
+        &mut self,
+        sync_state: &mut sync::State,
+        message: sync::Message,
+    ) -> Result<(), AutomergeError> {
+        self.receive_sync_message_inner(sync_state, message, &mut PatchLog::inactive())
+    }
+
+    fn receive_sync_message_log_patches(
+        &mut self,
+        sync_state: &mut sync::State,
+        message: sync::Message,
+        patch_log: &mut PatchLog,
+    ) -> Result<(), AutomergeError> {
+        self.receive_sync_message_inner(sync_state, message, patch_log)
+    }
+
+    fn sync_progress(&self, sync_state: &sync::State) -> Option<sync::Progress> {
+        self.sync_progress_inner(sync_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transaction::{CommitOptions, Transactable},
+        AutoCommit, ReadDoc, ROOT,
+    };
+
+    fn sync(a: &mut impl SyncDoc, b: &mut impl SyncDoc) {
+        let mut a_state = sync::State::new();
+        let mut b_state = sync::State::new();
+        for _ in 0..10 {
+            let a_to_b = a.generate_sync_message(&mut a_state);
+            if let Some(message) = a_to_b.clone() {
+                b.receive_sync_message(&mut b_state, message).unwrap();
+            }
+            let b_to_a = b.generate_sync_message(&mut b_state);
+            if let Some(message) = b_to_a.clone() {
+                a.receive_sync_message(&mut a_state, message).unwrap();
+            }
+            if a_to_b.is_none() && b_to_a.is_none() {
+                return;
+            }
+        }
+        panic!("failed to sync in 10 rounds");
+    }
+
+    #[test]
+    fn relays_changes_between_peers() {
+        let mut doc1 = AutoCommit::new();
+        doc1.put(ROOT, "from1", "one").unwrap();
+        let mut doc1 = doc1.document().clone();
+        let mut doc2 = Automerge::new();
+        let mut store = ChangeStore::new();
+
+        sync(&mut doc1, &mut store);
+        sync(&mut store, &mut doc2);
+        assert_eq!(doc2.get_heads(), doc1.get_heads());
+
+        let mut doc2 = AutoCommit::load(&doc2.save()).unwrap();
+        doc2.put(ROOT, "from2", "two").unwrap();
+        let mut doc2 = doc2.document().clone();
+        sync(&mut doc2, &mut store);
+        sync(&mut store, &mut doc1);
+
+        assert_eq!(store.get_heads(), doc2.get_heads());
+        assert_eq!(doc1.get_heads(), doc2.get_heads());
+        assert_eq!(
+            doc1.get(ROOT, "from2").unwrap().unwrap().0.to_str(),
+            Some("two")
+        );
+    }
+
+    #[test]
+    fn save_builds_the_document() {
+        let mut doc = AutoCommit::new();
+        let list = doc.put_object(ROOT, "list", crate::ObjType::List).unwrap();
+        doc.insert(&list, 0, "a").unwrap();
+        doc.commit();
+        doc.insert(&list, 1, "b").unwrap();
+        doc.commit();
+
+        let store = ChangeStore::load(&doc.save()).unwrap();
+        assert_eq!(store.len(), 2);
+
+        let loaded = Automerge::load(&store.save().unwrap()).unwrap();
+        assert_eq!(loaded.get_heads(), doc.get_heads());
+        assert_eq!(loaded.length(&list), 2);
+    }
+
+    #[test]
+    fn changes_wait_for_their_dependencies() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        doc.commit_with(CommitOptions::default().with_time(0));
+        doc.put(ROOT, "b", 2).unwrap();
+        doc.commit_with(CommitOptions::default().with_time(0));
+        let changes = doc.get_changes(&[]);
+
+        let mut store = ChangeStore::new();
+        store.apply_changes([changes[1].clone()]).unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.get_missing_deps(&[]), vec![changes[0].hash()]);
+
+        store.apply_changes([changes[0].clone()]).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get_heads(), vec![changes[1].hash()]);
+        assert!(store.get_missing_deps(&[]).is_empty());
+    }
+}
@@ -79,6 +79,78 @@ impl Default for MoveCursor {
     }
 }
 
+/// A range in a sequence which follows its contents as the sequence is edited, made of two
+/// [`Cursor`]s
+///
+/// The start cursor is on the first element in the range and the end cursor is on the last
+/// element in the range, so text inserted at either edge of the range is outside it. An empty
+/// range has no end cursor, its start cursor marks where it is, and it stays empty.
+///
+/// Each end has its own [`MoveCursor`] policy for when its element is deleted. The default of
+/// [`MoveCursor::After`] for the start and [`MoveCursor::Before`] for the end shrinks the range
+/// to the elements which are left. If the whole range is deleted, and text is inserted
+/// concurrently where it was, the ends can cross, and the range collapses to an empty range as
+/// [`Self::collapse`] says.
+///
+/// A range is obtained from [`ReadDoc::get_cursor_range()`] or
+/// [`ReadDoc::get_cursor_range_moving()`] and resolved to a `Range<usize>` with
+/// [`ReadDoc::get_cursor_range_position()`]. It can be persisted using [`Self::to_bytes()`] and
+/// [`TryFrom<&[u8]>`][TryFrom].
+#[derive(Clone, PartialEq, Debug)]
+pub struct CursorRange {
+    pub start: Cursor,
+    /// The cursor on the last element in the range, `None` if the range is empty
+    pub end: Option<Cursor>,
+    pub collapse: RangeCollapse,
+}
+
+/// Where a [`CursorRange`] whose ends have crossed collapses to
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RangeCollapse {
+    /// An empty range at the position of the start cursor
+    #[default]
+    Start,
+    /// An empty range at the position of the end cursor
+    End,
+}
+
+/// Where a cursor resolved to, which decides where the ends of a [`CursorRange`] are
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum CursorResolution {
+    /// The element the cursor is on, at `index`
+    Element { index: usize, width: usize },
+    /// The element the cursor was on is gone, this is the visible element before it
+    Previous { index: usize, width: usize },
+    /// A position between elements
+    Boundary(usize),
+}
+
+impl CursorResolution {
+    pub(crate) fn position(&self) -> usize {
+        match self {
+            Self::Element { index, .. } | Self::Previous { index, .. } | Self::Boundary(index) => {
+                *index
+            }
+        }
+    }
+
+    /// The start of a range whose first element is the cursor's
+    pub(crate) fn range_start(&self) -> usize {
+        match self {
+            Self::Element { index, .. } | Self::Boundary(index) => *index,
+            Self::Previous { index, width } => index + width,
+        }
+    }
+
+    /// The end of a range whose last element is the cursor's
+    pub(crate) fn range_end(&self) -> usize {
+        match self {
+            Self::Element { index, width } | Self::Previous { index, width } => index + width,
+            Self::Boundary(index) => *index,
+        }
+    }
+}
+
 const VERSION_TAG: u8 = 1;
 
 const START_TAG: u8 = 1;
@@ -88,6 +160,11 @@ const OP_TAG: u8 = 3;
 const MOVE_BEFORE_TAG: u8 = 1;
 const MOVE_AFTER_TAG: u8 = 2;
 
+const RANGE_VERSION_TAG: u8 = 1;
+
+const COLLAPSE_START_TAG: u8 = 1;
+const COLLAPSE_END_TAG: u8 = 2;
+
 impl Cursor {
     fn from_str(s: &str) -> Option<Self> {
         if s.len() == 1 {
@@ -294,3 +371,106 @@ impl TryFrom<Vec<u8>> for Cursor {
         Self::try_from(value.as_slice())
     }
 }
+
+impl CursorRange {
+    /// A range from the element `start` is on to the element `end` is on, inclusive
+    pub fn new(start: Cursor, end: Cursor) -> Self {
+        Self {
+            start,
+            end: Some(end),
+            collapse: RangeCollapse::default(),
+        }
+    }
+
+    /// An empty range at the position of `at`
+    pub fn empty(at: Cursor) -> Self {
+        Self {
+            start: at,
+            end: None,
+            collapse: RangeCollapse::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end.is_none()
+    }
+
+    pub fn with_collapse(mut self, collapse: RangeCollapse) -> Self {
+        self.collapse = collapse;
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        /*
+        EBNF, using the definitions from `Cursor::to_bytes`:
+
+        range_version = %d01 ; for version 1
+
+        collapse  = %d01 ; RangeCollapse::Start
+                  / %d02 ; RangeCollapse::End
+
+        range = range_version collapse uleb128 cursor uleb128 [cursor]
+                ; the start and end cursors, each prefixed with its length,
+                ; an empty range has no end cursor so its length is zero
+         */
+        let start = self.start.to_bytes();
+        let end = self.end.as_ref().map(Cursor::to_bytes).unwrap_or_default();
+        let mut bytes = Vec::with_capacity(2 + 8 + start.len() + 8 + end.len());
+        bytes.push(RANGE_VERSION_TAG);
+        bytes.push(match self.collapse {
+            RangeCollapse::Start => COLLAPSE_START_TAG,
+            RangeCollapse::End => COLLAPSE_END_TAG,
+        });
+        for cursor in [start, end] {
+            leb128::write::unsigned(&mut bytes, cursor.len() as u64).unwrap();
+            bytes.extend(cursor);
+        }
+        bytes
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for CursorRange {
+    type Error = AutomergeError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let i = parse::Input::new(value);
+        let (i, version) =
+            parse::take1::<()>(i).map_err(|_| AutomergeError::InvalidCursorFormat)?;
+        if version != RANGE_VERSION_TAG {
+            return Err(AutomergeError::InvalidCursorFormat);
+        }
+        let (i, collapse) =
+            parse::take1::<()>(i).map_err(|_| AutomergeError::InvalidCursorFormat)?;
+        let collapse = match collapse {
+            COLLAPSE_START_TAG => RangeCollapse::Start,
+            COLLAPSE_END_TAG => RangeCollapse::End,
+            _ => return Err(AutomergeError::InvalidCursorFormat),
+        };
+        let (i, len) = parse::leb128_u64::<parse::leb128::Error>(i)
+            .map_err(|_| AutomergeError::InvalidCursorFormat)?;
+        let (i, start) = parse::take_n::<()>(len as usize, i)
+            .map_err(|_| AutomergeError::InvalidCursorFormat)?;
+        let (i, len) = parse::leb128_u64::<parse::leb128::Error>(i)
+            .map_err(|_| AutomergeError::InvalidCursorFormat)?;
+        let (_i, end) = parse::take_n::<()>(len as usize, i)
+            .map_err(|_| AutomergeError::InvalidCursorFormat)?;
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(Cursor::try_from(end)?)
+        };
+        Ok(Self {
+            start: Cursor::try_from(start)?,
+            end,
+            collapse,
+        })
+    }
+}
+
+impl TryFrom<Vec<u8>> for CursorRange {
+    type Error = AutomergeError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_slice())
+    }
+}
@@ -0,0 +1,523 @@
+//! Converting between text objects and the [Quill Delta] format
+//!
+//! A Delta is a list of operations which either describe a whole document, as a list of inserts,
+//! or a change to a document, as a list of retains, deletes and inserts which walk through it
+//! from the start. Each insert and retain can carry attributes, which map to marks: a mark named
+//! `bold` with the value `true` is the attribute `"bold": true`, and in a change a `null`
+//! attribute ([`ScalarValue::Null`]) removes the mark.
+//!
+//! Block markers are inserted as embeds ([`DeltaInsert::Block`]) holding the block map, so they
+//! take up one position like they do in the text. Lengths and positions are in the units of the
+//! document's [`crate::TextEncoding`].
+//!
+//! * [`document_delta()`] describes a whole text object
+//! * [`patches_to_deltas()`] turns the [`PatchAction::SpliceText`], [`PatchAction::DeleteSeq`],
+//!   [`PatchAction::Mark`] and block [`PatchAction::Insert`] patches for a text object into change
+//!   Deltas, which can be applied one after the other
+//! * [`apply_delta()`] applies a change Delta to a text object
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::collections::BTreeMap;
+//! # use automerge::{delta::{self, DeltaInsert, DeltaOp}, marks::UpdateSpansConfig};
+//! # use automerge::{transaction::Transactable, AutoCommit, ObjType, ScalarValue, ROOT};
+//! let mut doc = AutoCommit::new();
+//! let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+//! doc.splice_text(&text, 0, 0, "hello world").unwrap();
+//!
+//! // Make "world" bold
+//! let change = vec![
+//!     DeltaOp::Retain { retain: 6, attributes: BTreeMap::new() },
+//!     DeltaOp::Retain {
+//!         retain: 5,
+//!         attributes: BTreeMap::from([("bold".to_string(), ScalarValue::from(true))]),
+//!     },
+//! ];
+//! delta::apply_delta(&mut doc, &text, &change, &UpdateSpansConfig::default()).unwrap();
+//!
+//! assert_eq!(
+//!     delta::document_delta(&doc, &text).unwrap(),
+//!     vec![
+//!         DeltaOp::Insert {
+//!             insert: DeltaInsert::Text("hello ".to_string()),
+//!             attributes: BTreeMap::new(),
+//!         },
+//!         DeltaOp::Insert {
+//!             insert: DeltaInsert::Text("world".to_string()),
+//!             attributes: BTreeMap::from([("bold".to_string(), ScalarValue::from(true))]),
+//!         },
+//!     ]
+//! );
+//! ```
+//!
+//! [Quill Delta]: https://quilljs.com/docs/delta
+use std::collections::BTreeMap;
+
+use crate::error::UpdateObjectError;
+use crate::exid::ExId;
+use crate::hydrate;
+use crate::iter::Span;
+use crate::marks::{ExpandMark, Mark, MarkSet, UpdateSpansConfig};
+use crate::transaction::Transactable;
+use crate::{AutomergeError, ChangeHash, Patch, PatchAction, ReadDoc, ScalarValue, Value};
+
+/// One operation in a Delta
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeltaOp {
+    /// Insert text or a block marker at the current position, with the given marks
+    Insert {
+        insert: DeltaInsert,
+        attributes: BTreeMap<String, ScalarValue>,
+    },
+    /// Move past `retain` positions, setting or (with a null value) removing the given marks on
+    /// them
+    Retain {
+        retain: usize,
+        attributes: BTreeMap<String, ScalarValue>,
+    },
+    /// Delete `delete` positions
+    Delete { delete: usize },
+}
+
+/// What a [`DeltaOp::Insert`] inserts
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeltaInsert {
+    Text(String),
+    /// A block marker, with the contents of the block map
+    Block(hydrate::Map),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApplyDeltaError {
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+    #[error("the delta goes past the end of the text, at {position} of {length}")]
+    OutOfBounds { position: usize, length: usize },
+    #[error("unable to set the contents of a block: {0}")]
+    Block(#[from] UpdateObjectError),
+}
+
+/// Describe the text object `obj` as a document Delta
+pub fn document_delta<R: ReadDoc, O: AsRef<ExId>>(
+    doc: &R,
+    obj: O,
+) -> Result<Vec<DeltaOp>, AutomergeError> {
+    Ok(spans_to_delta(doc.spans(obj)?))
+}
+
+/// Describe the text object `obj` as at `heads` as a document Delta
+pub fn document_delta_at<R: ReadDoc, O: AsRef<ExId>>(
+    doc: &R,
+    obj: O,
+    heads: &[ChangeHash],
+) -> Result<Vec<DeltaOp>, AutomergeError> {
+    Ok(spans_to_delta(doc.spans_at(obj, heads)?))
+}
+
+fn spans_to_delta<I: IntoIterator<Item = Span>>(spans: I) -> Vec<DeltaOp> {
+    let mut delta = Vec::new();
+    for span in spans {
+        match span {
+            Span::Text { text, marks } => push(
+                &mut delta,
+                DeltaOp::Insert {
+                    insert: DeltaInsert::Text(text),
+                    attributes: attributes(marks.as_deref()),
+                },
+            ),
+            Span::Block(block) => push(
+                &mut delta,
+                DeltaOp::Insert {
+                    insert: DeltaInsert::Block(block),
+                    attributes: BTreeMap::new(),
+                },
+            ),
+        }
+    }
+    delta
+}
+
+/// Turn the patches to the text object `obj` into change Deltas
+///
+/// Each patch which changes the text or its marks becomes one Delta, in order, so the Deltas
+/// must be applied one after the other. Patches to other objects, including changes to the
+/// contents of block maps, are skipped. An inserted block is an embed of an empty map, the
+/// contents of the block follow in patches to the block map.
+pub fn patches_to_deltas<'a, I: IntoIterator<Item = &'a Patch>>(
+    patches: I,
+    obj: &ExId,
+) -> Vec<Vec<DeltaOp>> {
+    patches
+        .into_iter()
+        .filter(|patch| patch.obj == *obj)
+        .filter_map(|patch| patch_to_delta(&patch.action))
+        .collect()
+}
+
+/// Turn a patch to a text object into a change Delta, `None` if the patch doesn't change the
+/// text or its marks
+pub fn patch_to_delta(action: &PatchAction) -> Option<Vec<DeltaOp>> {
+    let mut delta = Vec::new();
+    match action {
+        PatchAction::SpliceText {
+            index,
+            value,
+            marks,
+        } => {
+            retain(&mut delta, *index, BTreeMap::new());
+            push(
+                &mut delta,
+                DeltaOp::Insert {
+                    insert: DeltaInsert::Text(value.make_string()),
+                    attributes: attributes(marks.as_ref()),
+                },
+            );
+        }
+        PatchAction::Insert { index, values } => {
+            retain(&mut delta, *index, BTreeMap::new());
+            for (value, _, _) in values.iter() {
+                if let Value::Object(_) = value {
+                    push(
+                        &mut delta,
+                        DeltaOp::Insert {
+                            insert: DeltaInsert::Block(hydrate::Map::default()),
+                            attributes: BTreeMap::new(),
+                        },
+                    );
+                }
+            }
+        }
+        PatchAction::DeleteSeq { index, length } => {
+            retain(&mut delta, *index, BTreeMap::new());
+            push(&mut delta, DeltaOp::Delete { delete: *length });
+        }
+        PatchAction::Mark { marks } => mark_delta(&mut delta, marks),
+        _ => return None,
+    }
+    // A retain at the end of a change does nothing
+    if let Some(DeltaOp::Retain { attributes, .. }) = delta.last() {
+        if attributes.is_empty() {
+            delta.pop();
+        }
+    }
+    if delta.is_empty() {
+        None
+    } else {
+        Some(delta)
+    }
+}
+
+/// Retain the ranges covered by `marks`, with the marks covering each part of them as attributes
+fn mark_delta(delta: &mut Vec<DeltaOp>, marks: &[Mark]) {
+    let mut bounds = marks
+        .iter()
+        .flat_map(|m| [m.start, m.end])
+        .collect::<Vec<_>>();
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut pos = 0;
+    for range in bounds.windows(2) {
+        let (start, end) = (range[0], range[1]);
+        // Later marks in the patch override earlier ones
+        let attributes = marks
+            .iter()
+            .filter(|m| m.start <= start && end <= m.end)
+            .map(|m| (m.stored_name().to_string(), m.value.clone()))
+            .collect::<BTreeMap<_, _>>();
+        if attributes.is_empty() {
+            continue;
+        }
+        retain(delta, start - pos, BTreeMap::new());
+        retain(delta, end - start, attributes);
+        pos = end;
+    }
+}
+
+/// Apply the change Delta `delta` to the text object `obj`
+///
+/// Inserted text has exactly the marks in its attributes, any marks it would have inherited
+/// from the text around it are removed. Marks are created with the expand flags in `config`.
+pub fn apply_delta<T: Transactable, O: AsRef<ExId>>(
+    tx: &mut T,
+    obj: O,
+    delta: &[DeltaOp],
+    config: &UpdateSpansConfig,
+) -> Result<(), ApplyDeltaError> {
+    let obj = obj.as_ref();
+    let mut pos = 0;
+    for op in delta {
+        match op {
+            DeltaOp::Retain { retain, attributes } => {
+                check_bounds(tx, obj, pos + retain)?;
+                set_marks(tx, obj, pos, pos + retain, attributes, config)?;
+                pos += retain;
+            }
+            DeltaOp::Delete { delete } => {
+                check_bounds(tx, obj, pos + delete)?;
+                tx.splice_text(obj, pos, *delete as isize, "")?;
+            }
+            DeltaOp::Insert { insert, attributes } => {
+                check_bounds(tx, obj, pos)?;
+                let len = match insert {
+                    DeltaInsert::Text(text) => {
+                        let before = tx.length(obj);
+                        tx.splice_text(obj, pos, 0, text)?;
+                        tx.length(obj) - before
+                    }
+                    DeltaInsert::Block(block) => {
+                        let block_id = tx.split_block(obj, pos)?;
+                        tx.update_object(&block_id, &hydrate::Value::Map(block.clone()))?;
+                        1
+                    }
+                };
+                if len == 0 {
+                    continue;
+                }
+                let inherited = tx.get_marks(obj, pos, None)?;
+                for (name, value) in inherited.iter_stored() {
+                    if !value.is_null() && !attributes.contains_key(name) {
+                        tx.unmark(obj, name, pos, pos + len, ExpandMark::None)?;
+                    }
+                }
+                set_marks(tx, obj, pos, pos + len, attributes, config)?;
+                pos += len;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_marks<T: Transactable>(
+    tx: &mut T,
+    obj: &ExId,
+    start: usize,
+    end: usize,
+    attributes: &BTreeMap<String, ScalarValue>,
+    config: &UpdateSpansConfig,
+) -> Result<(), AutomergeError> {
+    if start == end {
+        return Ok(());
+    }
+    for (name, value) in attributes {
+        let expand = config
+            .per_mark_expands
+            .get(name)
+            .copied()
+            .unwrap_or(config.default_expand);
+        if value.is_null() {
+            tx.unmark(obj, name, start, end, expand)?;
+        } else {
+            tx.mark(
+                obj,
+                Mark::new(name.clone(), value.clone(), start, end),
+                expand,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn check_bounds<T: Transactable>(
+    tx: &T,
+    obj: &ExId,
+    position: usize,
+) -> Result<(), ApplyDeltaError> {
+    let length = tx.length(obj);
+    if position > length {
+        Err(ApplyDeltaError::OutOfBounds { position, length })
+    } else {
+        Ok(())
+    }
+}
+
+/// The attributes for the marks in `marks`, leaving out removed marks
+fn attributes(marks: Option<&MarkSet>) -> BTreeMap<String, ScalarValue> {
+    marks
+        .into_iter()
+        .flat_map(|marks| marks.iter_stored())
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+fn retain(delta: &mut Vec<DeltaOp>, len: usize, attributes: BTreeMap<String, ScalarValue>) {
+    if len > 0 {
+        push(
+            delta,
+            DeltaOp::Retain {
+                retain: len,
+                attributes,
+            },
+        );
+    }
+}
+
+/// Push `op` onto `delta`, merging it with the last op if they're the same kind of op with the
+/// same attributes
+fn push(delta: &mut Vec<DeltaOp>, op: DeltaOp) {
+    match (delta.last_mut(), op) {
+        (
+            Some(DeltaOp::Insert {
+                insert: DeltaInsert::Text(last),
+                attributes: last_attrs,
+            }),
+            DeltaOp::Insert {
+                insert: DeltaInsert::Text(text),
+                attributes,
+            },
+        ) if *last_attrs == attributes => last.push_str(&text),
+        (
+            Some(DeltaOp::Retain {
+                retain: last,
+                attributes: last_attrs,
+            }),
+            DeltaOp::Retain { retain, attributes },
+        ) if *last_attrs == attributes => *last += retain,
+        (Some(DeltaOp::Delete { delete: last }), DeltaOp::Delete { delete }) => *last += delete,
+        (_, op) => delta.push(op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hydrate_map, AutoCommit, ObjType, ROOT};
+
+    fn attrs(entries: &[(&str, ScalarValue)]) -> BTreeMap<String, ScalarValue> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    fn text(s: &str, attributes: BTreeMap<String, ScalarValue>) -> DeltaOp {
+        DeltaOp::Insert {
+            insert: DeltaInsert::Text(s.to_string()),
+            attributes,
+        }
+    }
+
+    #[test]
+    fn export_document_with_blocks() {
+        let mut doc = AutoCommit::new();
+        let text_obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text_obj, 0, 0, "hello world").unwrap();
+        doc.mark(
+            &text_obj,
+            Mark::new("link".to_string(), "https://x.org", 6, 11),
+            ExpandMark::None,
+        )
+        .unwrap();
+        let block = doc.split_block(&text_obj, 0).unwrap();
+        doc.update_object(&block, &hydrate_map! { "type" => "heading" }.into())
+            .unwrap();
+
+        assert_eq!(
+            document_delta(&doc, &text_obj).unwrap(),
+            vec![
+                DeltaOp::Insert {
+                    insert: DeltaInsert::Block(hydrate_map! { "type" => "heading" }),
+                    attributes: BTreeMap::new(),
+                },
+                text("hello ", BTreeMap::new()),
+                text("world", attrs(&[("link", "https://x.org".into())])),
+            ]
+        );
+    }
+
+    #[test]
+    fn patches_become_change_deltas() {
+        let mut doc = AutoCommit::new();
+        let text_obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text_obj, 0, 0, "hello world").unwrap();
+        doc.update_diff_cursor();
+
+        doc.splice_text(&text_obj, 5, 6, "").unwrap();
+        doc.splice_text(&text_obj, 0, 0, "oh ").unwrap();
+        doc.mark(
+            &text_obj,
+            Mark::new("bold".to_string(), true, 3, 8),
+            ExpandMark::None,
+        )
+        .unwrap();
+        doc.split_block(&text_obj, 0).unwrap();
+        let patches = doc.diff_incremental();
+
+        assert_eq!(
+            patches_to_deltas(&patches, &text_obj),
+            vec![
+                vec![
+                    DeltaOp::Retain {
+                        retain: 5,
+                        attributes: BTreeMap::new()
+                    },
+                    DeltaOp::Delete { delete: 6 },
+                ],
+                vec![text("oh ", BTreeMap::new())],
+                vec![
+                    DeltaOp::Retain {
+                        retain: 3,
+                        attributes: BTreeMap::new()
+                    },
+                    DeltaOp::Retain {
+                        retain: 5,
+                        attributes: attrs(&[("bold", true.into())])
+                    },
+                ],
+                vec![DeltaOp::Insert {
+                    insert: DeltaInsert::Block(hydrate::Map::default()),
+                    attributes: BTreeMap::new(),
+                }],
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_change_delta() {
+        let mut doc = AutoCommit::new();
+        let text_obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text_obj, 0, 0, "hello world").unwrap();
+        doc.mark(
+            &text_obj,
+            Mark::new("bold".to_string(), true, 0, 11),
+            ExpandMark::After,
+        )
+        .unwrap();
+
+        // Unbold "hello", replace " world" with an italic " there", which would otherwise
+        // inherit the bold mark
+        let change = vec![
+            DeltaOp::Retain {
+                retain: 5,
+                attributes: attrs(&[("bold", ScalarValue::Null)]),
+            },
+            DeltaOp::Delete { delete: 6 },
+            text(" there", attrs(&[("italic", true.into())])),
+            DeltaOp::Insert {
+                insert: DeltaInsert::Block(hydrate_map! { "type" => "paragraph" }),
+                attributes: BTreeMap::new(),
+            },
+        ];
+        let config = UpdateSpansConfig::default();
+        apply_delta(&mut doc, &text_obj, &change, &config).unwrap();
+
+        assert_eq!(
+            document_delta(&doc, &text_obj).unwrap(),
+            vec![
+                text("hello", BTreeMap::new()),
+                text(" there", attrs(&[("italic", true.into())])),
+                DeltaOp::Insert {
+                    insert: DeltaInsert::Block(hydrate_map! { "type" => "paragraph" }),
+                    attributes: BTreeMap::new(),
+                },
+            ]
+        );
+
+        let too_long = vec![DeltaOp::Delete { delete: 100 }];
+        assert!(matches!(
+            apply_delta(&mut doc, &text_obj, &too_long, &config),
+            Err(ApplyDeltaError::OutOfBounds { .. })
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::storage::load::Error as LoadError;
+use crate::storage::{CodecNotCompiledIn, LimitExceeded, LoadCancelled};
 use crate::types::{ActorId, ScalarValue};
 use crate::value::DataType;
 use crate::{ChangeHash, Cursor, LoadChangeError, ObjType, PatchAction};
@@ -46,13 +47,21 @@ pub enum AutomergeError {
     InvalidCursorOp,
     #[error("cursor format is invalid")]
     InvalidCursorFormat,
+    #[error("mark kind {0:?} contains the mark instance separator")]
+    InvalidMarkKind(String),
     #[error("invalid type of value, expected `{expected}` but received `{unexpected}`")]
     InvalidValueType {
         expected: String,
         unexpected: String,
     },
     #[error(transparent)]
-    Load(#[from] LoadError),
+    CodecNotCompiledIn(#[from] CodecNotCompiledIn),
+    #[error(transparent)]
+    Load(LoadError),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    #[error(transparent)]
+    LoadCancelled(#[from] LoadCancelled),
     #[error(transparent)]
     LoadChangeError(#[from] LoadChangeError),
     #[error("increment operations must be against a counter value")]
@@ -75,6 +84,16 @@ pub enum AutomergeError {
     Unbundle(Box<dyn std::error::Error + Send + Sync + 'static>),
 }
 
+impl From<LoadError> for AutomergeError {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::LimitExceeded(e) => Self::LimitExceeded(e),
+            LoadError::Cancelled(e) => Self::LoadCancelled(e),
+            e => Self::Load(e),
+        }
+    }
+}
+
 impl AutomergeError {
     pub(crate) fn encoding(error: impl std::fmt::Display) -> Self {
         Self::EncodingError(error.to_string())
@@ -1,3 +1,4 @@
+use crate::marks::MARK_SCHEMA_KEY;
 use crate::op_set2::{Op, OpSet, OpType};
 use crate::types::{Clock, ObjId, ScalarValue, SequenceType};
 use crate::TextEncoding;
@@ -250,6 +251,10 @@ impl OpSet {
         let mut map = Map::new();
         for top in self.top_ops(obj, clock.cloned()) {
             let key = self.to_string(top.elemid_or_key());
+            // The mark schema isn't part of the content of the document
+            if obj.is_root() && key == MARK_SCHEMA_KEY {
+                continue;
+            }
             let id = self.id_to_exid(top.id);
             let conflict = top.conflict;
             let value = self.hydrate_op(top, clock, encoding);
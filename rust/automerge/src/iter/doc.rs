@@ -4,6 +4,7 @@ use super::{
 };
 use crate::clock::{Clock, ClockRange};
 use crate::exid::ExId;
+use crate::marks::MARK_SCHEMA_KEY;
 use crate::op_set2::op_set::{ObjIdIter, OpSet};
 use crate::op_set2::types::ValueRef;
 use crate::patches::PatchLog;
@@ -322,6 +323,22 @@ impl<'a> Iterator for DocIterInternal<'a> {
     type Item = DocObjItemInternal<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let item = self.next_item()?;
+            if !item.is_mark_schema() {
+                return Some(item);
+            }
+            // The mark schema isn't part of the content of the document, skip its objects too
+            if let Some((obj, _)) = item.item.make_obj() {
+                self.next_objs.remove(&obj);
+                self.path_map.remove(&obj);
+            }
+        }
+    }
+}
+
+impl<'a> DocIterInternal<'a> {
+    fn next_item(&mut self) -> Option<DocObjItemInternal<'a>> {
         if let Some(item) = self.next_prop() {
             return Some(item);
         }
@@ -345,6 +362,13 @@ pub(crate) struct DocObjItemInternal<'a> {
     pub(crate) item: DocItemInternal<'a>,
 }
 
+impl DocObjItemInternal<'_> {
+    fn is_mark_schema(&self) -> bool {
+        self.obj.is_root()
+            && matches!(&self.item, DocItemInternal::Map(item) if item.key == MARK_SCHEMA_KEY)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct DocObjDiffItem<'a> {
     pub(crate) obj: ObjId,
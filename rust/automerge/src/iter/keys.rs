@@ -7,6 +7,7 @@ use std::fmt::Debug;
 #[derive(Clone, Debug, Default)]
 pub struct Keys<'a> {
     pub(crate) iter: Option<(&'a OpSet, TopOps<'a>)>,
+    hidden: Option<&'static str>,
 }
 
 impl Iterator for Keys<'_> {
@@ -14,8 +15,12 @@ impl Iterator for Keys<'_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let (op_set, iter) = self.iter.as_mut()?;
-        let op = iter.next()?;
-        Some(op_set.to_string(op.elemid_or_key()))
+        loop {
+            let key = op_set.to_string(iter.next()?.elemid_or_key());
+            if Some(key.as_str()) != self.hidden {
+                return Some(key);
+            }
+        }
     }
 }
 
@@ -23,8 +28,15 @@ impl<'a> Keys<'a> {
     pub(crate) fn new(op_set: &'a OpSet, iter: TopOps<'a>) -> Self {
         Self {
             iter: Some((op_set, iter)),
+            hidden: None,
         }
     }
+
+    /// Skip the key `key`
+    pub(crate) fn without_key(mut self, key: &'static str) -> Self {
+        self.hidden = Some(key);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -94,13 +94,19 @@ impl<'a> MapDiffItem<'a> {
 #[derive(Debug, Clone, Default)]
 pub struct MapRange<'a> {
     iter: MapDiff<'a>,
+    hidden: Option<&'static str>,
 }
 
 impl<'a> Iterator for MapRange<'a> {
     type Item = MapRangeItem<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.iter.next()?.export(self.iter.op_set?))
+        loop {
+            let item = self.iter.next()?.export(self.iter.op_set?);
+            if Some(item.key.as_ref()) != self.hidden {
+                return Some(item);
+            }
+        }
     }
 }
 
@@ -203,7 +209,13 @@ impl<'a> Iterator for MapIter<'a> {
 impl<'a> MapRange<'a> {
     pub(crate) fn new(op_set: &'a OpSet, range: Range<usize>, clock: Option<Clock>) -> Self {
         let iter = MapDiff::new(op_set, range, ClockRange::current(clock));
-        Self { iter }
+        Self { iter, hidden: None }
+    }
+
+    /// Skip the key `key`
+    pub(crate) fn without_key(mut self, key: &'static str) -> Self {
+        self.hidden = Some(key);
+        self
     }
 
     pub(crate) fn shift_next(&mut self, range: Range<usize>) -> Option<<Self as Iterator>::Item> {
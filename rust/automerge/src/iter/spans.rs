@@ -1,7 +1,7 @@
 use crate::clock::{Clock, ClockRange};
 use crate::hydrate::Value;
 use crate::iter::tools::{Diff, DiffIter, Unshift};
-use crate::marks::{MarkSet, MarkSetIter, MarkStateMachine};
+use crate::marks::{MarkSchema, MarkSet, MarkSetIter, MarkStateMachine};
 use crate::op_set2::op_set::{ActionValueIter, MarkInfoIter, OpIdIter, OpSet, TopIter};
 use crate::op_set2::types::{Action, MarkData, ScalarValue};
 use crate::patches::PatchLog;
@@ -285,6 +285,10 @@ impl<'a> SpansInternal<'a> {
 #[derive(Clone, Debug)]
 pub struct Spans<'a> {
     internal: SpansInternal<'a>,
+    /// The document's mark schema, which decides which of the stored marks apply
+    schema: Option<Arc<MarkSchema>>,
+    /// The span after the one being returned, which was read to see if it could be joined to it
+    next: Option<Span>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -325,16 +329,16 @@ impl MarkDiff {
 
     fn iter_before(&self) -> impl Iterator<Item = (&str, &value::ScalarValue)> {
         match &self {
-            MarkDiff::Before(b) => b.iter(),
-            MarkDiff::Diff(b, _) => b.iter(),
+            MarkDiff::Before(b) => b.iter_stored(),
+            MarkDiff::Diff(b, _) => b.iter_stored(),
             _ => MarkSetIter::default(),
         }
     }
 
     fn iter_after(&self) -> impl Iterator<Item = (&str, &value::ScalarValue)> {
         match &self {
-            MarkDiff::After(a) => a.iter(),
-            MarkDiff::Diff(_, a) => a.iter(),
+            MarkDiff::After(a) => a.iter_stored(),
+            MarkDiff::Diff(_, a) => a.iter_stored(),
             _ => MarkSetIter::default(),
         }
     }
@@ -626,22 +630,67 @@ impl SpanInternal {
     }
 }
 
-impl Spans<'_> {
-    pub(crate) fn new(internal: SpansInternal<'_>) -> Spans<'_> {
-        Spans { internal }
+impl<'a> Spans<'a> {
+    pub(crate) fn new(internal: SpansInternal<'a>) -> Spans<'a> {
+        Spans {
+            internal,
+            schema: None,
+            next: None,
+        }
     }
-}
 
-impl Iterator for Spans<'_> {
-    type Item = Span;
+    pub(crate) fn with_schema(mut self, schema: Option<Arc<MarkSchema>>) -> Self {
+        self.schema = schema;
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn next_stored(&mut self) -> Option<Span> {
         Some(self.internal.next()?.export(
             self.internal.iter.op_set?,
             self.internal.iter.clock.after(),
             self.internal.iter.state.encoding,
         ))
     }
+
+    fn next_resolved(&mut self, schema: &MarkSchema) -> Option<Span> {
+        let mut span = self.next_stored()?;
+        if let Span::Text { marks, .. } = &mut span {
+            let resolved = marks
+                .as_deref()
+                .map(|marks| schema.resolve(marks))
+                .filter(|marks| !marks.is_empty());
+            *marks = resolved.map(Arc::new);
+        }
+        Some(span)
+    }
+}
+
+impl Iterator for Spans<'_> {
+    type Item = Span;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(schema) = self.schema.clone() else {
+            return self.next_stored();
+        };
+        let mut span = match self.next.take() {
+            Some(span) => span,
+            None => self.next_resolved(&schema)?,
+        };
+        // Spans whose stored marks differ can have the same marks once the schema is applied
+        while let Span::Text { text, marks } = &mut span {
+            match self.next_resolved(&schema) {
+                Some(Span::Text {
+                    text: next_text,
+                    marks: next_marks,
+                }) if next_marks == *marks => text.push_str(&next_text),
+                next => {
+                    self.next = next;
+                    break;
+                }
+            }
+        }
+        Some(span)
+    }
 }
 
 #[derive(Debug, Default, Clone)]
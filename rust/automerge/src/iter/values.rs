@@ -1,6 +1,7 @@
 use crate::{exid::ExId, types, types::Clock};
 
 use crate::op_set2::op_set::OpQueryTerm;
+use crate::op_set2::types::KeyRef;
 use crate::op_set2::OpSet;
 
 use std::fmt::Debug;
@@ -8,6 +9,7 @@ use std::fmt::Debug;
 #[derive(Default, Debug)]
 pub struct Values<'a> {
     iter: Option<(&'a OpSet, Box<dyn OpQueryTerm<'a> + 'a>)>,
+    hidden: Option<&'static str>,
 }
 
 impl<'a> Values<'a> {
@@ -19,8 +21,15 @@ impl<'a> Values<'a> {
     ) -> Self {
         Self {
             iter: Some((op_set, Box::new(iter))),
+            hidden: None,
         }
     }
+
+    /// Skip the value of the map key `key`
+    pub(crate) fn without_key(mut self, key: &'static str) -> Self {
+        self.hidden = Some(key);
+        self
+    }
 }
 
 impl<'a> Iterator for Values<'a> {
@@ -28,7 +37,13 @@ impl<'a> Iterator for Values<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let (op_set, iter) = self.iter.as_mut()?;
-        let op = iter.next()?;
+        let op = loop {
+            let op = iter.next()?;
+            match (&op.key, self.hidden) {
+                (KeyRef::Map(key), Some(hidden)) if key == hidden => continue,
+                _ => break op,
+            }
+        };
         let value = op.value().to_value();
         let id = op_set.id_to_exid(op.id);
         Some((value, id))
@@ -266,10 +266,12 @@ mod autoserde;
 mod change;
 mod change_graph;
 mod change_queue;
+mod change_store;
 mod clock;
 mod columnar;
 mod convert;
 mod cursor;
+pub mod delta;
 pub mod error;
 mod exid;
 pub mod hydrate;
@@ -278,12 +280,15 @@ pub mod iter;
 pub use iter::Span;
 #[doc(hidden)]
 pub mod legacy;
+pub mod line_index;
 pub mod marks;
 pub mod op_set2;
+mod parallel;
 pub mod patches;
 mod read;
+pub mod rich_text;
 mod sequence_tree;
-mod storage;
+pub mod storage;
 pub mod sync;
 mod text_diff;
 mod text_value;
@@ -292,13 +297,17 @@ mod types;
 mod value;
 
 pub use crate::anonymize::AnonymizeError;
-pub use crate::automerge::{Automerge, LoadOptions, OnPartialLoad, SaveOptions, StringMigration};
+pub use crate::automerge::{
+    Automerge, LazyChange, LazyDocument, LoadOptions, OnPartialLoad, RepairIssue, RepairReport,
+    SaveOptions, StringMigration,
+};
 pub use autocommit::AutoCommit;
 pub use autoserde::AutoSerde;
 pub use change::{Change, LoadError as LoadChangeError};
 #[doc(hidden)]
 pub use change_graph::Fragment;
-pub use cursor::{Cursor, CursorPosition, MoveCursor, OpCursor};
+pub use change_store::ChangeStore;
+pub use cursor::{Cursor, CursorPosition, CursorRange, MoveCursor, OpCursor, RangeCollapse};
 pub use error::InvalidActorId;
 pub use error::InvalidChangeHashSlice;
 pub use error::{AutomergeError, PatchLogMismatch};
@@ -308,7 +317,10 @@ pub use op_set2::{ChangeMetadata, Parent, Parents, ScalarValue as ScalarValueRef
 pub use patches::{Patch, PatchAction, PatchLog};
 pub use read::{ReadDoc, Stats};
 pub use sequence_tree::SequenceTree;
-pub use storage::{Bundle, BundleChange, BundleChangeIter, VerificationMode};
+pub use storage::{
+    Bundle, BundleChange, BundleChangeIter, Codec, CodecNotCompiledIn, Limit, LimitExceeded,
+    LoadCancelled, LoadLimits, LoadPhase, LoadProgress, VerificationMode,
+};
 pub use text_value::ConcreteTextValue;
 pub use transaction::BlockOrText;
 pub use types::{ActorId, ChangeHash, ObjType, OpType, ParseChangeHashError, Prop, TextEncoding};
@@ -0,0 +1,487 @@
+//! Addressing text objects by line and column
+//!
+//! Editors and protocols such as LSP work in lines and columns, while a text object is addressed
+//! by offsets in the units of the document's [`TextEncoding`]. A [`LineIndex`], obtained from
+//! [`crate::ReadDoc::line_index()`], converts between the two and hands out individual lines without
+//! calling [`crate::ReadDoc::text()`] and scanning it.
+//!
+//! Lines end at `\n`, which belongs to the line it ends, and columns count the same units as
+//! offsets.
+//!
+//! ## Keeping up with the document
+//!
+//! The document counts the `\n`s in each text object alongside the widths it already keeps for
+//! offsets, and updates the counts wherever it updates the widths: in
+//! [`crate::transaction::Transactable::splice_text()`], when merging or applying changes and when
+//! loading. A [`LineIndex`] borrows the document and reads those counts, so it is always up to
+//! date and looking up a line or an offset takes logarithmic time in the length of the document.
+//!
+//! An index of the text as it was at some heads, from [`crate::ReadDoc::line_index_at()`], isn't covered
+//! by the counts and reads the text once when it's created instead.
+//!
+//! ## Example
+//!
+//! ```
+//! # use automerge::line_index::LineColumn;
+//! # use automerge::{transaction::Transactable, AutoCommit, ObjType, ReadDoc, ROOT};
+//! let mut doc = AutoCommit::new();
+//! let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+//! doc.splice_text(&text, 0, 0, "fn main() {\n}\n").unwrap();
+//! assert_eq!(doc.line_index(&text).unwrap().line_count(), 3);
+//!
+//! doc.splice_text(&text, 12, 0, "    todo!()\n").unwrap();
+//!
+//! let lines = doc.line_index(&text).unwrap();
+//! assert_eq!(lines.line(1), Some("    todo!()".to_string()));
+//! assert_eq!(lines.offset_to_position(16).unwrap(), LineColumn { line: 1, column: 4 });
+//! assert_eq!(lines.position_to_offset(LineColumn { line: 2, column: 1 }).unwrap(), 25);
+//! ```
+use std::ops::Range;
+
+use crate::op_set2::OpSet;
+use crate::types::ObjId;
+use crate::TextEncoding;
+
+/// A position in a text object as a zero based line and column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum LineIndexError {
+    #[error("offset {offset} is out of bounds for text of length {length}")]
+    OffsetOutOfBounds { offset: usize, length: usize },
+    #[error("offset {0} is inside a character")]
+    InsideCharacter(usize),
+    #[error("line {line} is out of bounds, there are {lines} lines")]
+    LineOutOfBounds { line: usize, lines: usize },
+    #[error("column {column} is past the end of line {line}, which has {width} columns")]
+    ColumnOutOfBounds {
+        line: usize,
+        column: usize,
+        width: usize,
+    },
+}
+
+/// Converts between offsets and lines and columns in a text object, see the [module
+/// documentation](self)
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    encoding: TextEncoding,
+    text: Text<'a>,
+}
+
+#[derive(Debug, Clone)]
+enum Text<'a> {
+    /// The current text, read from the document's indexes
+    Current { ops: &'a OpSet, obj: ObjId },
+    /// The text at some heads
+    Snapshot(Snapshot),
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    text: String,
+    /// The byte offset and the offset of the start of each line
+    starts: Vec<(usize, usize)>,
+    length: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn current(ops: &'a OpSet, obj: ObjId, encoding: TextEncoding) -> Self {
+        Self {
+            encoding,
+            text: Text::Current { ops, obj },
+        }
+    }
+
+    pub(crate) fn snapshot(text: String, encoding: TextEncoding) -> Self {
+        let mut starts = vec![(0, 0)];
+        let (mut byte, mut offset) = (0, 0);
+        for line in text.split_inclusive('\n') {
+            byte += line.len();
+            offset += encoding.width(line);
+            if line.ends_with('\n') {
+                starts.push((byte, offset));
+            }
+        }
+        Self {
+            encoding,
+            text: Text::Snapshot(Snapshot {
+                text,
+                starts,
+                length: offset,
+            }),
+        }
+    }
+
+    /// The length of the text
+    pub fn len(&self) -> usize {
+        match &self.text {
+            Text::Current { ops, obj } => ops.seq_length(obj, self.encoding, None),
+            Text::Snapshot(snapshot) => snapshot.length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of lines, which is one more than the number of `\n`s
+    pub fn line_count(&self) -> usize {
+        match &self.text {
+            Text::Current { ops, obj } => ops.line_count(obj),
+            Text::Snapshot(snapshot) => snapshot.starts.len(),
+        }
+    }
+
+    /// The text of `line` without the `\n` which ends it
+    pub fn line(&self, line: usize) -> Option<String> {
+        self.lines(line..line + 1).pop()
+    }
+
+    /// The text of each line in `lines` without the `\n`s which end them
+    pub fn lines(&self, lines: Range<usize>) -> Vec<String> {
+        let count = lines.end.saturating_sub(lines.start);
+        match &self.text {
+            Text::Current { ops, obj } => ops.lines(obj, lines.start, count),
+            Text::Snapshot(snapshot) => {
+                let starts = &snapshot.starts;
+                let end = lines.end.min(starts.len());
+                (lines.start.min(end)..end)
+                    .map(|line| {
+                        let start = starts[line].0;
+                        let end = starts
+                            .get(line + 1)
+                            .map_or(snapshot.text.len(), |s| s.0 - 1);
+                        snapshot.text[start..end].to_string()
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The offsets `line` covers, not including the `\n` which ends it
+    pub fn line_offsets(&self, line: usize) -> Option<Range<usize>> {
+        let start = self.line_offset(line)?;
+        // `\n` is one unit wide in every encoding
+        let end = match self.line_offset(line + 1) {
+            Some(next) => next - 1,
+            None => self.len(),
+        };
+        Some(start..end)
+    }
+
+    /// The line and column of `offset`
+    ///
+    /// An offset at the `\n` which ends a line is at the end of that line, the offset after it
+    /// is at the start of the next line.
+    pub fn offset_to_position(&self, offset: usize) -> Result<LineColumn, LineIndexError> {
+        let length = self.len();
+        if offset > length {
+            return Err(LineIndexError::OffsetOutOfBounds { offset, length });
+        }
+        let line = match &self.text {
+            Text::Current { ops, obj } => ops.line_at_offset(obj, offset),
+            Text::Snapshot(snapshot) => {
+                let line = snapshot
+                    .starts
+                    .partition_point(|(_, start)| *start <= offset)
+                    - 1;
+                let (byte, start) = snapshot.starts[line];
+                newlines_before(&snapshot.text[byte..], offset - start, self.encoding).map(|_| line)
+            }
+        }
+        .ok_or(LineIndexError::InsideCharacter(offset))?;
+        let start = self.line_offset(line).expect("line is in the text");
+        Ok(LineColumn {
+            line,
+            column: offset - start,
+        })
+    }
+
+    /// The offset of `position`
+    ///
+    /// The column can be anywhere from the start of the line to the `\n` which ends it.
+    pub fn position_to_offset(&self, position: LineColumn) -> Result<usize, LineIndexError> {
+        let LineColumn { line, column } = position;
+        let offsets = self
+            .line_offsets(line)
+            .ok_or(LineIndexError::LineOutOfBounds {
+                line,
+                lines: self.line_count(),
+            })?;
+        if column > offsets.len() {
+            return Err(LineIndexError::ColumnOutOfBounds {
+                line,
+                column,
+                width: offsets.len(),
+            });
+        }
+        Ok(offsets.start + column)
+    }
+
+    /// The offset `line` starts at
+    fn line_offset(&self, line: usize) -> Option<usize> {
+        match &self.text {
+            Text::Current { ops, obj } => ops.line_offset(obj, line),
+            Text::Snapshot(snapshot) => snapshot.starts.get(line).map(|(_, start)| *start),
+        }
+    }
+}
+
+/// The number of `\n`s in the first `units` units of `text`, `None` if that ends inside a
+/// character
+pub(crate) fn newlines_before(text: &str, units: usize, encoding: TextEncoding) -> Option<usize> {
+    let chars: Box<dyn Iterator<Item = &str>> = match encoding {
+        TextEncoding::GraphemeCluster => Box::new(
+            unicode_segmentation::UnicodeSegmentation::graphemes(text, true),
+        ),
+        _ => Box::new(text.split_inclusive(|_| true)),
+    };
+    let mut width = 0;
+    let mut newlines = 0;
+    for c in chars {
+        if width >= units {
+            break;
+        }
+        width += encoding.width(c);
+        // a `\n` always ends the grapheme it's in
+        if c.ends_with('\n') {
+            newlines += 1;
+        }
+    }
+    (width == units).then_some(newlines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ObjType, ReadDoc, ROOT};
+
+    /// Check every query of `index` against the text it should hold
+    fn assert_matches_text(index: &LineIndex<'_>, text: &str) {
+        let snapshot = LineIndex::snapshot(text.to_string(), index.encoding);
+        let lines = text.split('\n').collect::<Vec<_>>();
+        for index in [index, &snapshot] {
+            assert_eq!(index.len(), index.encoding.width(text));
+            assert_eq!(index.line_count(), lines.len());
+            assert_eq!(index.lines(0..lines.len() + 1), lines);
+            let mut start = 0;
+            for (line, text) in lines.iter().enumerate() {
+                let width = index.encoding.width(text);
+                assert_eq!(index.line(line).as_deref(), Some(*text));
+                assert_eq!(index.line_offsets(line), Some(start..start + width));
+                for column in 0..=width {
+                    let position = LineColumn { line, column };
+                    let offset = index.position_to_offset(position).unwrap();
+                    assert_eq!(offset, start + column);
+                    match index.offset_to_position(offset) {
+                        Ok(found) => assert_eq!(found, position),
+                        Err(e) => assert_eq!(e, LineIndexError::InsideCharacter(offset)),
+                    }
+                }
+                start += width + 1;
+            }
+        }
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let mut doc = AutoCommit::new_with_encoding(TextEncoding::UnicodeCodePoint);
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "one\ntwo\n\nfour").unwrap();
+        let index = doc.line_index(&text).unwrap();
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.lines(0..10), vec!["one", "two", "", "four"]);
+        assert_eq!(index.line_offsets(1), Some(4..7));
+        for offset in 0..=index.len() {
+            let position = index.offset_to_position(offset).unwrap();
+            assert_eq!(index.position_to_offset(position).unwrap(), offset);
+        }
+        assert_eq!(
+            index.offset_to_position(3).unwrap(),
+            LineColumn { line: 0, column: 3 }
+        );
+        assert_eq!(
+            index.position_to_offset(LineColumn { line: 0, column: 4 }),
+            Err(LineIndexError::ColumnOutOfBounds {
+                line: 0,
+                column: 4,
+                width: 3
+            })
+        );
+        assert_eq!(
+            index.position_to_offset(LineColumn { line: 4, column: 0 }),
+            Err(LineIndexError::LineOutOfBounds { line: 4, lines: 4 })
+        );
+        assert_matches_text(&index, "one\ntwo\n\nfour");
+    }
+
+    #[test]
+    fn splices_are_reflected_immediately() {
+        for encoding in [
+            TextEncoding::UnicodeCodePoint,
+            TextEncoding::Utf8CodeUnit,
+            TextEncoding::Utf16CodeUnit,
+            TextEncoding::GraphemeCluster,
+        ] {
+            let mut doc = AutoCommit::new_with_encoding(encoding);
+            let obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+            doc.splice_text(&obj, 0, 0, "héllo\nwörld 😀\n").unwrap();
+            // (character index, characters to delete, text to insert)
+            let edits = [
+                (6, 0, "a\nb\n"),
+                (0, 0, "\n\n"),
+                (3, 9, "x"),
+                (11, 1, ""),
+                (0, 11, ""),
+                (0, 0, "new\n"),
+            ];
+            for (at, delete, insert) in edits {
+                let text = doc.text(&obj).unwrap();
+                let start = text.char_indices().nth(at).map_or(text.len(), |(i, _)| i);
+                let end = text[start..]
+                    .char_indices()
+                    .nth(delete)
+                    .map_or(text.len(), |(i, _)| start + i);
+                let offset = encoding.width(&text[..start]);
+                let deleted = encoding.width(&text[start..end]);
+                doc.splice_text(&obj, offset, deleted as isize, insert)
+                    .unwrap();
+                assert_matches_text(&doc.line_index(&obj).unwrap(), &doc.text(&obj).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn long_texts() {
+        let mut doc = AutoCommit::new_with_encoding(TextEncoding::UnicodeCodePoint);
+        let obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        let text = (0..500)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        doc.splice_text(&obj, 0, 0, &text).unwrap();
+        let index = doc.line_index(&obj).unwrap();
+        assert_eq!(index.line(321), Some("line 321".to_string()));
+        assert_eq!(
+            index.offset_to_position(text.find("line 321").unwrap() + 2),
+            Ok(LineColumn {
+                line: 321,
+                column: 2
+            })
+        );
+
+        // Splices within a line, across many lines and adding many lines
+        let edits = [
+            (text.find("line 100").unwrap(), 4, "LINE".to_string()),
+            (text.find("line 60").unwrap(), 800, "joined".to_string()),
+            (text.find("line 250").unwrap(), 0, "new\n".repeat(300)),
+            (0, 3000, String::new()),
+        ];
+        for (at, delete, insert) in edits {
+            doc.splice_text(&obj, at, delete as isize, &insert).unwrap();
+            let index = doc.line_index(&obj).unwrap();
+            assert_matches_text(&index, &doc.text(&obj).unwrap());
+            for line in 0..index.line_count() {
+                let offsets = index.line_offsets(line).unwrap();
+                assert_eq!(
+                    index.offset_to_position(offsets.end),
+                    Ok(LineColumn {
+                        line,
+                        column: offsets.len()
+                    })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn offsets_inside_characters_are_rejected() {
+        let mut doc = AutoCommit::new_with_encoding(TextEncoding::Utf16CodeUnit);
+        let obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&obj, 0, 0, "a😀b").unwrap();
+        let index = doc.line_index(&obj).unwrap();
+        assert_eq!(index.len(), 4);
+        assert_eq!(
+            index.offset_to_position(2),
+            Err(LineIndexError::InsideCharacter(2))
+        );
+        doc.splice_text(&obj, 3, 0, "\n").unwrap();
+        assert_eq!(
+            doc.line_index(&obj).unwrap().line(0),
+            Some("a😀".to_string())
+        );
+    }
+
+    #[test]
+    fn follows_merges_and_heads() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "one\ntwo\nthree").unwrap();
+        let before = doc.get_heads();
+
+        doc.splice_text(&text, 3, 5, " and ").unwrap();
+        doc.splice_text(&text, 0, 0, "zero\n").unwrap();
+        let mut other = doc.fork();
+        other.splice_text(&text, 18, 0, "\nfour").unwrap();
+        doc.merge(&mut other).unwrap();
+
+        let index = doc.line_index(&text).unwrap();
+        assert_matches_text(&index, &doc.text(&text).unwrap());
+        assert_eq!(index.lines(0..3), vec!["zero", "one and three", "four"]);
+
+        let old = doc.line_index_at(&text, &before).unwrap();
+        assert_matches_text(&old, "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn follows_rollbacks_conflicts_and_longer_elements() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(
+            &text, 0, 0, "ab
+cd
+ef",
+        )
+        .unwrap();
+        doc.commit();
+
+        doc.splice_text(&text, 2, 4, "").unwrap();
+        assert_matches_text(&doc.line_index(&text).unwrap(), "abef");
+        doc.rollback();
+        assert_matches_text(
+            &doc.line_index(&text).unwrap(),
+            "ab
+cd
+ef",
+        );
+
+        // concurrent puts to the same element, one of which wins
+        let mut other = doc.fork();
+        doc.put(&text, 0, "\n").unwrap();
+        other.put(&text, 0, "x").unwrap();
+        other.put(&text, 3, "\n\n").unwrap();
+        doc.merge(&mut other).unwrap();
+        let expected = doc.text(&text).unwrap();
+        assert_matches_text(&doc.line_index(&text).unwrap(), &expected);
+
+        // an element holding several characters
+        doc.insert(&text, 1, "1\n2\n3").unwrap();
+        let expected = doc.text(&text).unwrap();
+        assert_matches_text(&doc.line_index(&text).unwrap(), &expected);
+    }
+
+    #[test]
+    fn loaded_documents_are_indexed() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "one\ntwo\nthree").unwrap();
+        doc.delete(&text, 4).unwrap();
+        let loaded = AutoCommit::load(&doc.save()).unwrap();
+        assert_matches_text(&loaded.line_index(&text).unwrap(), "one\nwo\nthree");
+    }
+}
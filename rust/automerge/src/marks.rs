@@ -10,6 +10,13 @@ use crate::op_set2::{MarkData, Op, OpType};
 use crate::types::{Clock, ObjType, OpId, SmallHashMap};
 use crate::value::ScalarValue;
 
+mod instance;
+mod schema;
+pub use instance::MARK_INSTANCE_SEPARATOR;
+pub(crate) use instance::{instance_name, instance_name_unchecked, mark_kind, split_mark_name};
+pub(crate) use schema::{CachedMarkSchema, MarkSchemaCache};
+pub use schema::{MarkDefinition, MarkSchema, MarkValueType, MARK_SCHEMA_KEY};
+
 /// Marks let you store out-of-bound information about sequences.
 ///
 /// The motivating use-case is rich text editing, see <https://www.inkandswitch.com/peritext/>.
@@ -21,8 +28,11 @@ use crate::value::ScalarValue;
 pub struct Mark {
     pub start: usize,
     pub end: usize,
+    /// The name of the mark, which for an instance of a multi-instance mark is its kind
     pub name: SmolStr,
     pub value: ScalarValue,
+    /// The ID of an instance of a multi-instance mark, see [`Mark::instance()`]
+    pub instance_id: Option<SmolStr>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,7 +45,7 @@ pub struct OldMark<'a> {
 impl Mark {
     pub(crate) fn old_data(&self) -> OldMarkData {
         OldMarkData {
-            name: SmolStr::from(self.name.as_str()),
+            name: self.stored_name(),
             value: self.value.clone(),
         }
     }
@@ -47,7 +57,7 @@ impl Mark {
 
     pub(crate) fn into_mark_set(self) -> Arc<MarkSet> {
         let mut m = MarkSet::default();
-        m.insert(self.name, self.value);
+        m.insert(self.stored_name(), self.value);
         Arc::new(m)
     }
 }
@@ -112,12 +122,15 @@ use std::collections::btree_map;
 #[derive(Debug, Clone, Default)]
 pub struct MarkSetIter<'a> {
     set: Option<btree_map::Iter<'a, SmolStr, ScalarValue>>,
+    /// Whether to include mark instances, under their stored names
+    stored: bool,
 }
 
 impl<'a> MarkSetIter<'a> {
-    fn new(set: &'a MarkSet) -> Self {
+    fn new(set: &'a MarkSet, stored: bool) -> Self {
         Self {
             set: Some(set.marks.iter()),
+            stored,
         }
     }
 }
@@ -126,16 +139,35 @@ impl<'a> Iterator for MarkSetIter<'a> {
     type Item = (&'a str, &'a ScalarValue);
 
     fn next(&mut self) -> Option<Self::Item> {
+        let stored = self.stored;
         self.set
             .as_mut()?
-            .next()
+            .find(|(name, _)| stored || split_mark_name(name).1.is_none())
             .map(|(name, value)| (name.as_str(), value))
     }
 }
 
 impl MarkSet {
+    /// The names and values of the marks in this set, apart from instances of multi-instance
+    /// marks, which are in [`Self::iter_instances()`]
     pub fn iter(&self) -> MarkSetIter<'_> {
-        MarkSetIter::new(self)
+        MarkSetIter::new(self, false)
+    }
+
+    /// The kinds, IDs and values of the instances of multi-instance marks in this set
+    pub fn iter_instances(&self) -> impl Iterator<Item = (&str, &str, &ScalarValue)> + '_ {
+        self.marks
+            .iter()
+            .filter_map(|(name, value)| match split_mark_name(name) {
+                (kind, Some(id)) => Some((kind, id, value)),
+                (_, None) => None,
+            })
+    }
+
+    /// The names and values of every mark in this set, with instances of multi-instance marks
+    /// under the name they're stored with
+    pub(crate) fn iter_stored(&self) -> MarkSetIter<'_> {
+        MarkSetIter::new(self, true)
     }
 
     pub fn num_marks(&self) -> usize {
@@ -221,12 +253,26 @@ impl std::iter::FromIterator<(String, ScalarValue)> for MarkSet {
 }
 
 impl Mark {
+    /// A mark named `name`
+    ///
+    /// A name containing [`MARK_INSTANCE_SEPARATOR`] is read as the stored name of a mark
+    /// instance, so the part before the separator becomes the name and the rest the instance ID.
     pub fn new<V: Into<ScalarValue>>(name: String, value: V, start: usize, end: usize) -> Mark {
+        let (name, instance_id) = split_mark_name(&name);
         Mark {
-            name: SmolStr::from(&name),
+            name: SmolStr::from(name),
             value: value.into(),
             start,
             end,
+            instance_id: instance_id.map(SmolStr::from),
+        }
+    }
+
+    /// The name this mark is stored under, which includes the instance ID of a mark instance
+    pub(crate) fn stored_name(&self) -> SmolStr {
+        match &self.instance_id {
+            Some(id) => SmolStr::from(instance_name_unchecked(&self.name, id)),
+            None => self.name.clone(),
         }
     }
 
@@ -0,0 +1,259 @@
+use crate::marks::{Mark, MarkSet};
+use crate::{AutomergeError, ScalarValue};
+
+/// Separates the kind of a mark instance from its ID in the stored mark name
+///
+/// Mark instances are stored as ordinary marks named `<kind>\u{1f}<id>` so documents with them
+/// load in any version of automerge. The kind must not contain the separator, the ID can.
+/// Nothing read from a document has the separator in it: a [`Mark`] has the kind as its name and
+/// the ID in [`Mark::instance_id`], and [`MarkSet::iter()`] leaves instances out for
+/// [`MarkSet::iter_instances()`] to report.
+pub const MARK_INSTANCE_SEPARATOR: char = '\u{1f}';
+
+/// Split a stored mark name into the kind of the mark and, for a mark instance, its ID
+pub(crate) fn split_mark_name(name: &str) -> (&str, Option<&str>) {
+    match name.split_once(MARK_INSTANCE_SEPARATOR) {
+        Some((kind, id)) => (kind, Some(id)),
+        None => (name, None),
+    }
+}
+
+/// The kind of the mark named `name`, which is the name itself for marks which aren't instances
+pub(crate) fn mark_kind(name: &str) -> &str {
+    split_mark_name(name).0
+}
+
+impl Mark {
+    /// Create an instance of a multi-instance mark
+    ///
+    /// Ordinary marks with the same name merge, so each character has at most one value for
+    /// each name. Instances of a mark kind are keyed by `id` as well, so instances with
+    /// different IDs can overlap, as comment threads do. Each instance can be found in a
+    /// [`MarkSet`] with [`MarkSet::instances()`], in the marks of a text object with
+    /// [`crate::ReadDoc::mark_instances()`], and removed with
+    /// [`crate::transaction::Transactable::remove_mark_instance()`].
+    ///
+    /// # Errors
+    ///
+    /// If `kind` contains [`MARK_INSTANCE_SEPARATOR`]
+    pub fn instance<V: Into<ScalarValue>>(
+        kind: &str,
+        id: &str,
+        value: V,
+        start: usize,
+        end: usize,
+    ) -> Result<Mark, AutomergeError> {
+        let mut mark = Mark::new(kind.to_string(), value, start, end);
+        if mark.instance_id.is_some() {
+            return Err(AutomergeError::InvalidMarkKind(kind.to_string()));
+        }
+        mark.instance_id = Some(id.into());
+        Ok(mark)
+    }
+
+    /// The kind of the mark, which is its name
+    pub fn kind(&self) -> &str {
+        &self.name
+    }
+
+    /// The ID of the mark if it's an instance of a multi-instance mark, see [`Self::instance()`]
+    pub fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+}
+
+impl MarkSet {
+    /// The IDs and values of the instances of the multi-instance mark `kind` in this set
+    pub fn instances<'a>(
+        &'a self,
+        kind: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a ScalarValue)> + 'a {
+        self.iter_instances()
+            .filter(move |(k, _, value)| *k == kind && !value.is_null())
+            .map(|(_, id, value)| (id, value))
+    }
+
+    /// The value of the instance `id` of the multi-instance mark `kind`, if it's in this set
+    pub fn instance(&self, kind: &str, id: &str) -> Option<&ScalarValue> {
+        self.iter_instances()
+            .find(|(k, i, _)| (*k, *i) == (kind, id))
+            .map(|(_, _, value)| value)
+            .filter(|value| !value.is_null())
+    }
+}
+
+/// The name a mark instance is stored under
+pub(crate) fn instance_name(kind: &str, id: &str) -> Result<String, AutomergeError> {
+    if kind.contains(MARK_INSTANCE_SEPARATOR) {
+        return Err(AutomergeError::InvalidMarkKind(kind.to_string()));
+    }
+    Ok(instance_name_unchecked(kind, id))
+}
+
+/// The name a mark instance is stored under, for a `kind` known not to contain the separator
+pub(crate) fn instance_name_unchecked(kind: &str, id: &str) -> String {
+    format!("{}{}{}", kind, MARK_INSTANCE_SEPARATOR, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marks::ExpandMark;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ObjType, PatchAction, ReadDoc, ROOT};
+
+    fn commented_doc() -> (AutoCommit, crate::ObjId) {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello world").unwrap();
+        doc.mark(
+            &text,
+            Mark::instance("comment", "abc", "first", 0, 8).unwrap(),
+            ExpandMark::None,
+        )
+        .unwrap();
+        doc.mark(
+            &text,
+            Mark::instance("comment", "def", "second", 6, 11).unwrap(),
+            ExpandMark::None,
+        )
+        .unwrap();
+        doc.mark(
+            &text,
+            Mark::new("bold".to_string(), true, 0, 11),
+            ExpandMark::None,
+        )
+        .unwrap();
+        (doc, text)
+    }
+
+    #[test]
+    fn instances_overlap() {
+        let (doc, text) = commented_doc();
+        let marks = doc.get_marks(&text, 7, None).unwrap();
+        assert_eq!(
+            marks.instances("comment").collect::<Vec<_>>(),
+            vec![("abc", &"first".into()), ("def", &"second".into())]
+        );
+        assert_eq!(marks.instance("comment", "def"), Some(&"second".into()));
+        assert_eq!(marks.instances("bold").count(), 0);
+
+        let mut comments = doc.mark_instances(&text, "comment").unwrap();
+        comments.sort_by_key(|m| m.start);
+        assert_eq!(
+            comments
+                .iter()
+                .map(|m| (m.kind(), m.instance_id(), m.start, m.end))
+                .collect::<Vec<_>>(),
+            vec![
+                ("comment", Some("abc"), 0, 8),
+                ("comment", Some("def"), 6, 11)
+            ]
+        );
+    }
+
+    #[test]
+    fn instances_are_reported_in_patches() {
+        let (mut doc, _) = commented_doc();
+        let heads = doc.get_heads();
+        let patches = doc.diff(&[], &heads);
+        let mut splices = patches.iter().filter_map(|p| match &p.action {
+            PatchAction::SpliceText { value, marks, .. } => {
+                Some((value.make_string(), marks.clone()))
+            }
+            _ => None,
+        });
+        let (_, marks) = splices.find(|(value, _)| value == "wo").unwrap();
+        assert_eq!(marks.unwrap().instances("comment").count(), 2);
+    }
+
+    #[test]
+    fn names_are_split_from_ids() {
+        let (mut doc, text) = commented_doc();
+        let has_separator = |name: &str| name.contains(MARK_INSTANCE_SEPARATOR);
+
+        let marks = doc.marks(&text).unwrap();
+        assert!(marks.iter().all(|m| !has_separator(&m.name)));
+        assert!(marks
+            .iter()
+            .any(|m| m.name == "comment" && m.instance_id() == Some("abc")));
+
+        let set = doc.get_marks(&text, 7, None).unwrap();
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![("bold", &true.into())]);
+        assert_eq!(
+            set.iter_instances().collect::<Vec<_>>(),
+            vec![
+                ("comment", "abc", &"first".into()),
+                ("comment", "def", &"second".into())
+            ]
+        );
+
+        for span in doc.spans(&text).unwrap() {
+            if let crate::iter::Span::Text { marks: Some(m), .. } = span {
+                assert!(m.iter().all(|(name, _)| !has_separator(name)));
+            }
+        }
+
+        let heads = doc.get_heads();
+        for patch in doc.diff(&[], &heads) {
+            match patch.action {
+                PatchAction::SpliceText { marks: Some(m), .. } => {
+                    assert!(m.iter().all(|(name, _)| !has_separator(name)))
+                }
+                PatchAction::Mark { marks } => {
+                    assert!(marks.iter().all(|m| !has_separator(&m.name)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn remove_one_instance() {
+        let (mut doc, text) = commented_doc();
+        doc.remove_mark_instance(&text, "comment", "abc").unwrap();
+        let comments = doc.mark_instances(&text, "comment").unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].instance_id(), Some("def"));
+        assert_eq!(doc.get_marks(&text, 0, None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn kinds_with_the_separator_are_rejected() {
+        let (mut doc, text) = commented_doc();
+        let kind = format!("com{}ment", MARK_INSTANCE_SEPARATOR);
+        assert!(matches!(
+            Mark::instance(&kind, "abc", true, 0, 1),
+            Err(AutomergeError::InvalidMarkKind(k)) if k == kind
+        ));
+        assert!(matches!(
+            doc.remove_mark_instance(&text, &kind, "abc"),
+            Err(AutomergeError::InvalidMarkKind(_))
+        ));
+    }
+
+    #[test]
+    fn exclusive_marks_hide_instances() {
+        let (mut doc, text) = commented_doc();
+        doc.define_mark(
+            "code",
+            &crate::marks::MarkDefinition::default().with_exclusive(true),
+        )
+        .unwrap();
+        doc.mark(
+            &text,
+            Mark::new("code".to_string(), true, 0, 3),
+            ExpandMark::None,
+        )
+        .unwrap();
+        let mut comments = doc.mark_instances(&text, "comment").unwrap();
+        comments.sort_by_key(|m| m.start);
+        assert_eq!(
+            comments
+                .iter()
+                .map(|m| (m.instance_id(), m.start, m.end))
+                .collect::<Vec<_>>(),
+            vec![(Some("abc"), 3, 8), (Some("def"), 6, 11)]
+        );
+    }
+}
@@ -0,0 +1,740 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+
+use smol_str::SmolStr;
+
+use crate::hydrate;
+use crate::marks::{mark_kind, ExpandMark, Mark, MarkSet};
+use crate::types::ObjId;
+use crate::ScalarValue;
+
+/// The key in the root map of a document where its [`MarkSchema`] is stored
+///
+/// The key is reserved. Listing the root map with [`crate::ReadDoc::keys()`],
+/// [`crate::ReadDoc::map_range()`], [`crate::ReadDoc::values()`], [`crate::ReadDoc::length()`],
+/// [`crate::ReadDoc::iter()`] or [`crate::ReadDoc::hydrate()`] leaves it out, and so do patches.
+/// [`crate::ReadDoc::get()`] and [`crate::ReadDoc::get_all()`] and their `_at` versions read
+/// nothing there, including in documents which stored something else under the key before it was
+/// reserved. Writing to the key changes the schema.
+pub const MARK_SCHEMA_KEY: &str = "_automerge_mark_schema";
+
+/// The mark definitions a document declares, so every peer treats marks the same way
+///
+/// The schema is stored in the document itself, as a map under [`MARK_SCHEMA_KEY`] in the root
+/// map with a map for each definition, so it travels with the document and is merged like any
+/// other data. Use [`crate::transaction::Transactable::define_mark()`] to add a definition and
+/// [`crate::ReadDoc::mark_schema()`] to read them. If two peers create the schema map
+/// concurrently the definitions in both maps apply, and where both define the same mark the
+/// definition in the map which wins the conflict is used.
+///
+/// The definition of a mark kind applies to all its instances, see [`Mark::instance()`]. Once a
+/// mark is defined:
+///
+/// * [`crate::transaction::Transactable::mark()`] (and so
+///   [`crate::transaction::Transactable::update_spans()`]) uses the definition's
+///   [`ExpandMark`] instead of the one passed in, and rejects values of the wrong type
+/// * [`crate::ReadDoc::spans()`], [`crate::ReadDoc::marks()`] and [`crate::ReadDoc::get_marks()`]
+///   ignore values of the wrong type, which a peer without the schema may have written, and hide
+///   the marks an exclusive mark overlaps
+///
+/// Patches report marks as they apply under the schema. A mark patch which changes an exclusive
+/// mark reports every mark in the range it covers, with a null value where a mark is hidden.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkSchema {
+    definitions: BTreeMap<String, MarkDefinition>,
+}
+
+/// How marks with a particular name behave, see [`MarkSchema`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkDefinition {
+    /// Whether the mark grows to include text inserted at its edges
+    pub expand: ExpandMark,
+    /// An exclusive mark can't be combined with other marks, where it overlaps them only the
+    /// exclusive mark applies. Where exclusive marks overlap each other the one whose name sorts
+    /// first applies.
+    pub exclusive: bool,
+    /// The type of the values of the mark, `None` to allow any type
+    pub value_type: Option<MarkValueType>,
+}
+
+impl MarkDefinition {
+    pub fn with_expand(mut self, expand: ExpandMark) -> Self {
+        self.expand = expand;
+        self
+    }
+
+    pub fn with_exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    pub fn with_value_type(mut self, value_type: MarkValueType) -> Self {
+        self.value_type = Some(value_type);
+        self
+    }
+
+    /// Whether `value` is a valid value for the mark, a null value (which removes the mark) always
+    /// is
+    pub fn accepts(&self, value: &ScalarValue) -> bool {
+        value.is_null()
+            || self
+                .value_type
+                .map(|value_type| value_type.matches(value))
+                .unwrap_or(true)
+    }
+
+    /// The keys and values of the map the definition is stored as
+    pub(crate) fn to_entries(&self) -> Vec<(&'static str, ScalarValue)> {
+        let mut entries = vec![
+            ("expand", ScalarValue::from(expand_name(self.expand))),
+            ("exclusive", ScalarValue::Boolean(self.exclusive)),
+        ];
+        if let Some(value_type) = self.value_type {
+            entries.push(("type", ScalarValue::from(value_type.name())));
+        }
+        entries
+    }
+
+    /// Parse a stored definition, values which aren't understood are left at their defaults so
+    /// a newer peer can add fields
+    fn from_hydrate(map: &hydrate::Map) -> Self {
+        let scalar = |key: &str| match map.get(key) {
+            Some(hydrate::Value::Scalar(s)) => Some(s),
+            _ => None,
+        };
+        let mut definition = Self::default();
+        if let Some(ScalarValue::Str(expand)) = scalar("expand") {
+            if let Some(expand) = parse_expand(expand.as_str()) {
+                definition.expand = expand;
+            }
+        }
+        if let Some(ScalarValue::Boolean(exclusive)) = scalar("exclusive") {
+            definition.exclusive = *exclusive;
+        }
+        if let Some(ScalarValue::Str(value_type)) = scalar("type") {
+            definition.value_type = MarkValueType::from_name(value_type.as_str());
+        }
+        definition
+    }
+}
+
+/// The types a [`MarkDefinition`] can require mark values to have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkValueType {
+    Boolean,
+    String,
+    Int,
+    Uint,
+    F64,
+    Timestamp,
+    Bytes,
+}
+
+impl MarkValueType {
+    pub fn matches(&self, value: &ScalarValue) -> bool {
+        matches!(
+            (self, value),
+            (Self::Boolean, ScalarValue::Boolean(_))
+                | (Self::String, ScalarValue::Str(_))
+                | (Self::Int, ScalarValue::Int(_))
+                | (Self::Uint, ScalarValue::Uint(_))
+                | (Self::F64, ScalarValue::F64(_))
+                | (Self::Timestamp, ScalarValue::Timestamp(_))
+                | (Self::Bytes, ScalarValue::Bytes(_))
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Boolean => "boolean",
+            Self::String => "string",
+            Self::Int => "int",
+            Self::Uint => "uint",
+            Self::F64 => "f64",
+            Self::Timestamp => "timestamp",
+            Self::Bytes => "bytes",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "boolean" => Some(Self::Boolean),
+            "string" => Some(Self::String),
+            "int" => Some(Self::Int),
+            "uint" => Some(Self::Uint),
+            "f64" => Some(Self::F64),
+            "timestamp" => Some(Self::Timestamp),
+            "bytes" => Some(Self::Bytes),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MarkValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl MarkSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mark<S: Into<String>>(mut self, name: S, definition: MarkDefinition) -> Self {
+        self.definitions.insert(name.into(), definition);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MarkDefinition> {
+        self.definitions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MarkDefinition)> {
+        self.definitions.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+
+    /// Add the definitions in a schema map stored under [`MARK_SCHEMA_KEY`], replacing any with
+    /// the same name and ignoring entries which aren't maps
+    pub(crate) fn merge_hydrate(&mut self, map: &hydrate::Map) {
+        self.definitions
+            .extend(map.iter().filter_map(|(name, value)| match &value.value {
+                hydrate::Value::Map(definition) => {
+                    Some((name.clone(), MarkDefinition::from_hydrate(definition)))
+                }
+                _ => None,
+            }));
+    }
+
+    fn accepts(&self, name: &str, value: &ScalarValue) -> bool {
+        self.get(mark_kind(name))
+            .map(|d| d.accepts(value))
+            .unwrap_or(true)
+    }
+
+    /// Whether any definition is exclusive, in which case a mark can be hidden by marks of
+    /// other kinds
+    pub(crate) fn has_exclusive(&self) -> bool {
+        self.definitions.values().any(|d| d.exclusive)
+    }
+
+    fn is_exclusive(&self, name: &str) -> bool {
+        self.get(mark_kind(name))
+            .map(|d| d.exclusive)
+            .unwrap_or(false)
+    }
+
+    /// The marks which apply to a character with the stored marks `marks`
+    pub(crate) fn resolve(&self, marks: &MarkSet) -> MarkSet {
+        let valid = marks
+            .iter_stored()
+            .filter(|(name, value)| !value.is_null() && self.accepts(name, value));
+        // Mark sets iterate in name order so this is the exclusive mark whose name sorts first
+        match valid.clone().find(|(name, _)| self.is_exclusive(name)) {
+            Some((name, value)) => std::iter::once((name.to_string(), value.clone())).collect(),
+            None => valid
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// The marks a mark patch reports, given the marks it changed as stored
+    ///
+    /// Where no definition is exclusive a mark applies if its value is valid, so invalid values
+    /// are reported as null. Otherwise a changed mark can hide or reveal the other marks it
+    /// overlaps, so every mark stored in the range the patch covers is reported with the value
+    /// which applies, null where none does. `runs` returns the stored marks of the runs of text
+    /// in a range.
+    pub(crate) fn resolve_mark_patch<F>(&self, marks: Vec<Mark>, runs: F) -> Vec<Mark>
+    where
+        F: FnOnce(Range<usize>) -> Vec<(Range<usize>, Option<Arc<MarkSet>>)>,
+    {
+        if !self.has_exclusive() {
+            return marks
+                .into_iter()
+                .map(|mut mark| {
+                    if !self.accepts(&mark.name, &mark.value) {
+                        mark.value = ScalarValue::Null;
+                    }
+                    mark
+                })
+                .collect();
+        }
+        let (Some(start), Some(end)) = (
+            marks.iter().map(|m| m.start).min(),
+            marks.iter().map(|m| m.end).max(),
+        ) else {
+            return marks;
+        };
+        let runs = runs(start..end);
+        let mut names = marks
+            .iter()
+            .map(|m| m.stored_name())
+            .collect::<BTreeSet<SmolStr>>();
+        for stored in runs.iter().filter_map(|(_, marks)| marks.as_deref()) {
+            names.extend(stored.iter_stored().map(|(name, _)| SmolStr::from(name)));
+        }
+        let runs = runs
+            .into_iter()
+            .map(|(range, marks)| {
+                let resolved = marks.map(|marks| self.resolve(&marks)).unwrap_or_default();
+                (range, resolved)
+            })
+            .collect::<Vec<_>>();
+        let mut resolved: Vec<Mark> = Vec::new();
+        for name in names {
+            let first = resolved.len();
+            for (range, marks) in &runs {
+                let value = marks
+                    .iter_stored()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or(ScalarValue::Null);
+                match resolved[first..].last_mut() {
+                    Some(last) if last.end == range.start && last.value == value => {
+                        last.end = range.end
+                    }
+                    _ => resolved.push(Mark::new(name.to_string(), value, range.start, range.end)),
+                }
+            }
+        }
+        resolved
+    }
+
+    /// The marks which apply to a sequence with the stored marks `marks`
+    pub(crate) fn resolve_marks(&self, marks: Vec<Mark>) -> Vec<Mark> {
+        let marks = marks
+            .into_iter()
+            .filter(|m| self.accepts(&m.name, &m.value))
+            .collect::<Vec<_>>();
+        if !marks.iter().any(|m| self.is_exclusive(&m.name)) {
+            return marks;
+        }
+        let mut bounds = marks
+            .iter()
+            .flat_map(|m| [m.start, m.end])
+            .collect::<Vec<_>>();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let mut resolved: Vec<Mark> = Vec::new();
+        // The index in `resolved` of the last range of each mark, so adjacent ranges can be joined
+        let mut last = HashMap::<_, usize>::new();
+        for range in bounds.windows(2) {
+            let (start, end) = (range[0], range[1]);
+            let covering = marks
+                .iter()
+                .filter(|m| m.start <= start && end <= m.end)
+                .collect::<Vec<_>>();
+            let winner = covering
+                .iter()
+                .filter(|m| self.is_exclusive(&m.name))
+                .min_by_key(|m| m.stored_name());
+            let applied = match winner {
+                Some(winner) => vec![*winner],
+                None => covering,
+            };
+            for mark in applied {
+                let name = mark.stored_name();
+                match last.get(&name).copied() {
+                    Some(i) if resolved[i].end == start && resolved[i].value == mark.value => {
+                        resolved[i].end = end
+                    }
+                    _ => {
+                        last.insert(name.clone(), resolved.len());
+                        resolved.push(Mark::new(name.to_string(), mark.value.clone(), start, end));
+                    }
+                }
+            }
+        }
+        resolved.sort_by_cached_key(|m| (m.start, m.stored_name()));
+        resolved
+    }
+}
+
+/// The schema of the current state of a document, kept in the `OpSet` so reads
+/// and marks don't parse it again until one of the objects it was parsed from changes
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MarkSchemaCache(OnceLock<CachedMarkSchema>);
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CachedMarkSchema {
+    pub(crate) schema: Option<Arc<MarkSchema>>,
+    /// The schema maps and the definition maps in them
+    pub(crate) objects: HashSet<ObjId>,
+    /// Whether the root map has a value under [`MARK_SCHEMA_KEY`], map or not
+    pub(crate) declared: bool,
+}
+
+impl MarkSchemaCache {
+    pub(crate) fn get_or_init<F: FnOnce() -> CachedMarkSchema>(
+        &self,
+        f: F,
+    ) -> Option<Arc<MarkSchema>> {
+        self.get_or_init_cached(f).schema.clone()
+    }
+
+    pub(crate) fn get_or_init_cached<F: FnOnce() -> CachedMarkSchema>(
+        &self,
+        f: F,
+    ) -> &CachedMarkSchema {
+        self.0.get_or_init(f)
+    }
+
+    pub(crate) fn is_cached(&self) -> bool {
+        self.0.get().is_some()
+    }
+
+    /// Forget the schema if an op in `obj` at `key` could change it
+    pub(crate) fn invalidate(&mut self, obj: ObjId, key: Option<&str>) {
+        let Some(cached) = self.0.get() else {
+            return;
+        };
+        if cached.objects.contains(&obj) || (obj.is_root() && key == Some(MARK_SCHEMA_KEY)) {
+            self.clear();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.take();
+    }
+}
+
+fn expand_name(expand: ExpandMark) -> &'static str {
+    match expand {
+        ExpandMark::Before => "before",
+        ExpandMark::After => "after",
+        ExpandMark::Both => "both",
+        ExpandMark::None => "none",
+    }
+}
+
+fn parse_expand(name: &str) -> Option<ExpandMark> {
+    match name {
+        "before" => Some(ExpandMark::Before),
+        "after" => Some(ExpandMark::After),
+        "both" => Some(ExpandMark::Both),
+        "none" => Some(ExpandMark::None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iter::Span;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, AutomergeError, ObjType, ReadDoc, ROOT};
+
+    fn text_spans(
+        doc: &AutoCommit,
+        text: &crate::ObjId,
+    ) -> Vec<(String, Vec<(String, ScalarValue)>)> {
+        doc.spans(text)
+            .unwrap()
+            .filter_map(|span| match span {
+                Span::Text { text, marks } => Some((
+                    text,
+                    marks
+                        .map(|m| m.iter().map(|(n, v)| (n.to_string(), v.clone())).collect())
+                        .unwrap_or_default(),
+                )),
+                Span::Block(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn definitions_are_stored_in_the_document() {
+        let mut doc = AutoCommit::new();
+        let bold = MarkDefinition::default()
+            .with_expand(ExpandMark::Both)
+            .with_value_type(MarkValueType::Boolean);
+        let code = MarkDefinition::default().with_exclusive(true);
+        doc.define_mark("bold", &bold).unwrap();
+        doc.define_mark("code", &code).unwrap();
+
+        let expected = MarkSchema::new()
+            .with_mark("bold", bold)
+            .with_mark("code", code);
+        assert_eq!(doc.mark_schema(None).unwrap(), expected);
+
+        let loaded = AutoCommit::load(&doc.save()).unwrap();
+        assert_eq!(loaded.mark_schema(None).unwrap(), expected);
+    }
+
+    #[test]
+    fn marks_use_the_defined_expand_and_type() {
+        let mut doc = AutoCommit::new();
+        doc.define_mark(
+            "bold",
+            &MarkDefinition::default()
+                .with_expand(ExpandMark::None)
+                .with_value_type(MarkValueType::Boolean),
+        )
+        .unwrap();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello").unwrap();
+
+        // The definition overrides the expand flag passed in
+        doc.mark(
+            &text,
+            Mark::new("bold".to_string(), true, 0, 5),
+            ExpandMark::Both,
+        )
+        .unwrap();
+        doc.splice_text(&text, 5, 0, "!").unwrap();
+        assert_eq!(
+            text_spans(&doc, &text),
+            vec![
+                ("hello".to_string(), vec![("bold".to_string(), true.into())]),
+                ("!".to_string(), vec![]),
+            ]
+        );
+
+        assert!(matches!(
+            doc.mark(
+                &text,
+                Mark::new("bold".to_string(), "yes", 0, 5),
+                ExpandMark::None,
+            ),
+            Err(AutomergeError::InvalidValueType { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_resolve_marks_with_the_schema() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello world").unwrap();
+        // A peer which doesn't know the schema makes marks which break it
+        let mut other = doc.fork();
+        other
+            .mark(
+                &text,
+                Mark::new("bold".to_string(), true, 0, 11),
+                ExpandMark::None,
+            )
+            .unwrap();
+        other
+            .mark(
+                &text,
+                Mark::new("code".to_string(), true, 6, 11),
+                ExpandMark::None,
+            )
+            .unwrap();
+        other
+            .mark(
+                &text,
+                Mark::new("italic".to_string(), "yes", 0, 5),
+                ExpandMark::None,
+            )
+            .unwrap();
+
+        doc.define_mark("code", &MarkDefinition::default().with_exclusive(true))
+            .unwrap();
+        doc.define_mark(
+            "italic",
+            &MarkDefinition::default().with_value_type(MarkValueType::Boolean),
+        )
+        .unwrap();
+        doc.merge(&mut other).unwrap();
+
+        // The invalid italic mark is ignored and the code mark hides the bold mark
+        assert_eq!(
+            text_spans(&doc, &text),
+            vec![
+                (
+                    "hello ".to_string(),
+                    vec![("bold".to_string(), true.into())]
+                ),
+                ("world".to_string(), vec![("code".to_string(), true.into())]),
+            ]
+        );
+        assert_eq!(
+            doc.marks(&text).unwrap(),
+            vec![
+                Mark::new("bold".to_string(), true, 0, 6),
+                Mark::new("code".to_string(), true, 6, 11),
+            ]
+        );
+        let marks = doc.get_marks(&text, 8, None).unwrap();
+        assert_eq!(
+            marks.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+            vec!["code"]
+        );
+
+        // Without the schema every mark applies
+        let heads = other.get_heads();
+        assert_eq!(doc.marks_at(&text, &heads).unwrap().len(), 3);
+        let spans = doc.spans_at(&text, &heads).unwrap().collect::<Vec<_>>();
+        assert!(matches!(
+            &spans[0],
+            Span::Text { marks: Some(m), .. } if m.iter().count() == 2
+        ));
+    }
+
+    #[test]
+    fn concurrent_definitions_are_merged() {
+        let mut doc = AutoCommit::new();
+        let mut other = doc.fork();
+        doc.define_mark("bold", &MarkDefinition::default().with_exclusive(true))
+            .unwrap();
+        other
+            .define_mark("code", &MarkDefinition::default().with_exclusive(true))
+            .unwrap();
+        doc.merge(&mut other).unwrap();
+
+        let schema = doc.mark_schema(None).unwrap();
+        assert!(schema.get("bold").is_some());
+        assert!(schema.get("code").is_some());
+
+        // Definitions made after the merge are kept alongside the concurrent ones
+        doc.define_mark("italic", &MarkDefinition::default())
+            .unwrap();
+        assert_eq!(doc.mark_schema(None).unwrap().iter().count(), 3);
+    }
+
+    #[test]
+    fn the_schema_is_not_part_of_the_content() {
+        let mut doc = AutoCommit::new();
+        let before = doc.get_heads();
+        doc.define_mark("bold", &MarkDefinition::default()).unwrap();
+        doc.put(ROOT, "title", "hello").unwrap();
+
+        assert_eq!(doc.keys(ROOT).collect::<Vec<_>>(), vec!["title"]);
+        assert_eq!(doc.map_range(ROOT, ..).count(), 1);
+        assert_eq!(doc.values(ROOT).count(), 1);
+        assert_eq!(doc.length(ROOT), 1);
+        assert_eq!(doc.iter().count(), 1);
+        assert_eq!(
+            doc.hydrate(ROOT, None).unwrap(),
+            crate::hydrate_map! { "title" => "hello" }.into()
+        );
+        let after = doc.get_heads();
+        let patches = doc.diff(&before, &after);
+        assert_eq!(patches.len(), 1);
+        assert!(
+            matches!(&patches[0].action, crate::PatchAction::PutMap { key, .. } if key == "title")
+        );
+        // Nor is it read directly, now or at past heads
+        assert_eq!(doc.get(ROOT, MARK_SCHEMA_KEY).unwrap(), None);
+        assert_eq!(doc.get_all(ROOT, MARK_SCHEMA_KEY).unwrap(), vec![]);
+        assert_eq!(doc.get_at(ROOT, MARK_SCHEMA_KEY, &after).unwrap(), None);
+        assert_eq!(doc.length_at(ROOT, &after), 1);
+        let mut doc = crate::Automerge::load(&doc.save()).unwrap();
+        let mut tx = doc.transaction();
+        assert_eq!(tx.get(ROOT, MARK_SCHEMA_KEY).unwrap(), None);
+        // but it's still where the schema is kept
+        tx.define_mark("italic", &MarkDefinition::default())
+            .unwrap();
+        assert_eq!(tx.length(ROOT), 1);
+        tx.commit();
+        assert_eq!(doc.mark_schema(None).unwrap().iter().count(), 2);
+        assert_eq!(doc.get_all(ROOT, MARK_SCHEMA_KEY).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn patches_report_marks_with_the_schema() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello world").unwrap();
+        doc.mark(
+            &text,
+            Mark::new("bold".to_string(), true, 0, 11),
+            ExpandMark::None,
+        )
+        .unwrap();
+        // A peer without the schema marks some code and writes an invalid italic mark
+        let mut other = doc.fork();
+        doc.define_mark("code", &MarkDefinition::default().with_exclusive(true))
+            .unwrap();
+        doc.define_mark(
+            "italic",
+            &MarkDefinition::default().with_value_type(MarkValueType::Boolean),
+        )
+        .unwrap();
+        let before = doc.get_heads();
+        other
+            .mark(
+                &text,
+                Mark::new("code".to_string(), true, 6, 11),
+                ExpandMark::None,
+            )
+            .unwrap();
+        other
+            .mark(
+                &text,
+                Mark::new("italic".to_string(), "yes", 0, 5),
+                ExpandMark::None,
+            )
+            .unwrap();
+        doc.merge(&mut other).unwrap();
+        let after = doc.get_heads();
+
+        let marks = doc
+            .diff(&before, &after)
+            .into_iter()
+            .filter_map(|patch| match patch.action {
+                crate::PatchAction::Mark { marks } => Some(marks),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        // The code mark hides the bold mark and the italic mark doesn't apply
+        assert_eq!(
+            marks,
+            vec![
+                Mark::new("bold".to_string(), true, 0, 6),
+                Mark::new("bold".to_string(), ScalarValue::Null, 6, 11),
+                Mark::new("code".to_string(), ScalarValue::Null, 0, 6),
+                Mark::new("code".to_string(), true, 6, 11),
+                Mark::new("italic".to_string(), ScalarValue::Null, 0, 11),
+            ]
+        );
+
+        // Splices report the marks which apply too
+        let before = doc.get_heads();
+        doc.splice_text(&text, 8, 0, "r").unwrap();
+        let after = doc.get_heads();
+        let splice = doc.diff(&before, &after).remove(0);
+        assert!(matches!(
+            splice.action,
+            crate::PatchAction::SpliceText { marks: Some(m), .. }
+                if m.iter().map(|(n, _)| n).collect::<Vec<_>>() == vec!["code"]
+        ));
+    }
+
+    #[test]
+    fn the_cached_schema_follows_changes() {
+        let mut doc = AutoCommit::new();
+        doc.define_mark("bold", &MarkDefinition::default()).unwrap();
+        assert!(doc.mark_schema(None).unwrap().get("bold").is_some());
+        doc.commit();
+
+        doc.define_mark("bold", &MarkDefinition::default().with_exclusive(true))
+            .unwrap();
+        assert!(
+            doc.mark_schema(None)
+                .unwrap()
+                .get("bold")
+                .unwrap()
+                .exclusive
+        );
+        doc.rollback();
+        assert!(
+            !doc.mark_schema(None)
+                .unwrap()
+                .get("bold")
+                .unwrap()
+                .exclusive
+        );
+
+        doc.delete(ROOT, MARK_SCHEMA_KEY).unwrap();
+        assert!(doc.mark_schema(None).unwrap().is_empty());
+    }
+}
@@ -1831,6 +1831,7 @@ mod tests {
                         end: end as usize,
                         name,
                         value,
+                        instance_id: None,
                     };
                     tmp.mark(&text1, mark, ExpandMark::After).unwrap();
                 }
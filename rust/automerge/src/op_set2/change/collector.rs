@@ -17,11 +17,13 @@ use crate::op_set2::change::{write_change_ops, GetHash};
 use crate::op_set2::op_set::{IndexBuilder, MarkOrderValidator};
 use crate::storage::bundle::BundleChange;
 use crate::storage::change::{Change as StoredChange, Verified};
+use crate::storage::document::ReconstructError;
 use crate::storage::load::change_collector::Error;
+use crate::storage::load::{LoadPhase, Progress};
 use crate::storage::{ChunkType, Header};
 use crate::{
     change::Change,
-    op_set2::{ChangeMetadata, KeyRef, Op, OpBuilder, OpSet, ReadOpError},
+    op_set2::{ChangeMetadata, KeyRef, Op, OpBuilder, OpSet},
     types::{ActorId, ChangeHash, ObjId, OpId},
 };
 
@@ -35,8 +37,14 @@ pub(crate) struct IndexedChangeCollector<'a> {
 }
 
 impl<'a> IndexedChangeCollector<'a> {
-    pub(crate) fn process_ops(&mut self, op_set: &'a OpSet) -> Result<(), ReadOpError> {
+    pub(crate) fn process_ops(
+        &mut self,
+        op_set: &'a OpSet,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), ReconstructError> {
         let mut iter = op_set.iter();
+        let total = op_set.len();
+        let mut done = 0;
 
         while let Some(op) = iter.try_next()? {
             let op_id = op.id;
@@ -49,12 +57,19 @@ impl<'a> IndexedChangeCollector<'a> {
                 self.index.process_succ(op_is_counter, id);
                 self.collector.process_succ(op_id, id);
             }
+
+            done += 1;
+            progress.report_items(LoadPhase::Reconstruct, done, total)?;
         }
         Ok(())
     }
 
-    pub(crate) fn collect(self, op_set: &OpSet) -> Result<CollectedChanges, Error> {
-        self.collector.collect(op_set)
+    pub(crate) fn collect(
+        self,
+        op_set: &OpSet,
+        progress: &mut Progress<'_>,
+    ) -> Result<CollectedChanges, Error> {
+        self.collector.collect(op_set, progress)
     }
 
     pub(crate) fn process_op(&mut self, op: Op<'a>) {
@@ -584,8 +599,11 @@ impl<'a> ChangeCollector<'a> {
         &mut self,
         op_set: &'a OpSet,
         mark_order: &mut MarkOrderValidator,
-    ) -> Result<(), ReadOpError> {
+        progress: &mut Progress<'_>,
+    ) -> Result<(), ReconstructError> {
         let mut iter = op_set.iter();
+        let total = op_set.len();
+        let mut done = 0;
 
         while let Some(op) = iter.try_next()? {
             let op_id = op.id;
@@ -597,6 +615,9 @@ impl<'a> ChangeCollector<'a> {
             for id in op_succ {
                 self.process_succ(op_id, id);
             }
+
+            done += 1;
+            progress.report_items(LoadPhase::Reconstruct, done, total)?;
         }
         Ok(())
     }
@@ -900,7 +921,11 @@ impl<'a> ChangeCollector<'a> {
         Ok(changes)
     }
 
-    pub(crate) fn collect(mut self, op_set: &OpSet) -> Result<CollectedChanges, Error> {
+    pub(crate) fn collect(
+        mut self,
+        op_set: &OpSet,
+        progress: &mut Progress<'_>,
+    ) -> Result<CollectedChanges, Error> {
         self.flush_deletes();
 
         let num_actors = op_set.actors.len();
@@ -908,8 +933,9 @@ impl<'a> ChangeCollector<'a> {
         let mut seq = vec![0; num_actors];
         let mut changes = Vec::with_capacity(self.changes.len());
         let mut heads = BTreeSet::new();
+        let total = self.changes.len();
 
-        for change in self.changes.into_iter() {
+        for (i, change) in self.changes.into_iter().enumerate() {
             let actor = change.actor;
 
             if actor >= num_actors {
@@ -942,6 +968,8 @@ impl<'a> ChangeCollector<'a> {
             heads.insert(hash);
 
             changes.push(Change::from(change));
+
+            progress.report_items(LoadPhase::VerifyHeads, i + 1, total)?;
         }
 
         Ok(CollectedChanges { changes, heads })
@@ -37,6 +37,8 @@ pub(super) struct Columns {
 #[derive(Debug, Clone)]
 pub(super) struct Indexes {
     pub(super) text: hexane::PrefixColumn<Option<u32>>,
+    /// The number of `\n`s in each top op, `None` where `text` is
+    pub(super) lines: hexane::PrefixColumn<Option<u32>>,
     pub(super) top: hexane::PrefixColumn<bool>,
     pub(super) visible: hexane::Column<bool>,
     pub(super) inc: hexane::Column<Option<i64>>,
@@ -47,6 +49,7 @@ impl Default for Indexes {
     fn default() -> Self {
         Self {
             text: hexane::PrefixColumn::new(),
+            lines: hexane::PrefixColumn::new(),
             top: hexane::PrefixColumn::new(),
             visible: hexane::Column::new(),
             inc: hexane::Column::new(),
@@ -170,14 +173,25 @@ impl Columns {
     }
 
     pub(crate) fn export(&self) -> (RawColumns<Uncompressed>, Vec<u8>) {
-        let mut data = vec![];
-
         let mut cols = ALL_COLUMN_SPECS;
         cols.sort();
 
-        let raw: RawColumns<Uncompressed> = cols
-            .iter()
-            .filter_map(|spec| self.export_column(spec, &mut data))
+        // Encode each column into its own buffer so they can be encoded at the same time
+        let encoded = crate::parallel::map(&cols, |spec| {
+            let mut data = vec![];
+            let col = self.export_column(spec, &mut data);
+            (col, data)
+        });
+
+        let mut data = vec![];
+        let raw: RawColumns<Uncompressed> = encoded
+            .into_iter()
+            .filter_map(|(col, col_data)| {
+                let col = col?;
+                let start = data.len();
+                data.extend(col_data);
+                Some(RawColumn::new(col.spec(), start..data.len()))
+            })
             .collect();
 
         (raw, data)
@@ -209,6 +223,7 @@ impl Columns {
             ("inc", self.index.inc.save()),
             ("mark", self.index.mark.save()),
             ("text", self.index.text.save()),
+            ("lines", self.index.lines.save()),
             ("top", self.index.top.save()),
             ("visible", self.index.visible.save()),
         ]
@@ -261,50 +276,98 @@ impl Columns {
 
         let opts = hexane::LoadOpts::new().with_length(len);
 
-        let id_ctr = hexane::DeltaColumn::<u32>::load_with(data_for(ID_COUNTER_COL_SPEC), opts)?;
-
-        let obj_actor = hexane::Column::<Option<ActorIdx>>::load_with(
-            data_for(OBJ_ID_ACTOR_COL_SPEC),
-            opts.with_fill(None),
-        )?;
-
-        let obj_ctr = hexane::Column::<Option<u32>>::load_with(
-            data_for(OBJ_ID_COUNTER_COL_SPEC),
-            opts.with_fill(None),
-        )?;
-
-        let key_actor = hexane::Column::<Option<ActorIdx>>::load_with(
-            data_for(KEY_ACTOR_COL_SPEC),
-            opts.with_fill(None),
-        )?;
-
-        let key_ctr = hexane::DeltaColumn::<Option<u32>>::load_with(
-            data_for(KEY_COUNTER_COL_SPEC),
-            opts.with_fill(None),
-        )?;
-        let key_str = hexane::Column::load_with(data_for(KEY_STR_COL_SPEC), opts.with_fill(None))?;
-        let insert =
-            hexane::PrefixColumn::load_with(data_for(INSERT_COL_SPEC), opts.with_fill(false))?;
-        let action = hexane::Column::<Action>::load_with(data_for(ACTION_COL_SPEC), opts)?;
-        let mark_name =
-            hexane::Column::load_with(data_for(MARK_NAME_COL_SPEC), opts.with_fill(None))?;
-
-        let expand = hexane::Column::load_with(data_for(EXPAND_COL_SPEC), opts.with_fill(false))?;
-
-        let succ_count =
-            hexane::PrefixColumn::<u32>::load_with(data_for(SUCC_COUNT_COL_SPEC), opts)?;
-
-        let succ_len = succ_count.get_prefix(succ_count.len()) as usize;
-        let succ_opts = hexane::LoadOpts::new().with_length(succ_len);
-        let succ_actor =
-            hexane::Column::<ActorIdx>::load_with(data_for(SUCC_ACTOR_COL_SPEC), succ_opts)?;
-
-        let succ_ctr =
-            hexane::DeltaColumn::<u32>::load_with(data_for(SUCC_COUNTER_COL_SPEC), succ_opts)?;
-
-        let value_meta =
-            hexane::PrefixColumn::<ValueMeta>::load_with(data_for(VALUE_META_COL_SPEC), opts)?;
-        let value = hexane::RawColumn::load(data_for(VALUE_COL_SPEC))?;
+        // The columns are independent, apart from the succ columns whose length comes from
+        // succ_count, so we load them in four groups which can run at the same time
+        let (ids, (keys, (flags, succ))) = crate::parallel::join(
+            || -> Result<_, PackError> {
+                let id_ctr =
+                    hexane::DeltaColumn::<u32>::load_with(data_for(ID_COUNTER_COL_SPEC), opts)?;
+
+                let obj_actor = hexane::Column::<Option<ActorIdx>>::load_with(
+                    data_for(OBJ_ID_ACTOR_COL_SPEC),
+                    opts.with_fill(None),
+                )?;
+
+                let obj_ctr = hexane::Column::<Option<u32>>::load_with(
+                    data_for(OBJ_ID_COUNTER_COL_SPEC),
+                    opts.with_fill(None),
+                )?;
+                Ok((id_ctr, obj_actor, obj_ctr))
+            },
+            || {
+                crate::parallel::join(
+                    || -> Result<_, PackError> {
+                        let key_actor = hexane::Column::<Option<ActorIdx>>::load_with(
+                            data_for(KEY_ACTOR_COL_SPEC),
+                            opts.with_fill(None),
+                        )?;
+
+                        let key_ctr = hexane::DeltaColumn::<Option<u32>>::load_with(
+                            data_for(KEY_COUNTER_COL_SPEC),
+                            opts.with_fill(None),
+                        )?;
+                        let key_str = hexane::Column::load_with(
+                            data_for(KEY_STR_COL_SPEC),
+                            opts.with_fill(None),
+                        )?;
+                        Ok((key_actor, key_ctr, key_str))
+                    },
+                    || {
+                        crate::parallel::join(
+                            || -> Result<_, PackError> {
+                                let insert = hexane::PrefixColumn::load_with(
+                                    data_for(INSERT_COL_SPEC),
+                                    opts.with_fill(false),
+                                )?;
+                                let action = hexane::Column::<Action>::load_with(
+                                    data_for(ACTION_COL_SPEC),
+                                    opts,
+                                )?;
+                                let mark_name = hexane::Column::load_with(
+                                    data_for(MARK_NAME_COL_SPEC),
+                                    opts.with_fill(None),
+                                )?;
+
+                                let expand = hexane::Column::load_with(
+                                    data_for(EXPAND_COL_SPEC),
+                                    opts.with_fill(false),
+                                )?;
+
+                                let value_meta = hexane::PrefixColumn::<ValueMeta>::load_with(
+                                    data_for(VALUE_META_COL_SPEC),
+                                    opts,
+                                )?;
+                                let value = hexane::RawColumn::load(data_for(VALUE_COL_SPEC))?;
+                                Ok((insert, action, mark_name, expand, value_meta, value))
+                            },
+                            || -> Result<_, PackError> {
+                                let succ_count = hexane::PrefixColumn::<u32>::load_with(
+                                    data_for(SUCC_COUNT_COL_SPEC),
+                                    opts,
+                                )?;
+
+                                let succ_len = succ_count.get_prefix(succ_count.len()) as usize;
+                                let succ_opts = hexane::LoadOpts::new().with_length(succ_len);
+                                let succ_actor = hexane::Column::<ActorIdx>::load_with(
+                                    data_for(SUCC_ACTOR_COL_SPEC),
+                                    succ_opts,
+                                )?;
+
+                                let succ_ctr = hexane::DeltaColumn::<u32>::load_with(
+                                    data_for(SUCC_COUNTER_COL_SPEC),
+                                    succ_opts,
+                                )?;
+                                Ok((succ_count, succ_actor, succ_ctr))
+                            },
+                        )
+                    },
+                )
+            },
+        );
+        let (id_ctr, obj_actor, obj_ctr) = ids?;
+        let (key_actor, key_ctr, key_str) = keys?;
+        let (insert, action, mark_name, expand, value_meta, value) = flags?;
+        let (succ_count, succ_actor, succ_ctr) = succ?;
 
         let index = Indexes::default();
 
@@ -410,6 +473,7 @@ impl Columns {
         let marks = ops.clone().map(O::mark_index).collect();
         self.index.mark.undo(pos, marks);
         self.index.text.splice(pos, del, [] as [Option<u32>; 0]);
+        self.index.lines.splice(pos, del, [] as [Option<u32>; 0]);
         self.index.top.splice(pos, del, [] as [bool; 0]);
         self.index.visible.splice(pos, del, [] as [bool; 0]);
 
@@ -483,6 +547,11 @@ impl Columns {
                 }
             }),
         );
+        self.index.lines.splice(
+            pos,
+            0,
+            ops.clone().map(|s| O::top(s).then(|| O::newlines(s))),
+        );
         self.index.top.splice(pos, 0, ops.clone().map(O::top));
         self.index
             .visible
@@ -209,6 +209,11 @@ impl OpBuilder<'_> {
         }
     }
 
+    /// The number of `\n`s this op contributes to a text object
+    pub(crate) fn newlines(&self) -> usize {
+        self.as_str().bytes().filter(|b| *b == b'\n').count()
+    }
+
     pub(crate) fn is_inc(&self) -> bool {
         self.action == Action::Increment
     }
@@ -491,6 +496,10 @@ impl OpLike for &TxOp {
         op.bld.width(seq_type, text_encoding) as u64
     }
 
+    fn newlines(op: &Self) -> u32 {
+        op.bld.newlines() as u32
+    }
+
     fn visible(op: &Self) -> bool {
         !op.bld.is_inc()
     }
@@ -570,6 +579,10 @@ impl OpLike for TxOp {
         op.bld.width(seq_type, text_encoding) as u64
     }
 
+    fn newlines(op: &Self) -> u32 {
+        op.bld.newlines() as u32
+    }
+
     fn visible(op: &Self) -> bool {
         !op.bld.is_inc()
     }
@@ -653,6 +666,10 @@ impl OpLike for ChangeOp {
         }
     }
 
+    fn newlines(op: &Self) -> u32 {
+        op.bld.newlines() as u32
+    }
+
     fn visible(op: &Self) -> bool {
         !(op.bld.is_inc() || op.bld.is_delete() || op.succ.iter().any(|(_, inc)| inc.is_none()))
     }
@@ -758,6 +775,10 @@ impl<'a> OpLike for Op<'a> {
         op.width(seq_type, text_encoding) as u64
     }
 
+    fn newlines(op: &Self) -> u32 {
+        op.newlines() as u32
+    }
+
     fn visible(_op: &Self) -> bool {
         true // FIXME
     }
@@ -1012,6 +1033,11 @@ impl<'a> Op<'a> {
         }
     }
 
+    /// The number of `\n`s this op contributes to a text object
+    pub(crate) fn newlines(&self) -> usize {
+        self.as_str().bytes().filter(|b| *b == b'\n').count()
+    }
+
     pub(crate) fn op_type(&self) -> OpType<'a> {
         OpType::from_action_and_value(self.action, &self.value, self.mark_name, self.expand)
     }
@@ -1353,6 +1379,7 @@ pub(crate) trait OpLike: Debug {
     fn mark_name(op: &Self) -> Option<&str>;
     fn mark_index(op: &Self) -> Option<MarkIndexBuilder>;
     fn width(op: &Self, seq_type: SequenceType, text_encoding: TextEncoding) -> u64;
+    fn newlines(op: &Self) -> u32;
     fn visible(op: &Self) -> bool;
     fn top(op: &Self) -> bool {
         Self::visible(op)
@@ -2,8 +2,8 @@ use super::parents::Parents;
 use crate::clock::{Clock, ClockRange};
 use crate::exid::ExId;
 use crate::iter::tools::{MergeIter, SkipIter, SkipWrap};
-use crate::marks::{MarkSet, RichTextQueryState};
-use crate::op_set2::op_set::index::Indexes;
+use crate::line_index::newlines_before;
+use crate::marks::{MarkSchemaCache, MarkSet, RichTextQueryState};
 use crate::storage::columns::BadColumnLayout;
 use crate::storage::{columns::compression::Uncompressed, Document, RawColumns};
 use crate::types;
@@ -38,7 +38,7 @@ mod op_query;
 mod top_op;
 mod visible;
 
-pub(crate) use index::{IndexBuilder, MarkOrderValidator, ObjIndex, ObjInfo};
+pub(crate) use index::{IndexBuilder, Indexes, MarkOrderValidator, ObjIndex, ObjInfo};
 
 pub(crate) use crate::iter::{Keys, ListRange, MapRange, SpansInternal};
 
@@ -62,6 +62,7 @@ pub(crate) struct OpSet {
     pub(crate) obj_info: ObjIndex,
     cols: Columns,
     pub(crate) text_encoding: TextEncoding,
+    pub(crate) mark_schema: MarkSchemaCache,
 }
 
 impl OpSet {
@@ -82,6 +83,7 @@ impl OpSet {
             cols: Columns::default(),
             obj_info: ObjIndex::default(),
             text_encoding: encoding,
+            mark_schema: MarkSchemaCache::default(),
         }
     }
 
@@ -137,11 +139,28 @@ impl OpSet {
         }
     }
 
+    /// Measure text in `text_encoding` from now on, which means recalculating the width of every
+    /// visible op in the text index
+    pub(crate) fn set_text_encoding(&mut self, text_encoding: TextEncoding) {
+        if text_encoding == self.text_encoding {
+            return;
+        }
+        self.text_encoding = text_encoding;
+        let top = self.top_index_range(&(0..self.len()));
+        let text = self
+            .iter()
+            .zip(top)
+            .map(|(op, top)| top.then(|| op.width(SequenceType::Text, text_encoding) as u32))
+            .collect();
+        self.cols.index.text = text;
+    }
+
     pub(crate) fn conflict(&mut self, pos: usize) {
         const NONE: Option<u32> = None;
         self.cols.index.top.splice(pos, 1, [false]);
         // Make sure losing ops are not contributing width to the text sequence length.
         self.cols.index.text.splice(pos, 1, [NONE]);
+        self.cols.index.lines.splice(pos, 1, [NONE]);
     }
 
     pub(crate) fn expose(&mut self, pos: usize) {
@@ -151,10 +170,13 @@ impl OpSet {
         // anyway. We could alternatively require the caller pass the object type
         // and just not set the width for non-text ops, but we haven't done that
         // here.
-        let width = self
-            .get(pos)
+        let op = self.get(pos);
+        let width = op
+            .as_ref()
             .map(|op| op.width(SequenceType::Text, self.text_encoding) as u32);
+        let lines = op.map(|op| op.newlines() as u32);
         self.cols.index.text.splice(pos, 1, [width]);
+        self.cols.index.lines.splice(pos, 1, [lines]);
     }
 
     pub(crate) fn validate(
@@ -217,11 +239,13 @@ impl OpSet {
         // let indexes = builder.finish();
 
         assert_eq!(indexes.text.len(), self.len());
+        assert_eq!(indexes.lines.len(), self.len());
         assert_eq!(indexes.mark.len(), self.len());
         assert_eq!(indexes.visible.len(), self.len());
         assert_eq!(indexes.inc.len(), self.cols.sub_len());
 
         self.cols.index.text = indexes.text;
+        self.cols.index.lines = indexes.lines;
         self.cols.index.top = indexes.top;
         self.cols.index.visible = indexes.visible;
         self.cols.index.inc = indexes.inc;
@@ -229,6 +253,23 @@ impl OpSet {
         self.obj_info = indexes.obj_info;
     }
 
+    /// Forget the cached mark schema if any of the ops at `positions` are part of it
+    fn invalidate_mark_schema_at<I: Iterator<Item = usize>>(&mut self, positions: I) {
+        if !self.mark_schema.is_cached() {
+            return;
+        }
+        for pos in positions {
+            let Some(op) = self.get(pos) else {
+                continue;
+            };
+            let (obj, key) = match op.key {
+                KeyRef::Map(key) => (op.obj, Some(key.into_owned())),
+                KeyRef::Seq(_) => (op.obj, None),
+            };
+            self.mark_schema.invalidate(obj, key.as_deref());
+        }
+    }
+
     pub(crate) fn splice_objects<O: OpLike>(&mut self, ops: &[O]) {
         for op in ops {
             if let Some(obj_info) = op.obj_info() {
@@ -238,12 +279,16 @@ impl OpSet {
     }
 
     pub(crate) fn splice<O: OpLike>(&mut self, pos: usize, ops: &[O]) -> usize {
+        for op in ops {
+            self.mark_schema.invalidate(op.obj(), O::key_str(op));
+        }
         let added = self.cols.splice(pos, ops, self.text_encoding);
         self.splice_objects(ops);
         added
     }
 
     pub(crate) fn undo_op(&mut self, op: &TxOp) {
+        self.mark_schema.invalidate(op.obj(), TxOp::key_str(op));
         if !op.undo.is_empty() {
             self.undo_succ(&op.undo);
         }
@@ -257,6 +302,7 @@ impl OpSet {
     }
 
     pub(crate) fn add_succ(&mut self, op_pos: &[SuccInsert]) {
+        self.invalidate_mark_schema_at(op_pos.iter().map(|i| i.pos));
         let mut succ_inc = 0;
         let mut last_pos = None;
         for i in op_pos.iter().rev() {
@@ -277,12 +323,14 @@ impl OpSet {
             if i.inc.is_none() {
                 self.cols.index.visible.splice(i.pos, 1, [false]);
                 self.cols.index.text.splice(i.pos, 1, [None::<u32>]);
+                self.cols.index.lines.splice(i.pos, 1, [None::<u32>]);
                 self.cols.index.top.splice(i.pos, 1, [false]);
             }
         }
     }
 
     pub(crate) fn add_succ_with_undo(&mut self, op_pos: &[SuccInsert]) -> Vec<SuccUndo> {
+        self.invalidate_mark_schema_at(op_pos.iter().map(|i| i.pos));
         let mut undo = vec![];
         let mut succ_inc = 0;
         let mut last_pos = None;
@@ -311,6 +359,7 @@ impl OpSet {
                 undo.push(SuccUndo::new(*i, visible, text, top));
                 self.cols.index.visible.splice(i.pos, 1, [false]);
                 self.cols.index.text.splice(i.pos, 1, [None::<u32>]);
+                self.cols.index.lines.splice(i.pos, 1, [None::<u32>]);
                 self.cols.index.top.splice(i.pos, 1, [false]);
             } else if delete && !expose {
                 expose = true;
@@ -325,6 +374,7 @@ impl OpSet {
     }
 
     pub(crate) fn undo_succ(&mut self, op_pos: &[SuccUndo]) {
+        self.invalidate_mark_schema_at(op_pos.iter().map(|undo| undo.succ.pos));
         for undo in op_pos.iter().rev() {
             let i = undo.succ;
             self.cols.succ_count.splice(i.pos, 1, [i.len as u32]);
@@ -342,7 +392,12 @@ impl OpSet {
                 self.cols.index.visible.splice(i.pos, 1, [vis]);
             }
             if let Some(text) = undo.text {
+                // an op has a line count exactly where it has a width
+                let lines = text
+                    .and_then(|_| self.get(i.pos))
+                    .map(|op| op.newlines() as u32);
                 self.cols.index.text.splice(i.pos, 1, [text]);
+                self.cols.index.lines.splice(i.pos, 1, [lines]);
             }
             if let Some(top) = undo.top {
                 self.cols.index.top.splice(i.pos, 1, [top]);
@@ -895,6 +950,65 @@ impl OpSet {
         None
     }
 
+    /// Like `seek_list_opid` for many op IDs at once
+    ///
+    /// Looking an op up at a clock means walking the object up to the op, so rather than one walk
+    /// per op this makes one walk for all of them. Without a clock each op is found through the
+    /// op ID and prefix indexes in logarithmic time, so there's nothing to share between them.
+    ///
+    /// Unlike `seek_list_opid`, an op which was deleted with everything after it is found, at the
+    /// end of the sequence.
+    pub(crate) fn seek_list_opids(
+        &self,
+        obj: &ObjId,
+        opids: &[OpId],
+        seq_type: SequenceType,
+        clock: Option<&Clock>,
+    ) -> Vec<Option<FoundOpId<'_>>> {
+        if clock.is_none() {
+            return opids
+                .iter()
+                .map(|opid| self.seek_list_opid_fast(obj, *opid, seq_type))
+                .collect();
+        }
+        let obj_range = self.scope_to_obj(obj);
+        let mut targets = opids
+            .iter()
+            .enumerate()
+            .filter_map(|(i, opid)| {
+                let pos = self.get_op_id_pos(*opid)?;
+                obj_range.contains(&pos).then_some((pos, i))
+            })
+            .collect::<Vec<_>>();
+        targets.sort_unstable();
+
+        let mut found = vec![None; opids.len()];
+        let mut targets = targets.into_iter().peekable();
+        let mut index = 0;
+        for ops in OpsFoundIter::new(self.iter_obj(obj).no_marks(), clock.cloned()) {
+            while let Some((pos, i)) = targets.next_if(|(pos, _)| ops.end_pos > *pos) {
+                let Some(op) = self.get(pos) else {
+                    continue;
+                };
+                let visible = ops.ops.contains(&op);
+                found[i] = Some(FoundOpId { op, index, visible });
+            }
+            if targets.peek().is_none() {
+                break;
+            }
+            index += ops.width(seq_type, self.text_encoding);
+        }
+        // the ops left were deleted and nothing after them is visible
+        for (pos, i) in targets {
+            found[i] = self.get(pos).map(|op| FoundOpId {
+                op,
+                index,
+                visible: false,
+            });
+        }
+        found
+    }
+
     pub(crate) fn action_iter_range(&self, range: &Range<usize>) -> ActionIter<'_> {
         ActionIter::new(self.cols.action.iter_range(range.clone()))
     }
@@ -958,7 +1072,14 @@ impl OpSet {
         SkipIter::new(iter, top)
     }
 
-    pub(crate) fn calculate_marks_fast(&self, obj: &ObjId) -> Vec<crate::marks::Mark> {
+    /// The current marks of the text object `obj` whose names satisfy `keep`
+    ///
+    /// Marks with other names are skipped before they're accumulated.
+    pub(crate) fn calculate_marks_fast<F: Fn(&str) -> bool>(
+        &self,
+        obj: &ObjId,
+        keep: F,
+    ) -> Vec<crate::marks::Mark> {
         use super::op_set::mark_index::MarkIdx;
         use crate::marks::MarkAccumulator;
 
@@ -1001,7 +1122,9 @@ impl OpSet {
                 match idx {
                     MarkIdx::Start(id) => {
                         if let Some(data) = self.cols.index.mark.mark_data(&id) {
-                            state.map.insert(id, data.clone());
+                            if keep(&data.name) {
+                                state.map.insert(id, data.clone());
+                            }
                         }
                     }
                     MarkIdx::End(id) => {
@@ -1036,6 +1159,95 @@ impl OpSet {
             .collect()
     }
 
+    /// The number of lines in the text object `obj`, one more than the number of `\n`s in it
+    pub(crate) fn line_count(&self, obj: &ObjId) -> usize {
+        let range = self.scope_to_obj(obj);
+        self.cols.index.lines.sum_range(range) as usize + 1
+    }
+
+    /// The offset `line` of the text object `obj` starts at
+    pub(crate) fn line_offset(&self, obj: &ObjId, line: usize) -> Option<usize> {
+        self.line_start(obj, line).map(|start| start.offset)
+    }
+
+    /// The line of the text object `obj` which `offset` is on, `None` if `offset` is inside a
+    /// character
+    ///
+    /// `offset` must not be past the end of the text.
+    pub(crate) fn line_at_offset(&self, obj: &ObjId, offset: usize) -> Option<usize> {
+        let range = self.scope_to_obj(obj);
+        let mut text_iter = self.cols.index.text.iter_range(range.clone());
+        let Some(seek) = text_iter.advance_prefix(offset as u64) else {
+            // the end of the text is on the last line
+            return Some(self.cols.index.lines.sum_range(range) as usize);
+        };
+        let before = self.cols.index.lines.sum_range(range.start..seek.pos) as usize;
+        let op = self.get(seek.pos)?;
+        let units = offset - seek.delta as usize;
+        newlines_before(op.as_str(), units, self.text_encoding).map(|n| before + n)
+    }
+
+    /// The text of `count` lines of the text object `obj` from `line` on, without the `\n`s
+    /// which end them
+    pub(crate) fn lines(&self, obj: &ObjId, line: usize, count: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let Some(start) = self.line_start(obj, line).filter(|_| count > 0) else {
+            return lines;
+        };
+        let range = self.scope_to_obj(obj);
+        let mut current = String::new();
+        let mut skip = start.byte;
+        for (action, value, _) in self.action_value_top_iter(start.pos..range.end, None) {
+            let text = match (action, &value) {
+                (Action::Set, ScalarValue::Str(s)) => s.as_ref(),
+                (Action::Mark, _) => "",
+                (_, _) => "\u{fffc}",
+            };
+            // the line may start part way through the first op
+            let text = &text[std::mem::take(&mut skip)..];
+            for piece in text.split_inclusive('\n') {
+                match piece.strip_suffix('\n') {
+                    Some(piece) => {
+                        current.push_str(piece);
+                        lines.push(std::mem::take(&mut current));
+                        if lines.len() == count {
+                            return lines;
+                        }
+                    }
+                    None => current.push_str(piece),
+                }
+            }
+        }
+        // the last line has no `\n`
+        lines.push(current);
+        lines
+    }
+
+    /// Where `line` of the text object `obj` starts
+    fn line_start(&self, obj: &ObjId, line: usize) -> Option<LineStart> {
+        let range = self.scope_to_obj(obj);
+        let Some(before) = line.checked_sub(1) else {
+            return Some(LineStart {
+                pos: range.start,
+                byte: 0,
+                offset: 0,
+            });
+        };
+        // find the op holding the `\n` which ends the line before
+        let mut lines_iter = self.cols.index.lines.iter_range(range.clone());
+        let seek = lines_iter.advance_prefix(before as u64)?;
+        let op = self.get(seek.pos)?;
+        let text = op.as_str();
+        let (newline, _) = text.match_indices('\n').nth(before - seek.delta as usize)?;
+        let byte = newline + 1;
+        let width = self.cols.index.text.sum_range(range.start..seek.pos) as usize;
+        Some(LineStart {
+            pos: seek.pos,
+            byte,
+            offset: width + self.text_encoding.width(&text[..byte]),
+        })
+    }
+
     pub(crate) fn id_to_exid(&self, id: OpId) -> ExId {
         if id == types::ROOT {
             ExId::Root
@@ -1179,6 +1391,7 @@ impl OpSet {
             cols: Columns::default(),
             obj_info: ObjIndex::default(),
             text_encoding,
+            mark_schema: MarkSchemaCache::default(),
         }
     }
 
@@ -1203,6 +1416,7 @@ impl OpSet {
             cols,
             obj_info: ObjIndex::default(),
             text_encoding: TextEncoding::platform_default(),
+            mark_schema: MarkSchemaCache::default(),
         }
     }
 
@@ -1219,6 +1433,7 @@ impl OpSet {
             cols,
             obj_info: ObjIndex::default(),
             text_encoding,
+            mark_schema: MarkSchemaCache::default(),
         };
 
         Ok(op_set)
@@ -1390,6 +1605,7 @@ impl OpSet {
     }
 
     pub(crate) fn rewrite_with_new_actor(&mut self, idx: usize) {
+        self.mark_schema.clear();
         self.cols.rewrite_with_new_actor(idx);
         self.cols.index.mark.rewrite_with_new_actor(idx);
         self.obj_info = ObjIndex(
@@ -1402,6 +1618,7 @@ impl OpSet {
     }
 
     pub(crate) fn remove_actor(&mut self, idx: usize) {
+        self.mark_schema.clear();
         self.actors.remove(idx);
         self.cols.rewrite_without_actor(idx);
         self.obj_info = ObjIndex(
@@ -1569,6 +1786,17 @@ impl Iterator for IterObjIds<'_> {
     }
 }
 
+/// Where a line of a text object starts
+#[derive(Debug, Clone, Copy)]
+struct LineStart {
+    /// The position of the op the line starts in
+    pos: usize,
+    /// How far into the text of that op the line starts, in bytes
+    byte: usize,
+    /// The offset of the start of the line in the text
+    offset: usize,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) struct SuccUndo {
     pub(crate) succ: SuccInsert,
@@ -11,6 +11,7 @@ pub(crate) struct IndexBuilder {
     succ: Vec<u32>,
     top: Vec<bool>,
     widths: Vec<u64>,
+    newlines: Vec<u32>,
     incs: Vec<Option<i64>>,
     marks: Vec<Option<MarkIndexBuilder>>,
     obj_info: ObjIndex,
@@ -149,6 +150,7 @@ impl OpBuilder<'_> {
 
 pub(crate) struct Indexes {
     pub(crate) text: hexane::PrefixColumn<Option<u32>>,
+    pub(crate) lines: hexane::PrefixColumn<Option<u32>>,
     pub(crate) top: hexane::PrefixColumn<bool>,
     pub(crate) visible: hexane::Column<bool>,
     pub(crate) inc: hexane::Column<Option<i64>>,
@@ -163,6 +165,7 @@ impl IndexBuilder {
             succ: Vec::with_capacity(op_set.len()),
             top: Vec::with_capacity(op_set.len()),
             widths: Vec::with_capacity(op_set.len()),
+            newlines: Vec::with_capacity(op_set.len()),
             incs: Vec::with_capacity(op_set.sub_len()),
             marks: Vec::with_capacity(op_set.len()),
             obj_info: ObjIndex::default(),
@@ -192,6 +195,7 @@ impl IndexBuilder {
 
         self.widths
             .push(op.width(SequenceType::Text, self.text_encoding) as u64);
+        self.newlines.push(op.newlines() as u32);
 
         let count = self.counters.remove(&op.id);
 
@@ -227,6 +231,13 @@ impl IndexBuilder {
             .map(|(w, t)| if *t { Some(*w as u32) } else { None })
             .collect();
 
+        let lines = self
+            .newlines
+            .iter()
+            .zip(self.top.iter())
+            .map(|(n, t)| t.then_some(*n))
+            .collect();
+
         let visible: Vec<bool> = self.succ.iter().map(|&n| n == 0).collect();
         let visible = hexane::Column::from_values(visible);
 
@@ -243,6 +254,7 @@ impl IndexBuilder {
         (
             Indexes {
                 text,
+                lines,
                 top,
                 visible,
                 inc,
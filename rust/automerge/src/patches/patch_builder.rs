@@ -3,8 +3,8 @@ use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 
 use crate::exid::ExId;
-use crate::iter::SpanInternal;
-use crate::marks::MarkSet;
+use crate::iter::{Span, SpanInternal, Spans};
+use crate::marks::{MarkSchema, MarkSet, MARK_SCHEMA_KEY};
 use crate::text_value::ConcreteTextValue;
 use crate::types::{Clock, ObjId, ObjType};
 use crate::{Automerge, Prop, TextEncoding, Value};
@@ -20,6 +20,8 @@ pub(crate) struct PatchBuilder<'a> {
     seen: HashSet<ObjId>,
     text_encoding: TextEncoding,
     clock: Option<Clock>,
+    /// Marks are reported as they apply under the document's schema
+    schema: Option<Arc<MarkSchema>>,
     doc: &'a Automerge,
 }
 
@@ -33,6 +35,7 @@ impl<'a> PatchBuilder<'a> {
         // If we are expecting a lot of patches then precompute all the visible
         // paths up front to avoid doing many seek operations in the `Parents`
         // iterator in `Self::get_path`
+        let schema = doc.mark_schema_for(clock.as_ref());
         Self {
             patches: Vec::new(),
             last_mark_set: None,
@@ -40,6 +43,7 @@ impl<'a> PatchBuilder<'a> {
             seen: HashSet::new(),
             doc,
             clock,
+            schema,
             text_encoding,
         }
     }
@@ -165,7 +169,45 @@ impl PatchBuilder<'_> {
     }
 
     pub(crate) fn take_patches(&mut self) -> Vec<Patch> {
-        std::mem::take(&mut self.patches)
+        let mut patches = std::mem::take(&mut self.patches);
+        if let Some(schema) = self.schema.clone() {
+            for patch in &mut patches {
+                if let PatchAction::Mark { marks } = &mut patch.action {
+                    let stored = std::mem::take(marks);
+                    *marks = schema
+                        .resolve_mark_patch(stored, |range| self.stored_marks(&patch.obj, range));
+                }
+            }
+        }
+        patches
+    }
+
+    /// The runs of text in `range` of the text object `obj` with their marks as stored
+    fn stored_marks(
+        &self,
+        obj: &ExId,
+        range: std::ops::Range<usize>,
+    ) -> Vec<(std::ops::Range<usize>, Option<Arc<MarkSet>>)> {
+        let Ok(obj) = self.doc.exid_to_obj(obj) else {
+            return Vec::new();
+        };
+        let mut runs = Vec::new();
+        let mut index = 0;
+        for span in Spans::new(self.doc.ops.spans(&obj.id, self.clock.clone())) {
+            let (width, marks) = match span {
+                Span::Text { text, marks } => (self.text_encoding.width(&text), marks),
+                Span::Block(_) => (1, None),
+            };
+            let run = index..index + width;
+            index = run.end;
+            if run.start >= range.end {
+                break;
+            }
+            if run.end > range.start {
+                runs.push((run.start.max(range.start)..run.end.min(range.end), marks));
+            }
+        }
+        runs
     }
 
     pub(crate) fn insert(
@@ -197,7 +239,21 @@ impl PatchBuilder<'_> {
     }
 
     fn push(&mut self, patch: Patch) {
-        self.patches.push(patch);
+        // The mark schema is stored in the document but isn't part of its content
+        let schema_prop = Prop::Map(MARK_SCHEMA_KEY.to_string());
+        let in_schema = match (patch.path.first(), &patch.action) {
+            (Some((_, prop)), _) => *prop == schema_prop,
+            (None, PatchAction::PutMap { key, .. } | PatchAction::DeleteMap { key }) => {
+                key == MARK_SCHEMA_KEY
+            }
+            (None, PatchAction::Increment { prop, .. } | PatchAction::Conflict { prop }) => {
+                *prop == schema_prop
+            }
+            (None, _) => false,
+        };
+        if !in_schema {
+            self.patches.push(patch);
+        }
         self.last_mark_set = None;
     }
 
@@ -208,6 +264,13 @@ impl PatchBuilder<'_> {
         value: &str,
         marks: Option<Arc<MarkSet>>,
     ) {
+        let marks = match &self.schema {
+            Some(schema) => marks
+                .map(|marks| schema.resolve(&marks))
+                .filter(|marks| !marks.is_empty())
+                .map(Arc::new),
+            None => marks,
+        };
         if let Some(PatchAction::SpliceText {
             index: tail_index,
             value: prev_value,
@@ -3,14 +3,15 @@ use crate::{
     error::AutomergeError,
     exid::ExId,
     hydrate,
-    marks::{Mark, MarkSet},
+    line_index::LineIndex,
+    marks::{Mark, MarkSchema, MarkSet},
     op_set2::Parents,
-    Change, ChangeHash, Cursor, ObjType, Prop, TextEncoding, Value, ROOT,
+    Change, ChangeHash, Cursor, CursorRange, ObjType, Prop, TextEncoding, Value, ROOT,
 };
 
 use crate::iter::{DocIter, Keys, ListRange, MapRange, Spans, Values};
 
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
 
 /// Methods for reading values from an automerge document
 ///
@@ -168,6 +169,22 @@ pub trait ReadDoc {
         heads: Option<&[ChangeHash]>,
     ) -> Result<MarkSet, AutomergeError>;
 
+    /// Get the instances of the multi-instance mark `kind` in the sequence `obj`, see
+    /// [`Mark::instance()`]
+    ///
+    /// Marks of other kinds are skipped while the marks are gathered, unless the document
+    /// defines an exclusive mark, which can hide instances where it overlaps them.
+    fn mark_instances<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        kind: &str,
+    ) -> Result<Vec<Mark>, AutomergeError>;
+
+    /// Get the mark definitions the document declares, as at `heads` if given
+    ///
+    /// See [`MarkSchema`] for how the definitions affect marks.
+    fn mark_schema(&self, heads: Option<&[ChangeHash]>) -> Result<MarkSchema, AutomergeError>;
+
     /// Get the string represented by the given text object.
     fn text<O: AsRef<ExId>>(&self, obj: O) -> Result<String, AutomergeError>;
 
@@ -189,6 +206,16 @@ pub trait ReadDoc {
         heads: &[ChangeHash],
     ) -> Result<Spans<'_>, AutomergeError>;
 
+    /// Index the lines of the text object `obj`, see [`crate::line_index`]
+    fn line_index<O: AsRef<ExId>>(&self, obj: O) -> Result<LineIndex<'_>, AutomergeError>;
+
+    /// Index the lines of the text object `obj` as it was at `heads`, see [`crate::line_index`]
+    fn line_index_at<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        heads: &[ChangeHash],
+    ) -> Result<LineIndex<'_>, AutomergeError>;
+
     /// Obtain the stable address (Cursor) for a [`usize`] position in a Sequence (either [`ObjType::List`] or [`ObjType::Text`]).
     ///
     /// **This is equivalent to [`Self::get_cursor_moving()`] with `move_cursor` = `MoveCursor::After`.**
@@ -237,6 +264,121 @@ pub trait ReadDoc {
         at: Option<&[ChangeHash]>,
     ) -> Result<usize, AutomergeError>;
 
+    /// Translate many cursors in a Sequence into positions at once.
+    ///
+    /// Returns the same positions as calling [`Self::get_cursor_position()`] for each cursor in
+    /// turn, but looks the cursors up together, which is much faster when there are many of
+    /// them. Fails if any of the cursors is invalid.
+    fn get_cursor_positions<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        cursors: &[Cursor],
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Vec<usize>, AutomergeError>;
+
+    /// Obtain a [`CursorRange`] which follows `range` in a Sequence as it's edited.
+    ///
+    /// **This is equivalent to [`Self::get_cursor_range_moving()`] with `start_move` =
+    /// `MoveCursor::After` and `end_move` = `MoveCursor::Before`**, so the range shrinks to what's
+    /// left of it when its ends are deleted.
+    fn get_cursor_range<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        range: Range<usize>,
+        at: Option<&[ChangeHash]>,
+    ) -> Result<CursorRange, AutomergeError> {
+        self.get_cursor_range_moving(obj, range, at, MoveCursor::After, MoveCursor::Before)
+    }
+
+    /// Obtain a [`CursorRange`] which follows `range` in a Sequence as it's edited, with the given
+    /// [`MoveCursor`] policies for its start and end.
+    ///
+    /// To translate the range back into positions, see [`Self::get_cursor_range_position()`].
+    fn get_cursor_range_moving<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        range: Range<usize>,
+        at: Option<&[ChangeHash]>,
+        start_move: MoveCursor,
+        end_move: MoveCursor,
+    ) -> Result<CursorRange, AutomergeError> {
+        let obj = obj.as_ref();
+        if range.start > range.end {
+            return Err(AutomergeError::InvalidIndex(range.start));
+        }
+        let length = match at {
+            Some(heads) => self.length_at(obj, heads),
+            None => self.length(obj),
+        };
+        let start = if range.start == length {
+            Cursor::End
+        } else {
+            self.get_cursor_moving(obj, range.start, at, start_move)?
+        };
+        if range.is_empty() {
+            return Ok(CursorRange::empty(start));
+        }
+        let end = self.get_cursor_moving(obj, range.end - 1, at, end_move)?;
+        Ok(CursorRange::new(start, end))
+    }
+
+    /// Translate a [`CursorRange`] in a Sequence into the range of positions it covers.
+    ///
+    /// To reverse the operation, see [`Self::get_cursor_range()`].
+    fn get_cursor_range_position<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        range: &CursorRange,
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Range<usize>, AutomergeError>;
+
+    /// Translate many [`CursorRange`]s in a Sequence into ranges of positions at once.
+    ///
+    /// See [`Self::get_cursor_positions()`].
+    fn get_cursor_range_positions<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        ranges: &[CursorRange],
+        at: Option<&[ChangeHash]>,
+    ) -> Result<Vec<Range<usize>>, AutomergeError>;
+
+    /// Map a position in a Sequence as it was at `from_heads` to the same position at `to_heads`.
+    ///
+    /// This is for positions which were computed without a [`Cursor`], such as an index a peer
+    /// sent from an older version of the document. `index` is a position between elements, so
+    /// `bias` decides which of its neighbours it sticks to when the two are pulled apart:
+    ///
+    /// * With [`MoveCursor::After`] the position stays just before the element after it, so
+    ///   anything inserted at the position ends up before it. If that element is deleted the
+    ///   position moves to the next element, as a cursor does.
+    /// * With [`MoveCursor::Before`] the position stays just after the element before it, so
+    ///   anything inserted at the position ends up after it.
+    ///
+    /// `from_heads` and `to_heads` don't need to be ancestors of each other.
+    fn transform_index<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        index: usize,
+        from_heads: &[ChangeHash],
+        to_heads: &[ChangeHash],
+        bias: MoveCursor,
+    ) -> Result<usize, AutomergeError> {
+        let transformed = self.transform_indices(obj, &[index], from_heads, to_heads, bias)?;
+        Ok(transformed[0])
+    }
+
+    /// Map many positions in a Sequence from `from_heads` to `to_heads` at once.
+    ///
+    /// See [`Self::transform_index()`].
+    fn transform_indices<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        indices: &[usize],
+        from_heads: &[ChangeHash],
+        to_heads: &[ChangeHash],
+        bias: MoveCursor,
+    ) -> Result<Vec<usize>, AutomergeError>;
+
     /// Get a value out of the document.
     ///
     /// This returns a tuple of `(value, object ID)`. This is for two reasons:
@@ -0,0 +1,732 @@
+//! Rendering rich text as HTML and Markdown, and parsing it back
+//!
+//! The text, marks and block markers returned by [`ReadDoc::spans()`] are rendered according to
+//! a [`RichTextConfig`], which maps the names of marks and the `type` of blocks to a
+//! [`MarkStyle`] or a [`BlockStyle`]. Marks which aren't in the config are ignored, and blocks
+//! whose type isn't in the config are rendered as paragraphs.
+//!
+//! Block markers are expected to be maps with a `type` string, a `parents` list of the types of
+//! the blocks they are nested in (outermost first), and an `attrs` map. Consecutive blocks with
+//! the same parents are rendered inside the same parent elements, and a block whose parents end
+//! with the type of the block before it is rendered inside that block. Consecutive list items
+//! share a list element.
+//!
+//! Marks always nest in the order they were added to the config, with the first outermost. Where
+//! marks overlap without nesting, the inner marks are closed and reopened so the output is well
+//! formed.
+//!
+//! `markdown_to_spans()` and [`html_to_spans()`] go the other way, producing spans in the same
+//! block structure, and `update_from_markdown()` and [`update_from_html()`] write them to a text
+//! object with [`Transactable::update_spans()`]. Only the parts of the text which changed are
+//! rewritten, so cursors in the rest of the text stay where they were. Only a safe subset of HTML
+//! is understood: scripts, styles and comments are dropped, unknown elements are replaced by their
+//! contents and links to URLs other than relative, `http`, `https` and `mailto` ones lose their
+//! link. The Markdown functions need the `markdown` feature, which pulls in `pulldown-cmark`.
+//!
+//! ## Example
+//!
+//! ```
+//! # use automerge::{rich_text::{self, RichTextConfig}, transaction::Transactable, AutoCommit, ObjType, ROOT};
+//! # use automerge::marks::{ExpandMark, Mark};
+//! let mut doc = AutoCommit::new();
+//! let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+//! doc.splice_text(&text, 0, 0, "hello world").unwrap();
+//! doc.mark(&text, Mark::new("bold".to_string(), true, 0, 5), ExpandMark::None).unwrap();
+//!
+//! let config = RichTextConfig::default();
+//! assert_eq!(rich_text::html(&doc, &text, &config).unwrap(), "<strong>hello</strong> world");
+//! assert_eq!(rich_text::markdown(&doc, &text, &config).unwrap(), "**hello** world\n");
+//! ```
+use std::collections::HashMap;
+
+use crate::exid::ExId;
+use crate::hydrate;
+use crate::iter::Span;
+use crate::marks::{MarkSet, UpdateSpansConfig};
+use crate::transaction::Transactable;
+use crate::{AutomergeError, ChangeHash, ReadDoc, ScalarValue};
+
+mod builder;
+mod html;
+mod markdown;
+
+/// How a mark is rendered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkStyle {
+    /// `<strong>` in HTML, `**` in Markdown
+    Bold,
+    /// `<em>` in HTML, `*` in Markdown
+    Italic,
+    /// `<u>` in HTML, not rendered in Markdown
+    Underline,
+    /// `<s>` in HTML, `~~` in Markdown
+    Strikethrough,
+    /// `<code>` in HTML, a code span in Markdown
+    Code,
+    /// A link to the value of the mark. Only relative, `http`, `https` and `mailto` URLs are
+    /// rendered as links.
+    Link,
+    /// An HTML element, with the value of the mark in `attribute` if there is one. Not rendered
+    /// in Markdown.
+    Element {
+        tag: String,
+        attribute: Option<String>,
+    },
+}
+
+/// How a block is rendered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockStyle {
+    Paragraph,
+    /// A heading whose level is the `level` attribute of the block, 1 if there isn't one
+    Heading,
+    /// An item in a numbered list
+    OrderedListItem,
+    /// An item in a bulleted list
+    UnorderedListItem,
+    Blockquote,
+    /// Preformatted text in the language given by the `language` attribute of the block, if
+    /// there is one. Marks aren't rendered in code blocks in Markdown.
+    CodeBlock,
+    /// An HTML element, rendered as a paragraph in Markdown
+    Element {
+        tag: String,
+    },
+}
+
+impl BlockStyle {
+    fn is_list_item(&self) -> bool {
+        matches!(self, Self::OrderedListItem | Self::UnorderedListItem)
+    }
+}
+
+/// How to render each mark and block type, see the [module documentation](self)
+///
+/// The default config renders the marks `link`, `comment` (as a `<span data-comment>`), `bold`,
+/// `italic`, `underline`, `strikethrough` and `code`, nested in that order, and the block types
+/// `paragraph`, `heading`, `ordered-list-item`, `unordered-list-item`, `blockquote` and
+/// `code-block`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RichTextConfig {
+    /// In nesting order, outermost first
+    marks: Vec<(String, MarkStyle)>,
+    blocks: HashMap<String, BlockStyle>,
+}
+
+impl Default for RichTextConfig {
+    fn default() -> Self {
+        Self::empty()
+            .with_mark("link", MarkStyle::Link)
+            .with_mark(
+                "comment",
+                MarkStyle::Element {
+                    tag: "span".to_string(),
+                    attribute: Some("data-comment".to_string()),
+                },
+            )
+            .with_mark("bold", MarkStyle::Bold)
+            .with_mark("italic", MarkStyle::Italic)
+            .with_mark("underline", MarkStyle::Underline)
+            .with_mark("strikethrough", MarkStyle::Strikethrough)
+            .with_mark("code", MarkStyle::Code)
+            .with_block("paragraph", BlockStyle::Paragraph)
+            .with_block("heading", BlockStyle::Heading)
+            .with_block("ordered-list-item", BlockStyle::OrderedListItem)
+            .with_block("unordered-list-item", BlockStyle::UnorderedListItem)
+            .with_block("blockquote", BlockStyle::Blockquote)
+            .with_block("code-block", BlockStyle::CodeBlock)
+    }
+}
+
+impl RichTextConfig {
+    /// A config which doesn't render any marks and renders every block as a paragraph
+    pub fn empty() -> Self {
+        Self {
+            marks: Vec::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Render the mark called `name` with `style`
+    ///
+    /// A mark which is already in the config keeps its place in the nesting order, otherwise
+    /// it's nested inside all the marks already in the config.
+    pub fn with_mark<S: Into<String>>(mut self, name: S, style: MarkStyle) -> Self {
+        let name = name.into();
+        match self.marks.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, s)) => *s = style,
+            None => self.marks.push((name, style)),
+        }
+        self
+    }
+
+    /// Render blocks whose `type` is `name` with `style`
+    pub fn with_block<S: Into<String>>(mut self, name: S, style: BlockStyle) -> Self {
+        self.blocks.insert(name.into(), style);
+        self
+    }
+
+    pub fn mark_style(&self, name: &str) -> Option<&MarkStyle> {
+        self.marks.iter().find(|(n, _)| n == name).map(|(_, s)| s)
+    }
+
+    pub fn block_style(&self, block_type: &str) -> Option<&BlockStyle> {
+        self.blocks.get(block_type)
+    }
+
+    /// The name of the first mark rendered with `style`
+    fn mark_name_for(&self, style: &MarkStyle) -> Option<&str> {
+        self.marks
+            .iter()
+            .find(|(_, s)| s == style)
+            .map(|(n, _)| n.as_str())
+    }
+
+    /// The style of the first mark rendered as the HTML element `tag`
+    fn element_mark(&self, tag: &str) -> Option<&MarkStyle> {
+        self.marks
+            .iter()
+            .map(|(_, s)| s)
+            .find(|s| matches!(s, MarkStyle::Element { tag: t, .. } if t == tag))
+    }
+
+    /// Whether a block is rendered as the HTML element `tag`
+    fn is_element_block(&self, tag: &str) -> bool {
+        self.blocks
+            .values()
+            .any(|s| matches!(s, BlockStyle::Element { tag: t } if t == tag))
+    }
+
+    /// The type of the blocks rendered with `style`, the first alphabetically if there are
+    /// several, or the type the default config uses if there are none
+    fn block_type_for(&self, style: &BlockStyle) -> String {
+        let configured = self
+            .blocks
+            .iter()
+            .filter(|(_, s)| *s == style)
+            .map(|(n, _)| n)
+            .min();
+        if let Some(name) = configured {
+            return name.clone();
+        }
+        match style {
+            BlockStyle::Paragraph => "paragraph",
+            BlockStyle::Heading => "heading",
+            BlockStyle::OrderedListItem => "ordered-list-item",
+            BlockStyle::UnorderedListItem => "unordered-list-item",
+            BlockStyle::Blockquote => "blockquote",
+            BlockStyle::CodeBlock => "code-block",
+            BlockStyle::Element { tag } => tag,
+        }
+        .to_string()
+    }
+
+    fn block_style_or_default(&self, block_type: &str) -> &BlockStyle {
+        self.blocks
+            .get(block_type)
+            .unwrap_or(&BlockStyle::Paragraph)
+    }
+
+    /// The marks in `marks` which are rendered, in nesting order
+    fn active_marks(&self, marks: Option<&MarkSet>) -> Vec<ActiveMark<'_>> {
+        let Some(marks) = marks else {
+            return Vec::new();
+        };
+        let mut active = marks
+            .iter()
+            .filter(|(_, value)| !matches!(value, ScalarValue::Null | ScalarValue::Boolean(false)))
+            .filter_map(|(name, value)| {
+                let index = self.marks.iter().position(|(n, _)| n == name)?;
+                Some(ActiveMark {
+                    index,
+                    style: &self.marks[index].1,
+                    value: value_string(value),
+                })
+            })
+            .filter(|mark| {
+                !matches!(mark.style, MarkStyle::Link) || builder::is_safe_url(&mark.value)
+            })
+            .collect::<Vec<_>>();
+        active.sort_by_key(|m| m.index);
+        active
+    }
+}
+
+/// Render the text object `obj` as HTML
+pub fn html<R: ReadDoc, O: AsRef<ExId>>(
+    doc: &R,
+    obj: O,
+    config: &RichTextConfig,
+) -> Result<String, AutomergeError> {
+    Ok(spans_to_html(doc.spans(obj)?, config))
+}
+
+/// Render the text object `obj` as at `heads` as HTML
+pub fn html_at<R: ReadDoc, O: AsRef<ExId>>(
+    doc: &R,
+    obj: O,
+    heads: &[ChangeHash],
+    config: &RichTextConfig,
+) -> Result<String, AutomergeError> {
+    Ok(spans_to_html(doc.spans_at(obj, heads)?, config))
+}
+
+/// Render the text object `obj` as CommonMark
+pub fn markdown<R: ReadDoc, O: AsRef<ExId>>(
+    doc: &R,
+    obj: O,
+    config: &RichTextConfig,
+) -> Result<String, AutomergeError> {
+    Ok(spans_to_markdown(doc.spans(obj)?, config))
+}
+
+/// Render the text object `obj` as at `heads` as CommonMark
+pub fn markdown_at<R: ReadDoc, O: AsRef<ExId>>(
+    doc: &R,
+    obj: O,
+    heads: &[ChangeHash],
+    config: &RichTextConfig,
+) -> Result<String, AutomergeError> {
+    Ok(spans_to_markdown(doc.spans_at(obj, heads)?, config))
+}
+
+/// Render the output of [`ReadDoc::spans()`] as HTML
+pub fn spans_to_html<I: IntoIterator<Item = Span>>(spans: I, config: &RichTextConfig) -> String {
+    html::render(spans, config)
+}
+
+/// Render the output of [`ReadDoc::spans()`] as CommonMark
+///
+/// Strikethrough is rendered with the `~~` extension from GitHub Flavored Markdown.
+pub fn spans_to_markdown<I: IntoIterator<Item = Span>>(
+    spans: I,
+    config: &RichTextConfig,
+) -> String {
+    markdown::render(spans, config)
+}
+
+/// Parse CommonMark into spans which can be passed to [`Transactable::update_spans()`]
+///
+/// Strikethrough is parsed with the `~~` extension from GitHub Flavored Markdown. Marks and
+/// blocks which aren't in `config` are replaced by their text.
+///
+/// Only available with the `markdown` feature.
+#[cfg(feature = "markdown")]
+pub fn markdown_to_spans(markdown: &str, config: &RichTextConfig) -> Vec<Span> {
+    markdown::parse(markdown, config)
+}
+
+/// Parse a safe subset of HTML into spans which can be passed to
+/// [`Transactable::update_spans()`]
+///
+/// Elements which aren't in `config` are replaced by their contents.
+pub fn html_to_spans(html: &str, config: &RichTextConfig) -> Vec<Span> {
+    html::parse(html, config)
+}
+
+/// Update the text object `obj` to match `markdown`
+///
+/// Only available with the `markdown` feature.
+#[cfg(feature = "markdown")]
+pub fn update_from_markdown<T: Transactable, O: AsRef<ExId>>(
+    tx: &mut T,
+    obj: O,
+    markdown: &str,
+    config: &RichTextConfig,
+) -> Result<(), AutomergeError> {
+    tx.update_spans(
+        obj,
+        UpdateSpansConfig::default(),
+        markdown_to_spans(markdown, config),
+    )
+}
+
+/// Update the text object `obj` to match `html`
+pub fn update_from_html<T: Transactable, O: AsRef<ExId>>(
+    tx: &mut T,
+    obj: O,
+    html: &str,
+    config: &RichTextConfig,
+) -> Result<(), AutomergeError> {
+    tx.update_spans(
+        obj,
+        UpdateSpansConfig::default(),
+        html_to_spans(html, config),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ActiveMark<'a> {
+    /// The position of the mark in the nesting order
+    index: usize,
+    style: &'a MarkStyle,
+    value: String,
+}
+
+/// The parts of a block marker which affect how it's rendered
+#[derive(Debug, Clone, Default)]
+struct Block {
+    block_type: String,
+    parents: Vec<String>,
+    attrs: HashMap<String, ScalarValue>,
+}
+
+impl Block {
+    fn from_map(map: &hydrate::Map) -> Self {
+        let block_type = match map.get("type") {
+            Some(hydrate::Value::Scalar(ScalarValue::Str(s))) => s.to_string(),
+            _ => String::new(),
+        };
+        let parents = match map.get("parents") {
+            Some(hydrate::Value::List(parents)) => parents
+                .iter()
+                .filter_map(|p| match &p.value {
+                    hydrate::Value::Scalar(ScalarValue::Str(s)) => Some(s.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let attrs = match map.get("attrs") {
+            Some(hydrate::Value::Map(attrs)) => attrs
+                .iter()
+                .filter_map(|(k, v)| match &v.value {
+                    hydrate::Value::Scalar(s) => Some((k.clone(), s.clone())),
+                    _ => None,
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        Self {
+            block_type,
+            parents,
+            attrs,
+        }
+    }
+
+    fn heading_level(&self) -> u8 {
+        let level = match self.attrs.get("level") {
+            Some(ScalarValue::Int(i)) => *i,
+            Some(ScalarValue::Uint(u)) => *u as i64,
+            Some(ScalarValue::F64(f)) => *f as i64,
+            Some(ScalarValue::Str(s)) => s.parse().unwrap_or(1),
+            _ => 1,
+        };
+        level.clamp(1, 6) as u8
+    }
+
+    fn language(&self) -> Option<&str> {
+        self.attrs
+            .get("language")
+            .and_then(|l| l.to_str())
+            .filter(|l| !l.is_empty())
+    }
+}
+
+fn value_string(value: &ScalarValue) -> String {
+    match value {
+        ScalarValue::Str(s) => s.to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn common_prefix<A: PartialEq<B>, B>(a: &[A], b: &[B]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| *a == *b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marks::{ExpandMark, Mark};
+    use crate::{hydrate_map, transaction::Transactable, AutoCommit, ObjType, TextEncoding, ROOT};
+
+    fn block(doc: &mut AutoCommit, text: &ExId, index: usize, ty: &str, parents: &[&str]) {
+        let block = doc.split_block(text, index).unwrap();
+        let parent_list = hydrate::List::from(
+            parents
+                .iter()
+                .map(|p| hydrate::Value::from(*p))
+                .collect::<Vec<_>>(),
+        );
+        doc.update_object(
+            &block,
+            &hydrate_map! {
+                "type" => ty,
+                "parents" => parent_list,
+                "attrs" => hydrate_map!{ "level" => 2 },
+            }
+            .into(),
+        )
+        .unwrap();
+    }
+
+    fn mark(doc: &mut AutoCommit, text: &ExId, name: &str, value: &str, range: (usize, usize)) {
+        doc.mark(
+            text,
+            Mark::new(name.to_string(), value, range.0, range.1),
+            ExpandMark::None,
+        )
+        .unwrap();
+    }
+
+    /// The indexes in these tests count code points, whatever the default encoding is
+    fn new_doc() -> AutoCommit {
+        AutoCommit::new_with_encoding(TextEncoding::UnicodeCodePoint)
+    }
+
+    fn document() -> (AutoCommit, ExId) {
+        let mut doc = new_doc();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "Titlehello worldonetwonestedquoted")
+            .unwrap();
+        // Insert blocks from the end so the indexes above stay valid
+        block(&mut doc, &text, 28, "paragraph", &["blockquote"]);
+        block(
+            &mut doc,
+            &text,
+            22,
+            "unordered-list-item",
+            &["unordered-list-item"],
+        );
+        block(&mut doc, &text, 19, "unordered-list-item", &[]);
+        block(&mut doc, &text, 16, "unordered-list-item", &[]);
+        block(&mut doc, &text, 5, "paragraph", &[]);
+        block(&mut doc, &text, 0, "heading", &[]);
+        // "Title" is 1..6, "hello world" is 7..18
+        mark(&mut doc, &text, "bold", "true", (7, 18));
+        mark(&mut doc, &text, "link", "https://x.org/?a&b", (13, 18));
+        (doc, text)
+    }
+
+    #[test]
+    fn render_html() {
+        let (doc, text) = document();
+        let html = html(&doc, &text, &RichTextConfig::default()).unwrap();
+        assert_eq!(
+            html,
+            "<h2>Title</h2>\
+             <p><strong>hello </strong><a href=\"https://x.org/?a&amp;b\"><strong>world</strong></a></p>\
+             <ul><li>one</li><li>two<ul><li>nested</li></ul></li></ul>\
+             <blockquote><p>quoted</p></blockquote>"
+        );
+    }
+
+    #[test]
+    fn render_markdown() {
+        let (doc, text) = document();
+        let markdown = markdown(&doc, &text, &RichTextConfig::default()).unwrap();
+        assert_eq!(
+            markdown,
+            "## Title\n\
+             \n\
+             **hello** [**world**](https://x.org/?a&b)\n\
+             \n\
+             - one\n\
+             - two\n\
+             \x20 - nested\n\
+             \n\
+             > quoted\n"
+        );
+    }
+
+    #[test]
+    fn render_at_heads() {
+        let (mut doc, text) = document();
+        let heads = doc.get_heads();
+        doc.splice_text(&text, 1, 5, "Heading").unwrap();
+        let config = RichTextConfig::default();
+        assert!(html(&doc, &text, &config)
+            .unwrap()
+            .starts_with("<h2>Heading</h2>"));
+        assert!(html_at(&doc, &text, &heads, &config)
+            .unwrap()
+            .starts_with("<h2>Title</h2>"));
+        assert!(markdown_at(&doc, &text, &heads, &config)
+            .unwrap()
+            .starts_with("## Title\n"));
+    }
+
+    #[test]
+    fn code_blocks_and_ordered_lists() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "firstlet *x* = 1;\nx")
+            .unwrap();
+        block(&mut doc, &text, 5, "code-block", &["ordered-list-item"]);
+        block(&mut doc, &text, 0, "ordered-list-item", &[]);
+        let config = RichTextConfig::default();
+        assert_eq!(
+            html(&doc, &text, &config).unwrap(),
+            "<ol><li>first<pre><code>let *x* = 1;\nx</code></pre></li></ol>"
+        );
+        assert_eq!(
+            markdown(&doc, &text, &config).unwrap(),
+            "1. first\n\n   ```\n   let *x* = 1;\n   x\n   ```\n"
+        );
+    }
+
+    #[test]
+    fn custom_config() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "a <b> c").unwrap();
+        mark(&mut doc, &text, "highlight", "yellow", (2, 5));
+        mark(&mut doc, &text, "bold", "true", (0, 7));
+        block(&mut doc, &text, 0, "callout", &[]);
+
+        let config = RichTextConfig::empty()
+            .with_mark(
+                "highlight",
+                MarkStyle::Element {
+                    tag: "mark".to_string(),
+                    attribute: Some("data-color".to_string()),
+                },
+            )
+            .with_block(
+                "callout",
+                BlockStyle::Element {
+                    tag: "aside".to_string(),
+                },
+            );
+        assert_eq!(
+            html(&doc, &text, &config).unwrap(),
+            "<aside>a <mark data-color=\"yellow\">&lt;b&gt;</mark> c</aside>"
+        );
+        assert_eq!(markdown(&doc, &text, &config).unwrap(), "a \\<b\\> c\n");
+    }
+
+    #[test]
+    #[cfg(feature = "markdown")]
+    fn markdown_round_trip() {
+        let markdown = "## Title\n\
+                        \n\
+                        **hello** [*world*](https://x.org/) and `code`\n\
+                        \n\
+                        - one\n\
+                        - two\n\
+                        \x20 - nested\n\
+                        \n\
+                        > quoted\n\
+                        \n\
+                        ```rust\n\
+                        let x = 1;\n\
+                        ```\n";
+        let config = RichTextConfig::default();
+        let spans = markdown_to_spans(markdown, &config);
+        assert_eq!(spans_to_markdown(spans.clone(), &config), markdown);
+        assert_eq!(
+            spans_to_html(spans, &config),
+            "<h2>Title</h2>\
+             <p><strong>hello</strong> <a href=\"https://x.org/\"><em>world</em></a> and <code>code</code></p>\
+             <ul><li>one</li><li>two<ul><li>nested</li></ul></li></ul>\
+             <blockquote><p>quoted</p></blockquote>\
+             <pre><code class=\"language-rust\">let x = 1;</code></pre>"
+        );
+    }
+
+    #[test]
+    fn html_import() {
+        let html = "<!DOCTYPE html><h1 class=\"x\">Title</h1>\n\
+                    <p>Some   <b>bold</b> and <a href=\"javascript:alert(1)\">unsafe</a>\
+                    <script>alert(\"&lt;p&gt;\")</script> &amp; <i>more\n</i></p>\
+                    <ol><li>one<li><span>two</span></ol>\
+                    <pre class=\"language-js\"><code>let x;\n  y</code></pre>\
+                    loose text";
+        let config = RichTextConfig::default();
+        assert_eq!(
+            spans_to_html(html_to_spans(html, &config), &config),
+            "<h1>Title</h1>\
+             <p>Some <strong>bold</strong> and unsafe &amp; <em>more</em></p>\
+             <ol><li>one</li><li>two</li></ol>\
+             <pre><code class=\"language-js\">let x;\n  y</code></pre>\
+             <p>loose text</p>"
+        );
+    }
+
+    #[test]
+    fn unsafe_links_are_not_rendered() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "click me").unwrap();
+        mark(&mut doc, &text, "link", " JavaScript:alert(1)", (0, 5));
+        let config = RichTextConfig::default();
+        assert_eq!(html(&doc, &text, &config).unwrap(), "click me");
+        assert_eq!(markdown(&doc, &text, &config).unwrap(), "click me\n");
+
+        // Browsers remove these characters before reading the scheme
+        for url in [
+            "java\tscript:alert(1)",
+            "java\nscript:alert(1)",
+            "jav\rascript:alert(1)",
+            "\u{1}javascript:alert(1)",
+            "java\u{0}script:alert(1)",
+            "vb\u{1f}script:msgbox(1)",
+            "\u{a0}javascript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "file:///etc/passwd",
+        ] {
+            mark(&mut doc, &text, "link", url, (0, 5));
+            assert_eq!(html(&doc, &text, &config).unwrap(), "click me", "{:?}", url);
+            assert_eq!(markdown(&doc, &text, &config).unwrap(), "click me\n");
+        }
+
+        for url in [
+            "https://x.org/",
+            "mailto:a@x.org",
+            "/relative:path",
+            "page?q=a:b",
+        ] {
+            mark(&mut doc, &text, "link", url, (0, 5));
+            assert!(html(&doc, &text, &config).unwrap().starts_with("<a href"));
+        }
+    }
+
+    #[test]
+    fn unsafe_links_are_not_imported() {
+        let config = RichTextConfig::default();
+        for href in ["java\tscript:alert(1)", "java&#9;script:alert(1)"] {
+            let html = format!("<p><a href=\"{}\">click</a></p>", href);
+            assert_eq!(
+                spans_to_html(html_to_spans(&html, &config), &config),
+                "<p>click</p>"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "markdown")]
+    fn unsafe_markdown_links_are_not_imported() {
+        let config = RichTextConfig::default();
+        for markdown in [
+            "[click](<java\tscript:alert(1)>)\n",
+            "[click](java&#9;script:alert(1))\n",
+        ] {
+            assert_eq!(
+                spans_to_markdown(markdown_to_spans(markdown, &config), &config),
+                "click\n"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "markdown")]
+    fn update_from_markdown_keeps_cursors() {
+        let mut doc = new_doc();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        let config = RichTextConfig::default();
+        update_from_markdown(&mut doc, &text, "# Title\n\nhello world\n", &config).unwrap();
+        assert_eq!(
+            markdown(&doc, &text, &config).unwrap(),
+            "# Title\n\nhello world\n"
+        );
+
+        // The cursor is on the "w" of "world"
+        let cursor = doc.get_cursor(&text, 13, None).unwrap();
+        update_from_markdown(&mut doc, &text, "# Title\n\nhello *big* world\n", &config).unwrap();
+        assert_eq!(
+            markdown(&doc, &text, &config).unwrap(),
+            "# Title\n\nhello *big* world\n"
+        );
+        assert_eq!(doc.get_cursor_position(&text, &cursor, None).unwrap(), 17);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::hydrate;
+use crate::iter::Span;
+use crate::marks::MarkSet;
+use crate::ScalarValue;
+
+use super::{BlockStyle, MarkStyle, RichTextConfig};
+
+/// Builds the spans for parsed rich text, in the block structure [`super::spans_to_html()`] and
+/// [`super::spans_to_markdown()`] render
+///
+/// Containers (block quotes and list items) become the parents of the blocks inside them. A list
+/// item is itself a block, and the first paragraph in a list item is the text of the item rather
+/// than a block of its own.
+pub(super) struct SpanBuilder<'a> {
+    config: &'a RichTextConfig,
+    spans: Vec<Span>,
+    /// The open containers, outermost first
+    containers: Vec<Container>,
+    /// Whether there is a block which text can be added to
+    in_block: bool,
+    /// A mark for every open mark element, `None` for elements which aren't in the config
+    marks: Vec<Option<(String, ScalarValue)>>,
+}
+
+struct Container {
+    block_type: String,
+    is_item: bool,
+    /// Whether the item has had any text or blocks added to it
+    has_content: bool,
+}
+
+impl<'a> SpanBuilder<'a> {
+    pub(super) fn new(config: &'a RichTextConfig) -> Self {
+        Self {
+            config,
+            spans: Vec::new(),
+            containers: Vec::new(),
+            in_block: false,
+            marks: Vec::new(),
+        }
+    }
+
+    pub(super) fn start_container(&mut self, style: &BlockStyle) {
+        self.mark_container_used();
+        self.containers.push(Container {
+            block_type: self.config.block_type_for(style),
+            is_item: false,
+            has_content: false,
+        });
+        self.in_block = false;
+    }
+
+    pub(super) fn start_item(&mut self, style: &BlockStyle) {
+        let block_type = self.push_block(style, HashMap::new());
+        self.containers.push(Container {
+            block_type,
+            is_item: true,
+            has_content: false,
+        });
+        self.in_block = true;
+    }
+
+    /// End a container or a list item
+    pub(super) fn end_container(&mut self) {
+        self.containers.pop();
+        self.in_block = false;
+    }
+
+    pub(super) fn start_block(&mut self, style: &BlockStyle, attrs: HashMap<String, ScalarValue>) {
+        match self.containers.last_mut() {
+            Some(item) if item.is_item && !item.has_content && *style == BlockStyle::Paragraph => {
+                item.has_content = true;
+            }
+            _ => {
+                self.push_block(style, attrs);
+            }
+        }
+        self.in_block = true;
+    }
+
+    pub(super) fn end_block(&mut self) {
+        self.in_block = false;
+    }
+
+    /// Whether there is a block which text can be added to
+    pub(super) fn in_block(&self) -> bool {
+        self.in_block
+    }
+
+    pub(super) fn text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.in_block {
+            self.start_block(&BlockStyle::Paragraph, HashMap::new());
+        }
+        self.mark_container_used();
+        let marks = self.current_marks();
+        if let Some(Span::Text {
+            text: last,
+            marks: last_marks,
+        }) = self.spans.last_mut()
+        {
+            if *last_marks == marks {
+                last.push_str(text);
+                return;
+            }
+        }
+        self.spans.push(Span::Text {
+            text: text.to_string(),
+            marks,
+        });
+    }
+
+    /// Start a mark element, every call must be matched by a call to [`Self::end_mark()`]
+    pub(super) fn start_mark(&mut self, style: &MarkStyle, value: ScalarValue) {
+        let mark = self
+            .config
+            .mark_name_for(style)
+            .map(|name| (name.to_string(), value));
+        self.marks.push(mark);
+    }
+
+    /// Start an element which would be a mark but isn't rendered, such as a link to an unsafe URL
+    pub(super) fn start_ignored_mark(&mut self) {
+        self.marks.push(None);
+    }
+
+    pub(super) fn end_mark(&mut self) {
+        self.marks.pop();
+    }
+
+    /// Set an attribute of the block which was just started, if no text has been added to it
+    pub(super) fn set_block_attr(&mut self, key: &str, value: ScalarValue) {
+        if let Some(Span::Block(block)) = self.spans.last_mut() {
+            if let Some(hydrate::Value::Map(attrs)) = block.get_mut("attrs") {
+                attrs.insert(
+                    key.to_string(),
+                    hydrate::MapValue {
+                        value: hydrate::Value::from(value),
+                        conflict: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Remove whitespace from the end of the text in the current block
+    pub(super) fn trim_end(&mut self) {
+        if let Some(Span::Text { text, .. }) = self.spans.last_mut() {
+            let len = text.trim_end_matches([' ', '\t']).len();
+            text.truncate(len);
+            if text.is_empty() {
+                self.spans.pop();
+            }
+        }
+    }
+
+    pub(super) fn finish(self) -> Vec<Span> {
+        self.spans
+    }
+
+    fn mark_container_used(&mut self) {
+        if let Some(container) = self.containers.last_mut() {
+            container.has_content = true;
+        }
+    }
+
+    fn push_block(&mut self, style: &BlockStyle, attrs: HashMap<String, ScalarValue>) -> String {
+        self.mark_container_used();
+        let block_type = self.config.block_type_for(style);
+        let parents = self
+            .containers
+            .iter()
+            .map(|c| hydrate::Value::from(c.block_type.as_str()))
+            .collect::<Vec<_>>();
+        let attrs = attrs
+            .into_iter()
+            .map(|(k, v)| (k, hydrate::Value::from(v)))
+            .collect::<HashMap<_, _>>();
+        self.spans
+            .push(Span::Block(hydrate::Map::from(HashMap::from([
+                ("type", hydrate::Value::from(block_type.as_str())),
+                ("parents", hydrate::Value::from(parents)),
+                ("attrs", hydrate::Value::Map(hydrate::Map::from(attrs))),
+            ]))));
+        block_type
+    }
+
+    fn current_marks(&self) -> Option<Arc<MarkSet>> {
+        let mut marks = HashMap::new();
+        for (name, value) in self.marks.iter().flatten() {
+            marks.insert(name.clone(), value.clone());
+        }
+        if marks.is_empty() {
+            None
+        } else {
+            Some(Arc::new(marks.into_iter().collect()))
+        }
+    }
+}
+
+/// Whether `url` is safe to put in a link: a relative URL or one with an `http`, `https` or
+/// `mailto` scheme
+///
+/// Browsers remove tabs and newlines anywhere in a URL and control characters and spaces around
+/// it before they read the scheme, so those are removed here too, otherwise `java\tscript:` would
+/// get through.
+pub(super) fn is_safe_url(url: &str) -> bool {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_ascii_control())
+        .collect::<String>();
+    let url = url.trim_matches(' ');
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => matches!(
+            url[..end].to_ascii_lowercase().as_str(),
+            "http" | "https" | "mailto"
+        ),
+        _ => true,
+    }
+}
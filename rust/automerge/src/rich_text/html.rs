@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+
+use crate::iter::Span;
+use crate::ScalarValue;
+
+use super::builder::{is_safe_url, SpanBuilder};
+use super::{common_prefix, ActiveMark, Block, BlockStyle, MarkStyle, RichTextConfig};
+
+pub(super) fn render<I: IntoIterator<Item = Span>>(spans: I, config: &RichTextConfig) -> String {
+    let mut renderer = Renderer {
+        config,
+        out: String::new(),
+        blocks: Vec::new(),
+        marks: Vec::new(),
+    };
+    for span in spans {
+        match span {
+            Span::Block(block) => renderer.block(&Block::from_map(&block)),
+            Span::Text { text, marks } => renderer.text(&text, marks.as_deref()),
+        }
+    }
+    renderer.close_marks(0);
+    renderer.close_blocks(0, None);
+    renderer.out
+}
+
+struct Renderer<'a> {
+    config: &'a RichTextConfig,
+    out: String,
+    /// The block elements which are open, outermost first
+    blocks: Vec<OpenBlock<'a>>,
+    /// The mark elements which are open, outermost first
+    marks: Vec<ActiveMark<'a>>,
+}
+
+struct OpenBlock<'a> {
+    block_type: String,
+    style: &'a BlockStyle,
+    close: String,
+}
+
+impl<'a> Renderer<'a> {
+    fn block(&mut self, block: &Block) {
+        self.close_marks(0);
+        let open_types = self
+            .blocks
+            .iter()
+            .map(|b| b.block_type.as_str())
+            .collect::<Vec<_>>();
+        let common = common_prefix(&open_types, &block.parents);
+        let next_type = block.parents.get(common).unwrap_or(&block.block_type);
+        let mut list_open = self.close_blocks(common, Some(next_type));
+        for parent in &block.parents[common..] {
+            self.open_block(parent, None, list_open);
+            list_open = false;
+        }
+        self.open_block(&block.block_type, Some(block), list_open);
+    }
+
+    /// Close the blocks nested deeper than `depth`. Returns whether the list the block at `depth`
+    /// was in was left open because the next block to be opened is of the same type.
+    fn close_blocks(&mut self, depth: usize, next_type: Option<&String>) -> bool {
+        let mut list_open = false;
+        while self.blocks.len() > depth {
+            let block = self.blocks.pop().unwrap();
+            self.out.push_str(&block.close);
+            if let Some(list) = list_tag(block.style) {
+                if self.blocks.len() == depth && next_type == Some(&block.block_type) {
+                    list_open = true;
+                } else {
+                    self.out.push_str(&format!("</{}>", list));
+                }
+            }
+        }
+        list_open
+    }
+
+    fn open_block(&mut self, block_type: &str, block: Option<&Block>, list_open: bool) {
+        let style = self.config.block_style_or_default(block_type);
+        if let Some(list) = list_tag(style) {
+            if !list_open {
+                self.out.push_str(&format!("<{}>", list));
+            }
+        }
+        let (open, close) = match style {
+            BlockStyle::Paragraph => ("<p>".to_string(), "</p>".to_string()),
+            BlockStyle::Heading => {
+                let level = block.map(Block::heading_level).unwrap_or(1);
+                (format!("<h{}>", level), format!("</h{}>", level))
+            }
+            BlockStyle::OrderedListItem | BlockStyle::UnorderedListItem => {
+                ("<li>".to_string(), "</li>".to_string())
+            }
+            BlockStyle::Blockquote => ("<blockquote>".to_string(), "</blockquote>".to_string()),
+            BlockStyle::CodeBlock => match block.and_then(Block::language) {
+                Some(language) => (
+                    format!("<pre><code class=\"language-{}\">", escape(language)),
+                    "</code></pre>".to_string(),
+                ),
+                None => ("<pre><code>".to_string(), "</code></pre>".to_string()),
+            },
+            BlockStyle::Element { tag } => (format!("<{}>", tag), format!("</{}>", tag)),
+        };
+        self.out.push_str(&open);
+        self.blocks.push(OpenBlock {
+            block_type: block_type.to_string(),
+            style,
+            close,
+        });
+    }
+
+    fn text(&mut self, text: &str, marks: Option<&crate::marks::MarkSet>) {
+        let marks = self.config.active_marks(marks);
+        let common = common_prefix(&self.marks, &marks);
+        self.close_marks(common);
+        for mark in &marks[common..] {
+            let open = match mark.style {
+                MarkStyle::Bold => "<strong>".to_string(),
+                MarkStyle::Italic => "<em>".to_string(),
+                MarkStyle::Underline => "<u>".to_string(),
+                MarkStyle::Strikethrough => "<s>".to_string(),
+                MarkStyle::Code => "<code>".to_string(),
+                MarkStyle::Link => format!("<a href=\"{}\">", escape(&mark.value)),
+                MarkStyle::Element {
+                    tag,
+                    attribute: Some(attribute),
+                } => format!("<{} {}=\"{}\">", tag, attribute, escape(&mark.value)),
+                MarkStyle::Element {
+                    tag,
+                    attribute: None,
+                } => format!("<{}>", tag),
+            };
+            self.out.push_str(&open);
+        }
+        self.marks = marks;
+
+        let preformatted = matches!(
+            self.blocks.last(),
+            Some(OpenBlock {
+                style: BlockStyle::CodeBlock,
+                ..
+            })
+        );
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.out.push_str(if preformatted { "\n" } else { "<br>" });
+            }
+            self.out.push_str(&escape(line));
+        }
+    }
+
+    fn close_marks(&mut self, depth: usize) {
+        while self.marks.len() > depth {
+            let mark = self.marks.pop().unwrap();
+            let close = match mark.style {
+                MarkStyle::Bold => "</strong>",
+                MarkStyle::Italic => "</em>",
+                MarkStyle::Underline => "</u>",
+                MarkStyle::Strikethrough => "</s>",
+                MarkStyle::Code => "</code>",
+                MarkStyle::Link => "</a>",
+                MarkStyle::Element { tag, .. } => {
+                    self.out.push_str(&format!("</{}>", tag));
+                    continue;
+                }
+            };
+            self.out.push_str(close);
+        }
+    }
+}
+
+fn list_tag(style: &BlockStyle) -> Option<&'static str> {
+    match style {
+        BlockStyle::OrderedListItem => Some("ol"),
+        BlockStyle::UnorderedListItem => Some("ul"),
+        _ => None,
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// What an open element does to the spans
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Block,
+    Container,
+    Item,
+    List,
+    Mark,
+    /// An element which doesn't affect the spans, only its children do
+    Transparent,
+}
+
+struct OpenElement {
+    tag: String,
+    role: Role,
+}
+
+pub(super) fn parse(html: &str, config: &RichTextConfig) -> Vec<Span> {
+    let mut parser = Parser {
+        builder: SpanBuilder::new(config),
+        config,
+        open: Vec::new(),
+        lists: Vec::new(),
+        preformatted: 0,
+        at_line_start: true,
+    };
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => parser.text(&text),
+            Token::Start { tag, attrs } => parser.start(tag, attrs),
+            Token::End(tag) => parser.end(&tag),
+        }
+    }
+    parser.close_to(0);
+    parser.builder.finish()
+}
+
+struct Parser<'a> {
+    builder: SpanBuilder<'a>,
+    config: &'a RichTextConfig,
+    open: Vec<OpenElement>,
+    /// Whether each open list is ordered
+    lists: Vec<bool>,
+    /// The number of open `<pre>` elements
+    preformatted: usize,
+    /// Whether the next text starts a line, so leading whitespace is dropped
+    at_line_start: bool,
+}
+
+impl Parser<'_> {
+    fn text(&mut self, text: &str) {
+        if self.preformatted > 0 {
+            self.builder.text(text);
+            return;
+        }
+        let mut collapsed = String::with_capacity(text.len());
+        for word in text.split_ascii_whitespace() {
+            if !collapsed.is_empty() {
+                collapsed.push(' ');
+            }
+            collapsed.push_str(word);
+        }
+        if text.starts_with(|c: char| c.is_ascii_whitespace()) && !self.at_line_start {
+            collapsed.insert(0, ' ');
+        }
+        if text.ends_with(|c: char| c.is_ascii_whitespace()) && !collapsed.is_empty() {
+            collapsed.push(' ');
+        }
+        if collapsed.trim().is_empty() && !self.builder.in_block() {
+            return;
+        }
+        let collapsed = if self.at_line_start {
+            collapsed.trim_start()
+        } else {
+            &collapsed
+        };
+        if !collapsed.is_empty() {
+            self.builder.text(collapsed);
+            self.at_line_start = false;
+        }
+    }
+
+    fn start(&mut self, tag: String, attrs: HashMap<String, String>) {
+        let heading_level = match tag.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => tag[1..].parse::<i64>().ok(),
+            _ => None,
+        };
+        let role = match tag.as_str() {
+            "br" => {
+                self.builder.text("\n");
+                self.at_line_start = true;
+                return;
+            }
+            "img" | "hr" | "input" | "meta" | "link" | "wbr" | "col" | "area" | "source" => return,
+            "p" | "div" => {
+                self.close_block();
+                self.builder
+                    .start_block(&BlockStyle::Paragraph, HashMap::new());
+                Role::Block
+            }
+            _ if heading_level.is_some() => {
+                self.close_block();
+                let level = ScalarValue::Int(heading_level.unwrap());
+                self.builder.start_block(
+                    &BlockStyle::Heading,
+                    HashMap::from([("level".to_string(), level)]),
+                );
+                Role::Block
+            }
+            "pre" => {
+                self.close_block();
+                self.builder
+                    .start_block(&BlockStyle::CodeBlock, language(&attrs));
+                self.preformatted += 1;
+                Role::Block
+            }
+            "code" if self.preformatted > 0 => {
+                for (key, value) in language(&attrs) {
+                    self.builder.set_block_attr(&key, value);
+                }
+                Role::Transparent
+            }
+            "blockquote" => {
+                self.close_block();
+                self.builder.start_container(&BlockStyle::Blockquote);
+                Role::Container
+            }
+            "ul" | "ol" => {
+                self.close_block();
+                self.lists.push(tag == "ol");
+                Role::List
+            }
+            "li" => {
+                // A list item without an end tag ends at the next item in the same list
+                let item = self.open.iter().rposition(|e| e.role == Role::Item);
+                let list = self.open.iter().rposition(|e| e.role == Role::List);
+                if let (Some(item), Some(list)) = (item, list) {
+                    if item > list {
+                        self.close_to(item);
+                    }
+                }
+                self.close_block();
+                self.builder
+                    .start_item(if self.lists.last() == Some(&true) {
+                        &BlockStyle::OrderedListItem
+                    } else {
+                        &BlockStyle::UnorderedListItem
+                    });
+                Role::Item
+            }
+            "strong" | "b" => self.start_mark(&MarkStyle::Bold, true.into()),
+            "em" | "i" => self.start_mark(&MarkStyle::Italic, true.into()),
+            "u" => self.start_mark(&MarkStyle::Underline, true.into()),
+            "s" | "del" | "strike" => self.start_mark(&MarkStyle::Strikethrough, true.into()),
+            "code" => self.start_mark(&MarkStyle::Code, true.into()),
+            "a" => match attrs.get("href") {
+                Some(href) if is_safe_url(href) => {
+                    self.start_mark(&MarkStyle::Link, href.as_str().into())
+                }
+                _ => {
+                    self.builder.start_ignored_mark();
+                    Role::Mark
+                }
+            },
+            _ => {
+                if let Some(style) = self.config.element_mark(&tag).cloned() {
+                    let value = match &style {
+                        // Without the attribute which holds the value this is some other use of
+                        // the element
+                        MarkStyle::Element {
+                            attribute: Some(attribute),
+                            ..
+                        } => attrs.get(attribute).map(|v| ScalarValue::from(v.as_str())),
+                        _ => Some(ScalarValue::Boolean(true)),
+                    };
+                    match value {
+                        Some(value) => self.start_mark(&style, value),
+                        None => {
+                            self.builder.start_ignored_mark();
+                            Role::Mark
+                        }
+                    }
+                } else if self.config.is_element_block(&tag) {
+                    self.close_block();
+                    self.builder
+                        .start_block(&BlockStyle::Element { tag: tag.clone() }, HashMap::new());
+                    Role::Block
+                } else {
+                    Role::Transparent
+                }
+            }
+        };
+        if role != Role::Mark && role != Role::Transparent {
+            self.at_line_start = true;
+        }
+        self.open.push(OpenElement { tag, role });
+    }
+
+    fn start_mark(&mut self, style: &MarkStyle, value: ScalarValue) -> Role {
+        self.builder.start_mark(style, value);
+        Role::Mark
+    }
+
+    fn end(&mut self, tag: &str) {
+        if let Some(index) = self.open.iter().rposition(|e| e.tag == tag) {
+            self.close_to(index);
+        }
+    }
+
+    /// Close the innermost open element if it's a block, blocks can't contain other blocks
+    fn close_block(&mut self) {
+        if let Some(index) = self.open.iter().rposition(|e| e.role == Role::Block) {
+            let inside_container = self.open[index..]
+                .iter()
+                .any(|e| matches!(e.role, Role::Container | Role::Item | Role::List));
+            if !inside_container {
+                self.close_to(index);
+            }
+        }
+    }
+
+    /// Close the open elements from `index` onwards
+    fn close_to(&mut self, index: usize) {
+        while self.open.len() > index {
+            let element = self.open.pop().unwrap();
+            match element.role {
+                Role::Block => {
+                    if element.tag == "pre" {
+                        self.preformatted -= 1;
+                    } else {
+                        self.builder.trim_end();
+                    }
+                    self.builder.end_block();
+                }
+                Role::Container | Role::Item => {
+                    self.builder.trim_end();
+                    self.builder.end_container();
+                }
+                Role::List => {
+                    self.lists.pop();
+                }
+                Role::Mark => self.builder.end_mark(),
+                Role::Transparent => {}
+            }
+            if element.role != Role::Mark && element.role != Role::Transparent {
+                self.at_line_start = true;
+            }
+        }
+    }
+}
+
+/// The language of a code block from a `language-` class, as used by most syntax highlighters
+fn language(attrs: &HashMap<String, String>) -> HashMap<String, ScalarValue> {
+    attrs
+        .get("class")
+        .and_then(|class| {
+            class
+                .split_ascii_whitespace()
+                .find_map(|c| c.strip_prefix("language-"))
+        })
+        .map(|language| HashMap::from([("language".to_string(), ScalarValue::from(language))]))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Start {
+        tag: String,
+        attrs: HashMap<String, String>,
+    },
+    End(String),
+}
+
+/// Split HTML into text and tags, dropping comments, doctypes and the contents of elements which
+/// aren't text, such as scripts
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.split_once("-->").map(|(_, r)| r).unwrap_or("");
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.split_once('>').map(|(_, r)| r).unwrap_or("");
+        } else if let Some(end) = rest
+            .strip_prefix("</")
+            .filter(|r| r.starts_with(|c: char| c.is_ascii_alphabetic()))
+        {
+            let (tag, after) = end.split_once('>').unwrap_or((end, ""));
+            let tag = tag
+                .split(|c: char| c.is_ascii_whitespace())
+                .next()
+                .unwrap_or("");
+            tokens.push(Token::End(tag.to_ascii_lowercase()));
+            rest = after;
+        } else if let Some(start) = rest
+            .strip_prefix('<')
+            .filter(|r| r.starts_with(|c: char| c.is_ascii_alphabetic()))
+        {
+            let (tag, attrs, after) = parse_start_tag(start);
+            rest = after;
+            if matches!(
+                tag.as_str(),
+                "script" | "style" | "template" | "title" | "head" | "textarea" | "iframe"
+            ) {
+                // Skip to the end tag
+                let close = format!("</{}", tag);
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(i) => rest[i..].split_once('>').map(|(_, r)| r).unwrap_or(""),
+                    None => "",
+                };
+            } else {
+                tokens.push(Token::Start { tag, attrs });
+            }
+        } else {
+            // Text runs to the next tag, a `<` which doesn't start a tag is text
+            let end = rest[1..].find('<').map(|i| i + 1).unwrap_or(rest.len());
+            tokens.push(Token::Text(decode_entities(&rest[..end])));
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+/// Parse a start tag after the `<`, returning the tag, its attributes and the input after it
+fn parse_start_tag(input: &str) -> (String, HashMap<String, String>, &str) {
+    let name_end = input
+        .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .unwrap_or(input.len());
+    let tag = input[..name_end].to_ascii_lowercase();
+    let mut attrs = HashMap::new();
+    let mut rest = &input[name_end..];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let inner = &after[1..];
+                    match inner.find(quote) {
+                        Some(end) => (&inner[..end], &inner[end + 1..]),
+                        None => (inner, ""),
+                    }
+                }
+                _ => {
+                    let end = after
+                        .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                        .unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = remaining;
+        }
+        if !name.is_empty() {
+            attrs.entry(name).or_insert(value);
+        }
+    }
+    (tag, attrs, rest)
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| {
+                let entity = &rest[1..end + 1];
+                let c = match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some('\u{a0}'),
+                    _ => entity
+                        .strip_prefix("#x")
+                        .or_else(|| entity.strip_prefix("#X"))
+                        .map(|hex| u32::from_str_radix(hex, 16))
+                        .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                        .and_then(Result::ok)
+                        .and_then(char::from_u32),
+                };
+                c.map(|c| (c, end + 2))
+            });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,359 @@
+#[cfg(feature = "markdown")]
+use std::collections::HashMap;
+
+#[cfg(feature = "markdown")]
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+use crate::iter::Span;
+use crate::marks::MarkSet;
+#[cfg(feature = "markdown")]
+use crate::ScalarValue;
+
+#[cfg(feature = "markdown")]
+use super::builder::{is_safe_url, SpanBuilder};
+use super::{common_prefix, ActiveMark, Block, BlockStyle, MarkStyle, RichTextConfig};
+
+pub(super) fn render<I: IntoIterator<Item = Span>>(spans: I, config: &RichTextConfig) -> String {
+    let mut renderer = Renderer {
+        config,
+        out: String::new(),
+        path: Vec::new(),
+        prev_list_item: false,
+        current: None,
+        content: String::new(),
+        marks: Vec::new(),
+    };
+    for span in spans {
+        match span {
+            Span::Block(block) => renderer.block(&Block::from_map(&block)),
+            Span::Text { text, marks } => renderer.text(&text, marks.as_deref()),
+        }
+    }
+    renderer.flush();
+    if !renderer.out.is_empty() {
+        renderer.out.push('\n');
+    }
+    renderer.out
+}
+
+#[cfg(feature = "markdown")]
+pub(super) fn parse(markdown: &str, config: &RichTextConfig) -> Vec<Span> {
+    let mut builder = SpanBuilder::new(config);
+    // Whether each open list is ordered
+    let mut lists = Vec::new();
+    // The text of the code block being parsed
+    let mut code: Option<String> = None;
+    for event in Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph => builder.start_block(&BlockStyle::Paragraph, HashMap::new()),
+                Tag::Heading { level, .. } => builder.start_block(
+                    &BlockStyle::Heading,
+                    HashMap::from([("level".to_string(), ScalarValue::Int(level as i64))]),
+                ),
+                Tag::BlockQuote(_) => builder.start_container(&BlockStyle::Blockquote),
+                Tag::CodeBlock(kind) => {
+                    let mut attrs = HashMap::new();
+                    if let CodeBlockKind::Fenced(info) = kind {
+                        // The language is the first word of the info string
+                        if let Some(language) = info.split_whitespace().next() {
+                            attrs.insert("language".to_string(), ScalarValue::from(language));
+                        }
+                    }
+                    builder.start_block(&BlockStyle::CodeBlock, attrs);
+                    code = Some(String::new());
+                }
+                Tag::List(start) => lists.push(start.is_some()),
+                Tag::Item => builder.start_item(if lists.last() == Some(&true) {
+                    &BlockStyle::OrderedListItem
+                } else {
+                    &BlockStyle::UnorderedListItem
+                }),
+                Tag::Emphasis => builder.start_mark(&MarkStyle::Italic, true.into()),
+                Tag::Strong => builder.start_mark(&MarkStyle::Bold, true.into()),
+                Tag::Strikethrough => builder.start_mark(&MarkStyle::Strikethrough, true.into()),
+                Tag::Link { dest_url, .. } if is_safe_url(&dest_url) => {
+                    builder.start_mark(&MarkStyle::Link, dest_url.as_ref().into())
+                }
+                Tag::Link { .. } => builder.start_ignored_mark(),
+                _ => {}
+            },
+            Event::End(tag) => match tag {
+                TagEnd::Paragraph | TagEnd::Heading(_) => builder.end_block(),
+                TagEnd::CodeBlock => {
+                    let text = code.take().unwrap_or_default();
+                    builder.text(text.strip_suffix('\n').unwrap_or(&text));
+                    builder.end_block();
+                }
+                TagEnd::BlockQuote(_) | TagEnd::Item => builder.end_container(),
+                TagEnd::List(_) => {
+                    lists.pop();
+                }
+                TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link => {
+                    builder.end_mark()
+                }
+                _ => {}
+            },
+            Event::Text(text) => match code.as_mut() {
+                Some(code) => code.push_str(&text),
+                None => builder.text(&text),
+            },
+            Event::Code(text) => {
+                builder.start_mark(&MarkStyle::Code, true.into());
+                builder.text(&text);
+                builder.end_mark();
+            }
+            Event::SoftBreak => builder.text(" "),
+            Event::HardBreak => builder.text("\n"),
+            _ => {}
+        }
+    }
+    builder.finish()
+}
+
+struct Renderer<'a> {
+    config: &'a RichTextConfig,
+    out: String,
+    /// The types of the current block and its parents, outermost first
+    path: Vec<String>,
+    prev_list_item: bool,
+    /// The block being rendered, `None` for text before the first block
+    current: Option<CurrentBlock<'a>>,
+    /// The rendered text of the current block, without the line prefixes
+    content: String,
+    marks: Vec<ActiveMark<'a>>,
+}
+
+struct CurrentBlock<'a> {
+    style: &'a BlockStyle,
+    /// The prefix of the first line of the block
+    first_prefix: String,
+    /// The prefix of every other line of the block
+    prefix: String,
+    language: Option<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn block(&mut self, block: &Block) {
+        self.flush();
+        let common = common_prefix(&self.path, &block.parents);
+        let mut first_prefix = String::new();
+        let mut prefix = String::new();
+        for (i, parent) in block.parents.iter().enumerate() {
+            let style = self.config.block_style_or_default(parent);
+            // Parents which were open for the previous block continue, the rest start here
+            if i < common {
+                first_prefix.push_str(continuation(style));
+            } else {
+                first_prefix.push_str(&marker(style, None));
+            }
+            prefix.push_str(continuation(style));
+        }
+        let style = self.config.block_style_or_default(&block.block_type);
+        first_prefix.push_str(&marker(style, Some(block)));
+        prefix.push_str(continuation(style));
+
+        if !self.out.is_empty() {
+            if style.is_list_item() && self.prev_list_item {
+                self.out.push('\n');
+            } else {
+                let shared = block.parents[..common]
+                    .iter()
+                    .map(|p| continuation(self.config.block_style_or_default(p)))
+                    .collect::<String>();
+                self.out.push('\n');
+                self.out.push_str(shared.trim_end());
+                self.out.push('\n');
+            }
+        }
+
+        self.path = block.parents.clone();
+        self.path.push(block.block_type.clone());
+        self.prev_list_item = style.is_list_item();
+        self.current = Some(CurrentBlock {
+            style,
+            first_prefix,
+            prefix,
+            language: block.language().map(str::to_string),
+        });
+    }
+
+    fn text(&mut self, text: &str, marks: Option<&MarkSet>) {
+        if matches!(
+            self.current,
+            Some(CurrentBlock {
+                style: BlockStyle::CodeBlock,
+                ..
+            })
+        ) {
+            self.content.push_str(text);
+            return;
+        }
+        let marks = self
+            .config
+            .active_marks(marks)
+            .into_iter()
+            .filter(|m| delimiters(m).is_some())
+            .collect::<Vec<_>>();
+        let common = common_prefix(&self.marks, &marks);
+        self.close_marks(common);
+        let mut text = text;
+        if common < marks.len() {
+            // Delimiters can't be followed by whitespace so open them after it
+            let trimmed = text.trim_start_matches([' ', '\t']);
+            self.push_text(&text[..text.len() - trimmed.len()]);
+            text = trimmed;
+            for mark in &marks[common..] {
+                let (open, _) = delimiters(mark).unwrap();
+                self.content.push_str(open);
+            }
+        }
+        self.marks = marks;
+        self.push_text(text);
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let in_code = self.marks.iter().any(|m| *m.style == MarkStyle::Code);
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                match self.current.as_ref().map(|c| c.style) {
+                    None => self.content.push('\n'),
+                    Some(BlockStyle::Heading) => self.content.push(' '),
+                    Some(_) => self.content.push_str("\\\n"),
+                }
+            }
+            if in_code {
+                self.content.push_str(line);
+            } else {
+                let line_start = self.content.is_empty() || self.content.ends_with('\n');
+                escape(line, line_start, &mut self.content);
+            }
+        }
+    }
+
+    fn close_marks(&mut self, depth: usize) {
+        if self.marks.len() <= depth {
+            return;
+        }
+        // Delimiters can't be preceded by whitespace so close them before it
+        let trimmed_len = self.content.trim_end_matches([' ', '\t']).len();
+        let whitespace = self.content.split_off(trimmed_len);
+        while self.marks.len() > depth {
+            let mark = self.marks.pop().unwrap();
+            let (_, close) = delimiters(&mark).unwrap();
+            self.content.push_str(&close);
+        }
+        self.content.push_str(&whitespace);
+    }
+
+    /// Write the current block to the output
+    fn flush(&mut self) {
+        self.close_marks(0);
+        let content = std::mem::take(&mut self.content);
+        let Some(block) = self.current.take() else {
+            self.out.push_str(&content);
+            return;
+        };
+        if *block.style == BlockStyle::CodeBlock {
+            let fence = if content.contains("```") {
+                "~~~"
+            } else {
+                "```"
+            };
+            self.out.push_str(&block.first_prefix);
+            self.out.push_str(fence);
+            self.out.push_str(block.language.as_deref().unwrap_or(""));
+            for line in content.split('\n') {
+                self.out.push('\n');
+                self.push_line(&block.prefix, line);
+            }
+            self.out.push('\n');
+            self.out.push_str(&block.prefix);
+            self.out.push_str(fence);
+        } else {
+            for (i, line) in content.split('\n').enumerate() {
+                if i == 0 {
+                    self.push_line(&block.first_prefix, line);
+                } else {
+                    self.out.push('\n');
+                    self.push_line(&block.prefix, line);
+                }
+            }
+        }
+    }
+
+    fn push_line(&mut self, prefix: &str, line: &str) {
+        if line.is_empty() {
+            self.out.push_str(prefix.trim_end());
+        } else {
+            self.out.push_str(prefix);
+            self.out.push_str(line);
+        }
+    }
+}
+
+/// The start of the first line of a block
+fn marker(style: &BlockStyle, block: Option<&Block>) -> String {
+    match style {
+        BlockStyle::Heading => {
+            let level = block.map(Block::heading_level).unwrap_or(1);
+            format!("{} ", "#".repeat(level as usize))
+        }
+        BlockStyle::OrderedListItem => "1. ".to_string(),
+        BlockStyle::UnorderedListItem => "- ".to_string(),
+        BlockStyle::Blockquote => "> ".to_string(),
+        BlockStyle::Paragraph | BlockStyle::CodeBlock | BlockStyle::Element { .. } => String::new(),
+    }
+}
+
+/// The start of every other line of a block, and of every line of the blocks nested in it
+fn continuation(style: &BlockStyle) -> &'static str {
+    match style {
+        BlockStyle::OrderedListItem => "   ",
+        BlockStyle::UnorderedListItem => "  ",
+        BlockStyle::Blockquote => "> ",
+        _ => "",
+    }
+}
+
+/// The delimiters which open and close a mark, if it can be rendered in Markdown
+fn delimiters(mark: &ActiveMark<'_>) -> Option<(&'static str, String)> {
+    match mark.style {
+        MarkStyle::Bold => Some(("**", "**".to_string())),
+        MarkStyle::Italic => Some(("*", "*".to_string())),
+        MarkStyle::Strikethrough => Some(("~~", "~~".to_string())),
+        MarkStyle::Code => Some(("`", "`".to_string())),
+        MarkStyle::Link => {
+            let url = &mark.value;
+            if url.contains(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '<' | '>')) {
+                Some(("[", format!("](<{}>)", url.replace(['<', '>'], ""))))
+            } else {
+                Some(("[", format!("]({})", url)))
+            }
+        }
+        MarkStyle::Underline | MarkStyle::Element { .. } => None,
+    }
+}
+
+fn escape(text: &str, line_start: bool, out: &mut String) {
+    for (i, c) in text.char_indices() {
+        let escaped = match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '~' | '&' | '|' => true,
+            // These start a list or a setext heading underline at the start of a line
+            '-' | '+' | '=' => i == 0 && line_start,
+            // A number followed by one of these starts an ordered list
+            '.' | ')' => {
+                let rest = &text[i + 1..];
+                line_start
+                    && i > 0
+                    && text[..i].chars().all(|c| c.is_ascii_digit())
+                    && (rest.is_empty() || rest.starts_with([' ', '\t']))
+            }
+            _ => false,
+        };
+        if escaped {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
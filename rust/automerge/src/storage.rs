@@ -1,15 +1,39 @@
+//! Persisting documents
+//!
+//! A [`DocumentStore`] saves documents to any key value store which implements [`Backend`],
+//! appending the changes made since the last save and periodically compacting them into a single
+//! snapshot. [`FsBackend`] stores data in a directory and [`MemoryBackend`] keeps it in memory.
+//!
+//! A [`WriteAheadLog`] appends each change to a file as it's made, so that a crash loses at most
+//! the changes which hadn't been synced to disk.
+//!
+//! An [`Archive`] packs many documents into one file, each of which can be read or replaced
+//! without reading or rewriting the others.
+
 use std::ops::Range;
 
+mod archive;
+mod backend;
 pub(crate) mod bundle;
 pub(crate) mod change;
 mod chunk;
+mod codec;
 pub(crate) mod columns;
+mod doc_store;
 pub(crate) mod document;
 pub(crate) mod load;
 pub(crate) mod parse;
+mod wal;
 
+pub use archive::{Archive, ArchiveBatch, ArchiveEntry, ArchiveError};
+pub use backend::{Backend, Entry, FsBackend, MemoryBackend};
 pub use bundle::{Bundle, BundleChange, BundleChangeIter};
-pub use load::VerificationMode;
+pub use codec::{Codec, CodecNotCompiledIn};
+pub use doc_store::{CompactionPolicy, DocumentStore, StoreError};
+pub use load::{
+    Limit, LimitExceeded, LoadCancelled, LoadLimits, LoadPhase, LoadProgress, VerificationMode,
+};
+pub use wal::{SyncPolicy, WalError, WriteAheadLog};
 
 pub(crate) use {
     bundle::{BundleMetadata, BundleStorage},
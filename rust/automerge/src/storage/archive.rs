@@ -0,0 +1,638 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use sha2::{Digest, Sha256};
+
+use super::MAGIC_BYTES;
+use crate::{Automerge, AutomergeError, ChangeHash};
+
+/// The bytes at the start of every archive, the automerge magic bytes followed by "arc" and the
+/// format version
+const HEADER: [u8; 8] = [
+    MAGIC_BYTES[0],
+    MAGIC_BYTES[1],
+    MAGIC_BYTES[2],
+    MAGIC_BYTES[3],
+    b'a',
+    b'r',
+    b'c',
+    1,
+];
+
+/// The bytes at the end of every footer
+const FOOTER_MAGIC: [u8; 4] = *b"AIDX";
+
+/// The index offset (8 bytes), the index length (8 bytes), the index checksum (4 bytes) and
+/// [`FOOTER_MAGIC`]
+const FOOTER_LEN: u64 = 24;
+
+/// How much of the file [`find_last_index`] reads at a time while searching for a footer
+const SCAN_BLOCK_LEN: u64 = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("error accessing the archive: {0}")]
+    Io(#[from] io::Error),
+    #[error("not an archive")]
+    NotAnArchive,
+    /// There is no index in the archive whose checksum is valid
+    #[error("the archive index is corrupt")]
+    Corrupt,
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+}
+
+/// A document stored in an [`Archive`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// The heads of the document when it was stored
+    pub heads: Vec<ChangeHash>,
+    /// The length in bytes of the saved document
+    pub len: u64,
+    offset: u64,
+}
+
+/// Many documents, each identified by a string, packed into one file
+///
+/// Each document is stored in the format [`Automerge::save()`] produces, followed by an index
+/// which maps every ID to the position of the latest version of its document and a footer which
+/// points at the index. Reading a document seeks from the footer to the index to the document, so
+/// it doesn't read any of the others, and [`Self::put()`] appends the new version of a document
+/// followed by a new index, so it doesn't rewrite any of the others.
+///
+/// Every call to [`Self::put()`] or [`Self::remove()`] writes a new index, which holds every ID in
+/// the archive. To store or remove many documents use [`Self::batch()`], which writes the index
+/// once for the whole batch.
+///
+/// Versions of a document which have been replaced and documents which have been removed still
+/// take up space in the file until the archive is copied with [`Self::compact_into()`].
+///
+/// If a write is interrupted the footer at the end of the file will be incomplete.
+/// [`Self::open()`] then falls back to the last complete index, so the archive is as it was
+/// before the interrupted write.
+///
+/// ## Example
+///
+/// ```
+/// # use automerge::{transaction::Transactable, AutoCommit, Automerge, ReadDoc, ROOT};
+/// # use automerge::storage::Archive;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut doc = AutoCommit::new();
+/// doc.put(ROOT, "title", "notes")?;
+///
+/// let mut archive = Archive::create(std::io::Cursor::new(Vec::new()))?;
+/// archive.put("notes", doc.document())?;
+///
+/// let mut archive = Archive::open(archive.into_inner())?;
+/// let loaded = archive.load("notes")?.unwrap();
+/// assert_eq!(loaded.get_heads(), doc.get_heads());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Archive<F> {
+    file: F,
+    entries: BTreeMap<String, ArchiveEntry>,
+    /// The end of the last complete footer, where the next write starts
+    end: u64,
+}
+
+impl<F: Read + Seek> Archive<F> {
+    /// Open an existing archive
+    ///
+    /// If the end of the file is not a complete footer because a write was interrupted, the last
+    /// complete index in the file is used instead.
+    pub fn open(mut file: F) -> Result<Self, ArchiveError> {
+        let mut header = [0; HEADER.len()];
+        file.seek(SeekFrom::Start(0))?;
+        match file.read_exact(&mut header) {
+            Ok(()) if header == HEADER => {}
+            Ok(()) => return Err(ArchiveError::NotAnArchive),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(ArchiveError::NotAnArchive)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let file_len = file.seek(SeekFrom::End(0))?;
+        let (entries, end) = match read_index_at(&mut file, file_len)? {
+            Some(entries) => (entries, file_len),
+            None => find_last_index(&mut file, file_len)?,
+        };
+        Ok(Self { file, entries, end })
+    }
+
+    /// The IDs of the documents in the archive, in order
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// The IDs of the documents in the archive and what is stored for each of them
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &ArchiveEntry)> {
+        self.entries.iter().map(|(id, entry)| (id.as_str(), entry))
+    }
+
+    /// What is stored for `id`, if anything
+    pub fn entry(&self, id: &str) -> Option<&ArchiveEntry> {
+        self.entries.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Read the saved document stored for `id`
+    pub fn get(&mut self, id: &str) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let Some(entry) = self.entries.get(id) else {
+            return Ok(None);
+        };
+        let mut data = vec![0; entry.len as usize];
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Read and load the document stored for `id`
+    pub fn load(&mut self, id: &str) -> Result<Option<Automerge>, ArchiveError> {
+        match self.get(id)? {
+            Some(data) => Ok(Some(Automerge::load(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Copy the latest version of every document into a new archive in `file`, leaving out
+    /// replaced versions and removed documents
+    pub fn compact_into<G: Read + Write + Seek>(
+        &mut self,
+        file: G,
+    ) -> Result<Archive<G>, ArchiveError> {
+        let mut out = Archive {
+            file,
+            entries: BTreeMap::new(),
+            end: 0,
+        };
+        out.file.seek(SeekFrom::Start(0))?;
+        out.file.write_all(&HEADER)?;
+        out.end = HEADER.len() as u64;
+        let ids = self.entries.keys().cloned().collect::<Vec<_>>();
+        for id in ids {
+            let entry = self.entries[&id].clone();
+            let data = self.get(&id)?.unwrap_or_default();
+            out.file.write_all(&data)?;
+            out.entries.insert(
+                id,
+                ArchiveEntry {
+                    offset: out.end,
+                    ..entry
+                },
+            );
+            out.end += data.len() as u64;
+        }
+        out.write_index()?;
+        Ok(out)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+}
+
+impl<F: Read + Write + Seek> Archive<F> {
+    /// Start a new, empty archive in `file`, which should be empty
+    pub fn create(mut file: F) -> Result<Self, ArchiveError> {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&HEADER)?;
+        let mut archive = Self {
+            file,
+            entries: BTreeMap::new(),
+            end: HEADER.len() as u64,
+        };
+        archive.write_index()?;
+        Ok(archive)
+    }
+
+    /// Store `doc` as the latest version of the document with this `id`
+    pub fn put(&mut self, id: &str, doc: &Automerge) -> Result<(), ArchiveError> {
+        self.put_saved(id, &doc.save(), doc.get_heads())
+    }
+
+    /// Store the output of [`Automerge::save()`] for a document whose heads are `heads` as the
+    /// latest version of the document with this `id`
+    ///
+    /// The data is not checked, use [`Self::put()`] if you have the document.
+    pub fn put_saved(
+        &mut self,
+        id: &str,
+        data: &[u8],
+        heads: Vec<ChangeHash>,
+    ) -> Result<(), ArchiveError> {
+        self.write_document(id, data, heads)?;
+        self.write_index()
+    }
+
+    /// Remove the document with this `id`, returns whether there was one
+    pub fn remove(&mut self, id: &str) -> Result<bool, ArchiveError> {
+        if self.entries.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.write_index()?;
+        Ok(true)
+    }
+
+    /// Start a batch of puts and removes which share a single index
+    ///
+    /// Nothing in the batch is visible to [`Self::open()`] until [`ArchiveBatch::commit()`]
+    /// writes the index. Dropping the batch without committing it leaves the archive as it was.
+    ///
+    /// ```
+    /// # use automerge::{transaction::Transactable, AutoCommit, ReadDoc, ROOT};
+    /// # use automerge::storage::Archive;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut archive = Archive::create(std::io::Cursor::new(Vec::new()))?;
+    /// let mut batch = archive.batch();
+    /// for i in 0..100 {
+    ///     let mut doc = AutoCommit::new();
+    ///     doc.put(ROOT, "number", i)?;
+    ///     batch.put(&format!("doc-{}", i), doc.document())?;
+    /// }
+    /// batch.commit()?;
+    /// assert_eq!(archive.len(), 100);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn batch(&mut self) -> ArchiveBatch<'_, F> {
+        ArchiveBatch {
+            entries: self.entries.clone(),
+            end: self.end,
+            archive: self,
+        }
+    }
+
+    /// Write `data` at `self.end` and point the entry for `id` at it, without writing the index
+    fn write_document(
+        &mut self,
+        id: &str,
+        data: &[u8],
+        heads: Vec<ChangeHash>,
+    ) -> Result<(), ArchiveError> {
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(data)?;
+        self.entries.insert(
+            id.to_string(),
+            ArchiveEntry {
+                heads,
+                len: data.len() as u64,
+                offset: self.end,
+            },
+        );
+        self.end += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write the index and footer at `self.end`
+    fn write_index(&mut self) -> Result<(), ArchiveError> {
+        let index = encode_index(&self.entries);
+        let mut footer = Vec::with_capacity(FOOTER_LEN as usize);
+        footer.extend(self.end.to_le_bytes());
+        footer.extend((index.len() as u64).to_le_bytes());
+        footer.extend(checksum(&index));
+        footer.extend(FOOTER_MAGIC);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&index)?;
+        self.file.write_all(&footer)?;
+        self.file.flush()?;
+        self.end += index.len() as u64 + FOOTER_LEN;
+        Ok(())
+    }
+}
+
+/// Puts and removes on an [`Archive`] which are written with a single index, see
+/// [`Archive::batch()`]
+#[derive(Debug)]
+pub struct ArchiveBatch<'a, F: Read + Write + Seek> {
+    archive: &'a mut Archive<F>,
+    /// The entries and end of the archive as they were before the batch, restored if the batch
+    /// isn't committed
+    entries: BTreeMap<String, ArchiveEntry>,
+    end: u64,
+}
+
+impl<F: Read + Write + Seek> ArchiveBatch<'_, F> {
+    /// As [`Archive::put()`]
+    pub fn put(&mut self, id: &str, doc: &Automerge) -> Result<(), ArchiveError> {
+        self.put_saved(id, &doc.save(), doc.get_heads())
+    }
+
+    /// As [`Archive::put_saved()`]
+    pub fn put_saved(
+        &mut self,
+        id: &str,
+        data: &[u8],
+        heads: Vec<ChangeHash>,
+    ) -> Result<(), ArchiveError> {
+        self.archive.write_document(id, data, heads)
+    }
+
+    /// As [`Archive::remove()`]
+    pub fn remove(&mut self, id: &str) -> bool {
+        self.archive.entries.remove(id).is_some()
+    }
+
+    /// Write the index for everything in the batch
+    pub fn commit(mut self) -> Result<(), ArchiveError> {
+        self.archive.write_index()?;
+        self.entries = self.archive.entries.clone();
+        self.end = self.archive.end;
+        Ok(())
+    }
+}
+
+impl<F: Read + Write + Seek> Drop for ArchiveBatch<'_, F> {
+    fn drop(&mut self) {
+        self.archive.entries = std::mem::take(&mut self.entries);
+        self.archive.end = self.end;
+    }
+}
+
+fn checksum(data: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(data);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn encode_index(entries: &BTreeMap<String, ArchiveEntry>) -> Vec<u8> {
+    let mut out = Vec::new();
+    leb128::write::unsigned(&mut out, entries.len() as u64).unwrap();
+    for (id, entry) in entries {
+        leb128::write::unsigned(&mut out, id.len() as u64).unwrap();
+        out.extend(id.as_bytes());
+        leb128::write::unsigned(&mut out, entry.offset).unwrap();
+        leb128::write::unsigned(&mut out, entry.len).unwrap();
+        leb128::write::unsigned(&mut out, entry.heads.len() as u64).unwrap();
+        for head in &entry.heads {
+            out.extend(head.as_bytes());
+        }
+    }
+    out
+}
+
+/// Decode an index, `None` if it's malformed or points at data after `data_end`
+fn decode_index(mut index: &[u8], data_end: u64) -> Option<BTreeMap<String, ArchiveEntry>> {
+    let input = &mut index;
+    let read_u64 = |input: &mut &[u8]| leb128::read::unsigned(input).ok();
+    let count = read_u64(input)?;
+    let mut entries = BTreeMap::new();
+    for _ in 0..count {
+        let id_len = read_u64(input)? as usize;
+        let id = std::str::from_utf8(input.get(..id_len)?).ok()?.to_string();
+        *input = &input[id_len..];
+        let offset = read_u64(input)?;
+        let len = read_u64(input)?;
+        if offset.checked_add(len)? > data_end {
+            return None;
+        }
+        let num_heads = read_u64(input)? as usize;
+        let mut heads = Vec::with_capacity(num_heads.min(input.len() / 32));
+        for _ in 0..num_heads {
+            let head: [u8; 32] = input.get(..32)?.try_into().ok()?;
+            heads.push(ChangeHash(head));
+            *input = &input[32..];
+        }
+        entries.insert(id, ArchiveEntry { heads, len, offset });
+    }
+    input.is_empty().then_some(entries)
+}
+
+/// Read the index whose footer ends at `footer_end`, `None` if there is no valid footer there
+fn read_index_at<F: Read + Seek>(
+    file: &mut F,
+    footer_end: u64,
+) -> Result<Option<BTreeMap<String, ArchiveEntry>>, ArchiveError> {
+    let Some(footer_start) = footer_end.checked_sub(FOOTER_LEN) else {
+        return Ok(None);
+    };
+    if footer_start < HEADER.len() as u64 {
+        return Ok(None);
+    }
+    let mut footer = [0; FOOTER_LEN as usize];
+    file.seek(SeekFrom::Start(footer_start))?;
+    file.read_exact(&mut footer)?;
+    if footer[20..] != FOOTER_MAGIC {
+        return Ok(None);
+    }
+    let index_offset = u64::from_le_bytes(footer[0..8].try_into().unwrap());
+    let index_len = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+    if index_offset < HEADER.len() as u64
+        || index_offset.checked_add(index_len) != Some(footer_start)
+    {
+        return Ok(None);
+    }
+    let mut index = vec![0; index_len as usize];
+    file.seek(SeekFrom::Start(index_offset))?;
+    file.read_exact(&mut index)?;
+    if checksum(&index) != footer[16..20] {
+        return Ok(None);
+    }
+    Ok(decode_index(&index, index_offset))
+}
+
+/// Search backwards from `file_len` for the last valid footer
+///
+/// The file is read a block at a time from the end, so only the part of the file after the last
+/// valid footer, which is usually a single torn write, has to be read.
+fn find_last_index<F: Read + Seek>(
+    file: &mut F,
+    file_len: u64,
+) -> Result<(BTreeMap<String, ArchiveEntry>, u64), ArchiveError> {
+    let data_start = HEADER.len() as u64;
+    // Blocks overlap by less than the length of the magic bytes so that magic bytes which
+    // straddle two blocks are found exactly once
+    let overlap = FOOTER_MAGIC.len() as u64 - 1;
+    let mut block_end = file_len;
+    let mut block = Vec::new();
+    while block_end > data_start + overlap {
+        let block_start = block_end.saturating_sub(SCAN_BLOCK_LEN).max(data_start);
+        block.resize((block_end - block_start) as usize, 0);
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        let candidates = block
+            .windows(FOOTER_MAGIC.len())
+            .enumerate()
+            .rev()
+            .filter(|(_, window)| *window == FOOTER_MAGIC)
+            .map(|(i, _)| block_start + (i + FOOTER_MAGIC.len()) as u64)
+            .collect::<Vec<_>>();
+        for end in candidates {
+            if let Some(entries) = read_index_at(file, end)? {
+                return Ok((entries, end));
+            }
+        }
+        block_end = block_start + overlap;
+    }
+    Err(ArchiveError::Corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{transaction::Transactable, AutoCommit, ReadDoc, ROOT};
+
+    fn doc(value: &str) -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "value", value).unwrap();
+        doc
+    }
+
+    fn value(doc: &Automerge) -> String {
+        doc.get(ROOT, "value").unwrap().unwrap().0.to_string()
+    }
+
+    #[test]
+    fn put_and_get_documents() {
+        let mut archive = Archive::create(Cursor::new(Vec::new())).unwrap();
+        for i in 0..20 {
+            archive
+                .put(&format!("doc-{}", i), doc(&i.to_string()).document())
+                .unwrap();
+        }
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(archive.len(), 20);
+        assert_eq!(value(&archive.load("doc-7").unwrap().unwrap()), "\"7\"");
+        assert!(archive.load("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn replacing_a_document_appends_to_the_file() {
+        let mut archive = Archive::create(Cursor::new(Vec::new())).unwrap();
+        archive.put("a", doc("a1").document()).unwrap();
+        archive.put("b", doc("b1").document()).unwrap();
+        let before = archive.file.get_ref().clone();
+        let b_entry = archive.entry("b").unwrap().clone();
+
+        let mut a2 = doc("a2");
+        archive.put("a", a2.document()).unwrap();
+
+        // Everything before the old index is untouched
+        let after = archive.file.get_ref().clone();
+        let data_end = (b_entry.offset + b_entry.len) as usize;
+        assert_eq!(before[..data_end], after[..data_end]);
+
+        let mut archive = Archive::open(Cursor::new(after)).unwrap();
+        assert_eq!(archive.entry("a").unwrap().heads, a2.get_heads());
+        assert_eq!(value(&archive.load("a").unwrap().unwrap()), "\"a2\"");
+        assert_eq!(value(&archive.load("b").unwrap().unwrap()), "\"b1\"");
+    }
+
+    #[test]
+    fn interrupted_write_falls_back_to_previous_index() {
+        let mut archive = Archive::create(Cursor::new(Vec::new())).unwrap();
+        archive.put("a", doc("a1").document()).unwrap();
+        let complete_len = archive.file.get_ref().len();
+        archive.put("b", doc("b1").document()).unwrap();
+
+        let mut data = archive.into_inner().into_inner();
+        data.truncate(data.len() - 3);
+        let mut archive = Archive::open(Cursor::new(data)).unwrap();
+        assert_eq!(archive.ids().collect::<Vec<_>>(), vec!["a"]);
+
+        // The next write replaces the torn one
+        archive.put("c", doc("c1").document()).unwrap();
+        assert_eq!(
+            archive.file.get_ref().len() as u64,
+            archive.end,
+            "writing didn't start at the end of the last complete index"
+        );
+        assert!(archive.file.get_ref().len() > complete_len);
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(archive.ids().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(value(&archive.load("c").unwrap().unwrap()), "\"c1\"");
+    }
+
+    #[test]
+    fn batches_write_one_index() {
+        let mut archive = Archive::create(Cursor::new(Vec::new())).unwrap();
+        archive.put("a", doc("a1").document()).unwrap();
+        let docs = (0..10)
+            .map(|i| doc(&i.to_string()).document().clone())
+            .collect::<Vec<_>>();
+
+        let mut batch = archive.batch();
+        for (i, doc) in docs.iter().enumerate() {
+            batch.put(&format!("doc-{}", i), doc).unwrap();
+        }
+        assert!(batch.remove("a"));
+        batch.commit().unwrap();
+
+        // The documents followed by a single index and footer
+        let data_len = docs.iter().map(|d| d.save().len()).sum::<usize>();
+        let index_len = encode_index(&archive.entries).len() as u64;
+        let before = archive.entries.values().map(|e| e.offset).min().unwrap();
+        assert_eq!(
+            archive.end,
+            before + data_len as u64 + index_len + FOOTER_LEN
+        );
+
+        let mut reopened = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(reopened.len(), 10);
+        assert!(reopened.entry("a").is_none());
+        assert_eq!(value(&reopened.load("doc-3").unwrap().unwrap()), "\"3\"");
+
+        // A batch which isn't committed changes nothing
+        let mut batch = reopened.batch();
+        batch.put("b", doc("b1").document()).unwrap();
+        assert!(batch.remove("doc-3"));
+        drop(batch);
+        assert!(reopened.entry("b").is_none());
+        assert!(reopened.entry("doc-3").is_some());
+        reopened.put("c", doc("c1").document()).unwrap();
+        let mut reopened = Archive::open(reopened.into_inner()).unwrap();
+        assert_eq!(reopened.len(), 11);
+        assert_eq!(value(&reopened.load("c").unwrap().unwrap()), "\"c1\"");
+    }
+
+    #[test]
+    fn finds_an_index_more_than_a_block_from_the_end() {
+        let mut archive = Archive::create(Cursor::new(Vec::new())).unwrap();
+        archive.put("a", doc("a1").document()).unwrap();
+        let mut data = archive.into_inner().into_inner();
+        let complete_len = data.len();
+        // A torn write several blocks long, with the magic bytes straddling a block boundary
+        data.resize(complete_len + 3 * SCAN_BLOCK_LEN as usize, 0xab);
+        let straddle = data.len() - SCAN_BLOCK_LEN as usize - 2;
+        data[straddle..straddle + 4].copy_from_slice(&FOOTER_MAGIC);
+
+        let mut archive = Archive::open(Cursor::new(data)).unwrap();
+        assert_eq!(archive.end, complete_len as u64);
+        assert_eq!(value(&archive.load("a").unwrap().unwrap()), "\"a1\"");
+    }
+
+    #[test]
+    fn remove_and_compact() {
+        let mut archive = Archive::create(Cursor::new(Vec::new())).unwrap();
+        archive.put("a", doc("a1").document()).unwrap();
+        archive.put("a", doc("a2").document()).unwrap();
+        archive.put("b", doc("b1").document()).unwrap();
+        assert!(archive.remove("b").unwrap());
+        assert!(!archive.remove("b").unwrap());
+
+        let before = archive.file.get_ref().len();
+        let compacted = archive.compact_into(Cursor::new(Vec::new())).unwrap();
+        assert!(compacted.file.get_ref().len() < before);
+        assert_eq!(compacted.ids().collect::<Vec<_>>(), vec!["a"]);
+
+        let mut reopened = Archive::open(compacted.into_inner()).unwrap();
+        assert_eq!(value(&reopened.load("a").unwrap().unwrap()), "\"a2\"");
+    }
+
+    #[test]
+    fn not_an_archive() {
+        assert!(matches!(
+            Archive::open(Cursor::new(b"hello".to_vec())),
+            Err(ArchiveError::NotAnArchive)
+        ));
+    }
+}
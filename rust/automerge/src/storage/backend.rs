@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A key in a [`Backend`] and the value stored under it
+pub type Entry = (Vec<String>, Vec<u8>);
+
+/// A key value store which a [`DocumentStore`](super::DocumentStore) can persist documents to
+///
+/// Keys are paths made up of string components. Implementations must treat a key as a prefix of
+/// any longer key which starts with the same components, so that [`Self::load_range()`] can find
+/// all the data for a document. The components used by [`DocumentStore`](super::DocumentStore)
+/// are document IDs chosen by the application, and hex strings.
+pub trait Backend {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Load every key which starts with `prefix`, along with its value
+    fn load_range(&self, prefix: &[String]) -> Result<Vec<Entry>, Self::Error>;
+
+    /// Store `data` under `key`, replacing any existing value
+    ///
+    /// Once this returns the data must be visible to [`Self::load_range()`] in its entirety, a
+    /// concurrent reader must never see a partially written value.
+    fn put(&mut self, key: &[String], data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Remove `key`, doing nothing if it doesn't exist
+    fn remove(&mut self, key: &[String]) -> Result<(), Self::Error>;
+}
+
+/// A [`Backend`] which keeps everything in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    data: BTreeMap<Vec<String>, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of keys in the store
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Backend for MemoryBackend {
+    type Error = Infallible;
+
+    fn load_range(&self, prefix: &[String]) -> Result<Vec<Entry>, Self::Error> {
+        Ok(self
+            .data
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn put(&mut self, key: &[String], data: &[u8]) -> Result<(), Self::Error> {
+        self.data.insert(key.to_vec(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[String]) -> Result<(), Self::Error> {
+        self.data.remove(key);
+        Ok(())
+    }
+}
+
+/// A [`Backend`] which stores each key as a file in a directory tree
+///
+/// The components of a key are the directories leading to the file, so `["doc", "snapshot",
+/// "abc"]` is stored at `<root>/doc/snapshot/abc`. Components must be valid file names: they
+/// can't be empty, `.` or `..`, or contain path separators.
+///
+/// Values are written to a temporary file which is then renamed into place, so readers never see
+/// partially written values.
+#[derive(Debug, Clone)]
+pub struct FsBackend {
+    root: PathBuf,
+}
+
+// Suffix of the files values are written to before being renamed into place, these are skipped
+// by `load_range`
+const TEMP_SUFFIX: &str = ".tmp";
+
+impl FsBackend {
+    /// Create a backend storing files under `root`, which is created if it doesn't exist
+    pub fn new<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, key: &[String]) -> io::Result<PathBuf> {
+        let mut path = self.root.clone();
+        for component in key {
+            if component.is_empty()
+                || component == "."
+                || component == ".."
+                || component.contains(['/', '\\'])
+                || component.ends_with(TEMP_SUFFIX)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid key component {:?}", component),
+                ));
+            }
+            path.push(component);
+        }
+        Ok(path)
+    }
+
+    fn load_dir(dir: &Path, key: &mut Vec<String>, out: &mut Vec<Entry>) -> io::Result<()> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name.ends_with(TEMP_SUFFIX) {
+                continue;
+            }
+            key.push(name);
+            if entry.file_type()?.is_dir() {
+                Self::load_dir(&entry.path(), key, out)?;
+            } else {
+                match std::fs::read(entry.path()) {
+                    Ok(data) => out.push((key.clone(), data)),
+                    // removed since we listed the directory
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            key.pop();
+        }
+        Ok(())
+    }
+}
+
+impl Backend for FsBackend {
+    type Error = io::Error;
+
+    fn load_range(&self, prefix: &[String]) -> Result<Vec<Entry>, Self::Error> {
+        let path = self.path(prefix)?;
+        let mut out = Vec::new();
+        if path.is_file() {
+            out.push((prefix.to_vec(), std::fs::read(&path)?));
+        } else {
+            Self::load_dir(&path, &mut prefix.to_vec(), &mut out)?;
+        }
+        out.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(out)
+    }
+
+    fn put(&mut self, key: &[String], data: &[u8]) -> Result<(), Self::Error> {
+        let path = self.path(key)?;
+        let Some(dir) = path.parent() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty key"));
+        };
+        std::fs::create_dir_all(dir)?;
+        let mut temp = path.clone().into_os_string();
+        temp.push(format!(
+            ".{}-{:x}{}",
+            std::process::id(),
+            rand::random::<u32>(),
+            TEMP_SUFFIX
+        ));
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&temp, &path)?;
+        sync_parent(&path)
+    }
+
+    fn remove(&mut self, key: &[String]) -> Result<(), Self::Error> {
+        match std::fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Sync the directory holding `path`, so a file created or renamed there survives a crash
+#[cfg(unix)]
+pub(super) fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::File::open(dir)?.sync_all(),
+        _ => std::fs::File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(components: &[&str]) -> Vec<String> {
+        components.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn check_backend<B: Backend>(backend: &mut B) {
+        backend.put(&key(&["a", "x", "1"]), b"one").unwrap();
+        backend.put(&key(&["a", "y", "2"]), b"two").unwrap();
+        backend.put(&key(&["ab", "x", "1"]), b"other").unwrap();
+        backend.put(&key(&["a", "x", "1"]), b"replaced").unwrap();
+
+        assert_eq!(
+            backend.load_range(&key(&["a"])).unwrap(),
+            vec![
+                (key(&["a", "x", "1"]), b"replaced".to_vec()),
+                (key(&["a", "y", "2"]), b"two".to_vec()),
+            ]
+        );
+        assert_eq!(backend.load_range(&key(&["a", "y"])).unwrap().len(), 1);
+        assert!(backend.load_range(&key(&["b"])).unwrap().is_empty());
+
+        backend.remove(&key(&["a", "x", "1"])).unwrap();
+        backend.remove(&key(&["a", "x", "1"])).unwrap();
+        assert_eq!(
+            backend.load_range(&key(&["a"])).unwrap(),
+            vec![(key(&["a", "y", "2"]), b"two".to_vec())]
+        );
+    }
+
+    #[test]
+    fn memory_backend() {
+        check_backend(&mut MemoryBackend::new());
+    }
+
+    #[test]
+    fn fs_backend() {
+        let root = std::env::temp_dir().join(format!(
+            "automerge-fs-backend-{}-{}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let mut backend = FsBackend::new(&root).unwrap();
+        check_backend(&mut backend);
+        assert!(backend.put(&key(&["a", ".."]), b"escape").is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
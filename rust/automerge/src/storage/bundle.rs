@@ -155,7 +155,7 @@ impl<'a> TryFrom<&'a [u8]> for Bundle {
         let input = parse::Input::new(bytes);
         let (i, header) = Header::parse::<crate::storage::chunk::error::Header>(input)
             .map_err(|e| InvalidBundle(format!("invalid header: {}", e)))?;
-        let (_i, bundle) = BundleStorage::parse_following_header(i, header)
+        let (_i, bundle) = BundleStorage::parse_following_header(i, header, None)
             .map_err(|e| InvalidBundle(format!("invalid contents: {}", e)))?;
         let verified = bundle
             .verify()
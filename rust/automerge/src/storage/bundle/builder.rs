@@ -11,7 +11,7 @@ use crate::op_set2::types::{Action, ActorIdx, KeyRef};
 use crate::op_set2::{ReadOpError, ScalarValue};
 use crate::storage::change::DEFLATE_MIN_SIZE;
 use crate::storage::columns::{compression, ColumnType};
-use crate::storage::{ChunkType, Header, RawColumn, RawColumns};
+use crate::storage::{ChunkType, Codec, Header, RawColumn, RawColumns};
 use crate::types::{ChangeHash, ObjId, OpId};
 
 use super::{Bundle, BundleChange, BundleMetadata, BundleStorage, ParseError};
@@ -198,12 +198,17 @@ impl<'a> BundleBuilder<'a> {
             &change_data_buf,
             &mut compressed_change_data,
             DEFLATE_MIN_SIZE,
+            Codec::Deflate,
         );
         changes_meta_c.write(&mut data_c);
         data_c.extend_from_slice(&compressed_change_data);
         let mut compressed_ops_data = Vec::new();
-        let ops_meta_c =
-            ops_meta.compress(&ops_data_buf, &mut compressed_ops_data, DEFLATE_MIN_SIZE);
+        let ops_meta_c = ops_meta.compress(
+            &ops_data_buf,
+            &mut compressed_ops_data,
+            DEFLATE_MIN_SIZE,
+            Codec::Deflate,
+        );
         ops_meta_c.write(&mut data_c);
         data_c.extend_from_slice(&compressed_ops_data);
 
@@ -36,6 +36,17 @@ pub(crate) enum ParseError {
     InverseLengthMismatch,
 }
 
+impl ParseError {
+    /// An error decompressing the change columns, or the op columns if `changes` is false
+    pub(super) fn uncompress(error: raw_column::ParseError, changes: bool) -> Self {
+        match error {
+            raw_column::ParseError::LimitExceeded(_) => Self::ParseColumns(error),
+            _ if changes => Self::CompressedChangeCols,
+            _ => Self::CompressedOpCols,
+        }
+    }
+}
+
 impl<E: Into<ParseError>> From<parse::ParseError<E>> for ParseError {
     fn from(e: parse::ParseError<E>) -> ParseError {
         match e {
@@ -6,7 +6,7 @@ use crate::op_set2::change::ChangeCollector;
 use crate::storage::change::{OpReadState, Unverified, Verified};
 use crate::storage::columns::compression;
 use crate::storage::columns::{ColumnId, ColumnType};
-use crate::storage::{parse, Header, RawColumns};
+use crate::storage::{parse, Codec, Header, RawColumns};
 use crate::types::{ActorId, ChangeHash};
 use crate::Change;
 
@@ -151,6 +151,7 @@ impl<'a> BundleStorage<'a, Unverified> {
     pub(crate) fn parse_following_header(
         input: parse::Input<'a>,
         header: Header,
+        max_column_size: Option<usize>,
     ) -> parse::ParseResult<'a, BundleStorage<'a, Unverified>, ParseError> {
         // `input.bytes()` returns the full chunk (header + body); positions
         // tracked by the parser are absolute offsets within that buffer.
@@ -223,8 +224,10 @@ impl<'a> BundleStorage<'a, Unverified> {
             .uncompress(
                 &full_bytes[changes_data_range.clone()],
                 &mut changes_data_buf,
+                max_column_size,
+                Codec::Deflate,
             )
-            .map_err(|_| parse::ParseError::Error(ParseError::CompressedChangeCols))?;
+            .map_err(|e| parse::ParseError::Error(ParseError::uncompress(e, true)))?;
         changes_meta.write(&mut out);
         let new_changes_start = out.len();
         out.extend_from_slice(&changes_data_buf);
@@ -232,8 +235,13 @@ impl<'a> BundleStorage<'a, Unverified> {
 
         let mut ops_data_buf = Vec::new();
         let ops_meta = ops_meta_raw
-            .uncompress(&full_bytes[ops_data_range.clone()], &mut ops_data_buf)
-            .map_err(|_| parse::ParseError::Error(ParseError::CompressedOpCols))?;
+            .uncompress(
+                &full_bytes[ops_data_range.clone()],
+                &mut ops_data_buf,
+                max_column_size,
+                Codec::Deflate,
+            )
+            .map_err(|e| parse::ParseError::Error(ParseError::uncompress(e, false)))?;
         ops_meta.write(&mut out);
         let new_ops_start = out.len();
         out.extend_from_slice(&ops_data_buf);
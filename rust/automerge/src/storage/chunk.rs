@@ -7,7 +7,10 @@ use std::{
 
 use sha2::{Digest, Sha256};
 
-use super::{change::Unverified, parse, BundleStorage, Change, Compressed, Document, MAGIC_BYTES};
+use super::{
+    change::Unverified, parse, BundleStorage, Change, Compressed, Document, Limit, LimitExceeded,
+    MAGIC_BYTES,
+};
 use crate::{columnar::encoding::leb128::ulebsize, ChangeHash};
 
 pub(crate) enum Chunk<'a> {
@@ -19,7 +22,7 @@ pub(crate) enum Chunk<'a> {
 
 pub(crate) mod error {
     use super::parse;
-    use crate::storage::{bundle, change, document};
+    use crate::storage::{bundle, change, columns::raw_column, document, LimitExceeded};
 
     #[derive(thiserror::Error, Debug)]
     pub(crate) enum Chunk {
@@ -37,6 +40,24 @@ pub(crate) mod error {
         Document(#[from] document::ParseError),
         #[error("unable to decompresse compressed chunk")]
         Deflate,
+        #[error(transparent)]
+        LimitExceeded(#[from] LimitExceeded),
+    }
+
+    impl Chunk {
+        /// The limit which was exceeded, if this error is because of a decompression limit
+        pub(crate) fn limit_exceeded(&self) -> Option<LimitExceeded> {
+            match self {
+                Self::LimitExceeded(e)
+                | Self::Document(document::ParseError::RawColumns(
+                    raw_column::ParseError::LimitExceeded(e),
+                ))
+                | Self::Bundle(bundle::ParseError::ParseColumns(
+                    raw_column::ParseError::LimitExceeded(e),
+                )) => Some(*e),
+                _ => None,
+            }
+        }
     }
 
     #[derive(thiserror::Error, Debug)]
@@ -53,6 +74,15 @@ pub(crate) mod error {
 impl<'a> Chunk<'a> {
     pub(crate) fn parse(
         input: parse::Input<'a>,
+    ) -> parse::ParseResult<'a, Chunk<'a>, error::Chunk> {
+        Self::parse_limited(input, None)
+    }
+
+    /// Like [`Self::parse()`] but fail if any decompressed column, or a decompressed change
+    /// chunk, would be larger than `max_column_size`
+    pub(crate) fn parse_limited(
+        input: parse::Input<'a>,
+        max_column_size: Option<usize>,
     ) -> parse::ParseResult<'a, Chunk<'a>, error::Chunk> {
         let (i, header) = Header::parse::<error::Chunk>(input)?;
         let parse::Split {
@@ -69,9 +99,9 @@ impl<'a> Chunk<'a> {
                 }
                 Chunk::Change(change)
             }
-            ChunkType::Document => {
+            ChunkType::Document | ChunkType::DocumentWithCodec => {
                 let (remaining, doc) =
-                    Document::parse(chunk_input, header).map_err(|e| e.lift())?;
+                    Document::parse(chunk_input, header, max_column_size).map_err(|e| e.lift())?;
                 if !remaining.is_empty() {
                     return Err(parse::ParseError::Error(error::Chunk::LeftoverData));
                 }
@@ -79,11 +109,21 @@ impl<'a> Chunk<'a> {
             }
             ChunkType::Compressed => {
                 let compressed = &input.unconsumed_bytes()[header.data_bytes()];
-                let mut decoder = flate2::bufread::DeflateDecoder::new(compressed);
+                let decoder = flate2::bufread::DeflateDecoder::new(compressed);
                 let mut decompressed = Vec::new();
+                let max = max_column_size.map(|max| max as u64).unwrap_or(u64::MAX);
                 decoder
+                    .take(max.saturating_add(1))
                     .read_to_end(&mut decompressed)
                     .map_err(|_| parse::ParseError::Error(error::Chunk::Deflate))?;
+                if let Some(max) = max_column_size.filter(|max| decompressed.len() > *max) {
+                    return Err(parse::ParseError::Error(error::Chunk::LimitExceeded(
+                        LimitExceeded {
+                            limit: Limit::ColumnSize,
+                            max,
+                        },
+                    )));
+                }
                 let inner_header = header.with_data(ChunkType::Change, &decompressed);
                 let mut inner_chunk = Vec::with_capacity(inner_header.len() + decompressed.len());
                 inner_header.write(&mut inner_chunk);
@@ -100,7 +140,7 @@ impl<'a> Chunk<'a> {
             }
             ChunkType::Bundle => {
                 let (remaining, bundle) =
-                    BundleStorage::parse_following_header(chunk_input, header)
+                    BundleStorage::parse_following_header(chunk_input, header, max_column_size)
                         .map_err(|e| e.lift())?;
                 if !remaining.is_empty() {
                     return Err(parse::ParseError::Error(error::Chunk::LeftoverData));
@@ -129,6 +169,11 @@ pub(crate) enum ChunkType {
     Change,
     Compressed,
     Bundle,
+    /// A document chunk whose data starts with a byte identifying the [`Codec`] used to compress
+    /// its columns, rather than DEFLATE
+    ///
+    /// [`Codec`]: super::Codec
+    DocumentWithCodec,
 }
 
 impl TryFrom<u8> for ChunkType {
@@ -140,6 +185,7 @@ impl TryFrom<u8> for ChunkType {
             1 => Ok(Self::Change),
             2 => Ok(Self::Compressed),
             3 => Ok(Self::Bundle),
+            4 => Ok(Self::DocumentWithCodec),
             other => Err(other),
         }
     }
@@ -152,6 +198,7 @@ impl From<ChunkType> for u8 {
             ChunkType::Change => 1,
             ChunkType::Compressed => 2,
             ChunkType::Bundle => 3,
+            ChunkType::DocumentWithCodec => 4,
         }
     }
 }
@@ -229,6 +276,10 @@ impl Header {
         self.header_size
     }
 
+    pub(crate) fn chunk_type(&self) -> ChunkType {
+        self.chunk_type
+    }
+
     pub(crate) fn write(&self, out: &mut Vec<u8>) {
         out.extend(MAGIC_BYTES);
         out.extend(self.checksum.bytes());
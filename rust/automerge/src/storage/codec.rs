@@ -0,0 +1,224 @@
+use std::io::{self, Read};
+
+/// The compression codec used for the columns of a saved document
+///
+/// Columns smaller than a few hundred bytes are never compressed. [`Self::Deflate`] is the
+/// default and produces documents which every version of automerge can load. Documents saved with
+/// `Zstd` or `Lz4` record the codec in the chunk header, so they can only be loaded by versions
+/// of automerge which know about it. Older versions fail with an unknown chunk type error.
+///
+/// `Zstd` and `Lz4` are only compiled in with the `zstd` and `lz4` cargo features, see
+/// [`Self::is_available()`]. Without them saving or loading a document which uses one of those
+/// codecs fails with [`CodecNotCompiledIn`].
+///
+/// See [`SaveOptions::codec`](crate::SaveOptions::codec).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Codec {
+    /// Don't compress columns at all
+    None,
+    /// DEFLATE, as in RFC 1951
+    #[default]
+    Deflate,
+    /// Zstandard, which usually produces smaller documents which are faster to load than DEFLATE
+    Zstd,
+    /// The LZ4 frame format, which compresses less than the others but is the fastest to
+    /// decompress
+    Lz4,
+}
+
+/// A document was saved or loaded with a [`Codec`] which wasn't compiled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("the {} codec is not compiled in, enable the `{}` feature of automerge", .0.name(), .0.name())]
+pub struct CodecNotCompiledIn(Codec);
+
+impl CodecNotCompiledIn {
+    /// The codec which isn't compiled in
+    pub fn codec(&self) -> Codec {
+        self.0
+    }
+}
+
+impl Codec {
+    /// Whether this codec was compiled in, which depends on the cargo features of automerge
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::None | Self::Deflate => true,
+            Self::Zstd => cfg!(feature = "zstd"),
+            Self::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    /// Fail with [`CodecNotCompiledIn`] if this codec isn't available
+    pub(crate) fn check_available(self) -> Result<(), CodecNotCompiledIn> {
+        if self.is_available() {
+            Ok(())
+        } else {
+            Err(CodecNotCompiledIn(self))
+        }
+    }
+
+    /// The name of the codec, which is also the name of the cargo feature for optional codecs
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+            Self::Zstd => "zstd",
+            Self::Lz4 => "lz4",
+        }
+    }
+
+    /// Compress `input` onto the end of `out`, returning the number of bytes written
+    ///
+    /// # Panics
+    ///
+    /// If the codec isn't available, callers should check with [`Self::check_available()`] first
+    pub(crate) fn compress(self, input: &[u8], out: &mut Vec<u8>) -> usize {
+        let start = out.len();
+        // These unwraps are fine as we're reading from and writing to in memory buffers
+        match self {
+            Self::None => out.extend(input),
+            Self::Deflate => {
+                let mut deflater =
+                    flate2::bufread::DeflateEncoder::new(input, flate2::Compression::default());
+                deflater.read_to_end(out).unwrap();
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => ruzstd::encoding::compress(
+                input,
+                &mut *out,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            ),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                use std::io::Write;
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut *out);
+                encoder.write_all(input).unwrap();
+                encoder.finish().unwrap();
+            }
+            #[allow(unreachable_patterns)]
+            codec => panic!("{}", CodecNotCompiledIn(codec)),
+        }
+        out.len() - start
+    }
+
+    /// Decompress `input` onto the end of `out`, returning the number of bytes written
+    ///
+    /// At most `max_len + 1` bytes are written, so that callers can tell whether the decompressed
+    /// data is longer than `max_len`.
+    pub(crate) fn decompress(
+        self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        max_len: Option<usize>,
+    ) -> Result<usize, io::Error> {
+        let max = max_len
+            .map(|max| (max as u64).saturating_add(1))
+            .unwrap_or(u64::MAX);
+        match self {
+            Self::None => {
+                let len = input.len().min(max.try_into().unwrap_or(usize::MAX));
+                out.extend(&input[..len]);
+                Ok(len)
+            }
+            Self::Deflate => flate2::bufread::DeflateDecoder::new(input)
+                .take(max)
+                .read_to_end(out),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => ruzstd::decoding::StreamingDecoder::new(input)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+                .take(max)
+                .read_to_end(out),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => lz4_flex::frame::FrameDecoder::new(input)
+                .take(max)
+                .read_to_end(out),
+            #[allow(unreachable_patterns)]
+            codec => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                CodecNotCompiledIn(codec),
+            )),
+        }
+    }
+}
+
+impl From<Codec> for u8 {
+    fn from(codec: Codec) -> Self {
+        match codec {
+            Codec::None => 0,
+            Codec::Deflate => 1,
+            Codec::Zstd => 2,
+            Codec::Lz4 => 3,
+        }
+    }
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Deflate),
+            2 => Ok(Self::Zstd),
+            3 => Ok(Self::Lz4),
+            other => Err(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codecs_round_trip() {
+        let data = b"some column data ".repeat(100);
+        let codecs = [
+            Codec::None,
+            Codec::Deflate,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4,
+        ];
+        for codec in codecs {
+            let mut compressed = Vec::new();
+            let len = codec.compress(&data, &mut compressed);
+            assert_eq!(len, compressed.len());
+            let mut decompressed = Vec::new();
+            codec
+                .decompress(&compressed, &mut decompressed, None)
+                .unwrap();
+            assert_eq!(decompressed, data, "{:?}", codec);
+            assert_eq!(Codec::try_from(u8::from(codec)), Ok(codec));
+
+            let mut limited = Vec::new();
+            let len = codec
+                .decompress(&compressed, &mut limited, Some(10))
+                .unwrap();
+            assert_eq!(len, 11, "{:?}", codec);
+        }
+        assert_eq!(Codec::try_from(4), Err(4));
+    }
+
+    #[test]
+    fn optional_codecs_are_known_without_their_features() {
+        assert_eq!(Codec::try_from(2), Ok(Codec::Zstd));
+        assert_eq!(Codec::try_from(3), Ok(Codec::Lz4));
+        assert_eq!(Codec::Zstd.is_available(), cfg!(feature = "zstd"));
+        assert_eq!(Codec::Lz4.is_available(), cfg!(feature = "lz4"));
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            if codec.is_available() {
+                continue;
+            }
+            let err = codec.check_available().unwrap_err();
+            assert_eq!(err.codec(), codec);
+            assert!(err.to_string().contains("is not compiled in"), "{}", err);
+            let err = codec
+                .decompress(&[0; 16], &mut Vec::new(), None)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
-use std::{io::Read, marker::PhantomData, ops::Range};
+use std::{marker::PhantomData, ops::Range};
 
-use crate::storage::parse;
+use crate::storage::{parse, Codec, Limit, LimitExceeded};
 
 use super::{compression, ColumnSpec};
 
@@ -59,18 +59,25 @@ impl<T: compression::ColumnCompression> RawColumn<T> {
         self.data.clone()
     }
 
-    fn compress(&self, input: &[u8], out: &mut Vec<u8>, threshold: usize) -> (ColumnSpec, usize) {
-        let (spec, len) = if self.data.len() < threshold || self.spec.deflate() {
-            out.extend(&input[self.data.clone()]);
-            (self.spec, self.data.len())
-        } else {
-            let mut deflater = flate2::bufread::DeflateEncoder::new(
-                &input[self.data.clone()],
-                flate2::Compression::default(),
-            );
-            //This unwrap should be okay as we're reading and writing to in memory buffers
-            (self.spec.deflated(), deflater.read_to_end(out).unwrap())
-        };
+    /// Write this column to `out`, compressing it with `codec` if it is at least `threshold`
+    /// bytes long. Compressed columns have the deflate bit set in their spec, whatever the codec.
+    fn compress(
+        &self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        threshold: usize,
+        codec: Codec,
+    ) -> (ColumnSpec, usize) {
+        let (spec, len) =
+            if self.data.len() < threshold || self.spec.deflate() || codec == Codec::None {
+                out.extend(&input[self.data.clone()]);
+                (self.spec, self.data.len())
+            } else {
+                (
+                    self.spec.deflated(),
+                    codec.compress(&input[self.data.clone()], out),
+                )
+            };
         (spec, len)
     }
 
@@ -90,10 +97,20 @@ impl<T: compression::ColumnCompression> RawColumn<T> {
         &self,
         input: &[u8],
         out: &mut Vec<u8>,
+        max_len: Option<usize>,
+        codec: Codec,
     ) -> Result<(ColumnSpec, usize), ParseError> {
         let len = if self.spec.deflate() {
-            let mut inflater = flate2::bufread::DeflateDecoder::new(&input[self.data.clone()]);
-            inflater.read_to_end(out).map_err(ParseError::Deflate)?
+            let len = codec
+                .decompress(&input[self.data.clone()], out, max_len)
+                .map_err(ParseError::Decompress)?;
+            if let Some(max) = max_len.filter(|max| len > *max) {
+                return Err(ParseError::LimitExceeded(LimitExceeded {
+                    limit: Limit::ColumnSize,
+                    max,
+                }));
+            }
+            len
         } else {
             out.extend(&input[self.data.clone()]);
             self.data.len()
@@ -119,7 +136,8 @@ impl<T: compression::ColumnCompression> RawColumns<T> {
         Some(RawColumns(result))
     }
 
-    /// Write each column in `input` represented by `self` into `out`, possibly compressing.
+    /// Write each column in `input` represented by `self` into `out`, compressing columns which
+    /// are at least `threshold` bytes long with `codec`.
     ///
     /// # Returns
     /// The `RawColumns` corresponding to the data written to `out`
@@ -131,11 +149,18 @@ impl<T: compression::ColumnCompression> RawColumns<T> {
         input: &[u8],
         out: &mut Vec<u8>,
         threshold: usize,
+        codec: Codec,
     ) -> RawColumns<compression::Unknown> {
+        let compressed = crate::parallel::map(&self.0, |col| {
+            let mut out = Vec::new();
+            let (spec, _) = col.compress(input, &mut out, threshold, codec);
+            (spec, out)
+        });
         let mut result = Vec::with_capacity(self.0.len());
         let mut start = 0;
-        for col in &self.0 {
-            let (spec, len) = col.compress(input, out, threshold);
+        for (spec, data) in compressed {
+            let len = data.len();
+            out.extend(data);
             result.push(RawColumn {
                 spec,
                 data: start..(start + len),
@@ -147,25 +172,44 @@ impl<T: compression::ColumnCompression> RawColumns<T> {
     }
 
     /// Read each column from `input` and write to `out`, decompressing any compressed columns
+    /// with `codec`
     ///
     /// # Returns
     /// The `RawColumns` corresponding to the data written to `out`
     ///
+    /// Fails if any decompressed column would be longer than `max_column_size`
+    ///
     /// # Panics
     /// * If any of the ranges in `self` is outside the bounds of `input`
     pub(crate) fn uncompress(
         &self,
         input: &[u8],
         out: &mut Vec<u8>,
+        max_column_size: Option<usize>,
+        codec: Codec,
     ) -> Result<RawColumns<compression::Uncompressed>, ParseError> {
+        // Compressed columns are independent so they can be decompressed at the same time
+        let decompressed = crate::parallel::map(&self.0, |col| {
+            if col.spec.deflate() {
+                let mut out = Vec::new();
+                col.decompress(input, &mut out, max_column_size, codec)
+                    .map(|(spec, _)| (spec, Some(out)))
+            } else {
+                Ok((col.spec, None))
+            }
+        });
         let mut result = Vec::with_capacity(self.0.len());
         let mut start = 0;
-        for col in &self.0 {
-            let (spec, len) = if let Some(decomp) = col.uncompressed() {
-                out.extend(&input[decomp.data.clone()]);
-                (decomp.spec, decomp.data.len())
-            } else {
-                col.decompress(input, out)?
+        for (col, decompressed) in self.0.iter().zip(decompressed) {
+            let (spec, len) = match decompressed? {
+                (spec, Some(data)) => {
+                    out.extend(&data);
+                    (spec, data.len())
+                }
+                (spec, None) => {
+                    out.extend(&input[col.data.clone()]);
+                    (spec, col.data.len())
+                }
             };
             result.push(RawColumn {
                 spec,
@@ -210,8 +254,10 @@ pub(crate) enum ParseError {
     NotInNormalOrder,
     #[error(transparent)]
     Leb128(#[from] parse::leb128::Error),
+    #[error("unable to decompress column: {0}")]
+    Decompress(#[source] std::io::Error),
     #[error(transparent)]
-    Deflate(#[from] std::io::Error),
+    LimitExceeded(#[from] LimitExceeded),
 }
 
 impl RawColumns<compression::Unknown> {
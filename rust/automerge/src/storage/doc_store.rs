@@ -0,0 +1,414 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sha2::{Digest, Sha256};
+
+use super::Backend;
+use crate::{Automerge, AutomergeError, ChangeHash};
+
+const SNAPSHOT: &str = "snapshot";
+const INCREMENTAL: &str = "incremental";
+
+/// When a [`DocumentStore`] compacts the incremental chunks of a document into a snapshot
+///
+/// Compaction happens as soon as either limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+    /// The number of incremental chunks
+    pub max_chunks: usize,
+    /// The total size in bytes of the incremental chunks
+    pub max_bytes: usize,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            max_chunks: 64,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError<E: std::error::Error + 'static> {
+    #[error("storage backend error: {0}")]
+    Backend(#[source] E),
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+}
+
+/// Persists documents to a [`Backend`]
+///
+/// Each document is stored as some number of snapshots (the output of [`Automerge::save()`]) and
+/// incremental chunks (the output of [`Automerge::save_after()`]) under keys starting with the
+/// document ID. [`Self::save()`] appends a chunk containing the changes since the document was
+/// last loaded or saved. Once the chunks reach the limits of the [`CompactionPolicy`] they are
+/// replaced by a single snapshot.
+///
+/// ## Concurrency
+///
+/// Several stores, possibly in different processes, can share the same backend. Keys are derived
+/// from the content they hold, so two stores writing the same data write the same key, and
+/// compaction only removes keys which this store has loaded or written itself, and only once the
+/// document being compacted contains everything in them. A chunk appended by another store while
+/// we are compacting is therefore never lost: it is either already in the document we are
+/// compacting or it is left alone to be picked up by the next load. The same goes for saving a
+/// document which doesn't descend from what is stored, the data it doesn't contain stays put.
+///
+/// ## Example
+///
+/// ```
+/// use automerge::{storage::{DocumentStore, MemoryBackend}, transaction::Transactable, AutoCommit, ReadDoc};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut store = DocumentStore::new(MemoryBackend::new());
+///
+/// let mut doc = AutoCommit::new();
+/// doc.put(automerge::ROOT, "key", "value")?;
+/// store.save("my-doc", doc.document())?;
+///
+/// let loaded = store.load("my-doc")?.unwrap();
+/// assert_eq!(loaded.get(automerge::ROOT, "key")?.unwrap().0.to_str(), Some("value"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DocumentStore<B> {
+    backend: B,
+    policy: CompactionPolicy,
+    docs: HashMap<String, Stored>,
+}
+
+/// What this store knows has been persisted for a document
+#[derive(Debug, Default)]
+struct Stored {
+    /// Heads which together cover everything we have loaded or saved
+    heads: Vec<ChangeHash>,
+    /// Snapshot keys we have loaded or written
+    snapshots: BTreeMap<Vec<String>, Persisted>,
+    /// Incremental chunk keys we have loaded or written
+    chunks: BTreeMap<Vec<String>, Persisted>,
+}
+
+/// A key we have loaded or written
+#[derive(Debug)]
+struct Persisted {
+    /// Heads of a document which contains everything under the key
+    heads: Vec<ChangeHash>,
+    len: usize,
+}
+
+impl Persisted {
+    fn contained_in(&self, doc: &Automerge) -> bool {
+        self.heads.iter().all(|hash| doc.has_change(hash))
+    }
+}
+
+impl Stored {
+    fn chunk_bytes(&self) -> usize {
+        self.chunks.values().map(|chunk| chunk.len).sum()
+    }
+
+    /// Add `heads` to the heads we know are stored
+    fn cover(&mut self, doc: &Automerge) {
+        let heads = doc.get_heads();
+        if self.heads.iter().all(|hash| doc.has_change(hash)) {
+            self.heads = heads;
+        } else {
+            for head in heads {
+                if !self.heads.contains(&head) {
+                    self.heads.push(head);
+                }
+            }
+            self.heads.sort();
+        }
+    }
+}
+
+impl<B: Backend> DocumentStore<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            policy: CompactionPolicy::default(),
+            docs: HashMap::new(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: CompactionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    /// Load the document stored under `doc_id`, returning `None` if there is no data for it
+    pub fn load(&mut self, doc_id: &str) -> Result<Option<Automerge>, StoreError<B::Error>> {
+        let mut entries = self
+            .backend
+            .load_range(&[doc_id.to_string()])
+            .map_err(StoreError::Backend)?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        // Snapshots first so that the chunks are mostly applied on top of a loaded document
+        entries.sort_by_key(|(key, _)| key.get(1).map(String::as_str) != Some(SNAPSHOT));
+
+        let mut doc = Automerge::new();
+        let mut snapshots = Vec::new();
+        let mut chunks = Vec::new();
+        for (key, data) in entries {
+            match key.get(1).map(String::as_str) {
+                Some(SNAPSHOT) => snapshots.push((key, data.len())),
+                Some(INCREMENTAL) => chunks.push((key, data.len())),
+                _ => continue,
+            }
+            doc.load_incremental(&data)?;
+        }
+        // We don't know which changes each key holds, but the loaded document contains all of them
+        let heads = doc.get_heads();
+        let persisted = |(key, len)| {
+            (
+                key,
+                Persisted {
+                    heads: heads.clone(),
+                    len,
+                },
+            )
+        };
+        let stored = Stored {
+            snapshots: snapshots.into_iter().map(persisted).collect(),
+            chunks: chunks.into_iter().map(persisted).collect(),
+            heads,
+        };
+        self.docs.insert(doc_id.to_string(), stored);
+        Ok(Some(doc))
+    }
+
+    /// Store the changes in `doc` which haven't been loaded from or saved to this store yet
+    ///
+    /// This compacts the document if the [`CompactionPolicy`] says so.
+    pub fn save(&mut self, doc_id: &str, doc: &Automerge) -> Result<(), StoreError<B::Error>> {
+        let stored = self.docs.entry(doc_id.to_string()).or_default();
+        let heads = doc.get_heads();
+        if stored.heads == heads {
+            return Ok(());
+        }
+        // If the document doesn't descend from everything we stored only the heads it has tell us
+        // what is already persisted
+        let known = stored
+            .heads
+            .iter()
+            .filter(|hash| doc.has_change(hash))
+            .copied()
+            .collect::<Vec<_>>();
+        let data = doc.save_after(&known);
+        if data.is_empty() {
+            stored.cover(doc);
+            return Ok(());
+        }
+        let key = vec![
+            doc_id.to_string(),
+            INCREMENTAL.to_string(),
+            hex::encode(Sha256::digest(&data)),
+        ];
+        self.backend.put(&key, &data).map_err(StoreError::Backend)?;
+        stored.chunks.insert(
+            key,
+            Persisted {
+                heads,
+                len: data.len(),
+            },
+        );
+        stored.cover(doc);
+
+        if stored.chunks.len() >= self.policy.max_chunks
+            || stored.chunk_bytes() >= self.policy.max_bytes
+        {
+            self.compact(doc_id, doc)?;
+        }
+        Ok(())
+    }
+
+    /// Replace everything stored for `doc_id` with a single snapshot of `doc`
+    ///
+    /// Only the keys this store has loaded or written are removed, and only those whose contents
+    /// `doc` contains. Anything else stays alongside the new snapshot.
+    pub fn compact(&mut self, doc_id: &str, doc: &Automerge) -> Result<(), StoreError<B::Error>> {
+        let heads = doc.get_heads();
+        let data = doc.save();
+        let mut hasher = Sha256::new();
+        for head in &heads {
+            hasher.update(head.as_bytes());
+        }
+        let key = vec![
+            doc_id.to_string(),
+            SNAPSHOT.to_string(),
+            hex::encode(hasher.finalize()),
+        ];
+        self.backend.put(&key, &data).map_err(StoreError::Backend)?;
+
+        let stored = self.docs.entry(doc_id.to_string()).or_default();
+        for keys in [&mut stored.snapshots, &mut stored.chunks] {
+            let merged = keys
+                .iter()
+                .filter(|(merged, persisted)| **merged != key && persisted.contained_in(doc))
+                .map(|(merged, _)| merged.clone())
+                .collect::<Vec<_>>();
+            for merged in merged {
+                self.backend.remove(&merged).map_err(StoreError::Backend)?;
+                keys.remove(&merged);
+            }
+        }
+        stored.snapshots.insert(
+            key,
+            Persisted {
+                heads,
+                len: data.len(),
+            },
+        );
+        stored.cover(doc);
+        Ok(())
+    }
+
+    /// Remove everything stored for `doc_id`
+    pub fn remove(&mut self, doc_id: &str) -> Result<(), StoreError<B::Error>> {
+        let entries = self
+            .backend
+            .load_range(&[doc_id.to_string()])
+            .map_err(StoreError::Backend)?;
+        for (key, _) in entries {
+            self.backend.remove(&key).map_err(StoreError::Backend)?;
+        }
+        self.docs.remove(doc_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryBackend;
+    use crate::{transaction::Transactable, AutoCommit, ReadDoc, ROOT};
+
+    fn keys(store: &DocumentStore<MemoryBackend>, kind: &str) -> usize {
+        store
+            .backend()
+            .load_range(&["doc".to_string()])
+            .unwrap()
+            .into_iter()
+            .filter(|(key, _)| key[1] == kind)
+            .count()
+    }
+
+    #[test]
+    fn appends_chunks_and_compacts_at_the_threshold() {
+        let mut store = DocumentStore::new(MemoryBackend::new()).with_policy(CompactionPolicy {
+            max_chunks: 3,
+            max_bytes: usize::MAX,
+        });
+        let mut doc = AutoCommit::new();
+        for i in 0..2 {
+            doc.put(ROOT, "count", i).unwrap();
+            store.save("doc", doc.document()).unwrap();
+        }
+        // nothing new, nothing written
+        store.save("doc", doc.document()).unwrap();
+        assert_eq!(keys(&store, INCREMENTAL), 2);
+        assert_eq!(keys(&store, SNAPSHOT), 0);
+
+        doc.put(ROOT, "count", 2).unwrap();
+        store.save("doc", doc.document()).unwrap();
+        assert_eq!(keys(&store, INCREMENTAL), 0);
+        assert_eq!(keys(&store, SNAPSHOT), 1);
+
+        doc.put(ROOT, "count", 3).unwrap();
+        store.save("doc", doc.document()).unwrap();
+        let backend = store.into_backend();
+        let loaded = DocumentStore::new(backend).load("doc").unwrap().unwrap();
+        assert_eq!(loaded.get_heads(), doc.get_heads());
+        assert_eq!(
+            loaded.get(ROOT, "count").unwrap().unwrap().0.to_i64(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn compaction_keeps_chunks_appended_concurrently() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let mut store = DocumentStore::new(MemoryBackend::new());
+        store.save("doc", doc.document()).unwrap();
+
+        // Another store appends a chunk we haven't loaded
+        let mut other = doc.fork();
+        other.put(ROOT, "b", 2).unwrap();
+        let mut other_store = DocumentStore::new(store.backend().clone());
+        other_store.load("doc").unwrap();
+        other_store.save("doc", other.document()).unwrap();
+        *store.backend_mut() = other_store.into_backend();
+
+        store.compact("doc", doc.document()).unwrap();
+        assert_eq!(keys(&store, SNAPSHOT), 1);
+        assert_eq!(keys(&store, INCREMENTAL), 1);
+
+        let loaded = store.load("doc").unwrap().unwrap();
+        assert_eq!(loaded.get(ROOT, "a").unwrap().unwrap().0.to_i64(), Some(1));
+        assert_eq!(loaded.get(ROOT, "b").unwrap().unwrap().0.to_i64(), Some(2));
+    }
+
+    #[test]
+    fn saving_a_diverged_document_keeps_what_it_lacks() {
+        let mut store = DocumentStore::new(MemoryBackend::new());
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        store.save("doc", doc.document()).unwrap();
+
+        // a fork which doesn't have the latest change to `doc`
+        let mut fork = doc.fork();
+        doc.put(ROOT, "b", 2).unwrap();
+        store.save("doc", doc.document()).unwrap();
+        fork.put(ROOT, "c", 3).unwrap();
+        store.save("doc", fork.document()).unwrap();
+
+        // and an unrelated document
+        let mut unrelated = AutoCommit::new();
+        unrelated.put(ROOT, "d", 4).unwrap();
+        store.save("doc", unrelated.document()).unwrap();
+
+        store.compact("doc", fork.document()).unwrap();
+        store.compact("doc", unrelated.document()).unwrap();
+
+        let loaded = DocumentStore::new(store.into_backend())
+            .load("doc")
+            .unwrap()
+            .unwrap();
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3), ("d", 4)] {
+            assert_eq!(
+                loaded.get(ROOT, key).unwrap().unwrap().0.to_i64(),
+                Some(value),
+                "{}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn remove_deletes_all_keys() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let mut store = DocumentStore::new(MemoryBackend::new());
+        store.save("doc", doc.document()).unwrap();
+        store.compact("doc", doc.document()).unwrap();
+        store.remove("doc").unwrap();
+        assert!(store.backend().is_empty());
+        assert!(store.load("doc").unwrap().is_none());
+    }
+}
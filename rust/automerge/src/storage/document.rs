@@ -2,14 +2,15 @@ use hexane::PackError;
 use std::collections::BTreeSet;
 use std::{borrow::Cow, ops::Range};
 
-use super::{parse, shift_range, ChunkType, Header, RawColumns};
+use super::{parse, shift_range, ChunkType, Codec, Header, RawColumns};
 
 use crate::change_graph::{ChangeGraph, ChangeGraphCols};
 use crate::op_set2::change::{ChangeCollector, CollectedChanges, OutOfMemory};
-use crate::op_set2::op_set::MarkOrderValidator;
+use crate::op_set2::op_set::{Indexes, MarkOrderValidator};
 use crate::op_set2::{OpSet, ReadOpError};
 use crate::storage::columns::compression::Uncompressed;
-use crate::storage::ColumnSpec;
+use crate::storage::load::{change_collector, LoadCancelled, Progress};
+use crate::storage::{ColumnSpec, Limit, LimitExceeded, LoadLimits};
 use crate::{ActorId, Automerge, Change, ChangeHash, TextEncoding};
 
 mod compression;
@@ -17,7 +18,8 @@ mod compression;
 #[allow(dead_code)]
 pub(crate) enum CompressConfig {
     None,
-    Threshold(usize),
+    /// Compress columns which are at least this many bytes long with the given codec
+    Threshold(usize, Codec),
 }
 
 #[derive(Debug, Clone)]
@@ -45,6 +47,10 @@ pub(crate) enum ParseError {
         column_type: &'static str,
         error: super::columns::BadColumnLayout,
     },
+    #[error("unknown compression codec: {0}")]
+    UnknownCodec(u8),
+    #[error(transparent)]
+    CodecNotCompiledIn(#[from] super::CodecNotCompiledIn),
 }
 
 impl<'a> Document<'a> {
@@ -58,15 +64,31 @@ impl<'a> Document<'a> {
     /// let chunkbytes: &[u8] = todo!();
     /// let input = Input::new(chunkbytes);
     /// let (i, header) = Header::parse(input)?;
-    /// let (i, doc) = Document::parse(i, header)?;
+    /// let (i, doc) = Document::parse(i, header, None)?;
     /// # }
     /// ```
+    ///
+    /// Fails if any compressed column would be longer than `max_column_size` once decompressed.
     pub(crate) fn parse(
         input: parse::Input<'a>,
         header: Header,
+        max_column_size: Option<usize>,
     ) -> parse::ParseResult<'a, Document<'a>, ParseError> {
         let i = input;
 
+        // Documents which aren't compressed with DEFLATE start with the codec they use
+        let (i, codec) = if header.chunk_type() == ChunkType::DocumentWithCodec {
+            let (i, raw_codec) = parse::take1(i)?;
+            let codec = Codec::try_from(raw_codec)
+                .map_err(|raw| parse::ParseError::Error(ParseError::UnknownCodec(raw)))?;
+            codec
+                .check_available()
+                .map_err(|e| parse::ParseError::Error(e.into()))?;
+            (i, codec)
+        } else {
+            (i, Codec::Deflate)
+        };
+
         // Because some columns in a document may be compressed we do some funky stuff when
         // parsing. As we're parsing the chunk we split the data into four parts:
         //
@@ -147,7 +169,10 @@ impl<'a> Document<'a> {
             original: Cow::Borrowed(input.bytes()),
             changes: compression::Cols::new(changes, change_meta),
             ops: compression::Cols::new(ops, ops_meta),
-            extra_args: (),
+            extra_args: compression::DecompressArgs {
+                max_column_size,
+                codec,
+            },
         })
         .map_err(|e| parse::ParseError::Error(ParseError::RawColumns(e)))?;
 
@@ -195,10 +220,14 @@ impl<'a> Document<'a> {
         change_graph: &ChangeGraph,
         compress: CompressConfig,
     ) -> Document<'static> {
-        let (op_metadata, ops_out_b) = op_set.export();
-
-        let mut change_out = Vec::new();
-        let change_metadata = change_graph.encode(&mut change_out);
+        let ((op_metadata, ops_out_b), (change_metadata, change_out)) = crate::parallel::join(
+            || op_set.export(),
+            || {
+                let mut change_out = Vec::new();
+                let change_metadata = change_graph.encode(&mut change_out);
+                (change_metadata, change_out)
+            },
+        );
 
         // actors already sorted
         let actors = op_set.actors.clone();
@@ -242,7 +271,7 @@ impl<'a> Document<'a> {
         let op_bytes = shift_range(ops_start..ops_end, header.len());
         let change_bytes = shift_range(change_start..change_end, header.len());
 
-        let compressed_bytes = if let CompressConfig::Threshold(threshold) = compress {
+        let compressed_bytes = if let CompressConfig::Threshold(threshold, codec) = compress {
             let compressed = Cow::Owned(compression::compress(compression::Args {
                 prefix: prefix_len + header.len(),
                 suffix: suffix_start + header.len(),
@@ -258,6 +287,7 @@ impl<'a> Document<'a> {
                 extra_args: compression::CompressArgs {
                     threshold,
                     original_header_len: header_len,
+                    codec,
                 },
             }));
             Some(compressed)
@@ -302,55 +332,24 @@ impl<'a> Document<'a> {
         &self.heads
     }
 
-    fn verify_changes(
-        &self,
-        cc: &CollectedChanges,
-        mode: VerificationMode,
-    ) -> Result<(), ReconstructError> {
-        if mode == VerificationMode::Check && !self.heads().iter().eq(cc.heads.iter()) {
-            let expected_heads: BTreeSet<_> = self.heads().iter().cloned().collect();
-            tracing::error!(?expected_heads, ?cc.heads, "mismatching heads");
-            Err(ReconstructError::MismatchingHeads(MismatchedHeads {
-                changes: cc.changes.clone(),
-                expected_heads,
-                derived_heads: cc.heads.clone(),
-            }))
-        } else {
-            Ok(())
-        }
+    /// Whether any of the columns of this document were compressed
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.compressed_bytes.is_some()
     }
 
     pub(crate) fn reconstruct(
         &self,
         mode: VerificationMode,
         text_encoding: TextEncoding,
+        limits: &LoadLimits,
+        progress: &mut Progress<'_>,
     ) -> Result<Automerge, ReconstructError> {
-        let mut op_set = OpSet::load(self, text_encoding)?;
-        let change_cols = ChangeGraphCols::load(self)?;
-
-        let mut index = op_set.index_builder();
-
-        let change_collector = ChangeCollector::try_new(&change_cols, &op_set)?;
-        let mut change_collector = change_collector.with_index(&mut index);
-
-        change_collector.process_ops(&op_set)?;
-
-        let changes = change_collector.collect(&op_set)?;
-
-        self.verify_changes(&changes, mode)?;
-
-        let (indexes, mut mark_order_validator) = index.finish();
-        op_set.set_indexes(indexes);
-
-        let change_graph = change_cols.finalize(&changes.changes);
-
-        debug_assert_eq!(changes.changes.len(), change_graph.len());
-
-        debug_assert!(op_set.validate_top_index());
-
-        let doc = Automerge::from_parts(op_set, change_graph);
-
-        if let Some(err) = mark_order_validator.take_error() {
+        let (op_set, change_cols) = self.load_columns(text_encoding, limits)?;
+        let mut rebuilt =
+            Rebuilt::new(&op_set, &change_cols, self.heads(), mode, limits, progress)?;
+        let mark_order_error = rebuilt.take_mark_order_error();
+        let doc = rebuilt.assemble(op_set, change_cols);
+        if let Some(err) = mark_order_error {
             Err(ReconstructError::InvalidMarkOrderDoc {
                 doc: Box::new(doc),
                 error_message: err,
@@ -363,14 +362,15 @@ impl<'a> Document<'a> {
     pub(crate) fn reconstruct_changes(
         &self,
         text_encoding: TextEncoding,
+        limits: &LoadLimits,
+        progress: &mut Progress<'_>,
     ) -> Result<Vec<Change>, ReconstructError> {
-        let op_set = OpSet::load(self, text_encoding)?;
-        let change_cols = ChangeGraphCols::load(self)?;
+        let (op_set, change_cols) = self.load_columns(text_encoding, limits)?;
 
         let mut mark_order = MarkOrderValidator::default();
         let mut change_collector = ChangeCollector::try_new(&change_cols, &op_set)?;
-        change_collector.process_ops(&op_set, &mut mark_order)?;
-        let changes = change_collector.collect(&op_set)?.changes;
+        change_collector.process_ops(&op_set, &mut mark_order, progress)?;
+        let changes = change_collector.collect(&op_set, progress)?.changes;
         if let Some(err) = mark_order.take_error() {
             return Err(ReconstructError::InvalidMarkOrderChanges {
                 changes,
@@ -379,6 +379,104 @@ impl<'a> Document<'a> {
         }
         Ok(changes)
     }
+
+    /// Load the op and change columns, checking their sizes against `limits` before we allocate
+    /// anything proportional to them
+    pub(crate) fn load_columns(
+        &self,
+        text_encoding: TextEncoding,
+        limits: &LoadLimits,
+    ) -> Result<(OpSet, ChangeGraphCols), ReconstructError> {
+        limits.check(Limit::Actors, self.actors.len())?;
+        let op_set = OpSet::load(self, text_encoding)?;
+        limits.check(Limit::Ops, op_set.len())?;
+        let change_cols = ChangeGraphCols::load(self)?;
+        limits.check(Limit::Changes, change_cols.len())?;
+        Ok((op_set, change_cols))
+    }
+}
+
+/// The changes and indexes rebuilt from the columns returned by [`Document::load_columns()`]
+///
+/// Everything which can fail happens in [`Self::new()`], which only borrows the columns, so a
+/// caller can hold on to them until it knows they can be turned into a document.
+pub(crate) struct Rebuilt {
+    changes: CollectedChanges,
+    indexes: Indexes,
+    mark_order: MarkOrderValidator,
+}
+
+impl Rebuilt {
+    /// Rebuild the changes in `change_cols` and the indexes over `op_set`, checking the changes
+    /// against `heads` if `mode` says so
+    pub(crate) fn new(
+        op_set: &OpSet,
+        change_cols: &ChangeGraphCols,
+        heads: &[ChangeHash],
+        mode: VerificationMode,
+        limits: &LoadLimits,
+        progress: &mut Progress<'_>,
+    ) -> Result<Self, ReconstructError> {
+        let mut index = op_set.index_builder();
+
+        let change_collector = ChangeCollector::try_new(change_cols, op_set)?;
+        let mut change_collector = change_collector.with_index(&mut index);
+
+        change_collector.process_ops(op_set, progress)?;
+
+        let changes = change_collector.collect(op_set, progress)?;
+
+        verify_changes(heads, &changes, mode)?;
+
+        limits.check_doc_depth(op_set)?;
+
+        let (indexes, mark_order) = index.finish();
+        Ok(Self {
+            changes,
+            indexes,
+            mark_order,
+        })
+    }
+
+    /// The error from validating the order of marks, if they were out of order
+    pub(crate) fn take_mark_order_error(&mut self) -> Option<String> {
+        self.mark_order.take_error()
+    }
+
+    pub(crate) fn changes(&self) -> &[Change] {
+        &self.changes.changes
+    }
+
+    /// Build the document from the columns this was rebuilt from
+    pub(crate) fn assemble(self, mut op_set: OpSet, change_cols: ChangeGraphCols) -> Automerge {
+        op_set.set_indexes(self.indexes);
+
+        let change_graph = change_cols.finalize(&self.changes.changes);
+
+        debug_assert_eq!(self.changes.changes.len(), change_graph.len());
+
+        debug_assert!(op_set.validate_top_index());
+
+        Automerge::from_parts(op_set, change_graph)
+    }
+}
+
+fn verify_changes(
+    heads: &[ChangeHash],
+    cc: &CollectedChanges,
+    mode: VerificationMode,
+) -> Result<(), ReconstructError> {
+    if mode == VerificationMode::Check && !heads.iter().eq(cc.heads.iter()) {
+        let expected_heads: BTreeSet<_> = heads.iter().cloned().collect();
+        tracing::error!(?expected_heads, ?cc.heads, "mismatching heads");
+        Err(ReconstructError::MismatchingHeads(MismatchedHeads {
+            changes: cc.changes.clone(),
+            expected_heads,
+            derived_heads: cc.heads.clone(),
+        }))
+    } else {
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -387,7 +485,7 @@ pub(crate) enum ReconstructError {
     //#[error("the document contained ops which were out of order")]
     //OpsOutOfOrder,
     #[error("invalid changes: {0}")]
-    InvalidChanges(#[from] crate::storage::load::change_collector::Error),
+    InvalidChanges(change_collector::Error),
     #[error("mismatching heads")]
     MismatchingHeads(MismatchedHeads),
     // FIXME - i need to do this check
@@ -417,12 +515,25 @@ pub(crate) enum ReconstructError {
     },
     #[error(transparent)]
     OutOfMemory(#[from] OutOfMemory),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    #[error(transparent)]
+    Cancelled(#[from] LoadCancelled),
+}
+
+impl From<change_collector::Error> for ReconstructError {
+    fn from(e: change_collector::Error) -> Self {
+        match e {
+            change_collector::Error::Cancelled(e) => Self::Cancelled(e),
+            e => Self::InvalidChanges(e),
+        }
+    }
 }
 
 pub(crate) struct MismatchedHeads {
-    changes: Vec<Change>,
-    expected_heads: BTreeSet<ChangeHash>,
-    derived_heads: BTreeSet<ChangeHash>,
+    pub(crate) changes: Vec<Change>,
+    pub(crate) expected_heads: BTreeSet<ChangeHash>,
+    pub(crate) derived_heads: BTreeSet<ChangeHash>,
 }
 
 impl std::fmt::Debug for MismatchedHeads {
@@ -2,7 +2,7 @@ use std::{borrow::Cow, convert::Infallible, ops::Range};
 
 use crate::storage::{
     columns::{compression, raw_column},
-    shift_range, ChunkType, Header, RawColumns,
+    shift_range, ChunkType, Codec, Header, RawColumns,
 };
 
 pub(super) struct Args<'a, T: compression::ColumnCompression, DirArgs> {
@@ -23,12 +23,20 @@ pub(super) struct Args<'a, T: compression::ColumnCompression, DirArgs> {
 pub(super) struct CompressArgs {
     pub(super) threshold: usize,
     pub(super) original_header_len: usize,
+    pub(super) codec: Codec,
+}
+
+pub(super) struct DecompressArgs {
+    pub(super) max_column_size: Option<usize>,
+    /// The codec recorded in the chunk header, [`Codec::Deflate`] for plain document chunks
+    pub(super) codec: Codec,
 }
 
 /// Compress a document chunk returning the compressed bytes
 pub(super) fn compress(args: Args<'_, compression::Uncompressed, CompressArgs>) -> Vec<u8> {
     let header_len = args.extra_args.original_header_len;
     let threshold = args.extra_args.threshold;
+    let codec = args.extra_args.codec;
     // Wrap in a closure so we can use `?` in the construction but still force the compiler
     // to check that the error type is `Infallible`
     let result: Result<_, Infallible> = (|| {
@@ -37,6 +45,7 @@ pub(super) fn compress(args: Args<'_, compression::Uncompressed, CompressArgs>)
             Compressing {
                 threshold,
                 header_len,
+                codec,
             },
         )
         .changes()?
@@ -49,7 +58,7 @@ pub(super) fn compress(args: Args<'_, compression::Uncompressed, CompressArgs>)
 }
 
 pub(super) fn decompress<'a>(
-    args: Args<'a, compression::Unknown, ()>,
+    args: Args<'a, compression::Unknown, DecompressArgs>,
 ) -> Result<Decompressed<'a>, raw_column::ParseError> {
     match (
         args.changes.raw_columns.uncompressed(),
@@ -63,13 +72,23 @@ pub(super) fn decompress<'a>(
             change_bytes: args.changes.data,
             op_bytes: args.ops.data,
         }),
-        _ => Ok(
-            Compression::<'a, Decompressing, _>::new(args, Decompressing)
-                .changes()?
-                .ops()?
-                .write_data()
-                .finish(),
-        ),
+        _ => {
+            let DecompressArgs {
+                max_column_size,
+                codec,
+            } = args.extra_args;
+            Ok(Compression::<'a, Decompressing, _>::new(
+                args,
+                Decompressing {
+                    max_column_size,
+                    codec,
+                },
+            )
+            .changes()?
+            .ops()?
+            .write_data()
+            .finish())
+        }
     }
 }
 
@@ -134,6 +153,7 @@ trait Direction: std::fmt::Debug {
 struct Compressing {
     threshold: usize,
     header_len: usize,
+    codec: Codec,
 }
 
 impl Direction for Compressing {
@@ -150,9 +170,9 @@ impl Direction for Compressing {
         meta_out: &mut Vec<u8>,
     ) -> Result<Cols<Self::Out>, Self::Error> {
         let start = out.len();
-        let raw_columns = cols
-            .raw_columns
-            .compress(&input[cols.data.clone()], out, self.threshold);
+        let raw_columns =
+            cols.raw_columns
+                .compress(&input[cols.data.clone()], out, self.threshold, self.codec);
         raw_columns.write(meta_out);
         Ok(Cols {
             data: start..out.len(),
@@ -162,13 +182,16 @@ impl Direction for Compressing {
 }
 
 #[derive(Debug)]
-struct Decompressing;
+struct Decompressing {
+    max_column_size: Option<usize>,
+    codec: Codec,
+}
 
 impl Direction for Decompressing {
     type Error = raw_column::ParseError;
     type Out = compression::Uncompressed;
     type In = compression::Unknown;
-    type Args = ();
+    type Args = DecompressArgs;
 
     fn process(
         &self,
@@ -178,9 +201,12 @@ impl Direction for Decompressing {
         meta_out: &mut Vec<u8>,
     ) -> Result<Cols<Self::Out>, raw_column::ParseError> {
         let start = out.len();
-        let raw_columns = cols
-            .raw_columns
-            .uncompress(&input[cols.data.clone()], out)?;
+        let raw_columns = cols.raw_columns.uncompress(
+            &input[cols.data.clone()],
+            out,
+            self.max_column_size,
+            self.codec,
+        )?;
         raw_columns.write(meta_out);
         Ok(Cols {
             data: start..out.len(),
@@ -351,12 +377,31 @@ impl<'a> Compression<'a, Decompressing, Finished<Decompressing>> {
 
 impl Compression<'_, Compressing, Finished<Compressing>> {
     fn finish(self) -> Vec<u8> {
-        let Finished { out, .. } = self.state;
+        let Finished {
+            out,
+            change_cols,
+            ops_cols,
+            ..
+        } = self.state;
         let headerless = &out[self.direction.header_len..];
-        let header = Header::new(ChunkType::Document, headerless);
-        let mut result = Vec::with_capacity(header.len() + out.len());
+        let any_compressed = change_cols.raw_columns.uncompressed().is_none()
+            || ops_cols.raw_columns.uncompressed().is_none();
+        // DEFLATE is what plain document chunks use, other codecs are recorded in a prefix byte.
+        // We only use the prefix when it's needed so that documents which are too small to
+        // compress can still be loaded by versions which don't know about codecs.
+        let mut tagged = Vec::new();
+        let (chunk_type, data) = if any_compressed && self.direction.codec != Codec::Deflate {
+            tagged.reserve(headerless.len() + 1);
+            tagged.push(u8::from(self.direction.codec));
+            tagged.extend(headerless);
+            (ChunkType::DocumentWithCodec, tagged.as_slice())
+        } else {
+            (ChunkType::Document, headerless)
+        };
+        let header = Header::new(chunk_type, data);
+        let mut result = Vec::with_capacity(header.len() + data.len());
         header.write(&mut result);
-        result.extend(headerless);
+        result.extend(data);
         result
     }
 }
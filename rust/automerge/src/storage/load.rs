@@ -8,6 +8,13 @@ use crate::{
 };
 
 pub(crate) mod change_collector;
+mod limits;
+mod progress;
+mod reader;
+pub use limits::{Limit, LimitExceeded, LoadLimits};
+pub(crate) use progress::Progress;
+pub use progress::{LoadCancelled, LoadPhase, LoadProgress};
+pub(crate) use reader::ChunkReader;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum VerificationMode {
@@ -46,6 +53,67 @@ pub enum Error {
     InflateDocument(Box<dyn std::error::Error + Send + Sync + 'static>),
     #[error("bad checksum")]
     BadChecksum,
+    #[error("error reading input: {0}")]
+    Io(#[source] std::io::Error),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    #[error(transparent)]
+    Cancelled(#[from] LoadCancelled),
+}
+
+impl Error {
+    /// Whether this error fails the load even when partial loads are allowed
+    pub(crate) fn is_fatal(&self) -> bool {
+        matches!(self, Self::LimitExceeded(_) | Self::Cancelled(_))
+    }
+}
+
+/// Parse the next chunk in `data`, enforcing `limits` on its decompressed columns
+pub(crate) fn parse_chunk<'a>(
+    data: parse::Input<'a>,
+    limits: &LoadLimits,
+    progress: &mut Progress<'_>,
+) -> Result<(parse::Input<'a>, storage::Chunk<'a>), Error> {
+    let (remaining, chunk) = parse_chunk_unreported(data, limits)?;
+    report_parsed(
+        &chunk,
+        data.bytes().len() - remaining.bytes().len(),
+        progress,
+    )?;
+    Ok((remaining, chunk))
+}
+
+fn parse_chunk_unreported<'a>(
+    data: parse::Input<'a>,
+    limits: &LoadLimits,
+) -> Result<(parse::Input<'a>, storage::Chunk<'a>), Error> {
+    storage::Chunk::parse_limited(data, limits.max_column_size).map_err(|e| match e {
+        parse::ParseError::Error(e) => match e.limit_exceeded() {
+            Some(limit) => Error::LimitExceeded(limit),
+            None => Error::Parse(Box::new(parse::ParseError::Error(e))),
+        },
+        e => Error::Parse(Box::new(e)),
+    })
+}
+
+fn report_parsed(
+    chunk: &storage::Chunk<'_>,
+    bytes: usize,
+    progress: &mut Progress<'_>,
+) -> Result<(), Error> {
+    // Decompression happens as part of parsing the chunk, with the columns of a document
+    // decompressed in parallel, so we can only report it once done
+    match chunk {
+        storage::Chunk::Document(d) if d.is_compressed() => {
+            progress.report(LoadPhase::Decompress, Some(1.0))?
+        }
+        storage::Chunk::CompressedChange(..) => {
+            progress.report(LoadPhase::Decompress, Some(1.0))?
+        }
+        _ => {}
+    }
+    progress.parsed(bytes)?;
+    Ok(())
 }
 
 pub(crate) enum LoadedChanges<'a> {
@@ -77,33 +145,149 @@ pub(crate) fn load_changes<'a>(
     text_encoding: TextEncoding,
     current: &ChangeGraph,
     mark_order: MarkOrderValidation,
+    limits: &LoadLimits,
+    progress: &mut Progress<'_>,
 ) -> LoadedChanges<'a> {
     let mut changes = Vec::new();
     while !data.is_empty() {
-        let remaining =
-            match load_next_change(data, &mut changes, text_encoding, current, mark_order) {
-                Ok(d) => d,
-                Err(e) => {
-                    return LoadedChanges::Partial {
-                        loaded: changes,
-                        remaining: data,
-                        error: e,
-                    };
+        // Change chunks don't depend on each other so we parse and hash runs of them as a batch,
+        // which happens across threads with the `parallel` feature
+        let batch = change_chunks(data.unconsumed_bytes());
+        if !batch.is_empty() {
+            let loaded = crate::parallel::map(&batch, |chunk| load_change_chunk(chunk, limits));
+            // The chunks in the batch are decompressed by now, report them one at a time so the
+            // callback can still cancel before they are parsed
+            let compressed_total = loaded
+                .iter()
+                .filter(|result| matches!(result, Ok((_, true))))
+                .count();
+            let mut compressed_done = 0;
+            let mut offset = 0;
+            for (chunk, result) in batch.iter().zip(loaded) {
+                let result = result.and_then(|(change, compressed)| {
+                    if compressed {
+                        compressed_done += 1;
+                        progress.report(
+                            LoadPhase::Decompress,
+                            Some(compressed_done as f64 / compressed_total as f64),
+                        )?;
+                    }
+                    progress.parsed(chunk.len())?;
+                    Ok(change)
+                });
+                match result {
+                    Ok(change) => changes.push(change),
+                    Err(e) => {
+                        return LoadedChanges::Partial {
+                            loaded: changes,
+                            remaining: parse::Input::new(&data.unconsumed_bytes()[offset..]),
+                            error: e,
+                        };
+                    }
                 }
-            };
+                offset += chunk.len();
+            }
+            data = parse::Input::new(&data.unconsumed_bytes()[offset..]);
+            continue;
+        }
+        let remaining = match load_next_change(
+            data,
+            &mut changes,
+            text_encoding,
+            current,
+            mark_order,
+            limits,
+            progress,
+        ) {
+            Ok(d) => d,
+            Err(e) => {
+                return LoadedChanges::Partial {
+                    loaded: changes,
+                    remaining: data,
+                    error: e,
+                };
+            }
+        };
         data = remaining.reset();
     }
     LoadedChanges::Complete(changes)
 }
 
+/// Split the longest run of change and compressed change chunks off the start of `data`
+///
+/// This only looks at the chunk headers, anything which isn't a well formed change chunk header
+/// ends the run and is left for [`load_next_change`] to deal with.
+fn change_chunks(mut data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    loop {
+        let Some((header, rest)) = data.split_at_checked(storage::MAGIC_BYTES.len() + 5) else {
+            break;
+        };
+        if header[..storage::MAGIC_BYTES.len()] != storage::MAGIC_BYTES {
+            break;
+        }
+        let chunk_type = storage::ChunkType::try_from(header[storage::MAGIC_BYTES.len() + 4]);
+        if !matches!(
+            chunk_type,
+            Ok(storage::ChunkType::Change | storage::ChunkType::Compressed)
+        ) {
+            break;
+        }
+        let Ok((i, data_len)) = parse::leb128_u64::<parse::leb128::Error>(parse::Input::new(rest))
+        else {
+            break;
+        };
+        let len = header.len() + (rest.len() - i.unconsumed_bytes().len());
+        let Some(len) = usize::try_from(data_len)
+            .ok()
+            .and_then(|data_len| len.checked_add(data_len))
+            .filter(|len| *len <= data.len())
+        else {
+            break;
+        };
+        let (chunk, remaining) = data.split_at(len);
+        chunks.push(chunk);
+        data = remaining;
+    }
+    chunks
+}
+
+/// Parse and verify a single change or compressed change chunk, returning the change and whether
+/// it was compressed
+fn load_change_chunk(data: &[u8], limits: &LoadLimits) -> Result<(Change, bool), Error> {
+    // `data` is exactly one chunk long so there's nothing remaining
+    let (_, chunk) = parse_chunk_unreported(parse::Input::new(data), limits)?;
+    if !chunk.checksum_valid() {
+        return Err(Error::BadChecksum);
+    }
+    match chunk {
+        storage::Chunk::Change(change) => {
+            let change = Change::new_from_unverified(change.into_owned(), None)
+                .map_err(|e| Error::InvalidChangeColumns(Box::new(e)))?;
+            tracing::trace!(actor=?change.actor_id(), num_ops=change.len(), "loaded change");
+            Ok((change, false))
+        }
+        storage::Chunk::CompressedChange(change, compressed) => {
+            let change =
+                Change::new_from_unverified(change.into_owned(), Some(compressed.into_owned()))
+                    .map_err(|e| Error::InvalidChangeColumns(Box::new(e)))?;
+            Ok((change, true))
+        }
+        // `change_chunks` only returns change chunks
+        _ => Err(Error::LeftoverData),
+    }
+}
+
 fn load_next_change<'a>(
     data: parse::Input<'a>,
     changes: &mut Vec<Change>,
     text_encoding: TextEncoding,
     current: &ChangeGraph,
     mark_order: MarkOrderValidation,
+    limits: &LoadLimits,
+    progress: &mut Progress<'_>,
 ) -> Result<parse::Input<'a>, Error> {
-    let (remaining, chunk) = storage::Chunk::parse(data).map_err(|e| Error::Parse(Box::new(e)))?;
+    let (remaining, chunk) = parse_chunk(data, limits, progress)?;
     if !chunk.checksum_valid() {
         return Err(Error::BadChecksum);
     }
@@ -111,8 +295,10 @@ fn load_next_change<'a>(
         storage::Chunk::Document(d) => {
             tracing::trace!("loading document chunk");
             if !d.heads().iter().all(|h| current.has_change(h)) {
-                let new_changes = match d.reconstruct_changes(text_encoding) {
+                let new_changes = match d.reconstruct_changes(text_encoding, limits, progress) {
                     Ok(c) => c,
+                    Err(ReconstructError::LimitExceeded(e)) => return Err(e.into()),
+                    Err(ReconstructError::Cancelled(e)) => return Err(e.into()),
                     Err(ReconstructError::InvalidMarkOrderChanges {
                         changes,
                         error_message: _,
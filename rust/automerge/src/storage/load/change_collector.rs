@@ -12,4 +12,6 @@ pub(crate) enum Error {
     MissingOps,
     #[error("missing ops")]
     MissingDep(#[from] crate::change_graph::MissingDep),
+    #[error(transparent)]
+    Cancelled(#[from] super::LoadCancelled),
 }
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{
+    change_graph::ChangeGraph,
+    change_queue::ChangeQueue,
+    op_set2::{types::Action, OpSet},
+    types::{ObjId, OpId},
+    ActorId, Change,
+};
+
+/// Limits on the size of the documents to accept when loading or syncing
+///
+/// The storage format is compact and columns can be compressed, so a small input can describe a
+/// very large document. When accepting data from an untrusted source set these limits so that
+/// hostile input fails with [`AutomergeError::LimitExceeded`](crate::AutomergeError::LimitExceeded)
+/// instead of using unbounded memory. Every limit defaults to `None`, which means unlimited.
+///
+/// The limits apply to the whole document which results from loading the data, not just to the
+/// new data, so the same limits can be used for every message received from a peer. See
+/// [`LoadOptions::limits()`](crate::LoadOptions::limits) and
+/// [`sync::State::load_limits`](crate::sync::State::load_limits).
+///
+/// ## Example
+///
+/// ```
+/// use automerge::{transaction::Transactable, AutoCommit, AutomergeError, Automerge, Limit, LoadLimits, LoadOptions};
+/// let mut doc = AutoCommit::new();
+/// for i in 0..10 {
+///     doc.put(automerge::ROOT, format!("key{}", i), i).unwrap();
+/// }
+/// let limits = LoadLimits {
+///     max_ops: Some(5),
+///     ..LoadLimits::unlimited()
+/// };
+/// let Err(AutomergeError::LimitExceeded(e)) = Automerge::load_with_options(&doc.save(), LoadOptions::new().limits(limits)) else {
+///     panic!("expected the load to fail");
+/// };
+/// assert_eq!(e.limit, Limit::Ops);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LoadLimits {
+    /// The total number of ops in the document
+    pub max_ops: Option<usize>,
+    /// The number of distinct actors in the document
+    pub max_actors: Option<usize>,
+    /// The total number of changes in the document, including changes which are waiting for
+    /// their dependencies
+    pub max_changes: Option<usize>,
+    /// The size in bytes of a single column once it has been decompressed. This also limits the
+    /// decompressed size of a compressed change chunk.
+    pub max_column_size: Option<usize>,
+    /// How deeply objects can be nested, objects in the root map are at depth 1
+    pub max_depth: Option<usize>,
+}
+
+/// One of the limits in [`LoadLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Ops,
+    Actors,
+    Changes,
+    ColumnSize,
+    Depth,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ops => write!(f, "number of ops"),
+            Self::Actors => write!(f, "number of actors"),
+            Self::Changes => write!(f, "number of changes"),
+            Self::ColumnSize => write!(f, "decompressed column size"),
+            Self::Depth => write!(f, "object nesting depth"),
+        }
+    }
+}
+
+/// The data being loaded would exceed one of the [`LoadLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("{limit} exceeds the limit of {max}")]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: usize,
+}
+
+impl LoadLimits {
+    /// No limits at all, this is the default
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn check(&self, limit: Limit, value: usize) -> Result<(), LimitExceeded> {
+        let max = match limit {
+            Limit::Ops => self.max_ops,
+            Limit::Actors => self.max_actors,
+            Limit::Changes => self.max_changes,
+            Limit::ColumnSize => self.max_column_size,
+            Limit::Depth => self.max_depth,
+        };
+        match max {
+            Some(max) if value > max => Err(LimitExceeded { limit, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the totals which would result from adding `changes` to a document or change store
+    ///
+    /// `actors` must be sorted. Changes which are already in `graph` or `queue` aren't counted
+    /// again.
+    pub(crate) fn check_changes(
+        &self,
+        changes: &[Change],
+        graph: &ChangeGraph,
+        queue: &ChangeQueue,
+        num_ops: usize,
+        actors: &[ActorId],
+    ) -> Result<(), LimitExceeded> {
+        let mut new_changes = HashSet::new();
+        let mut new_actors = HashSet::new();
+        let mut new_ops = 0;
+        for change in changes {
+            let hash = change.hash();
+            if graph.has_change(&hash) || queue.has_hash(&hash) || !new_changes.insert(hash) {
+                continue;
+            }
+            new_ops += change.len();
+            new_actors.extend(
+                change
+                    .actors()
+                    .filter(|actor| actors.binary_search(actor).is_err()),
+            );
+        }
+        self.check(
+            Limit::Changes,
+            graph.len() + queue.len() + new_changes.len(),
+        )?;
+        self.check(Limit::Ops, num_ops.saturating_add(new_ops))?;
+        self.check(Limit::Actors, actors.len() + new_actors.len())
+    }
+
+    /// Check the nesting depth of the objects which `changes` would create in `ops`
+    ///
+    /// Objects are found by actor ID and counter rather than by index so that changes which
+    /// create objects inside objects created by other changes in `changes` are checked, whatever
+    /// order they arrive in.
+    pub(crate) fn check_change_depth<'a, I: Iterator<Item = &'a Change>>(
+        &self,
+        changes: I,
+        ops: &OpSet,
+    ) -> Result<(), LimitExceeded> {
+        let Some(max) = self.max_depth else {
+            return Ok(());
+        };
+        let mut parents = HashMap::new();
+        for change in changes {
+            let actors = change.actors().collect::<Vec<_>>();
+            let start_op = change.start_op().get();
+            for (i, op) in change.iter_ops().enumerate() {
+                if !is_make(Action::try_from(op.action).ok()) {
+                    continue;
+                }
+                let parent = op.obj.id().and_then(|id| {
+                    actors
+                        .get(id.actor())
+                        .map(|actor| (id.counter(), (*actor).clone()))
+                });
+                parents.insert((start_op + i as u64, change.actor_id().clone()), parent);
+            }
+        }
+        check_depth(&parents, max, |(counter, actor)| {
+            let obj = ops.lookup_actor(actor).and_then(|idx| {
+                let counter = u32::try_from(*counter).ok()?;
+                Some(ObjId(OpId::new(counter.into(), idx)))
+            });
+            obj.map(|obj| doc_depth(ops, obj, max)).unwrap_or(0)
+        })
+    }
+
+    /// Check the nesting depth of every object in `ops`
+    pub(crate) fn check_doc_depth(&self, ops: &OpSet) -> Result<(), LimitExceeded> {
+        let Some(max) = self.max_depth else {
+            return Ok(());
+        };
+        let parents = ops
+            .iter()
+            .filter(|op| is_make(Some(op.action)))
+            .map(|op| (op.id, op.obj.id().copied()))
+            .collect::<HashMap<_, _>>();
+        check_depth(&parents, max, |_| 0)
+    }
+}
+
+fn is_make(action: Option<Action>) -> bool {
+    matches!(
+        action,
+        Some(Action::MakeMap | Action::MakeList | Action::MakeText | Action::MakeTable)
+    )
+}
+
+/// The depth of an object which is already in `ops`, giving up once it's deeper than `max`
+fn doc_depth(ops: &OpSet, mut obj: ObjId, max: usize) -> usize {
+    let mut depth = 0;
+    while let Some(id) = obj.id() {
+        let Some((op, _)) = ops.find_op_by_id_and_vis(id, None) else {
+            break;
+        };
+        depth += 1;
+        if depth > max {
+            break;
+        }
+        obj = op.obj;
+    }
+    depth
+}
+
+/// Check that no object in `parents` is nested more than `max` deep
+///
+/// `parents` maps each object to its parent, or `None` for objects in the root. `depth_of`
+/// gives the depth of parents which aren't themselves in `parents`.
+fn check_depth<K: Hash + Eq, F: FnMut(&K) -> usize>(
+    parents: &HashMap<K, Option<K>>,
+    max: usize,
+    mut depth_of: F,
+) -> Result<(), LimitExceeded> {
+    let exceeded = LimitExceeded {
+        limit: Limit::Depth,
+        max,
+    };
+    let mut depths: HashMap<&K, usize> = HashMap::new();
+    for obj in parents.keys() {
+        // Walk up to an object whose depth we know, then assign depths on the way back down
+        let mut path = Vec::new();
+        let mut next = Some(obj);
+        let base = loop {
+            let Some(obj) = next else {
+                break 0;
+            };
+            if let Some(depth) = depths.get(obj) {
+                break *depth;
+            }
+            let Some(parent) = parents.get(obj) else {
+                break depth_of(obj);
+            };
+            path.push(obj);
+            // This also stops us looping forever if the parents form a cycle
+            if path.len() > max {
+                return Err(exceeded);
+            }
+            next = parent.as_ref();
+        };
+        for (i, obj) in path.into_iter().rev().enumerate() {
+            let depth = base + i + 1;
+            if depth > max {
+                return Err(exceeded);
+            }
+            depths.insert(obj, depth);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_depth_counts_from_the_root() {
+        // 1 -> 2 -> 3, with 3 in the root
+        let parents = HashMap::from([(1, Some(2)), (2, Some(3)), (3, None)]);
+        assert!(check_depth(&parents, 3, |_| 0).is_ok());
+        assert_eq!(
+            check_depth(&parents, 2, |_| 0),
+            Err(LimitExceeded {
+                limit: Limit::Depth,
+                max: 2
+            })
+        );
+        // 3 is inside an existing object at depth 5
+        let parents = HashMap::from([(1, Some(2)), (2, Some(3)), (3, Some(4))]);
+        assert!(check_depth(&parents, 8, |_| 5).is_ok());
+        assert!(check_depth(&parents, 7, |_| 5).is_err());
+    }
+
+    #[test]
+    fn check_depth_rejects_cycles() {
+        let parents = HashMap::from([(1, Some(2)), (2, Some(1))]);
+        assert!(check_depth(&parents, 100, |_| 0).is_err());
+    }
+}
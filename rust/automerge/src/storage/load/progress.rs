@@ -0,0 +1,169 @@
+use std::fmt;
+use std::ops::ControlFlow;
+
+/// How many items (ops or changes) to process between calls to a progress callback
+const REPORT_EVERY: usize = 4096;
+
+/// The phases of loading a document, in the order they start
+///
+/// Documents made of many chunks go through [`Self::Parse`] and [`Self::Decompress`] once per
+/// chunk, so phases can be reported more than once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoadPhase {
+    /// Splitting the input into chunks and parsing their headers and columns
+    Parse,
+    /// Decompressing the columns of a document chunk or a compressed change chunk
+    ///
+    /// The columns of a document chunk are decompressed together, so this is reported once the
+    /// whole chunk is done. Runs of compressed change chunks report a fraction per chunk.
+    Decompress,
+    /// Building the op set from the ops of a document chunk, or applying loaded changes
+    Reconstruct,
+    /// Rebuilding the changes of a document chunk and checking their hashes against the heads
+    VerifyHeads,
+}
+
+impl fmt::Display for LoadPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse => write!(f, "parse"),
+            Self::Decompress => write!(f, "decompress"),
+            Self::Reconstruct => write!(f, "reconstruct"),
+            Self::VerifyHeads => write!(f, "verify heads"),
+        }
+    }
+}
+
+/// A progress report passed to the callback set with
+/// [`LoadOptions::progress()`](crate::LoadOptions::progress)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadProgress {
+    /// The phase the load is in
+    pub phase: LoadPhase,
+    /// How much of `phase` is complete, between 0.0 and 1.0. This is `None` when the total isn't
+    /// known, for example when parsing input from
+    /// [`Automerge::load_from_reader()`](crate::Automerge::load_from_reader)
+    pub fraction: Option<f64>,
+}
+
+/// The progress callback asked for the load to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("the load was cancelled")]
+pub struct LoadCancelled;
+
+/// Passes [`LoadProgress`] reports to an optional callback
+pub(crate) struct Progress<'a> {
+    callback: Option<Box<dyn FnMut(LoadProgress) -> ControlFlow<()> + 'a>>,
+    total_bytes: Option<usize>,
+    parsed_bytes: usize,
+}
+
+impl fmt::Debug for Progress<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("callback", &self.callback.is_some())
+            .field("total_bytes", &self.total_bytes)
+            .field("parsed_bytes", &self.parsed_bytes)
+            .finish()
+    }
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn none() -> Self {
+        Self {
+            callback: None,
+            total_bytes: None,
+            parsed_bytes: 0,
+        }
+    }
+
+    pub(crate) fn new<F: FnMut(LoadProgress) -> ControlFlow<()> + 'a>(callback: F) -> Self {
+        Self {
+            callback: Some(Box::new(callback)),
+            ..Self::none()
+        }
+    }
+
+    /// Set the total size of the input, so that [`LoadPhase::Parse`] can report a fraction
+    pub(crate) fn set_total_bytes(&mut self, total_bytes: usize) {
+        self.total_bytes = Some(total_bytes);
+        self.parsed_bytes = 0;
+    }
+
+    pub(crate) fn report(
+        &mut self,
+        phase: LoadPhase,
+        fraction: Option<f64>,
+    ) -> Result<(), LoadCancelled> {
+        let Some(callback) = self.callback.as_mut() else {
+            return Ok(());
+        };
+        let fraction = fraction.map(|f| f.clamp(0.0, 1.0));
+        match callback(LoadProgress { phase, fraction }) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => Err(LoadCancelled),
+        }
+    }
+
+    /// Report that `done` out of `total` items of `phase` are complete
+    ///
+    /// This is called for every item so it only calls the callback every [`REPORT_EVERY`]
+    /// items, and when the last item is done.
+    pub(crate) fn report_items(
+        &mut self,
+        phase: LoadPhase,
+        done: usize,
+        total: usize,
+    ) -> Result<(), LoadCancelled> {
+        if self.callback.is_none() || (!done.is_multiple_of(REPORT_EVERY) && done != total) {
+            return Ok(());
+        }
+        let fraction = if total == 0 {
+            1.0
+        } else {
+            done as f64 / total as f64
+        };
+        self.report(phase, Some(fraction))
+    }
+
+    /// Report that another `bytes` of the input have been parsed
+    pub(crate) fn parsed(&mut self, bytes: usize) -> Result<(), LoadCancelled> {
+        self.parsed_bytes += bytes;
+        let fraction = self
+            .total_bytes
+            .map(|total| self.parsed_bytes as f64 / total.max(1) as f64);
+        self.report(LoadPhase::Parse, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_items_is_throttled() {
+        let mut reports = Vec::new();
+        let callback = |p: LoadProgress| {
+            reports.push(p.fraction);
+            ControlFlow::Continue(())
+        };
+        let mut progress = Progress::new(callback);
+        let total = REPORT_EVERY * 2 + 1;
+        for done in 1..=total {
+            progress
+                .report_items(LoadPhase::Reconstruct, done, total)
+                .unwrap();
+        }
+        drop(progress);
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports.last(), Some(&Some(1.0)));
+    }
+
+    #[test]
+    fn break_cancels() {
+        let callback = |_| ControlFlow::Break(());
+        let mut progress = Progress::new(callback);
+        assert_eq!(progress.report(LoadPhase::Parse, None), Err(LoadCancelled));
+        assert!(Progress::none().report(LoadPhase::Parse, None).is_ok());
+    }
+}
@@ -0,0 +1,381 @@
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+use super::Error;
+use crate::storage::columns::compression::{Uncompressed, Unknown};
+use crate::storage::{
+    document, parse, ChunkType, Codec, Header, Limit, LimitExceeded, RawColumn, RawColumns,
+    MAGIC_BYTES,
+};
+
+// magic bytes, checksum and chunk type
+const FIXED_HEADER_LEN: usize = 4 + 4 + 1;
+// the longest LEB128 encoding of a u64
+const MAX_LEB128_LEN: usize = 10;
+// how much of a document chunk to read at a time while looking for the end of its metadata
+const PREFIX_READ_LEN: usize = 4096;
+
+/// Reads the chunks of an encoded document from a [`Read`] one at a time
+///
+/// Each chunk is returned as a buffer containing its header and data, ready for
+/// `storage::Chunk::parse`. Only one chunk is held in memory at a time. Change and bundle chunks
+/// are read in full, document chunks are read a column at a time, see [`Self::next_chunk()`].
+pub(crate) struct ChunkReader<R> {
+    reader: R,
+    max_column_size: Option<usize>,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Fails with [`Error::LimitExceeded`] if a compressed column of a document would be longer
+    /// than `max_column_size` once decompressed
+    pub(crate) fn new(reader: R, max_column_size: Option<usize>) -> Self {
+        Self {
+            reader,
+            max_column_size,
+        }
+    }
+
+    /// The next chunk, or `None` if the input ended cleanly at a chunk boundary
+    ///
+    /// Document chunks are returned uncompressed. Their columns are decompressed as they're read
+    /// and the checksum is checked as we go, so the compressed chunk is never in memory at once.
+    /// Loading the output of [`Automerge::save()`](crate::Automerge::save) this way needs the
+    /// decompressed columns and the largest compressed column, rather than the whole input as
+    /// well.
+    pub(crate) fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut chunk = Vec::with_capacity(FIXED_HEADER_LEN + MAX_LEB128_LEN);
+        chunk.resize(FIXED_HEADER_LEN, 0);
+        let read = read_fully(&mut self.reader, &mut chunk)?;
+        if read == 0 {
+            return Ok(None);
+        } else if read < FIXED_HEADER_LEN {
+            return Err(truncated());
+        }
+
+        let mut len = 0_u64;
+        for i in 0..MAX_LEB128_LEN {
+            let mut byte = [0];
+            if read_fully(&mut self.reader, &mut byte)? == 0 {
+                return Err(truncated());
+            }
+            chunk.push(byte[0]);
+            len |= u64::from(byte[0] & 0x7f) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+
+        // Chunks with bad magic bytes go through as they are so that parsing them fails
+        if chunk[..4] == MAGIC_BYTES {
+            if let Ok(ChunkType::Document | ChunkType::DocumentWithCodec) =
+                ChunkType::try_from(chunk[8])
+            {
+                return self.read_document(&chunk, len).map(Some);
+            }
+        }
+
+        // Don't trust the length to preallocate, a corrupt header would make us allocate before
+        // finding out that there isn't that much data
+        let header_len = chunk.len() as u64;
+        let read = (&mut self.reader)
+            .take(len)
+            .read_to_end(&mut chunk)
+            .map_err(Error::Io)?;
+        if (read as u64) < len {
+            return Err(truncated());
+        }
+        debug_assert_eq!(chunk.len() as u64, header_len + len);
+        Ok(Some(chunk))
+    }
+
+    /// Read the `len` bytes of data of the document chunk whose header is `header`, returning it
+    /// as an uncompressed document chunk
+    fn read_document(&mut self, header: &[u8], len: u64) -> Result<Vec<u8>, Error> {
+        let chunk_type = ChunkType::try_from(header[8]).expect("checked by the caller");
+        let mut body = HashingReader::new((&mut self.reader).take(len), chunk_type, len);
+
+        let codec = if chunk_type == ChunkType::DocumentWithCodec {
+            let mut raw = [0];
+            if read_fully(&mut body, &mut raw)? == 0 {
+                return Err(truncated());
+            }
+            let codec = Codec::try_from(raw[0])
+                .map_err(|raw| parse_error(document::ParseError::UnknownCodec(raw)))?;
+            codec
+                .check_available()
+                .map_err(|e| parse_error(document::ParseError::from(e)))?;
+            codec
+        } else {
+            Codec::Deflate
+        };
+
+        // Leave room to write the new header in front of the data once we know how long it is
+        let reserved = FIXED_HEADER_LEN + MAX_LEB128_LEN;
+        let mut out = vec![0; reserved];
+
+        // Read until we've got all of the actors, heads and column metadata. Whatever we read
+        // past them is the start of the column data.
+        let mut prefix = Vec::new();
+        let (actors_and_heads, change_meta, ops_meta, consumed) = loop {
+            let parsed = parse::range_of(
+                |i| -> parse::ParseResult<'_, _, document::ParseError> {
+                    let (i, _actors) = parse::length_prefixed(parse::actor_id)(i)?;
+                    let (i, _heads) = parse::length_prefixed(parse::change_hash)(i)?;
+                    Ok((i, ()))
+                },
+                parse::Input::new(&prefix),
+            )
+            .and_then(|(i, r)| {
+                let (i, change_meta) = RawColumns::parse::<document::ParseError>(i)?;
+                let (i, ops_meta) = RawColumns::parse::<document::ParseError>(i)?;
+                Ok((
+                    r.range,
+                    change_meta,
+                    ops_meta,
+                    prefix.len() - i.unconsumed_bytes().len(),
+                ))
+            });
+            match parsed {
+                Ok(parsed) => break parsed,
+                Err(parse::ParseError::Incomplete(_)) => {
+                    let want = prefix.len().max(PREFIX_READ_LEN) as u64;
+                    if (&mut body)
+                        .take(want)
+                        .read_to_end(&mut prefix)
+                        .map_err(Error::Io)?
+                        == 0
+                    {
+                        return Err(truncated());
+                    }
+                }
+                Err(e) => return Err(parse_error(e)),
+            }
+        };
+        out.extend(&prefix[actors_and_heads]);
+        let mut columns = io::Cursor::new(prefix.split_off(consumed)).chain(&mut body);
+        drop(prefix);
+
+        // The metadata goes before the data, but we only know the decompressed lengths once we've
+        // decompressed the data, so it's inserted afterwards
+        let meta_at = out.len();
+        let max = self.max_column_size;
+        let change_meta = read_columns(&mut columns, &change_meta, codec, max, &mut out)?;
+        let ops_meta = read_columns(&mut columns, &ops_meta, codec, max, &mut out)?;
+        let mut meta = Vec::new();
+        change_meta.write(&mut meta);
+        ops_meta.write(&mut meta);
+        out.splice(meta_at..meta_at, meta);
+
+        // The head indexes
+        columns.read_to_end(&mut out).map_err(Error::Io)?;
+        if body.read < len {
+            return Err(truncated());
+        }
+        if body.hasher.finalize()[..4] != header[4..8] {
+            return Err(Error::BadChecksum);
+        }
+
+        let rewritten = Header::new(ChunkType::Document, &out[reserved..]);
+        let start = reserved - rewritten.len();
+        let mut header_bytes = Vec::with_capacity(rewritten.len());
+        rewritten.write(&mut header_bytes);
+        out[start..reserved].copy_from_slice(&header_bytes);
+        out.drain(..start);
+        Ok(out)
+    }
+}
+
+/// Read the data for each of `columns` from `input` onto the end of `out`, decompressing
+/// compressed columns with `codec`
+///
+/// The ranges of the returned columns start at the end of `out` as it was when this was called.
+fn read_columns<I: Read>(
+    input: &mut I,
+    columns: &RawColumns<Unknown>,
+    codec: Codec,
+    max_column_size: Option<usize>,
+    out: &mut Vec<u8>,
+) -> Result<RawColumns<Uncompressed>, Error> {
+    let base = out.len();
+    let mut compressed = Vec::new();
+    let mut result = Vec::with_capacity(columns.0.len());
+    for col in columns.iter() {
+        let start = out.len();
+        let len = col.data().len() as u64;
+        if col.spec().deflate() {
+            compressed.clear();
+            if (&mut *input)
+                .take(len)
+                .read_to_end(&mut compressed)
+                .map_err(Error::Io)? as u64
+                != len
+            {
+                return Err(truncated());
+            }
+            let decompressed = codec
+                .decompress(&compressed, out, max_column_size)
+                .map_err(|e| {
+                    parse_error(document::ParseError::RawColumns(
+                        crate::storage::columns::raw_column::ParseError::Decompress(e),
+                    ))
+                })?;
+            if let Some(max) = max_column_size.filter(|max| decompressed > *max) {
+                return Err(Error::LimitExceeded(LimitExceeded {
+                    limit: Limit::ColumnSize,
+                    max,
+                }));
+            }
+        } else if (&mut *input)
+            .take(len)
+            .read_to_end(out)
+            .map_err(Error::Io)? as u64
+            != len
+        {
+            return Err(truncated());
+        }
+        result.push(RawColumn::new(col.spec(), start - base..out.len() - base));
+    }
+    Ok(RawColumns(result))
+}
+
+/// Hashes everything read through it, as the checksum in a chunk header is the start of the hash
+/// of the chunk type, length and data
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    read: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R, chunk_type: ChunkType, len: u64) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update([u8::from(chunk_type)]);
+        let mut encoded_len = Vec::with_capacity(MAX_LEB128_LEN);
+        leb128::write::unsigned(&mut encoded_len, len).unwrap();
+        hasher.update(&encoded_len);
+        Self {
+            inner,
+            hasher,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+fn parse_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Parse(Box::new(e))
+}
+
+/// Read until `buf` is full or the input ends, returning the number of bytes read
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+    Ok(read)
+}
+
+fn truncated() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "input ended in the middle of a chunk",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::Transactable, AutoCommit, ObjType, SaveOptions, ROOT};
+
+    #[test]
+    fn reads_each_chunk() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let mut data = doc.save();
+        let first_len = data.len();
+        doc.put(ROOT, "b", 2).unwrap();
+        data.extend(doc.save_incremental());
+
+        let mut reader = ChunkReader::new(data.as_slice(), None);
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), data[..first_len]);
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), data[first_len..]);
+        assert!(reader.next_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "a", 1).unwrap();
+        let data = doc.save();
+        for len in [3, FIXED_HEADER_LEN, data.len() - 1] {
+            let mut reader = ChunkReader::new(&data[..len], None);
+            assert!(matches!(reader.next_chunk(), Err(Error::Io(_))));
+        }
+    }
+
+    fn compressed_doc() -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, &"hello world ".repeat(500))
+            .unwrap();
+        doc
+    }
+
+    #[test]
+    fn documents_are_decompressed_as_they_are_read() {
+        let mut doc = compressed_doc();
+        let uncompressed = doc.save_nocompress();
+        let codecs = [
+            Codec::Deflate,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4,
+        ];
+        for codec in codecs {
+            let data = doc.save_with_options(SaveOptions {
+                codec,
+                ..Default::default()
+            });
+            assert!(data.len() < uncompressed.len(), "{:?}", codec);
+            let mut reader = ChunkReader::new(data.as_slice(), None);
+            assert_eq!(
+                reader.next_chunk().unwrap().unwrap(),
+                uncompressed,
+                "{:?}",
+                codec
+            );
+            assert!(reader.next_chunk().unwrap().is_none());
+
+            let mut reader = ChunkReader::new(data.as_slice(), Some(10));
+            assert!(matches!(reader.next_chunk(), Err(Error::LimitExceeded(_))));
+
+            for len in [data.len() / 2, data.len() - 1] {
+                let mut reader = ChunkReader::new(&data[..len], None);
+                assert!(matches!(reader.next_chunk(), Err(Error::Io(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn documents_with_a_bad_checksum_are_rejected() {
+        let mut data = compressed_doc().save();
+        data[4] ^= 1;
+        let mut reader = ChunkReader::new(data.as_slice(), None);
+        assert!(matches!(reader.next_chunk(), Err(Error::BadChecksum)));
+    }
+}
@@ -0,0 +1,401 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::backend::sync_parent;
+use super::{chunk, parse, Chunk, Header};
+use crate::{Automerge, AutomergeError, Change};
+
+/// When a [`WriteAheadLog`] syncs appended records to disk
+///
+/// Records are synced as soon as either limit is reached, and when [`WriteAheadLog::sync()`] is
+/// called. The default syncs every record.
+///
+/// The limits are only checked when something happens to the log, nothing runs in the
+/// background. If no more records are appended, records which are past `max_delay` stay unsynced
+/// until [`WriteAheadLog::flush_if_due()`] or [`WriteAheadLog::sync()`] is called, so call
+/// `flush_if_due()` from a timer when using a delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncPolicy {
+    /// The number of records which can be appended without syncing
+    pub max_records: usize,
+    /// How long after the last sync a record can be appended without syncing, see
+    /// [`WriteAheadLog::flush_if_due()`]
+    pub max_delay: Duration,
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self {
+            max_records: 1,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WalError {
+    #[error("error accessing the log: {0}")]
+    Io(#[from] io::Error),
+    /// A record before the end of the log is damaged, so this isn't a write which was interrupted
+    #[error("the log is corrupt at byte {offset}")]
+    Corrupt { offset: u64 },
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+}
+
+/// An append only log of changes which is safe to use across crashes
+///
+/// Each record in the log is a chunk in the same format as [`Automerge::save()`] and
+/// [`AutoCommit::save_incremental()`](crate::AutoCommit::save_incremental) produce, so the
+/// checksum in every chunk header detects records which were only partly written when the
+/// process crashed or the power failed. Such a record can only be at the end of the log, it is
+/// discarded by [`Self::open()`] and the log is truncated to the last complete record. A damaged
+/// record anywhere else fails with [`WalError::Corrupt`].
+///
+/// Appending a record is much cheaper than saving the whole document, so append every local
+/// change as it's made, and call [`Self::checkpoint()`] from time to time to replace the log with
+/// a snapshot of the document so it doesn't grow forever. The [`SyncPolicy`] controls how often
+/// appended records are synced to disk, trading the number of changes which can be lost in a
+/// crash for the cost of an `fsync` per record.
+///
+/// The log must describe the whole document: start it from an empty document or
+/// [`Self::checkpoint()`] the document before appending to it.
+///
+/// ## Example
+///
+/// ```
+/// use automerge::{storage::WriteAheadLog, transaction::Transactable, ReadDoc, ROOT};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let path = std::env::temp_dir().join(format!("automerge-wal-doc-{}", std::process::id()));
+/// let (mut wal, mut doc) = WriteAheadLog::open(&path)?;
+///
+/// let mut tx = doc.transaction();
+/// tx.put(ROOT, "key", "value")?;
+/// tx.commit();
+/// wal.append_change(&doc.get_last_local_change().unwrap())?;
+///
+/// drop(wal);
+/// let (_, replayed) = WriteAheadLog::open(&path)?;
+/// assert_eq!(replayed.get(ROOT, "key")?.unwrap().0.to_str(), Some("value"));
+/// # std::fs::remove_file(&path)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    file: File,
+    path: PathBuf,
+    policy: SyncPolicy,
+    /// The number of records appended since the last sync
+    unsynced: usize,
+    last_sync: Instant,
+}
+
+impl WriteAheadLog {
+    /// Open the log at `path`, creating it if it doesn't exist, and replay it into a document
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<(Self, Automerge), WalError> {
+        let path = path.into();
+        let created = !path.exists();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        if created {
+            // Otherwise a crash could lose the log's directory entry along with every record
+            // synced to it
+            sync_parent(&path)?;
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let complete = complete_records(&data)?;
+        if complete < data.len() {
+            tracing::warn!(
+                discarded = data.len() - complete,
+                "discarding a partly written record at the end of the log"
+            );
+            file.set_len(complete as u64)?;
+            file.sync_all()?;
+        }
+        let doc = Automerge::load(&data[..complete])?;
+        let wal = Self {
+            file,
+            path,
+            policy: SyncPolicy::default(),
+            unsynced: 0,
+            last_sync: Instant::now(),
+        };
+        Ok((wal, doc))
+    }
+
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of bytes in the log
+    pub fn len(&self) -> Result<u64, WalError> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, WalError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Append the output of [`Automerge::save_after()`] or
+    /// [`AutoCommit::save_incremental()`](crate::AutoCommit::save_incremental)
+    ///
+    /// Appending an empty slice does nothing.
+    pub fn append(&mut self, data: &[u8]) -> Result<(), WalError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(data)?;
+        self.unsynced += 1;
+        if self.unsynced >= self.policy.max_records
+            || self.last_sync.elapsed() >= self.policy.max_delay
+        {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Append a single change, such as the one returned by
+    /// [`Automerge::get_last_local_change()`]
+    pub fn append_change(&mut self, change: &Change) -> Result<(), WalError> {
+        self.append(change.raw_bytes())
+    }
+
+    /// Sync the appended records if the [`SyncPolicy::max_delay`] since the last sync has passed,
+    /// returning whether they were synced
+    ///
+    /// [`Self::append()`] only checks the delay when it's called, so call this periodically to
+    /// bound how long a record can stay unsynced when no more are appended.
+    pub fn flush_if_due(&mut self) -> Result<bool, WalError> {
+        if self.unsynced == 0 || self.last_sync.elapsed() < self.policy.max_delay {
+            return Ok(false);
+        }
+        self.sync()?;
+        Ok(true)
+    }
+
+    /// Sync every appended record to disk
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        if self.unsynced > 0 {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Replace the log with a snapshot of `doc`
+    ///
+    /// `doc` must contain every change in the log. The snapshot is written to a temporary file
+    /// which is renamed over the log, so a crash leaves either the old log or the snapshot.
+    pub fn checkpoint(&mut self, doc: &Automerge) -> Result<(), WalError> {
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".checkpoint");
+        let temp = PathBuf::from(temp);
+        {
+            let mut file = File::create(&temp)?;
+            file.write_all(&doc.save())?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&temp, &self.path)?;
+        sync_parent(&self.path)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for WriteAheadLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            tracing::warn!(err=?e, "failed to sync the log");
+        }
+    }
+}
+
+/// The length of the records at the start of `data` which are complete and have valid checksums
+///
+/// A damaged record which runs to the end of `data` was being written when we crashed and is
+/// ignored, any other damaged record is an error.
+fn complete_records(data: &[u8]) -> Result<usize, WalError> {
+    let mut offset = 0;
+    while offset < data.len() {
+        let input = parse::Input::new(&data[offset..]);
+        let corrupt = WalError::Corrupt {
+            offset: offset as u64,
+        };
+        let end = match Chunk::parse(input) {
+            Ok((remaining, chunk)) => {
+                let end = data.len() - remaining.unconsumed_bytes().len();
+                if chunk.checksum_valid() {
+                    offset = end;
+                    continue;
+                }
+                end
+            }
+            Err(_) => match Header::parse::<chunk::error::Header>(input) {
+                Ok((_, header)) => offset + header.len() + header.data_bytes().len(),
+                Err(parse::ParseError::Incomplete(_)) => return Ok(offset),
+                // Filesystems can extend a file with zeros when crashing during a write
+                Err(_) if data[offset..].iter().all(|b| *b == 0) => return Ok(offset),
+                Err(_) => return Err(corrupt),
+            },
+        };
+        return if end >= data.len() {
+            Ok(offset)
+        } else {
+            Err(corrupt)
+        };
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::Transactable, ReadDoc, ROOT};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "automerge-wal-{}-{}-{}",
+            name,
+            std::process::id(),
+            rand::random::<u64>()
+        ))
+    }
+
+    fn put(wal: &mut WriteAheadLog, doc: &mut Automerge, key: &str, value: i64) {
+        let mut tx = doc.transaction();
+        tx.put(ROOT, key, value).unwrap();
+        tx.commit();
+        wal.append_change(&doc.get_last_local_change().unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn ignores_a_torn_final_record() {
+        let path = temp_path("torn");
+        let (mut wal, mut doc) = WriteAheadLog::open(&path).unwrap();
+        put(&mut wal, &mut doc, "a", 1);
+        let first_len = wal.len().unwrap();
+        put(&mut wal, &mut doc, "b", 2);
+        drop(wal);
+
+        let data = std::fs::read(&path).unwrap();
+        for torn_len in [first_len as usize + 3, data.len() - 1] {
+            std::fs::write(&path, &data[..torn_len]).unwrap();
+            let (mut wal, mut replayed) = WriteAheadLog::open(&path).unwrap();
+            assert_eq!(wal.len().unwrap(), first_len);
+            assert_eq!(
+                replayed.get(ROOT, "a").unwrap().unwrap().0.to_i64(),
+                Some(1)
+            );
+            assert!(replayed.get(ROOT, "b").unwrap().is_none());
+
+            // Appending after a torn record works
+            put(&mut wal, &mut replayed, "c", 3);
+            drop(wal);
+            let (_, replayed) = WriteAheadLog::open(&path).unwrap();
+            assert_eq!(
+                replayed.get(ROOT, "c").unwrap().unwrap().0.to_i64(),
+                Some(3)
+            );
+        }
+
+        // A zero filled tail is also a torn write
+        let mut zeros = data[..first_len as usize].to_vec();
+        zeros.extend([0; 16]);
+        std::fs::write(&path, &zeros).unwrap();
+        let (wal, _) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(wal.len().unwrap(), first_len);
+        drop(wal);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_records_before_the_end_are_errors() {
+        let path = temp_path("corrupt");
+        let (mut wal, mut doc) = WriteAheadLog::open(&path).unwrap();
+        put(&mut wal, &mut doc, "a", 1);
+        put(&mut wal, &mut doc, "b", 2);
+        drop(wal);
+
+        let mut data = std::fs::read(&path).unwrap();
+        // The last byte of the first record
+        let first = doc.get_changes(&[])[0].raw_bytes().len();
+        data[first - 1] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        assert!(matches!(
+            WriteAheadLog::open(&path),
+            Err(WalError::Corrupt { offset: 0 })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn flush_if_due_syncs_after_the_delay() {
+        let path = temp_path("delay");
+        let (wal, mut doc) = WriteAheadLog::open(&path).unwrap();
+        let mut wal = wal.with_sync_policy(SyncPolicy {
+            max_records: 10,
+            max_delay: Duration::from_millis(20),
+        });
+        put(&mut wal, &mut doc, "a", 1);
+        assert_eq!(wal.unsynced, 1);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(wal.flush_if_due().unwrap());
+        assert_eq!(wal.unsynced, 0);
+        // nothing to sync
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!wal.flush_if_due().unwrap());
+        drop(wal);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_replaces_the_log() {
+        let path = temp_path("checkpoint");
+        let (wal, mut doc) = WriteAheadLog::open(&path).unwrap();
+        let mut wal = wal.with_sync_policy(SyncPolicy {
+            max_records: 10,
+            max_delay: Duration::from_secs(60),
+        });
+        for i in 0..5 {
+            put(&mut wal, &mut doc, "count", i);
+        }
+        assert_eq!(wal.unsynced, 5);
+        assert!(!wal.flush_if_due().unwrap());
+        wal.sync().unwrap();
+        assert_eq!(wal.unsynced, 0);
+
+        let before = wal.len().unwrap();
+        wal.checkpoint(&doc).unwrap();
+        assert!(wal.len().unwrap() < before);
+        put(&mut wal, &mut doc, "count", 5);
+        drop(wal);
+
+        let (_, replayed) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(replayed.get_heads(), doc.get_heads());
+        assert_eq!(
+            replayed.get(ROOT, "count").unwrap().unwrap().0.to_i64(),
+            Some(5)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -66,21 +66,37 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! ## Ephemeral data
+//!
+//! Data such as cursors and selections which shouldn't be stored in the document can be sent to
+//! peers in an [`EphemeralMessage`] on the same connection as sync messages, see [`Frame`].
+//!
+//! ## Async
+//!
+//! With the `async` feature enabled, `SyncDriver` runs this loop over a `futures::Stream` of
+//! incoming messages and a `futures::Sink` of outgoing messages, generating new messages
+//! whenever a local change is made to the document.
 
 use itertools::Itertools;
 use serde::ser::SerializeMap;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 #[cfg(test)]
 use crate::ReadDoc;
 use crate::{
+    change_graph::ChangeGraph,
     patches::PatchLog,
     storage::{parse, ReadChangeOpError},
-    Automerge, AutomergeError, ChangeHash,
+    Automerge, AutomergeError, Change, ChangeHash, LoadLimits,
 };
 
 mod bloom;
+#[cfg(feature = "async")]
+mod driver;
+mod ephemeral;
 mod message_builder;
+mod progress;
 mod state;
 use message_builder::MessageBuilder;
 
@@ -88,6 +104,10 @@ use message_builder::MessageBuilder;
 mod v1_compat_test;
 
 pub use bloom::{BloomFilter, DecodeError as DecodeBloomError};
+#[cfg(feature = "async")]
+pub use driver::{DriverError, SharedDoc, SyncDriver, SyncEvent};
+pub use ephemeral::{EphemeralMessage, EphemeralValue, Frame};
+pub use progress::{Progress, Remaining};
 pub use state::DecodeError as DecodeStateError;
 pub use state::{Have, State};
 
@@ -131,6 +151,13 @@ pub trait SyncDoc {
         message: Message,
         patch_log: &mut PatchLog,
     ) -> Result<(), AutomergeError>;
+
+    /// Estimate how much is left to sync with the peer represented by `sync_state`
+    ///
+    /// This is [`None`] until a message has been received from the peer. The estimate is worked
+    /// out on each call from what the peer last told us, see [`Progress`], rather than whenever a
+    /// message is received, so only callers who want it pay for it.
+    fn sync_progress(&self, sync_state: &State) -> Option<Progress>;
 }
 
 const MESSAGE_TYPE_SYNC: u8 = 0x42; // first byte of a sync message, for identification
@@ -163,9 +190,56 @@ impl MessageVersion {
     }
 }
 
-impl SyncDoc for Automerge {
-    fn generate_sync_message(&self, sync_state: &mut State) -> Option<Message> {
-        let our_heads = self.get_heads();
+/// The parts of a document which the sync protocol needs
+///
+/// This is implemented by [`Automerge`] and by [`ChangeStore`](crate::ChangeStore), which has
+/// the change graph but no materialized document, and provides the logic for [`SyncDoc`] on top.
+pub(crate) trait SyncStore {
+    fn change_graph(&self) -> &ChangeGraph;
+
+    /// See [`ChangeQueue::missing_deps_from`](crate::change_queue::ChangeQueue::missing_deps_from)
+    fn missing_deps(&self, start: impl Iterator<Item = ChangeHash>) -> Vec<ChangeHash>;
+
+    fn changes_by_hashes(&self, hashes: Vec<ChangeHash>) -> Result<Vec<Change>, AutomergeError>;
+
+    /// The whole document as a single chunk, or `None` if producing it would be expensive, in
+    /// which case we send individual changes to peers who have nothing
+    fn save_whole_doc(&self) -> Option<Vec<u8>>;
+
+    /// Apply the concatenated change chunks in a sync message
+    fn load_sync_changes(
+        &mut self,
+        data: &[u8],
+        limits: &LoadLimits,
+        patch_log: &mut PatchLog,
+    ) -> Result<(), AutomergeError>;
+
+    /// The total number of ops in the document, used to estimate the size of changes
+    fn num_ops(&self) -> usize;
+
+    fn heads(&self) -> Vec<ChangeHash> {
+        self.change_graph().heads().collect()
+    }
+
+    fn has_change(&self, hash: &ChangeHash) -> bool {
+        self.change_graph().has_change(hash)
+    }
+
+    /// Filter the changes down to those that are not transitive dependencies of the heads.
+    ///
+    /// Thus a graph with these heads has not seen the remaining changes.
+    fn filter_changes(&self, heads: &[ChangeHash], changes: &mut BTreeSet<ChangeHash>) {
+        let heads = heads
+            .iter()
+            .filter(|hash| self.has_change(hash))
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.change_graph().remove_ancestors(changes, &heads);
+    }
+
+    fn generate_sync_message_inner(&self, sync_state: &mut State) -> Option<Message> {
+        let our_heads = self.heads();
 
         let our_need = if sync_state.read_only {
             vec![]
@@ -179,7 +253,7 @@ impl SyncDoc for Automerge {
             // the peer never sends us the unrelated changes it _does_ have. We still pick the
             // orphans' dependencies back up if we later sync with a peer whose heads depend on
             // them.
-            self.missing_deps_from(sync_state.their_heads.iter().flatten().copied())
+            self.missing_deps(sync_state.their_heads.iter().flatten().copied())
         };
 
         let their_heads_set = if let Some(ref heads) = sync_state.their_heads {
@@ -205,14 +279,19 @@ impl SyncDoc for Automerge {
             }
         }
 
-        let message_builder = if sync_state.is_peer_read_only() {
+        let mut message_builder = if sync_state.is_peer_read_only() {
             // The remote peer is read-only and will ignore incoming changes.
             // Skip computing and sending changes to save bandwidth.
             MessageBuilder::new(vec![], sync_state)
         } else if let Some((their_have, their_need)) = sync_state.their() {
-            if sync_state.send_doc() {
-                let hashes = self.change_graph.get_hashes(&[]);
-                MessageBuilder::new_v2(self.save(), hashes)
+            let whole_doc = if sync_state.send_doc() {
+                self.save_whole_doc()
+            } else {
+                None
+            };
+            if let Some(doc) = whole_doc {
+                let hashes = self.change_graph().get_hashes(&[]);
+                MessageBuilder::new_v2(doc, hashes)
             } else {
                 let all_hashes = self
                     .get_hashes_to_send(their_have, their_need)
@@ -222,12 +301,19 @@ impl SyncDoc for Automerge {
                     .into_iter()
                     .filter(|hash| !sync_state.sent_hashes.contains(hash))
                     .collect();
-                if hashes.len() > self.change_graph.len() / 3 && sync_state.supports_v2_messages() {
+                let whole_doc = if hashes.len() > self.change_graph().len() / 3
+                    && sync_state.supports_v2_messages()
+                {
                     // sending more than a 1/3 of the document?  send everything
-                    let all_hashes = self.change_graph.get_hashes(&[]);
-                    MessageBuilder::new_v2(self.save(), all_hashes)
+                    self.save_whole_doc()
+                } else {
+                    None
+                };
+                if let Some(doc) = whole_doc {
+                    let all_hashes = self.change_graph().get_hashes(&[]);
+                    MessageBuilder::new_v2(doc, all_hashes)
                 } else {
-                    let changes = self.get_changes_by_hashes(hashes.iter().copied()).ok()?;
+                    let changes = self.get_changes_to_send(hashes, sync_state).ok()?;
                     MessageBuilder::new(changes, sync_state)
                 }
             }
@@ -250,10 +336,10 @@ impl SyncDoc for Automerge {
 
         sync_state.have_responded = true;
         sync_state.last_sent_heads.clone_from(&our_heads);
-        sync_state.sent_hashes.extend(message_builder.hashes());
 
         let mut flags = MessageFlags::new();
         flags.set(MessageFlags::SUPPORTS_SYNC_RESET);
+        flags.set(MessageFlags::SUPPORTS_EPHEMERAL);
         if sync_state.read_only {
             flags.set(MessageFlags::READ_ONLY);
         }
@@ -276,45 +362,61 @@ impl SyncDoc for Automerge {
             our_heads.clone()
         };
 
-        let sync_message = message_builder
+        message_builder = message_builder
             .heads(heads_to_send)
             .have(our_have)
             .need(our_need)
-            .flags(Some(flags))
-            .build();
-
-        sync_state.in_flight = true;
-        Some(sync_message)
-    }
-
-    fn receive_sync_message(
-        &mut self,
-        sync_state: &mut State,
-        message: Message,
-    ) -> Result<(), AutomergeError> {
-        let mut patch_log = PatchLog::inactive();
-        self.receive_sync_message_inner(sync_state, message, &mut patch_log)
-    }
+            .flags(Some(flags));
+
+        let mut complete = true;
+        if let Some(max_len) = sync_state.max_message_size {
+            if !message_builder.is_splittable() && message_builder.encoded_len() > max_len {
+                // The whole document doesn't fit, send the changes it is made of instead. If we
+                // have already sent some of them in earlier messages, skip those.
+                let hashes = message_builder
+                    .hashes()
+                    .filter(|hash| !sync_state.sent_hashes.contains(hash))
+                    .copied()
+                    .collect();
+                let changes = self.get_changes_to_send(hashes, sync_state).ok()?;
+                message_builder = message_builder.with_changes(changes, sync_state);
+            }
+            if message_builder.is_splittable() {
+                complete = !message_builder.truncate(max_len);
+            }
+        }
 
-    fn receive_sync_message_log_patches(
-        &mut self,
-        sync_state: &mut State,
-        message: Message,
-        patch_log: &mut PatchLog,
-    ) -> Result<(), AutomergeError> {
-        self.receive_sync_message_inner(sync_state, message, patch_log)
+        sync_state.sent_hashes.extend(message_builder.hashes());
+        // If some changes didn't fit in this message we are not waiting for the peer, the next
+        // call to `generate_sync_message` should produce the next batch of changes
+        sync_state.in_flight = complete;
+        Some(message_builder.build())
     }
-}
 
-impl Automerge {
     fn make_bloom_filter(&self, last_sync: Vec<ChangeHash>) -> Have {
-        let hashes = self.change_graph.get_hashes(&last_sync);
+        let hashes = self.change_graph().get_hashes(&last_sync);
         Have {
             last_sync,
             bloom: BloomFilter::from_hashes(hashes.iter()),
         }
     }
 
+    /// The changes for `hashes`, in causal order if `sync_state` limits the message size so that
+    /// every prefix of the changes can be applied by the peer on its own
+    fn get_changes_to_send(
+        &self,
+        hashes: Vec<ChangeHash>,
+        sync_state: &State,
+    ) -> Result<Vec<Change>, AutomergeError> {
+        let mut changes = self.changes_by_hashes(hashes)?;
+        if sync_state.max_message_size.is_some() {
+            // Changes are added to the change graph after their dependencies, so the order of
+            // their indexes is a causal order
+            changes.sort_by_key(|c| self.change_graph().hash_to_index(&c.hash()));
+        }
+        Ok(changes)
+    }
+
     fn get_hashes_to_send(
         &self,
         have: &[Have],
@@ -338,7 +440,7 @@ impl Automerge {
             }
             let last_sync_hashes = last_sync_hashes.into_iter().copied().collect::<Vec<_>>();
 
-            let hashes = self.change_graph.get_hashes(&last_sync_hashes);
+            let hashes = self.change_graph().get_hashes(&last_sync_hashes);
 
             let mut change_hashes = HashSet::with_capacity(hashes.len());
             let mut dependents: HashMap<ChangeHash, Vec<ChangeHash>> = HashMap::new();
@@ -347,7 +449,7 @@ impl Automerge {
             for hash in &*hashes {
                 change_hashes.insert(*hash);
 
-                for dep in self.change_graph.deps(hash) {
+                for dep in self.change_graph().deps(hash) {
                     dependents.entry(dep).or_default().push(*hash);
                 }
 
@@ -383,14 +485,21 @@ impl Automerge {
         }
     }
 
-    pub(crate) fn receive_sync_message_inner(
+    fn sync_progress_inner(&self, sync_state: &State) -> Option<Progress> {
+        sync_state
+            .their_heads
+            .as_ref()
+            .map(|_| progress::estimate(self, sync_state))
+    }
+
+    fn receive_sync_message_inner(
         &mut self,
         sync_state: &mut State,
         message: Message,
         patch_log: &mut PatchLog,
     ) -> Result<(), AutomergeError> {
         sync_state.in_flight = false;
-        let before_heads = self.get_heads();
+        let before_heads = self.heads();
 
         let Message {
             heads: message_heads,
@@ -408,6 +517,9 @@ impl Automerge {
             if flags.contains(MessageFlags::SUPPORTS_SYNC_RESET) {
                 caps.push(Capability::SyncReset);
             }
+            if flags.contains(MessageFlags::SUPPORTS_EPHEMERAL) {
+                caps.push(Capability::Ephemeral);
+            }
             sync_state.their_capabilities = Some(caps);
 
             // Process transient per-message signals
@@ -419,16 +531,16 @@ impl Automerge {
 
         let changes_is_empty = message_changes.is_empty();
         if !changes_is_empty && !sync_state.read_only {
-            self.load_incremental_log_patches(&message_changes.join(), patch_log)?;
+            self.load_sync_changes(&message_changes.join(), &sync_state.load_limits, patch_log)?;
             sync_state.shared_heads = advance_heads(
                 &before_heads.iter().collect(),
-                &self.get_heads().into_iter().collect(),
+                &self.heads().into_iter().collect(),
                 &sync_state.shared_heads,
             );
         }
 
         // trim down the sent hashes to those that we know they haven't seen
-        self.filter_changes(&message_heads, &mut sync_state.sent_hashes)?;
+        self.filter_changes(&message_heads, &mut sync_state.sent_hashes);
 
         if changes_is_empty && message_heads == before_heads {
             sync_state.last_sent_heads.clone_from(&message_heads);
@@ -464,6 +576,70 @@ impl Automerge {
     }
 }
 
+impl SyncStore for Automerge {
+    fn change_graph(&self) -> &ChangeGraph {
+        &self.change_graph
+    }
+
+    fn missing_deps(&self, start: impl Iterator<Item = ChangeHash>) -> Vec<ChangeHash> {
+        self.missing_deps_from(start)
+    }
+
+    fn changes_by_hashes(&self, hashes: Vec<ChangeHash>) -> Result<Vec<Change>, AutomergeError> {
+        self.get_changes_by_hashes(hashes)
+    }
+
+    fn save_whole_doc(&self) -> Option<Vec<u8>> {
+        Some(self.save())
+    }
+
+    fn load_sync_changes(
+        &mut self,
+        data: &[u8],
+        limits: &LoadLimits,
+        patch_log: &mut PatchLog,
+    ) -> Result<(), AutomergeError> {
+        self.load_incremental_with_limits(data, patch_log, limits)?;
+        Ok(())
+    }
+
+    fn num_ops(&self) -> usize {
+        self.ops.len()
+    }
+
+    fn heads(&self) -> Vec<ChangeHash> {
+        self.get_heads()
+    }
+}
+
+impl SyncDoc for Automerge {
+    fn generate_sync_message(&self, sync_state: &mut State) -> Option<Message> {
+        self.generate_sync_message_inner(sync_state)
+    }
+
+    fn receive_sync_message(
+        &mut self,
+        sync_state: &mut State,
+        message: Message,
+    ) -> Result<(), AutomergeError> {
+        let mut patch_log = PatchLog::inactive();
+        self.receive_sync_message_inner(sync_state, message, &mut patch_log)
+    }
+
+    fn receive_sync_message_log_patches(
+        &mut self,
+        sync_state: &mut State,
+        message: Message,
+        patch_log: &mut PatchLog,
+    ) -> Result<(), AutomergeError> {
+        self.receive_sync_message_inner(sync_state, message, patch_log)
+    }
+
+    fn sync_progress(&self, sync_state: &State) -> Option<Progress> {
+        self.sync_progress_inner(sync_state)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ReadMessageError {
     #[error("expected {expected_one_of:?} but found {found}")]
@@ -631,6 +807,7 @@ impl Message {
             flags: {
                 let mut f = MessageFlags::new();
                 f.set(MessageFlags::SUPPORTS_SYNC_RESET);
+                f.set(MessageFlags::SUPPORTS_EPHEMERAL);
                 Some(f)
             },
             version: MessageVersion::V1,
@@ -756,6 +933,9 @@ impl MessageFlags {
     /// Advertises that the sender understands the [`SYNC_RESET`](Self::SYNC_RESET)
     /// flag and will clear `sent_hashes` when it receives one.
     pub const SUPPORTS_SYNC_RESET: u8 = 1 << 2;
+    /// Advertises that the sender understands [`EphemeralMessage`]s sent on the same connection
+    /// as sync messages.
+    pub const SUPPORTS_EPHEMERAL: u8 = 1 << 3;
 
     const BITFIELD_MARKER: u8 = 0x80;
     /// The old MessageV2 byte, sent first for backwards compatibility.
@@ -797,12 +977,15 @@ impl MessageFlags {
 /// Persistent peer capabilities, derived from [`MessageFlags`]s on incoming
 /// messages. These describe what the remote peer supports.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum Capability {
     MessageV1,
     MessageV2,
     /// The peer understands the [`MessageFlags::SYNC_RESET`] flag and will
     /// clear its `sent_hashes` when it receives one.
     SyncReset,
+    /// The peer understands [`EphemeralMessage`]s.
+    Ephemeral,
 }
 
 fn encode_many<'a, I, It, F>(out: &mut Vec<u8>, data: I, f: F)
@@ -856,7 +1039,7 @@ mod tests {
     //use crate::change::gen::gen_change;
     use crate::storage::parse::Input;
     use crate::storage::Chunk;
-    use crate::transaction::Transactable;
+    use crate::transaction::{CommitOptions, Transactable};
     //use crate::types::gen::gen_hash;
     use crate::ActorId;
     //use proptest::prelude::*;
@@ -2030,6 +2213,19 @@ mod tests {
         assert_eq!(a.get_heads(), b.get_heads());
     }
 
+    #[test]
+    fn switching_to_read_write_keeps_configuration() {
+        let limits = LoadLimits {
+            max_ops: Some(10),
+            ..Default::default()
+        };
+        let mut state = State::new_read_only().with_max_message_size(100);
+        state.load_limits = limits;
+        state.set_read_only(false);
+        assert_eq!(state.load_limits, limits);
+        assert_eq!(state.max_message_size, Some(100));
+    }
+
     #[test]
     fn switch_read_write_to_read_only_mid_session() {
         // A and B sync normally (both read-write). Then A switches to
@@ -2492,4 +2688,214 @@ mod tests {
         assert!(a.get(crate::ROOT, "from_b").unwrap().is_some());
         assert_eq!(a.get_heads(), b.get_heads());
     }
+
+    /// Sync `a` and `b`, sending every message each side has before switching to the other, and
+    /// return the length of every encoded message
+    fn sync_all_messages(
+        a: &mut crate::AutoCommit,
+        b: &mut crate::AutoCommit,
+        a_sync_state: &mut State,
+        b_sync_state: &mut State,
+    ) -> Vec<usize> {
+        let mut lens = Vec::new();
+        for _ in 0..100 {
+            let a_sent = send_all_messages(a, a_sync_state, b, b_sync_state, &mut lens);
+            let b_sent = send_all_messages(b, b_sync_state, a, a_sync_state, &mut lens);
+            if !a_sent && !b_sent {
+                return lens;
+            }
+        }
+        panic!("failed to sync");
+    }
+
+    fn send_all_messages(
+        from: &mut crate::AutoCommit,
+        from_state: &mut State,
+        to: &mut crate::AutoCommit,
+        to_state: &mut State,
+        lens: &mut Vec<usize>,
+    ) -> bool {
+        let mut messages = Vec::new();
+        while let Some(msg) = from.sync().generate_sync_message(from_state) {
+            messages.push(msg.encode());
+        }
+        for msg in &messages {
+            lens.push(msg.len());
+            let msg = Message::decode(msg).unwrap();
+            to.sync().receive_sync_message(to_state, msg).unwrap();
+        }
+        !messages.is_empty()
+    }
+
+    #[test]
+    fn max_message_size_splits_changes_across_messages() {
+        let mut doc1 = crate::AutoCommit::new().with_actor(ActorId::try_from("abc123").unwrap());
+        let mut doc2 = crate::AutoCommit::new().with_actor(ActorId::try_from("def456").unwrap());
+        let text = doc1
+            .put_object(crate::ROOT, "text", crate::ObjType::Text)
+            .unwrap();
+        doc1.commit();
+        for i in 0..100 {
+            let chunk = format!("{:020}", i * 7919);
+            doc1.splice_text(&text, i * 20, 0, &chunk).unwrap();
+            doc1.commit();
+        }
+        doc2.put(crate::ROOT, "other", "value").unwrap();
+        doc2.commit();
+
+        let max = 600;
+        let mut s1 = State::new().with_max_message_size(max);
+        let mut s2 = State::new().with_max_message_size(max);
+        let lens = sync_all_messages(&mut doc1, &mut doc2, &mut s1, &mut s2);
+
+        assert!(lens.iter().all(|len| *len <= max), "{:?}", lens);
+        assert!(lens.len() > 4, "{:?}", lens);
+        assert_eq!(doc1.get_heads(), doc2.get_heads());
+        assert_eq!(doc2.text(&text).unwrap(), doc1.text(&text).unwrap());
+    }
+
+    #[test]
+    fn max_message_size_sends_changes_in_causal_order() {
+        let mut doc1 = crate::AutoCommit::new().with_actor(ActorId::try_from("abc123").unwrap());
+        for i in 0..50 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit();
+        }
+        // Make the peer far behind and ask for everything, including the heads explicitly
+        let mut doc2 = crate::AutoCommit::new().with_actor(ActorId::try_from("def456").unwrap());
+        let mut s1 = State::new().with_max_message_size(300);
+        let mut s2 = State::new();
+
+        let mut applied = 0;
+        for _ in 0..200 {
+            let mut progressed = false;
+            while let Some(msg) = doc1.sync().generate_sync_message(&mut s1) {
+                for change in msg.changes.iter() {
+                    // Every batch must be applicable without any of the later batches
+                    let mut fresh = crate::Automerge::load(&doc2.save()).unwrap();
+                    fresh.load_incremental(change).unwrap();
+                    assert!(fresh.get_missing_deps(&[]).is_empty());
+                }
+                doc2.sync().receive_sync_message(&mut s2, msg).unwrap();
+                assert!(doc2.get_missing_deps(&[]).is_empty());
+                progressed = true;
+            }
+            if let Some(msg) = doc2.sync().generate_sync_message(&mut s2) {
+                doc1.sync().receive_sync_message(&mut s1, msg).unwrap();
+                progressed = true;
+            }
+            if !progressed {
+                break;
+            }
+            applied += 1;
+        }
+        assert!(applied > 1);
+        assert_eq!(doc1.get_heads(), doc2.get_heads());
+    }
+
+    #[test]
+    fn max_message_size_does_not_send_whole_doc_that_is_too_large() {
+        let mut doc1 = crate::AutoCommit::new();
+        let mut doc2 = crate::AutoCommit::new();
+        for i in 0..200 {
+            doc2.put(crate::ROOT, format!("key{}", i), i * 7919)
+                .unwrap();
+            doc2.commit();
+        }
+        assert!(doc2.save().len() > 600);
+
+        let mut s1 = State::new();
+        let mut s2 = State::new().with_max_message_size(600);
+
+        let outgoing = doc1.sync().generate_sync_message(&mut s1).unwrap();
+        doc2.sync().receive_sync_message(&mut s2, outgoing).unwrap();
+        let response = doc2.sync().generate_sync_message(&mut s2).unwrap();
+        assert!(response.clone().encode().len() <= 600);
+        let (_, chunk) = Chunk::parse(Input::new(&response.changes.0[0])).unwrap();
+        assert!(matches!(chunk, Chunk::Change(_)));
+
+        // The rest of the changes follow without waiting for a reply
+        assert!(!s2.in_flight);
+        doc1.sync().receive_sync_message(&mut s1, response).unwrap();
+        let lens = sync_all_messages(&mut doc1, &mut doc2, &mut s1, &mut s2);
+        assert!(lens.iter().all(|len| *len <= 600), "{:?}", lens);
+        assert_eq!(doc1.get_heads(), doc2.get_heads());
+    }
+
+    #[test]
+    fn max_message_size_still_sends_a_change_larger_than_the_limit() {
+        let mut doc1 = crate::AutoCommit::new();
+        let mut doc2 = crate::AutoCommit::new();
+        doc1.put(crate::ROOT, "big", "x".repeat(1000)).unwrap();
+        doc1.commit();
+
+        let mut s1 = State::new().with_max_message_size(100);
+        let mut s2 = State::new().with_max_message_size(100);
+        sync_all_messages(&mut doc1, &mut doc2, &mut s1, &mut s2);
+        assert_eq!(doc1.get_heads(), doc2.get_heads());
+    }
+
+    #[test]
+    fn progress_estimates_changes_remaining_in_both_directions() {
+        let mut doc1 = crate::AutoCommit::new().with_actor(ActorId::try_from("abc123").unwrap());
+        let mut doc2 = crate::AutoCommit::new().with_actor(ActorId::try_from("def456").unwrap());
+        for i in 0..50 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit();
+        }
+        let mut s1 = State::new();
+        let mut s2 = State::new();
+        assert_eq!(doc1.sync().sync_progress(&s1), None);
+
+        let m1 = doc1.sync().generate_sync_message(&mut s1).unwrap();
+        doc2.sync().receive_sync_message(&mut s2, m1).unwrap();
+        let progress = doc2.sync().sync_progress(&s2).unwrap();
+        assert_eq!(progress.to_receive.changes, 50);
+        assert!(progress.to_receive.bytes > 0);
+        assert_eq!(progress.to_send, Remaining::default());
+
+        let m2 = doc2.sync().generate_sync_message(&mut s2).unwrap();
+        doc1.sync().receive_sync_message(&mut s1, m2).unwrap();
+        let progress = doc1.sync().sync_progress(&s1).unwrap();
+        assert_eq!(progress.to_send.changes, 50);
+        assert!(progress.to_send.bytes > 0);
+        assert_eq!(progress.to_receive, Remaining::default());
+
+        sync(&mut doc1, &mut doc2, &mut s1, &mut s2);
+        assert!(doc1.sync().sync_progress(&s1).unwrap().is_complete());
+        assert!(doc2.sync().sync_progress(&s2).unwrap().is_complete());
+    }
+
+    #[test]
+    fn progress_counts_only_changes_the_peer_is_missing() {
+        // Fixed timestamps so that the hashes, and so bloom filter false positives, are
+        // deterministic
+        let mut doc1 = crate::AutoCommit::new().with_actor(ActorId::try_from("abc123").unwrap());
+        for i in 0..10 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit_with(CommitOptions::default().with_time(0));
+        }
+        let mut doc2 = doc1.fork().with_actor(ActorId::try_from("def456").unwrap());
+        let mut s1 = State::new();
+        let mut s2 = State::new();
+        sync(&mut doc1, &mut doc2, &mut s1, &mut s2);
+
+        for i in 0..5 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit_with(CommitOptions::default().with_time(0));
+        }
+        for i in 0..3 {
+            doc2.put(crate::ROOT, "other", i).unwrap();
+            doc2.commit_with(CommitOptions::default().with_time(0));
+        }
+
+        // Send one change at a time so that doc2 is still waiting for some of doc1's changes
+        s1.max_message_size = Some(1);
+        let m1 = doc1.sync().generate_sync_message(&mut s1).unwrap();
+        assert_eq!(m1.changes.len(), 1);
+        doc2.sync().receive_sync_message(&mut s2, m1).unwrap();
+        let progress = doc2.sync().sync_progress(&s2).unwrap();
+        assert_eq!(progress.to_receive.changes, 4);
+        assert_eq!(progress.to_send.changes, 3);
+    }
 }
@@ -107,6 +107,11 @@ impl BloomFilter {
             .map(|byte| byte & (1 << (probe & 7)))
     }
 
+    /// The number of hashes which were added to this filter
+    pub fn num_entries(&self) -> u32 {
+        self.num_entries
+    }
+
     pub fn contains_hash(&self, hash: &ChangeHash) -> bool {
         if self.num_entries == 0 {
             false
@@ -0,0 +1,472 @@
+//! An async driver for the sync protocol, enabled with the `async` feature
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use futures::future::{self, Either};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use super::{Message, ReadMessageError, State, SyncDoc};
+use crate::{Automerge, AutomergeError, ChangeHash, Patch, PatchLog};
+
+/// A document shared between a [`SyncDriver`] and the rest of the application
+///
+/// All access to the document goes through [`Self::with_doc()`] or [`Self::read()`], which take
+/// a lock on the document for the duration of the closure. Whenever [`Self::with_doc()`] changes
+/// the heads of the document every [`SyncDriver`] using this document is woken up so that it can
+/// send the new changes to its peer.
+///
+/// Cloning a [`SharedDoc`] produces another handle to the same document.
+#[derive(Clone)]
+pub struct SharedDoc {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    doc: Mutex<Automerge>,
+    notify: Mutex<Notify>,
+}
+
+#[derive(Default)]
+struct Notify {
+    generation: u64,
+    wakers: Vec<Waker>,
+}
+
+impl SharedDoc {
+    pub fn new(doc: Automerge) -> Self {
+        Self {
+            inner: Arc::new(Shared {
+                doc: Mutex::new(doc),
+                notify: Mutex::new(Notify::default()),
+            }),
+        }
+    }
+
+    /// Run `f` with mutable access to the document
+    ///
+    /// If the heads of the document are different after `f` returns then any [`SyncDriver`]s
+    /// using this document will generate new sync messages.
+    pub fn with_doc<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Automerge) -> R,
+    {
+        let mut doc = self.lock();
+        let before = doc.get_heads();
+        let result = f(&mut doc);
+        let changed = doc.get_heads() != before;
+        drop(doc);
+        if changed {
+            self.notify_changed();
+        }
+        result
+    }
+
+    /// Run `f` with read only access to the document
+    pub fn read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Automerge) -> R,
+    {
+        f(&self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Automerge> {
+        // A panic in a user closure leaves the document as it was after the last complete
+        // operation, there's nothing to recover.
+        self.inner
+            .doc
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn notify(&self) -> MutexGuard<'_, Notify> {
+        self.inner
+            .notify
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn generation(&self) -> u64 {
+        self.notify().generation
+    }
+
+    fn notify_changed(&self) {
+        let wakers = {
+            let mut notify = self.notify();
+            notify.generation = notify.generation.wrapping_add(1);
+            std::mem::take(&mut notify.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// A future which resolves once the generation is different from `seen`
+    fn changed(&self, seen: u64) -> Changed<'_> {
+        Changed { doc: self, seen }
+    }
+}
+
+impl fmt::Debug for SharedDoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedDoc")
+            .field("generation", &self.generation())
+            .finish_non_exhaustive()
+    }
+}
+
+struct Changed<'a> {
+    doc: &'a SharedDoc,
+    seen: u64,
+}
+
+impl Future for Changed<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut notify = self.doc.notify();
+        if notify.generation != self.seen {
+            return Poll::Ready(());
+        }
+        // The same task polls a new `Changed` every time round the driver loop, don't let the
+        // waker list grow unboundedly while nothing changes locally
+        if !notify.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            notify.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Events emitted by [`SyncDriver::run()`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncEvent {
+    /// The peer sent us changes, these are the patches which represent the changes to the
+    /// current state of the document
+    Patches(Vec<Patch>),
+    /// We have nothing more to send and the peer has told us that its heads are the same as ours.
+    ///
+    /// This is emitted every time the driver reaches this state with new heads, so after a local
+    /// or remote change is synced it will be emitted again.
+    Synced(Vec<ChangeHash>),
+}
+
+/// Errors returned by [`SyncDriver::run()`]
+#[derive(Debug, thiserror::Error)]
+pub enum DriverError<E> {
+    #[error("unable to decode sync message: {0}")]
+    Decode(#[from] ReadMessageError),
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+    #[error("unable to send sync message: {0}")]
+    Send(E),
+}
+
+/// Runs the sync protocol for a [`SharedDoc`] over a [`Stream`] of incoming messages and a
+/// [`Sink`] of outgoing messages
+///
+/// The loop in the [module level documentation](crate::sync) is the core of the protocol but it
+/// leaves a lot to the caller. In particular it is easy to forget to generate a new message after
+/// making local changes to the document, and the obvious way of waiting for either an incoming
+/// message or a local change is to poll in a loop. [`SyncDriver`] handles both of these: it only
+/// wakes up when a message arrives or when the [`SharedDoc`] it is syncing changes, and it always
+/// generates a new message after either of those things happens.
+///
+/// ## Example
+///
+/// ```
+/// # use automerge::{sync::{SharedDoc, SyncDriver, SyncEvent}, transaction::Transactable, Automerge, ROOT};
+/// # use futures::channel::mpsc;
+/// # use futures::{future::{self, Either}, StreamExt};
+/// # futures::executor::block_on(async {
+/// let doc1 = SharedDoc::new(Automerge::new());
+/// let doc2 = SharedDoc::new(Automerge::new());
+///
+/// // In-memory channels standing in for a network connection
+/// let (tx1, rx1) = mpsc::unbounded::<Vec<u8>>();
+/// let (tx2, rx2) = mpsc::unbounded::<Vec<u8>>();
+///
+/// let mut driver1 = SyncDriver::new(doc1.clone(), rx2, tx1);
+/// let mut driver2 = SyncDriver::new(doc2.clone(), rx1, tx2);
+///
+/// doc1.with_doc(|doc| {
+///     let mut tx = doc.transaction();
+///     tx.put(ROOT, "key", "value").unwrap();
+///     tx.commit();
+/// });
+///
+/// let (events_tx, mut events) = mpsc::unbounded();
+/// let run1 = driver1.run(|_| {});
+/// let run2 = driver2.run(move |event| events_tx.unbounded_send(event).unwrap());
+/// let wait_for_patches = async {
+///     while let Some(event) = events.next().await {
+///         if let SyncEvent::Patches(_) = event {
+///             break;
+///         }
+///     }
+/// };
+/// futures::pin_mut!(run1, run2, wait_for_patches);
+/// let drivers = future::select(run1, run2);
+/// if let Either::Left(_) = future::select(drivers, wait_for_patches).await {
+///     panic!("a driver stopped early");
+/// }
+/// assert!(doc2.read(|doc| doc.get_heads() == doc1.read(|d| d.get_heads())));
+/// # });
+/// ```
+pub struct SyncDriver<S, K> {
+    doc: SharedDoc,
+    state: State,
+    incoming: S,
+    outgoing: K,
+    log_patches: bool,
+}
+
+impl<S, K> SyncDriver<S, K>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+    K: Sink<Vec<u8>> + Unpin,
+{
+    /// Create a driver with a fresh [`State`]
+    ///
+    /// * `doc` - The document to sync
+    /// * `incoming` - Encoded [`Message`]s received from the peer
+    /// * `outgoing` - Where to send encoded [`Message`]s for the peer
+    pub fn new(doc: SharedDoc, incoming: S, outgoing: K) -> Self {
+        Self {
+            doc,
+            state: State::new(),
+            incoming,
+            outgoing,
+            log_patches: true,
+        }
+    }
+
+    /// Use an existing [`State`], e.g. one restored with [`State::decode()`]
+    pub fn with_state(mut self, state: State) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Whether to emit [`SyncEvent::Patches`], defaults to `true`
+    ///
+    /// Logging patches makes applying incoming changes more expensive, turn it off if you don't
+    /// use them.
+    pub fn with_patches(mut self, log_patches: bool) -> Self {
+        self.log_patches = log_patches;
+        self
+    }
+
+    /// The current sync state, this can be persisted with [`State::encode()`] once
+    /// [`Self::run()`] has returned
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn into_state(self) -> State {
+        self.state
+    }
+
+    /// Run the sync protocol until the incoming stream ends
+    ///
+    /// `on_event` is called with every [`SyncEvent`] the driver produces. The returned future
+    /// completes with `Ok(())` when `incoming` is exhausted, i.e. when the peer has gone away.
+    /// Dropping the future stops syncing; the [`State`] is still valid and can be reused with
+    /// [`Self::run()`] on a new connection, although you should call this again rather than
+    /// reusing the in flight state if the connection was lost.
+    pub async fn run<F>(&mut self, mut on_event: F) -> Result<(), DriverError<K::Error>>
+    where
+        F: FnMut(SyncEvent),
+    {
+        let mut last_synced: Option<Vec<ChangeHash>> = None;
+        loop {
+            // Read the generation before generating messages so that any change made after this
+            // point wakes us up again
+            let seen = self.doc.generation();
+
+            while let Some(message) = self
+                .doc
+                .read(|doc| doc.generate_sync_message(&mut self.state))
+            {
+                self.outgoing
+                    .send(message.encode())
+                    .await
+                    .map_err(DriverError::Send)?;
+            }
+
+            let heads = self.doc.read(|doc| doc.get_heads());
+            if self.state.their_heads.as_ref() == Some(&heads) {
+                if last_synced.as_ref() != Some(&heads) {
+                    last_synced = Some(heads.clone());
+                    on_event(SyncEvent::Synced(heads));
+                }
+            } else {
+                last_synced = None;
+            }
+
+            let changed = self.doc.changed(seen);
+            let next = self.incoming.next();
+            match future::select(changed, next).await {
+                Either::Left(((), _)) => continue,
+                Either::Right((None, _)) => return Ok(()),
+                Either::Right((Some(bytes), _)) => {
+                    let message = Message::decode(&bytes)?;
+                    let log_patches = self.log_patches;
+                    let state = &mut self.state;
+                    let patches = self.doc.with_doc(|doc| {
+                        let mut patch_log = PatchLog::new(log_patches);
+                        doc.receive_sync_message_log_patches(state, message, &mut patch_log)?;
+                        Ok::<_, AutomergeError>(doc.make_patches(&mut patch_log))
+                    })?;
+                    if !patches.is_empty() {
+                        on_event(SyncEvent::Patches(patches));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S, K> fmt::Debug for SyncDriver<S, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncDriver")
+            .field("doc", &self.doc)
+            .field("state", &self.state)
+            .field("log_patches", &self.log_patches)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transactable;
+    use crate::{ReadDoc, ROOT};
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+
+    fn put(doc: &SharedDoc, key: &str, value: i64) {
+        doc.with_doc(|doc| {
+            let mut tx = doc.transaction();
+            tx.put(ROOT, key, value).unwrap();
+            tx.commit();
+        });
+    }
+
+    /// Run two drivers connected by in-memory channels alongside `body`, returning the result of
+    /// `body`. Events from each driver are forwarded to the returned receivers.
+    fn with_connected<B, Fut, R>(doc1: &SharedDoc, doc2: &SharedDoc, body: B) -> R
+    where
+        B: FnOnce(mpsc::UnboundedReceiver<SyncEvent>, mpsc::UnboundedReceiver<SyncEvent>) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let (tx1, rx1) = mpsc::unbounded::<Vec<u8>>();
+        let (tx2, rx2) = mpsc::unbounded::<Vec<u8>>();
+        let mut driver1 = SyncDriver::new(doc1.clone(), rx2, tx1);
+        let mut driver2 = SyncDriver::new(doc2.clone(), rx1, tx2);
+        let (events1_tx, events1) = mpsc::unbounded();
+        let (events2_tx, events2) = mpsc::unbounded();
+
+        block_on(async {
+            let run1 = driver1.run(|e| events1_tx.unbounded_send(e).unwrap());
+            let run2 = driver2.run(|e| events2_tx.unbounded_send(e).unwrap());
+            let body = body(events1, events2);
+            futures::pin_mut!(run1, run2, body);
+            match future::select(future::select(run1, run2), body).await {
+                Either::Left((Either::Left((r, _)) | Either::Right((r, _)), _)) => {
+                    panic!("driver finished before the test: {:?}", r)
+                }
+                Either::Right((r, _)) => r,
+            }
+        })
+    }
+
+    async fn wait_for_synced(events: &mut mpsc::UnboundedReceiver<SyncEvent>) -> Vec<ChangeHash> {
+        while let Some(event) = events.next().await {
+            if let SyncEvent::Synced(heads) = event {
+                return heads;
+            }
+        }
+        panic!("event stream ended")
+    }
+
+    #[test]
+    fn syncs_existing_changes() {
+        let doc1 = SharedDoc::new(Automerge::new());
+        let doc2 = SharedDoc::new(Automerge::new());
+        put(&doc1, "a", 1);
+        put(&doc2, "b", 2);
+
+        with_connected(&doc1, &doc2, |mut events1, mut events2| async move {
+            let heads1 = wait_for_synced(&mut events1).await;
+            let heads2 = wait_for_synced(&mut events2).await;
+            assert_eq!(heads1, heads2);
+        });
+
+        let heads = doc1.read(|d| d.get_heads());
+        assert_eq!(heads, doc2.read(|d| d.get_heads()));
+        assert!(doc2.read(|d| d.get(ROOT, "a").unwrap().is_some()));
+        assert!(doc1.read(|d| d.get(ROOT, "b").unwrap().is_some()));
+    }
+
+    #[test]
+    fn resyncs_after_local_changes() {
+        let doc1 = SharedDoc::new(Automerge::new());
+        let doc2 = SharedDoc::new(Automerge::new());
+
+        let doc1_handle = doc1.clone();
+        with_connected(&doc1, &doc2, |mut events1, mut events2| async move {
+            wait_for_synced(&mut events1).await;
+            wait_for_synced(&mut events2).await;
+
+            // Nothing is waiting on the driver except the local change
+            put(&doc1_handle, "key", 5);
+
+            let mut saw_patches = false;
+            while let Some(event) = events2.next().await {
+                match event {
+                    SyncEvent::Patches(patches) => {
+                        assert!(!patches.is_empty());
+                        saw_patches = true;
+                    }
+                    SyncEvent::Synced(heads) => {
+                        if heads == doc1_handle.read(|d| d.get_heads()) {
+                            break;
+                        }
+                    }
+                }
+            }
+            assert!(saw_patches);
+        });
+
+        assert_eq!(
+            doc2.read(|d| d.get(ROOT, "key").unwrap().map(|(v, _)| v.to_i64())),
+            Some(Some(5))
+        );
+    }
+
+    #[test]
+    fn returns_when_the_peer_disconnects() {
+        let doc = SharedDoc::new(Automerge::new());
+        let (tx, rx) = mpsc::unbounded::<Vec<u8>>();
+        let (out_tx, mut out_rx) = mpsc::unbounded::<Vec<u8>>();
+        drop(tx);
+        let mut driver = SyncDriver::new(doc, rx, out_tx);
+        block_on(driver.run(|_| {})).unwrap();
+        // The initial message is still sent
+        assert!(out_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn invalid_message_is_an_error() {
+        let doc = SharedDoc::new(Automerge::new());
+        let (tx, rx) = mpsc::unbounded::<Vec<u8>>();
+        let (out_tx, _out_rx) = mpsc::unbounded::<Vec<u8>>();
+        tx.unbounded_send(vec![1, 2, 3]).unwrap();
+        let mut driver = SyncDriver::new(doc, rx, out_tx);
+        assert!(matches!(
+            block_on(driver.run(|_| {})),
+            Err(DriverError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+
+use super::{encode_hashes, Message, ReadMessageError, MESSAGE_TYPE_SYNC, MESSAGE_TYPE_SYNC_V2};
+use crate::{storage::parse, ChangeHash, Cursor};
+
+// first byte of an ephemeral message, distinct from the sync message types so both kinds of
+// message can share a connection
+const MESSAGE_TYPE_EPHEMERAL: u8 = 0x44;
+
+// second byte of an ephemeral message. Entries carry their length so a reader can skip values of
+// types it doesn't know, the version only changes if the layout of the message itself does
+const EPHEMERAL_VERSION: u8 = 1;
+
+const BYTES_TAG: u8 = 1;
+const CURSOR_TAG: u8 = 2;
+const SELECTION_TAG: u8 = 3;
+
+/// A message carrying ephemeral data such as cursors, selections or "is typing" indicators
+///
+/// Ephemeral messages are not part of the document and are never persisted, they are sent
+/// alongside sync [`Message`]s to tell other peers what the sender is doing right now. Each
+/// message is a full snapshot of the sender's state under the keys it uses, so receivers can just
+/// replace whatever they last received from that peer.
+///
+/// Peers which understand ephemeral messages advertise
+/// [`MessageFlags::SUPPORTS_EPHEMERAL`](super::MessageFlags::SUPPORTS_EPHEMERAL) in their sync
+/// messages, so check [`State::supports_ephemeral_messages()`](super::State::supports_ephemeral_messages)
+/// before sending them. Use [`Frame::decode()`] to tell the two kinds of message apart when they
+/// arrive on the same connection.
+///
+/// ## Example
+///
+/// ```
+/// use automerge::{transaction::Transactable, sync::{EphemeralMessage, EphemeralValue, Frame}, AutoCommit, ObjType, ReadDoc};
+/// # fn main() -> Result<(), automerge::AutomergeError> {
+/// let mut doc = AutoCommit::new();
+/// let text = doc.put_object(automerge::ROOT, "text", ObjType::Text)?;
+/// doc.splice_text(&text, 0, 0, "hello")?;
+///
+/// let mut presence = EphemeralMessage::new(doc.get_heads());
+/// presence.entries.insert("cursor".to_string(), EphemeralValue::Cursor(doc.get_cursor(&text, 2, None)?));
+/// presence.entries.insert("typing".to_string(), EphemeralValue::Bytes(vec![1]));
+///
+/// let Frame::Ephemeral(received) = Frame::decode(&presence.encode()).unwrap() else {
+///     panic!("expected an ephemeral message");
+/// };
+/// let Some(EphemeralValue::Cursor(cursor)) = received.entries.get("cursor") else {
+///     panic!("expected a cursor");
+/// };
+/// assert_eq!(doc.get_cursor_position(&text, cursor, None)?, 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EphemeralMessage {
+    /// The heads of the sender's document when the message was created. Cursors in `entries`
+    /// refer to ops which are ancestors of these heads, a receiver which doesn't have them yet
+    /// may not be able to resolve the cursors until it has synced.
+    pub heads: Vec<ChangeHash>,
+    /// The ephemeral state of the sender
+    pub entries: BTreeMap<String, EphemeralValue>,
+}
+
+/// A value in an [`EphemeralMessage`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum EphemeralValue {
+    /// Application defined data
+    Bytes(Vec<u8>),
+    /// A position in a sequence, encoded with [`Cursor::to_bytes()`]
+    Cursor(Cursor),
+    /// A selected range of a sequence, `head` is the end which moves when the selection is
+    /// extended and may be before `anchor`
+    Selection { anchor: Cursor, head: Cursor },
+}
+
+impl EphemeralMessage {
+    /// Create an empty message for a document with `heads`
+    pub fn new(heads: Vec<ChangeHash>) -> Self {
+        Self {
+            heads,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, ReadMessageError> {
+        let input = parse::Input::new(input);
+        match Self::parse(input) {
+            Ok((_, msg)) => Ok(msg),
+            Err(parse::ParseError::Error(e)) => Err(e),
+            Err(parse::ParseError::Incomplete(_)) => Err(ReadMessageError::NotEnoughInput),
+        }
+    }
+
+    pub(crate) fn parse(input: parse::Input<'_>) -> parse::ParseResult<'_, Self, ReadMessageError> {
+        let (i, first_byte) = parse::take1(input)?;
+        if first_byte != MESSAGE_TYPE_EPHEMERAL {
+            return Err(parse::ParseError::Error(ReadMessageError::WrongType {
+                expected_one_of: vec![MESSAGE_TYPE_EPHEMERAL],
+                found: first_byte,
+            }));
+        }
+        let (i, version) = parse::take1(i)?;
+        if version != EPHEMERAL_VERSION {
+            return Err(parse::ParseError::Error(ReadMessageError::Parse(format!(
+                "unsupported ephemeral message version {}",
+                version
+            ))));
+        }
+        let (i, mut heads) = parse::length_prefixed(parse::change_hash)(i)?;
+        heads.sort();
+        let (i, entries) = parse::length_prefixed(parse_entry)(i)?;
+        Ok((
+            i,
+            Self {
+                heads,
+                entries: entries.into_iter().flatten().collect(),
+            },
+        ))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![MESSAGE_TYPE_EPHEMERAL, EPHEMERAL_VERSION];
+        let mut heads = self.heads.clone();
+        heads.sort();
+        encode_hashes(&mut buf, &heads);
+        leb128::write::unsigned(&mut buf, self.entries.len() as u64).unwrap();
+        for (key, value) in &self.entries {
+            encode_bytes(&mut buf, key.as_bytes());
+            let mut payload = Vec::new();
+            let tag = match value {
+                EphemeralValue::Bytes(bytes) => {
+                    payload.extend_from_slice(bytes);
+                    BYTES_TAG
+                }
+                EphemeralValue::Cursor(cursor) => {
+                    payload = cursor.to_bytes();
+                    CURSOR_TAG
+                }
+                EphemeralValue::Selection { anchor, head } => {
+                    encode_bytes(&mut payload, &anchor.to_bytes());
+                    encode_bytes(&mut payload, &head.to_bytes());
+                    SELECTION_TAG
+                }
+            };
+            buf.push(tag);
+            encode_bytes(&mut buf, &payload);
+        }
+        buf
+    }
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    leb128::write::unsigned(buf, bytes.len() as u64).unwrap();
+    buf.extend_from_slice(bytes);
+}
+
+/// Parse an entry, `None` if its value is of a type this version doesn't know
+fn parse_entry(
+    input: parse::Input<'_>,
+) -> parse::ParseResult<'_, Option<(String, EphemeralValue)>, ReadMessageError> {
+    let (i, key) = parse::length_prefixed_bytes(input)?;
+    let key = String::from_utf8(key.to_vec())
+        .map_err(|_| ReadMessageError::Parse("invalid UTF-8 in ephemeral key".to_string()))?;
+    let (i, tag) = parse::take1(i)?;
+    let (i, payload) = parse::length_prefixed_bytes(i)?;
+    let value = match tag {
+        BYTES_TAG => EphemeralValue::Bytes(payload.to_vec()),
+        CURSOR_TAG => EphemeralValue::Cursor(decode_cursor(payload)?),
+        SELECTION_TAG => {
+            let payload = parse::Input::new(payload);
+            let (payload, anchor) = parse::length_prefixed_bytes(payload)?;
+            let (_, head) = parse::length_prefixed_bytes(payload)?;
+            EphemeralValue::Selection {
+                anchor: decode_cursor(anchor)?,
+                head: decode_cursor(head)?,
+            }
+        }
+        _ => return Ok((i, None)),
+    };
+    Ok((i, Some((key, value))))
+}
+
+fn decode_cursor(bytes: &[u8]) -> Result<Cursor, ReadMessageError> {
+    Cursor::try_from(bytes).map_err(|e| ReadMessageError::Parse(format!("invalid cursor: {}", e)))
+}
+
+/// A message received on a connection which carries both sync and ephemeral messages
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Sync(Message),
+    Ephemeral(EphemeralMessage),
+}
+
+impl Frame {
+    /// Decode a message, using its first byte to determine which kind of message it is
+    pub fn decode(input: &[u8]) -> Result<Self, ReadMessageError> {
+        match input.first() {
+            Some(&MESSAGE_TYPE_EPHEMERAL) => EphemeralMessage::decode(input).map(Self::Ephemeral),
+            Some(&MESSAGE_TYPE_SYNC) | Some(&MESSAGE_TYPE_SYNC_V2) => {
+                Message::decode(input).map(Self::Sync)
+            }
+            Some(&found) => Err(ReadMessageError::WrongType {
+                expected_one_of: vec![
+                    MESSAGE_TYPE_SYNC,
+                    MESSAGE_TYPE_SYNC_V2,
+                    MESSAGE_TYPE_EPHEMERAL,
+                ],
+                found,
+            }),
+            None => Err(ReadMessageError::NotEnoughInput),
+        }
+    }
+
+    pub fn encode(self) -> Vec<u8> {
+        match self {
+            Self::Sync(message) => message.encode(),
+            Self::Ephemeral(message) => message.encode(),
+        }
+    }
+}
+
+impl From<Message> for Frame {
+    fn from(message: Message) -> Self {
+        Self::Sync(message)
+    }
+}
+
+impl From<EphemeralMessage> for Frame {
+    fn from(message: EphemeralMessage) -> Self {
+        Self::Ephemeral(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sync::SyncDoc, transaction::Transactable, AutoCommit, ObjType, ReadDoc, ROOT};
+
+    #[test]
+    fn encode_decode_round_trips() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello world").unwrap();
+
+        let mut message = EphemeralMessage::new(doc.get_heads());
+        message.entries.insert(
+            "selection".to_string(),
+            EphemeralValue::Selection {
+                anchor: doc.get_cursor(&text, 6, None).unwrap(),
+                head: Cursor::End,
+            },
+        );
+        message
+            .entries
+            .insert("name".to_string(), EphemeralValue::Bytes(b"alex".to_vec()));
+
+        let decoded = EphemeralMessage::decode(&message.encode()).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn unknown_entries_are_skipped() {
+        let mut message = EphemeralMessage::new(Vec::new());
+        message
+            .entries
+            .insert("a".to_string(), EphemeralValue::Bytes(vec![1]));
+        message
+            .entries
+            .insert("c".to_string(), EphemeralValue::Bytes(vec![3]));
+
+        // splice an entry with an unknown value type between the two entries
+        let mut encoded = vec![MESSAGE_TYPE_EPHEMERAL, EPHEMERAL_VERSION];
+        encode_hashes(&mut encoded, &[]);
+        encoded.push(3);
+        let known = message.encode();
+        let entries = &known[4..];
+        let (first, second) = entries.split_at(entries.len() / 2);
+        encoded.extend_from_slice(first);
+        encode_bytes(&mut encoded, b"b");
+        encoded.push(0x7f);
+        encode_bytes(&mut encoded, &[1, 2, 3]);
+        encoded.extend_from_slice(second);
+
+        assert_eq!(EphemeralMessage::decode(&encoded).unwrap(), message);
+
+        let mut future = message.encode();
+        future[1] = EPHEMERAL_VERSION + 1;
+        assert!(EphemeralMessage::decode(&future).is_err());
+    }
+
+    #[test]
+    fn frames_distinguish_sync_and_ephemeral_messages() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "key", "value").unwrap();
+        let mut state = crate::sync::State::new();
+        let sync_message = doc.sync().generate_sync_message(&mut state).unwrap();
+        let ephemeral = EphemeralMessage::new(doc.get_heads());
+
+        assert_eq!(
+            Frame::decode(&sync_message.clone().encode()).unwrap(),
+            Frame::Sync(sync_message)
+        );
+        assert_eq!(
+            Frame::decode(&ephemeral.encode()).unwrap(),
+            Frame::Ephemeral(ephemeral)
+        );
+        assert!(matches!(
+            Frame::decode(&[0x01]),
+            Err(ReadMessageError::WrongType { found: 0x01, .. })
+        ));
+    }
+
+    #[test]
+    fn peers_advertise_support_for_ephemeral_messages() {
+        let mut doc1 = AutoCommit::new();
+        let mut doc2 = AutoCommit::new();
+        let mut state1 = crate::sync::State::new();
+        let mut state2 = crate::sync::State::new();
+        assert!(!state2.supports_ephemeral_messages());
+
+        let message = doc1.sync().generate_sync_message(&mut state1).unwrap();
+        doc2.sync()
+            .receive_sync_message(&mut state2, message)
+            .unwrap();
+        assert!(state2.supports_ephemeral_messages());
+    }
+}
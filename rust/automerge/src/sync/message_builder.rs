@@ -1,6 +1,7 @@
+use crate::columnar::encoding::leb128::ulebsize;
 use crate::{Change, ChangeHash};
 
-use super::{Have, Message, MessageFlags, MessageVersion, State};
+use super::{ChunkList, Have, Message, MessageFlags, MessageVersion, State};
 
 use std::borrow::Cow;
 
@@ -10,6 +11,9 @@ pub(super) struct MessageBuilder<'a> {
     have: Vec<Have>,
     changes: Vec<Vec<u8>>,
     hashes: Cow<'a, [ChangeHash]>,
+    /// The length of each change in `hashes`, or `None` if `changes` is an entire document, which
+    /// can't be split up
+    change_lens: Option<Vec<usize>>,
     flags: Option<MessageFlags>,
     version: MessageVersion,
 }
@@ -25,6 +29,7 @@ impl<'a> MessageBuilder<'a> {
 
     fn new_v1(changes: Vec<Change>) -> MessageBuilder<'static> {
         let hashes = Cow::Owned(changes.iter().map(|c| c.hash()).collect());
+        let change_lens = Some(changes.iter().map(|c| c.raw_bytes().len()).collect());
         let changes = changes
             .into_iter()
             .map(|c| c.raw_bytes().to_vec())
@@ -35,6 +40,7 @@ impl<'a> MessageBuilder<'a> {
             have: Vec::new(),
             changes,
             hashes,
+            change_lens,
             flags: None,
             version: MessageVersion::V1,
         }
@@ -46,6 +52,7 @@ impl<'a> MessageBuilder<'a> {
             need: Vec::new(),
             hashes: Cow::Owned(vec![]),
             changes: Vec::new(),
+            change_lens: Some(Vec::new()),
             have: Vec::new(),
             flags: None,
             version: MessageVersion::V2,
@@ -54,12 +61,14 @@ impl<'a> MessageBuilder<'a> {
 
     fn new_v2_from_changes(changes: Vec<Change>) -> MessageBuilder<'static> {
         let hashes: Cow<'static, _> = Cow::Owned(changes.iter().map(|c| c.hash()).collect());
-        let size = changes.iter().map(|c| c.raw_bytes().len()).sum();
-        let mut encoded = Vec::with_capacity(size);
+        let change_lens: Vec<_> = changes.iter().map(|c| c.raw_bytes().len()).collect();
+        let mut encoded = Vec::with_capacity(change_lens.iter().sum());
         for c in changes {
             encoded.extend_from_slice(c.raw_bytes())
         }
-        Self::new_v2(encoded, hashes)
+        let mut builder = Self::new_v2(encoded, hashes);
+        builder.change_lens = Some(change_lens);
+        builder
     }
 
     pub(super) fn new_v2<'b>(data: Vec<u8>, hashes: Cow<'b, [ChangeHash]>) -> MessageBuilder<'b> {
@@ -71,16 +80,125 @@ impl<'a> MessageBuilder<'a> {
             need: Vec::new(),
             hashes,
             changes: vec![data],
+            change_lens: None,
             have: Vec::new(),
             flags: None,
             version: MessageVersion::V2,
         }
     }
 
+    /// Replace the changes in this message with `changes`, keeping the heads, have, need and
+    /// flags
+    pub(super) fn with_changes(self, changes: Vec<Change>, sync_state: &State) -> Self {
+        MessageBuilder {
+            heads: self.heads,
+            need: self.need,
+            have: self.have,
+            flags: self.flags,
+            ..MessageBuilder::new(changes, sync_state)
+        }
+    }
+
     pub(super) fn is_empty(&self) -> bool {
         self.hashes.is_empty()
     }
 
+    /// Whether the changes in this message can be split across several messages
+    pub(super) fn is_splittable(&self) -> bool {
+        self.change_lens.is_some()
+    }
+
+    /// The length of the encoded message
+    pub(super) fn encoded_len(&self) -> usize {
+        let lens: Vec<_> = match &self.change_lens {
+            Some(lens) => lens.clone(),
+            None => self.changes.iter().map(Vec::len).collect(),
+        };
+        self.header_len() + self.changes_len(&lens)
+    }
+
+    /// Drop changes from the end of the message until the encoded message is at most `max_len`
+    /// bytes long. The first change is always kept, even if it alone is larger than `max_len`,
+    /// otherwise the sync could never make progress.
+    ///
+    /// Returns `true` if any changes were dropped.
+    ///
+    /// # Panics
+    ///
+    /// If the message is not [splittable](Self::is_splittable())
+    pub(super) fn truncate(&mut self, max_len: usize) -> bool {
+        let lens = self
+            .change_lens
+            .as_ref()
+            .expect("truncating an unsplittable message");
+        let budget = max_len.saturating_sub(self.header_len());
+        let mut keep = 1;
+        let mut sum = 0;
+        for (i, len) in lens.iter().enumerate() {
+            sum += match self.version {
+                MessageVersion::V1 => ulebsize(*len as u64) as usize + len,
+                MessageVersion::V2 => *len,
+            };
+            if i > 0 && self.changes_len_with_sum(i + 1, sum) > budget {
+                break;
+            }
+            keep = i + 1;
+        }
+        if keep >= lens.len() {
+            return false;
+        }
+        match self.version {
+            MessageVersion::V1 => self.changes.truncate(keep),
+            MessageVersion::V2 => {
+                let total = lens[..keep].iter().sum();
+                self.changes[0].truncate(total);
+            }
+        }
+        self.hashes.to_mut().truncate(keep);
+        if let Some(lens) = self.change_lens.as_mut() {
+            lens.truncate(keep);
+        }
+        true
+    }
+
+    /// The length of the encoded message without the changes section
+    fn header_len(&self) -> usize {
+        Message {
+            heads: self.heads.clone(),
+            need: self.need.clone(),
+            have: self.have.clone(),
+            changes: ChunkList::empty(),
+            flags: self.flags,
+            version: self.version.clone(),
+        }
+        .encode()
+        .len()
+            // An empty change list is encoded as a single zero byte
+            - 1
+    }
+
+    /// The length of the encoded changes section if it contained changes of the given lengths
+    fn changes_len(&self, lens: &[usize]) -> usize {
+        let sum = match self.version {
+            MessageVersion::V1 => lens.iter().map(|l| ulebsize(*l as u64) as usize + l).sum(),
+            MessageVersion::V2 => lens.iter().sum(),
+        };
+        self.changes_len_with_sum(lens.len(), sum)
+    }
+
+    /// The length of the encoded changes section for `count` changes, where `sum` is the sum of
+    /// the length prefixed changes for V1 messages and the sum of the raw changes for V2 messages
+    fn changes_len_with_sum(&self, count: usize, sum: usize) -> usize {
+        let leb = |n: usize| ulebsize(n as u64) as usize;
+        if count == 0 {
+            return leb(0);
+        }
+        match self.version {
+            MessageVersion::V1 => leb(count) + sum,
+            MessageVersion::V2 => leb(1) + leb(sum) + sum,
+        }
+    }
+
     pub(super) fn hashes(&self) -> impl Iterator<Item = &ChangeHash> {
         self.hashes.iter()
     }
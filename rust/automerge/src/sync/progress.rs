@@ -0,0 +1,116 @@
+#[cfg(doc)]
+use super::SyncDoc;
+use super::{State, SyncStore};
+
+// Rough sizes of an encoded change, used to turn counts of changes and ops into byte estimates.
+// A change header carries its dependencies, actor, sequence number and column metadata, and each
+// op costs a handful of bytes once the columns are run length and delta encoded.
+const ESTIMATED_BYTES_PER_CHANGE: usize = 100;
+const ESTIMATED_BYTES_PER_OP: usize = 8;
+
+/// An estimate of how far through synchronisation with a peer we are
+///
+/// This is returned by [`SyncDoc::sync_progress()`]. It is derived from the heads, need and bloom
+/// filter the peer last sent us, so it is only as fresh as the last message received. Both directions are estimates: the bloom filter has false positives and we can't know
+/// exactly how large changes we haven't seen yet are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Progress {
+    /// What the peer still needs from us
+    pub to_send: Remaining,
+    /// What we still need from the peer
+    pub to_receive: Remaining,
+}
+
+impl Progress {
+    /// Whether both peers appear to have everything the other has
+    pub fn is_complete(&self) -> bool {
+        self.to_send.changes == 0 && self.to_receive.changes == 0
+    }
+}
+
+/// The amount of data remaining to be synced in one direction, see [`Progress`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Remaining {
+    /// The number of changes
+    pub changes: usize,
+    /// The approximate size of the changes in bytes
+    pub bytes: usize,
+}
+
+pub(super) fn estimate<D: SyncStore + ?Sized>(doc: &D, sync_state: &State) -> Progress {
+    Progress {
+        to_send: estimate_to_send(doc, sync_state),
+        to_receive: estimate_to_receive(doc, sync_state),
+    }
+}
+
+fn estimate_to_send<D: SyncStore + ?Sized>(doc: &D, sync_state: &State) -> Remaining {
+    if sync_state.is_peer_read_only() {
+        return Remaining::default();
+    }
+    let hashes = if sync_state.their_heads.as_deref() == Some(&[]) {
+        // The peer has nothing, so it needs everything
+        doc.change_graph().get_hashes(&[]).into_owned()
+    } else if let Some((have, need)) = sync_state.their() {
+        match doc.get_hashes_to_send(have, need) {
+            Ok(hashes) => hashes,
+            Err(_) => return Remaining::default(),
+        }
+    } else {
+        return Remaining::default();
+    };
+    let ops: u64 = hashes
+        .iter()
+        .filter_map(|hash| doc.change_graph().num_ops(hash))
+        .sum();
+    Remaining {
+        changes: hashes.len(),
+        bytes: hashes.len() * ESTIMATED_BYTES_PER_CHANGE + ops as usize * ESTIMATED_BYTES_PER_OP,
+    }
+}
+
+fn estimate_to_receive<D: SyncStore + ?Sized>(doc: &D, sync_state: &State) -> Remaining {
+    if sync_state.read_only {
+        return Remaining::default();
+    }
+    let Some(their_heads) = sync_state.their_heads.as_ref() else {
+        return Remaining::default();
+    };
+    let unknown_heads = their_heads
+        .iter()
+        .filter(|head| !doc.has_change(head))
+        .count();
+    if unknown_heads == 0 {
+        return Remaining::default();
+    }
+
+    // Each of their heads we don't have is at least one change we need. If they sent a bloom
+    // filter we can do better: it summarises every change they have since `last_sync`, so
+    // anything in it which we don't have since the same point is something we need.
+    let mut changes = unknown_heads;
+    if let Some(have) = sync_state.their_have.as_ref() {
+        for super::Have { last_sync, bloom } in have {
+            if !last_sync.iter().all(|hash| doc.has_change(hash)) {
+                continue;
+            }
+            let ours_since = doc.change_graph().get_hashes(last_sync);
+            let shared = ours_since
+                .iter()
+                .filter(|hash| bloom.contains_hash(hash))
+                .count();
+            let theirs = bloom.num_entries() as usize;
+            changes = changes.max(theirs.saturating_sub(shared));
+        }
+    }
+
+    let num_changes = doc.change_graph().len();
+    let ops_per_change = if num_changes == 0 {
+        1
+    } else {
+        doc.num_ops().div_ceil(num_changes)
+    };
+    Remaining {
+        changes,
+        bytes: changes * (ESTIMATED_BYTES_PER_CHANGE + ops_per_change * ESTIMATED_BYTES_PER_OP),
+    }
+}
@@ -4,7 +4,7 @@ use std::collections::BTreeSet;
 use super::SyncDoc;
 use super::{encode_hashes, BloomFilter, Capability};
 use crate::storage::parse;
-use crate::ChangeHash;
+use crate::{ChangeHash, LoadLimits};
 
 const SYNC_STATE_TYPE: u8 = 0x43; // first byte of an encoded sync state, for identification
 
@@ -75,6 +75,23 @@ pub struct State {
     /// clear its `sent_hashes`. Set by [`Self::set_read_only()`] when switching
     /// from read-only to read-write.
     pub needs_reset: bool,
+
+    /// The maximum size in bytes of the messages [`SyncDoc::generate_sync_message()`] produces,
+    /// if any.
+    ///
+    /// When the changes the peer needs don't fit in one message they are sent in causal order
+    /// across several messages, each of which can be applied on its own. A single change which is
+    /// larger than this limit is still sent, on its own. This is connection configuration and is
+    /// not persisted by [`Self::encode()`].
+    pub max_message_size: Option<usize>,
+
+    /// Limits on the size of the document which changes received from the peer can produce.
+    ///
+    /// A message which would take the document over these limits is rejected with
+    /// [`AutomergeError::LimitExceeded`](crate::AutomergeError::LimitExceeded) before any of its
+    /// changes are applied. This is connection configuration and is not persisted by
+    /// [`Self::encode()`].
+    pub load_limits: LoadLimits,
 }
 
 /// A summary of the changes that the sender of the message already has.
@@ -105,6 +122,12 @@ impl State {
         }
     }
 
+    /// Limit the size of generated messages, see [`Self::max_message_size`]
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = vec![SYNC_STATE_TYPE];
         encode_hashes(&mut buf, &self.shared_heads);
@@ -145,6 +168,8 @@ impl State {
                 read_only: false,
                 peer_read_only: false,
                 needs_reset: false,
+                max_message_size: None,
+                load_limits: LoadLimits::unlimited(),
             },
         ))
     }
@@ -168,6 +193,8 @@ impl State {
             let their_capabilities = self.their_capabilities.take();
             *self = Self {
                 their_capabilities,
+                max_message_size: self.max_message_size,
+                load_limits: self.load_limits,
                 read_only: false,
                 needs_reset: true,
                 ..Default::default()
@@ -188,6 +215,15 @@ impl State {
         self.peer_read_only
     }
 
+    /// Returns true if the remote peer has advertised that it understands
+    /// [`EphemeralMessage`](super::EphemeralMessage)s.
+    pub fn supports_ephemeral_messages(&self) -> bool {
+        self.their_capabilities
+            .as_ref()
+            .map(|caps| caps.contains(&Capability::Ephemeral))
+            .unwrap_or(false)
+    }
+
     pub(crate) fn peer_supports_sync_reset(&self) -> bool {
         self.their_capabilities
             .as_ref()
@@ -8,7 +8,7 @@ use std::collections::{HashMap, HashSet};
 use crate::{
     storage::parse::Input,
     storage::{parse, Change as StoredChange, ReadChangeOpError},
-    sync::SyncDoc,
+    sync::{SyncDoc, SyncStore},
     transaction::Transactable,
     AutoCommit, Automerge, AutomergeError, Change, ChangeHash, ReadDoc, ROOT,
 };
@@ -228,7 +228,7 @@ impl Automerge {
         }
 
         // trim down the sent hashes to those that we know they haven't seen
-        self.filter_changes(&message_heads, &mut sync_state.sent_hashes)?;
+        self.filter_changes(&message_heads, &mut sync_state.sent_hashes);
 
         if changes_is_empty && message_heads == before_heads {
             sync_state.last_sent_heads.clone_from(&message_heads);
@@ -182,7 +182,7 @@ fn apply_marks_diff(
                 let text_width = doc.text_encoding().width(text);
 
                 if let Some(mark_set) = marks {
-                    for (mark_name, mark_value) in mark_set.iter() {
+                    for (mark_name, mark_value) in mark_set.iter_stored() {
                         new_marks.push((
                             mark_name.to_string(),
                             mark_value.clone(),
@@ -203,8 +203,9 @@ fn apply_marks_diff(
     // Determine which marks to remove (those not in the new set)
     let mut marks_to_remove = Vec::new();
     for mark in current_marks {
+        let stored_name = mark.stored_name();
         let should_keep = new_marks.iter().any(|(name, value, start, end)| {
-            name == mark.name && value == &mark.value && *start == mark.start && *end == mark.end
+            name == stored_name && value == &mark.value && *start == mark.start && *end == mark.end
         });
 
         if !should_keep {
@@ -221,20 +222,29 @@ fn apply_marks_diff(
             .unwrap_or(config.default_expand);
 
         tx.unmark(
-            doc, patch_log, text_obj, &mark.name, mark.start, mark.end, expand,
+            doc,
+            patch_log,
+            text_obj,
+            &mark.stored_name(),
+            mark.start,
+            mark.end,
+            expand,
         )?;
     }
 
     // Add new marks that don't already exist
     for (mark_name, mark_value, start, end) in new_marks {
         let already_exists = doc.marks_for(text_obj, None)?.iter().any(|m| {
-            m.name == mark_name && m.value == mark_value && m.start == start && m.end == end
+            m.stored_name() == mark_name
+                && m.value == mark_value
+                && m.start == start
+                && m.end == end
         });
 
         if !already_exists {
             let expand = config
                 .per_mark_expands
-                .get(&mark_name)
+                .get(crate::marks::mark_kind(&mark_name))
                 .copied()
                 .unwrap_or(config.default_expand);
 
@@ -162,6 +162,22 @@ macro_rules! impl_read_doc_for_tx {
                     .spans_for(obj.as_ref(), self.get_scope(Some(heads)))
             }
 
+            fn line_index<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+            ) -> Result<crate::line_index::LineIndex<'_>, crate::AutomergeError> {
+                self.doc.line_index_for(obj.as_ref(), self.get_scope(None))
+            }
+
+            fn line_index_at<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+                heads: &[crate::ChangeHash],
+            ) -> Result<crate::line_index::LineIndex<'_>, crate::AutomergeError> {
+                self.doc
+                    .line_index_for(obj.as_ref(), self.get_scope(Some(heads)))
+            }
+
             fn get_cursor<O: AsRef<crate::exid::ExId>, I: Into<crate::cursor::CursorPosition>>(
                 &self,
                 obj: O,
@@ -204,6 +220,53 @@ macro_rules! impl_read_doc_for_tx {
                     .get_cursor_position_for(obj.as_ref(), address, self.get_scope(at))
             }
 
+            fn get_cursor_positions<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+                cursors: &[crate::Cursor],
+                at: Option<&[crate::ChangeHash]>,
+            ) -> Result<Vec<usize>, crate::AutomergeError> {
+                self.doc
+                    .get_cursor_positions_for(obj.as_ref(), cursors, self.get_scope(at))
+            }
+
+            fn get_cursor_range_position<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+                range: &crate::CursorRange,
+                at: Option<&[crate::ChangeHash]>,
+            ) -> Result<std::ops::Range<usize>, crate::AutomergeError> {
+                self.doc
+                    .get_cursor_range_position_for(obj.as_ref(), range, self.get_scope(at))
+            }
+
+            fn get_cursor_range_positions<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+                ranges: &[crate::CursorRange],
+                at: Option<&[crate::ChangeHash]>,
+            ) -> Result<Vec<std::ops::Range<usize>>, crate::AutomergeError> {
+                self.doc
+                    .get_cursor_range_positions_for(obj.as_ref(), ranges, self.get_scope(at))
+            }
+
+            fn transform_indices<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+                indices: &[usize],
+                from_heads: &[crate::ChangeHash],
+                to_heads: &[crate::ChangeHash],
+                bias: crate::cursor::MoveCursor,
+            ) -> Result<Vec<usize>, crate::AutomergeError> {
+                self.doc.transform_indices_for(
+                    obj.as_ref(),
+                    indices,
+                    self.get_scope(Some(from_heads)),
+                    self.get_scope(Some(to_heads)),
+                    bias,
+                )
+            }
+
             fn marks<O: AsRef<crate::exid::ExId>>(
                 &self,
                 obj: O,
@@ -238,6 +301,24 @@ macro_rules! impl_read_doc_for_tx {
                     .get_marks_for(obj.as_ref(), index, self.get_scope(heads))
             }
 
+            fn mark_instances<O: AsRef<crate::exid::ExId>>(
+                &self,
+                obj: O,
+                kind: &str,
+            ) -> Result<Vec<crate::marks::Mark>, crate::AutomergeError> {
+                self.doc
+                    .mark_instances_for(obj.as_ref(), kind, self.get_scope(None))
+            }
+
+            fn mark_schema(
+                &self,
+                heads: Option<&[crate::ChangeHash]>,
+            ) -> Result<crate::marks::MarkSchema, crate::AutomergeError> {
+                Ok(self
+                    .doc
+                    .mark_schema_or_default(self.get_scope(heads).as_ref()))
+            }
+
             fn get<O: AsRef<crate::exid::ExId>, P: Into<crate::Prop>>(
                 &self,
                 obj: O,
@@ -459,6 +540,14 @@ macro_rules! impl_transactable_for_tx {
                     .unwrap_or_default()
             }
 
+            fn define_mark(
+                &mut self,
+                name: &str,
+                definition: &crate::marks::MarkDefinition,
+            ) -> Result<(), crate::AutomergeError> {
+                self.do_tx(|tx, doc, hist| tx.define_mark(doc, hist, name, definition))
+            }
+
             fn update_text<S: AsRef<str>>(
                 &mut self,
                 obj: &crate::exid::ExId,
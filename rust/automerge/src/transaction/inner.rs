@@ -9,13 +9,13 @@ use crate::op_set2::op_set::ResolvedAction;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::exid::ExId;
-use crate::marks::{ExpandMark, Mark, MarkSet};
+use crate::marks::{ExpandMark, Mark, MarkDefinition, MarkSet, MARK_SCHEMA_KEY};
 use crate::op_set2::change::build_change;
 use crate::op_set2::{Op, OpSet, PropRef, SuccInsert, TxOp};
 use crate::patches::PatchLog;
 use crate::types::{Clock, ElemId, ObjMeta, OpId, ScalarValue, SequenceType, TextEncoding, HEAD};
 use crate::Automerge;
-use crate::{hydrate, AutomergeError, ObjType, OpType, ReadDoc};
+use crate::{hydrate, AutomergeError, ObjType, OpType, ReadDoc, Value};
 use crate::{Change, ChangeHash, Prop};
 
 #[derive(Debug, Clone)]
@@ -245,6 +245,29 @@ impl TransactionInner {
         Ok(())
     }
 
+    /// Declare how marks named `name` behave, creating the schema map if there isn't one
+    pub(crate) fn define_mark(
+        &mut self,
+        doc: &mut Automerge,
+        patch_log: &mut PatchLog,
+        name: &str,
+        definition: &MarkDefinition,
+    ) -> Result<(), AutomergeError> {
+        let existing = doc
+            .mark_schema_maps(self.scope.as_ref())
+            .pop()
+            .and_then(|(value, id)| matches!(value, Value::Object(ObjType::Map)).then_some(id));
+        let schema = match existing {
+            Some(schema) => schema,
+            None => self.put_object(doc, patch_log, &ExId::Root, MARK_SCHEMA_KEY, ObjType::Map)?,
+        };
+        let obj = self.put_object(doc, patch_log, &schema, name, ObjType::Map)?;
+        for (key, value) in definition.to_entries() {
+            self.put(doc, patch_log, &obj, key, value)?;
+        }
+        Ok(())
+    }
+
     /// Set the value of property `P` to value `V` in object `obj`.
     ///
     /// # Returns
@@ -796,6 +819,23 @@ impl TransactionInner {
         if ObjType::Text != obj.typ {
             return Err(AutomergeError::InvalidOp(obj.typ));
         }
+        // Marks the document defines behave the same way whoever makes them
+        let definition = doc
+            .mark_schema_for(None)
+            .and_then(|schema| schema.get(mark.kind()).cloned());
+        let expand = match definition {
+            Some(definition) if !definition.accepts(&mark.value) => {
+                return Err(AutomergeError::InvalidValueType {
+                    expected: definition
+                        .value_type
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                    unexpected: format!("{:?}", mark.value),
+                });
+            }
+            Some(definition) => definition.expand,
+            None => expand,
+        };
         if mark.start == mark.end && expand == ExpandMark::None {
             // In peritext terms this is the same as a mark which has a begin anchor before one
             // character and an end anchor after the character preceding that character. E.g in the
@@ -2,7 +2,7 @@ use std::borrow::Cow;
 
 use crate::exid::ExId;
 use crate::iter::Span;
-use crate::marks::{ExpandMark, Mark, UpdateSpansConfig};
+use crate::marks::{ExpandMark, Mark, MarkDefinition, UpdateSpansConfig};
 use crate::{AutomergeError, ChangeHash, ObjType, Prop, ReadDoc, ScalarValue};
 
 /// A way of mutating a document within a single change.
@@ -151,6 +151,41 @@ pub trait Transactable: ReadDoc {
         new_text: I,
     ) -> Result<(), AutomergeError>;
 
+    /// Remove the instance `id` of the multi-instance mark `kind` from the text object `obj`,
+    /// wherever it is, see [`Mark::instance()`]
+    ///
+    /// # Errors
+    ///
+    /// If `kind` contains [`crate::marks::MARK_INSTANCE_SEPARATOR`]
+    fn remove_mark_instance<O: AsRef<ExId>>(
+        &mut self,
+        obj: O,
+        kind: &str,
+        id: &str,
+    ) -> Result<(), AutomergeError> {
+        let obj = obj.as_ref();
+        let name = crate::marks::instance_name(kind, id)?;
+        if !self
+            .mark_instances(obj, kind)?
+            .iter()
+            .any(|mark| mark.instance_id() == Some(id))
+        {
+            return Ok(());
+        }
+        let length = self.length(obj);
+        self.unmark(obj, &name, 0, length, ExpandMark::None)
+    }
+
+    /// Declare how marks named `name` behave, replacing any existing definition
+    ///
+    /// The definition is stored in the document so every peer uses it, see
+    /// [`crate::marks::MarkSchema`].
+    fn define_mark(
+        &mut self,
+        name: &str,
+        definition: &MarkDefinition,
+    ) -> Result<(), AutomergeError>;
+
     /// The heads this transaction will be based on
     fn base_heads(&self) -> Vec<ChangeHash>;
 
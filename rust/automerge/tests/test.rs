@@ -3,9 +3,9 @@ use automerge::sync::{Message, MessageVersion, State};
 //use automerge::op_tree::B;
 use automerge::transaction::{CommitOptions, Transactable};
 use automerge::{
-    sync::SyncDoc, ActorId, AutoCommit, Automerge, AutomergeError, Change, ExpandedChange,
-    LoadOptions, ObjId, ObjType, Patch, PatchAction, PatchLog, Prop, ReadDoc, ScalarValue,
-    SequenceTree, TextEncoding, Value, ROOT,
+    sync::SyncDoc, ActorId, AutoCommit, Automerge, AutomergeError, Change, Cursor, CursorRange,
+    ExpandedChange, LoadOptions, MoveCursor, ObjId, ObjType, Patch, PatchAction, PatchLog, Prop,
+    RangeCollapse, ReadDoc, ScalarValue, SequenceTree, TextEncoding, Value, ROOT,
 };
 
 const B: usize = 16;
@@ -771,6 +771,7 @@ fn diff_spans_preserve_marks_on_inserted_text() {
             end: 3,
             name: "bold".into(),
             value: ScalarValue::from(1),
+            instance_id: None,
         },
         ExpandMark::After,
     )
@@ -4414,3 +4415,837 @@ fn patches_expose_surviving_conflict_after_deleting_other_branch_from_fuzz_trace
         .unwrap();
     assert_eq!(actual, expected);
 }
+
+#[test]
+fn load_from_reader_matches_load() {
+    let mut doc = AutoCommit::new();
+    let list = doc.put_object(ROOT, "list", ObjType::List).unwrap();
+    doc.insert(&list, 0, "a").unwrap();
+    let mut data = doc.save();
+    for i in 1..5 {
+        doc.insert(&list, i, i as i64).unwrap();
+        data.extend(doc.save_incremental());
+    }
+    // a lone change chunk can follow the document too
+    doc.put(ROOT, "key", "value").unwrap();
+    data.extend(doc.get_last_local_change().unwrap().raw_bytes());
+
+    let from_slice = Automerge::load(&data).unwrap();
+    let from_reader = Automerge::load_from_reader(data.as_slice(), LoadOptions::new()).unwrap();
+    assert_eq!(from_reader.get_heads(), from_slice.get_heads());
+    assert_eq!(from_reader.hydrate(None), from_slice.hydrate(None));
+
+    let empty = Automerge::load_from_reader(std::io::empty(), LoadOptions::new()).unwrap();
+    assert!(empty.get_heads().is_empty());
+}
+
+#[test]
+fn load_from_reader_compressed_document() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, &"hello world ".repeat(500))
+        .unwrap();
+    let data = doc.save();
+    assert!(data.len() < doc.save_nocompress().len());
+
+    let from_reader = Automerge::load_from_reader(data.as_slice(), LoadOptions::new()).unwrap();
+    assert_eq!(from_reader.get_heads(), doc.get_heads());
+    assert_eq!(from_reader.text(&text).unwrap(), doc.text(&text).unwrap());
+}
+
+#[test]
+fn load_from_reader_truncated_input() {
+    let mut doc = AutoCommit::new();
+    doc.put(ROOT, "a", 1).unwrap();
+    let mut data = doc.save();
+    let first_len = data.len();
+    doc.put(ROOT, "b", 2).unwrap();
+    data.extend(doc.save_incremental());
+    let truncated = &data[..data.len() - 1];
+
+    assert!(matches!(
+        Automerge::load_from_reader(truncated, LoadOptions::new()),
+        Err(AutomergeError::Load(_))
+    ));
+
+    let partial = Automerge::load_from_reader(
+        truncated,
+        LoadOptions::new().on_partial_load(automerge::OnPartialLoad::Ignore),
+    )
+    .unwrap();
+    assert_eq!(
+        partial.get_heads(),
+        Automerge::load(&data[..first_len]).unwrap().get_heads()
+    );
+}
+
+fn load_with_limits(
+    data: &[u8],
+    limits: automerge::LoadLimits,
+) -> Result<Automerge, AutomergeError> {
+    Automerge::load_with_options(data, LoadOptions::new().limits(limits))
+}
+
+fn exceeded(result: Result<Automerge, AutomergeError>) -> Option<automerge::Limit> {
+    match result {
+        Err(AutomergeError::LimitExceeded(e)) => Some(e.limit),
+        _ => None,
+    }
+}
+
+#[test]
+fn load_limits_on_document_chunks() {
+    use automerge::{Limit, LoadLimits};
+
+    let mut doc = AutoCommit::new();
+    let mut obj = ROOT;
+    for _ in 0..4 {
+        obj = doc.put_object(&obj, "child", ObjType::Map).unwrap();
+        doc.commit();
+    }
+    doc.put(&obj, "leaf", 1).unwrap();
+    doc.commit();
+    let mut other = doc.fork();
+    other.put(ROOT, "other", 2).unwrap();
+    doc.merge(&mut other).unwrap();
+    let data = doc.save();
+
+    let unlimited = LoadLimits::unlimited();
+    assert!(load_with_limits(&data, unlimited).is_ok());
+    let limits = LoadLimits {
+        max_ops: Some(6),
+        max_actors: Some(2),
+        max_changes: Some(6),
+        max_depth: Some(4),
+        ..unlimited
+    };
+    assert!(load_with_limits(&data, limits).is_ok());
+
+    for (limits, limit) in [
+        (
+            LoadLimits {
+                max_ops: Some(5),
+                ..unlimited
+            },
+            Limit::Ops,
+        ),
+        (
+            LoadLimits {
+                max_actors: Some(1),
+                ..unlimited
+            },
+            Limit::Actors,
+        ),
+        (
+            LoadLimits {
+                max_changes: Some(5),
+                ..unlimited
+            },
+            Limit::Changes,
+        ),
+        (
+            LoadLimits {
+                max_depth: Some(3),
+                ..unlimited
+            },
+            Limit::Depth,
+        ),
+    ] {
+        assert_eq!(exceeded(load_with_limits(&data, limits)), Some(limit));
+        // The same limits apply when the document is loaded change by change
+        let changes = doc
+            .get_changes(&[])
+            .into_iter()
+            .flat_map(|c| c.raw_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(exceeded(load_with_limits(&changes, limits)), Some(limit));
+        // Limits fail the load even when partial loads are allowed
+        assert_eq!(
+            exceeded(Automerge::load_with_options(
+                &changes,
+                LoadOptions::new()
+                    .limits(limits)
+                    .on_partial_load(automerge::OnPartialLoad::Ignore)
+            )),
+            Some(limit)
+        );
+    }
+}
+
+#[test]
+fn load_limits_on_decompressed_columns() {
+    use automerge::{Limit, LoadLimits};
+
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, &"a".repeat(10_000)).unwrap();
+    let data = doc.save();
+    // The text is compressed, so the document is much smaller than its decompressed columns
+    assert!(data.len() < 1_000);
+
+    let limits = LoadLimits {
+        max_column_size: Some(1_000),
+        ..LoadLimits::unlimited()
+    };
+    assert_eq!(
+        exceeded(load_with_limits(&data, limits)),
+        Some(Limit::ColumnSize)
+    );
+    let limits = LoadLimits {
+        max_column_size: Some(20_000),
+        ..LoadLimits::unlimited()
+    };
+    assert!(load_with_limits(&data, limits).is_ok());
+}
+
+#[test]
+fn sync_rejects_messages_exceeding_load_limits() {
+    use automerge::{Limit, LoadLimits};
+
+    let mut doc1 = AutoCommit::new();
+    let mut doc2 = AutoCommit::new();
+    let mut s1 = State::new();
+    let mut s2 = State::new();
+    s2.load_limits = LoadLimits {
+        max_depth: Some(2),
+        ..LoadLimits::unlimited()
+    };
+
+    let a = doc1.put_object(ROOT, "a", ObjType::Map).unwrap();
+    let b = doc1.put_object(&a, "b", ObjType::Map).unwrap();
+    doc1.commit();
+    doc1.put_object(&b, "c", ObjType::List).unwrap();
+    doc1.commit();
+
+    let result = loop {
+        let Some(message) = doc1.sync().generate_sync_message(&mut s1) else {
+            break Ok(());
+        };
+        if let Err(e) = doc2.sync().receive_sync_message(&mut s2, message) {
+            break Err(e);
+        }
+        if let Some(message) = doc2.sync().generate_sync_message(&mut s2) {
+            doc1.sync().receive_sync_message(&mut s1, message).unwrap();
+        }
+    };
+    let Err(AutomergeError::LimitExceeded(e)) = result else {
+        panic!("expected the sync to fail, got {:?}", result);
+    };
+    assert_eq!(e.limit, Limit::Depth);
+    // Nothing from the rejected message was applied
+    assert!(doc2.get_heads().is_empty());
+}
+
+#[test]
+fn load_reports_progress_and_can_be_cancelled() {
+    use automerge::{LoadPhase, LoadProgress};
+    use std::ops::ControlFlow;
+
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    for i in 0..20 {
+        doc.splice_text(&text, i * 500, 0, &"a".repeat(500))
+            .unwrap();
+        doc.commit();
+    }
+    // Large enough that the document chunk has compressed columns
+    let mut data = doc.save();
+    doc.put(ROOT, "key", "value").unwrap();
+    data.extend(doc.save_incremental());
+
+    let mut reports = Vec::new();
+    let loaded = Automerge::load_with_options(
+        &data,
+        LoadOptions::new().progress(|p: LoadProgress| {
+            reports.push(p);
+            ControlFlow::Continue(())
+        }),
+    )
+    .unwrap();
+    assert_eq!(loaded.get_heads(), doc.get_heads());
+
+    let phases = reports.iter().map(|p| p.phase).collect::<Vec<_>>();
+    for phase in [
+        LoadPhase::Parse,
+        LoadPhase::Decompress,
+        LoadPhase::Reconstruct,
+        LoadPhase::VerifyHeads,
+    ] {
+        assert!(phases.contains(&phase), "{} was not reported", phase);
+    }
+    assert!(reports
+        .iter()
+        .all(|p| p.fraction.is_some_and(|f| (0.0..=1.0).contains(&f))));
+    let parsed = reports
+        .iter()
+        .filter(|p| p.phase == LoadPhase::Parse)
+        .map(|p| p.fraction.unwrap())
+        .collect::<Vec<_>>();
+    assert!(parsed.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(parsed.last(), Some(&1.0));
+
+    // Cancelling in any phase fails the load, even when partial loads are allowed
+    for phase in [
+        LoadPhase::Parse,
+        LoadPhase::Decompress,
+        LoadPhase::Reconstruct,
+        LoadPhase::VerifyHeads,
+    ] {
+        let result = Automerge::load_with_options(
+            &data,
+            LoadOptions::new()
+                .on_partial_load(automerge::OnPartialLoad::Ignore)
+                .progress(|p: LoadProgress| {
+                    if p.phase == phase {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                }),
+        );
+        assert!(
+            matches!(result, Err(AutomergeError::LoadCancelled(_))),
+            "cancelling in {} gave {:?}",
+            phase,
+            result.map(|d| d.get_heads())
+        );
+    }
+
+    // When reading from a stream the total isn't known
+    let mut reports = Vec::new();
+    Automerge::load_from_reader(
+        &data[..],
+        LoadOptions::new().progress(|p: LoadProgress| {
+            reports.push(p);
+            ControlFlow::Continue(())
+        }),
+    )
+    .unwrap();
+    assert!(reports
+        .iter()
+        .any(|p| p.phase == LoadPhase::Parse && p.fraction.is_none()));
+}
+
+#[test]
+fn load_reports_progress_while_applying_changes() {
+    use automerge::{LoadPhase, LoadProgress};
+    use std::ops::ControlFlow;
+
+    let mut doc = AutoCommit::new();
+    let mut data = Vec::new();
+    for i in 0..2500 {
+        doc.put(ROOT, "count", i).unwrap();
+        data.extend(doc.save_incremental());
+    }
+
+    let mut reconstructed = Vec::new();
+    let loaded = Automerge::load_with_options(
+        &data,
+        LoadOptions::new().progress(|p: LoadProgress| {
+            if p.phase == LoadPhase::Reconstruct {
+                reconstructed.push(p.fraction.unwrap());
+            }
+            ControlFlow::Continue(())
+        }),
+    )
+    .unwrap();
+    assert_eq!(loaded.get_heads(), doc.get_heads());
+    assert!(reconstructed.len() > 3, "{:?}", reconstructed);
+    assert!(reconstructed.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(reconstructed.last(), Some(&1.0));
+
+    // Cancelling part way through applying the changes fails the load
+    let result = Automerge::load_with_options(
+        &data,
+        LoadOptions::new().progress(|p: LoadProgress| {
+            if p.phase == LoadPhase::Reconstruct && p.fraction.is_some_and(|f| f > 0.0) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }),
+    );
+    assert!(matches!(result, Err(AutomergeError::LoadCancelled(_))));
+}
+
+#[test]
+fn save_and_load_with_each_codec() {
+    use automerge::{Codec, SaveOptions};
+
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, &"hello world ".repeat(500))
+        .unwrap();
+    let list = doc.put_object(ROOT, "list", ObjType::List).unwrap();
+    for i in 0..500 {
+        doc.insert(&list, i, i as i64).unwrap();
+    }
+    let expected = doc.hydrate(&ROOT, None).unwrap();
+    let uncompressed_len = doc.save_nocompress().len();
+
+    for codec in [Codec::None, Codec::Deflate, Codec::Zstd, Codec::Lz4] {
+        if !codec.is_available() {
+            let result = doc.try_save_with_options(SaveOptions {
+                codec,
+                ..Default::default()
+            });
+            assert!(
+                matches!(result, Err(AutomergeError::CodecNotCompiledIn(e)) if e.codec() == codec),
+                "{:?}",
+                codec
+            );
+            continue;
+        }
+        let saved = doc.save_with_options(SaveOptions {
+            codec,
+            ..Default::default()
+        });
+        if codec != Codec::None {
+            assert!(
+                saved.len() < uncompressed_len,
+                "{:?} didn't compress",
+                codec
+            );
+        }
+        // Byte 8 is the chunk type, after the magic bytes and checksum
+        let tagged = !matches!(codec, Codec::None | Codec::Deflate);
+        assert_eq!(saved[8] == 4, tagged, "{:?}", codec);
+        let loaded = Automerge::load(&saved).unwrap();
+        assert_eq!(loaded.hydrate(None), expected, "{:?}", codec);
+        assert_eq!(loaded.get_heads(), doc.get_heads());
+    }
+
+    // Documents with nothing to compress are saved as plain document chunks
+    #[cfg(feature = "zstd")]
+    {
+        let mut small = AutoCommit::new();
+        small.put(ROOT, "key", "value").unwrap();
+        let saved = small.save_with_options(SaveOptions {
+            codec: Codec::Zstd,
+            ..Default::default()
+        });
+        assert_eq!(saved, small.save());
+    }
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn load_rejects_unknown_codecs() {
+    use automerge::{Codec, SaveOptions};
+
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, &"a".repeat(1_000)).unwrap();
+    let mut saved = doc.save_with_options(SaveOptions {
+        codec: Codec::Zstd,
+        ..Default::default()
+    });
+    // The codec is the first byte of the chunk data, after the LEB128 length of the chunk
+    let codec_at = 9 + saved[9..].iter().position(|b| b & 0x80 == 0).unwrap() + 1;
+    assert_eq!(saved[codec_at], u8::from(Codec::Zstd));
+    saved[codec_at] = 200;
+    let err = Automerge::load(&saved).unwrap_err();
+    assert!(
+        err.to_string().contains("unknown compression codec: 200"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn load_many_change_chunks_matches_one_at_a_time() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, &"hello world ".repeat(200))
+        .unwrap();
+    let mut data = doc.save();
+    let first_len = data.len();
+    let mut checkpoints = Vec::new();
+    for i in 0..50 {
+        doc.splice_text(&text, i * 3, 1, "xyz").unwrap();
+        doc.put(ROOT, format!("key{}", i), i as i64).unwrap();
+        data.extend(doc.save_incremental());
+        checkpoints.push((data.len(), doc.get_heads()));
+    }
+
+    let loaded = Automerge::load(&data).unwrap();
+    assert_eq!(loaded.get_heads(), doc.get_heads());
+    assert_eq!(loaded.hydrate(None), doc.hydrate(&ROOT, None).unwrap());
+    assert_eq!(loaded.save(), doc.save());
+
+    let mut one_at_a_time = Automerge::load(&data[..first_len]).unwrap();
+    let mut start = first_len;
+    for (end, _) in &checkpoints {
+        one_at_a_time.load_incremental(&data[start..*end]).unwrap();
+        start = *end;
+    }
+    assert_eq!(one_at_a_time.save(), loaded.save());
+
+    // Corrupting the checksum of one change keeps the changes before it
+    let (corrupt_at, expected_heads) = checkpoints[24].clone();
+    let mut corrupted = data.clone();
+    corrupted[corrupt_at + 5] ^= 0xff;
+    assert!(Automerge::load(&corrupted).is_err());
+    let mut partial = Automerge::load(&data[..first_len]).unwrap();
+    partial.load_incremental(&corrupted[first_len..]).unwrap();
+    assert_eq!(partial.get_heads(), expected_heads);
+}
+
+#[test]
+fn lazy_document_matches_full_load() {
+    use automerge::LazyDocument;
+
+    let mut doc1 = AutoCommit::new().with_actor(ActorId::from([1]));
+    doc1.put(ROOT, "deleted", "value").unwrap();
+    doc1.put(ROOT, "counter", ScalarValue::counter(1)).unwrap();
+    let map = doc1.put_object(ROOT, "map", ObjType::Map).unwrap();
+    doc1.put(&map, "nested", 5).unwrap();
+    let text = doc1.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc1.splice_text(&text, 0, 0, "hello").unwrap();
+    doc1.commit_with(CommitOptions::default().with_message("first".to_string()));
+
+    let mut doc2 = doc1.fork().with_actor(ActorId::from([2]));
+    doc1.put(ROOT, "conflict", "one").unwrap();
+    doc1.increment(ROOT, "counter", 2).unwrap();
+    doc1.delete(ROOT, "deleted").unwrap();
+    doc2.put(ROOT, "conflict", "two").unwrap();
+    doc2.increment(ROOT, "counter", 3).unwrap();
+    doc2.put(&map, "nested", ScalarValue::counter(10)).unwrap();
+    doc2.increment(&map, "nested", 1).unwrap();
+    doc1.merge(&mut doc2).unwrap();
+    doc1.increment(ROOT, "counter", 4).unwrap();
+    let saved = doc1.save();
+
+    let full = Automerge::load(&saved).unwrap();
+    let mut lazy = LazyDocument::load(&saved).unwrap();
+    assert_eq!(lazy.get_heads(), full.get_heads());
+    assert_eq!(lazy.object_type(&map).unwrap(), ObjType::Map);
+    assert_eq!(lazy.object_type(&text).unwrap(), ObjType::Text);
+    assert_eq!(lazy.object_type(ROOT).unwrap(), ObjType::Map);
+    for key in ["deleted", "counter", "map", "text", "conflict", "missing"] {
+        assert_eq!(
+            lazy.get_all(ROOT, key).unwrap(),
+            full.get_all(ROOT, key).unwrap(),
+            "{}",
+            key
+        );
+        assert_eq!(lazy.get(ROOT, key).unwrap(), full.get(ROOT, key).unwrap());
+    }
+    assert_eq!(
+        lazy.get_all(&map, "nested").unwrap(),
+        full.get_all(&map, "nested").unwrap()
+    );
+
+    let pending = LazyDocument::load(&saved).unwrap();
+    let changes = pending.changes().collect::<Vec<_>>();
+    assert_eq!(changes.len(), full.get_changes(&[]).len());
+    assert_eq!(changes[0].message.as_deref(), Some("first"));
+    assert!(changes[0].deps.is_empty());
+    assert_eq!(changes[0].actor, &ActorId::from([1]));
+    assert_eq!(lazy.changes().collect::<Vec<_>>(), changes);
+    assert!(!lazy.is_reconstructed());
+
+    // Anything other than reading maps reconstructs the document
+    assert_eq!(lazy.get(&text, 0).unwrap(), full.get(&text, 0).unwrap());
+    assert!(lazy.is_reconstructed());
+    assert_eq!(lazy.changes().collect::<Vec<_>>(), changes);
+    assert_eq!(lazy.into_doc().unwrap().save(), full.save());
+
+    // Input which isn't a single document chunk is reconstructed straight away
+    let mut incremental = saved.clone();
+    doc1.put(ROOT, "later", true).unwrap();
+    incremental.extend(doc1.save_incremental());
+    let lazy = LazyDocument::load(&incremental).unwrap();
+    assert!(lazy.is_reconstructed());
+    assert_eq!(lazy.get_heads(), doc1.get_heads());
+}
+
+#[test]
+fn cursor_ranges_follow_edits() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "hello big world").unwrap();
+
+    // "big " and an empty range just before "world"
+    let range = doc.get_cursor_range(&text, 6..10, None).unwrap();
+    let caret = doc.get_cursor_range(&text, 10..10, None).unwrap();
+    let heads = doc.get_heads();
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        6..10
+    );
+
+    // Text inserted at the edges is outside the range, text inserted inside it is inside it
+    doc.splice_text(&text, 10, 0, "new ").unwrap();
+    doc.splice_text(&text, 6, 0, "a ").unwrap();
+    doc.splice_text(&text, 11, 0, " bad").unwrap();
+    assert_eq!(doc.text(&text).unwrap(), "hello a big bad new world");
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        8..16
+    );
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &caret, None).unwrap(),
+        20..20
+    );
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, Some(&heads))
+            .unwrap(),
+        6..10
+    );
+
+    // Deleting the start shrinks the range, deleting all of it leaves an empty range
+    doc.splice_text(&text, 8, 1, "").unwrap();
+    assert_eq!(doc.text(&text).unwrap(), "hello a ig bad new world");
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        8..15
+    );
+    doc.splice_text(&text, 6, 9, "").unwrap();
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        6..6
+    );
+}
+
+#[test]
+fn single_element_cursor_ranges_are_not_empty() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "abc").unwrap();
+    let range = doc
+        .get_cursor_range_moving(&text, 1..2, None, MoveCursor::After, MoveCursor::After)
+        .unwrap();
+    assert!(!range.is_empty());
+    assert_eq!(range.end.as_ref(), Some(&range.start));
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        1..2
+    );
+    assert_eq!(
+        doc.get_cursor_range_positions(&text, std::slice::from_ref(&range), None)
+            .unwrap(),
+        vec![1..2]
+    );
+    let caret = doc.get_cursor_range(&text, 1..1, None).unwrap();
+    assert!(caret.is_empty());
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &caret, None).unwrap(),
+        1..1
+    );
+}
+
+#[test]
+fn crossed_cursor_ranges_collapse() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "abcdef").unwrap();
+    let range = doc.get_cursor_range(&text, 2..4, None).unwrap();
+
+    // One peer deletes "cd" while another inserts just before it, so the start of the range
+    // ends up after the new text and its end before it
+    let mut other = doc.fork();
+    doc.splice_text(&text, 2, 2, "").unwrap();
+    other.splice_text(&text, 2, 0, "XY").unwrap();
+    doc.merge(&mut other).unwrap();
+    assert_eq!(doc.text(&text).unwrap(), "abXYef");
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        4..4
+    );
+    let range = range.with_collapse(RangeCollapse::End);
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        2..2
+    );
+}
+
+#[test]
+fn cursor_range_bytes_round_trip() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "hello").unwrap();
+    let range = doc
+        .get_cursor_range(&text, 1..5, None)
+        .unwrap()
+        .with_collapse(RangeCollapse::End);
+    assert_eq!(CursorRange::try_from(range.to_bytes()), Ok(range.clone()));
+    let caret = doc.get_cursor_range(&text, 2..2, None).unwrap();
+    assert_eq!(CursorRange::try_from(caret.to_bytes()), Ok(caret.clone()));
+    assert_eq!(
+        CursorRange::try_from(&range.to_bytes()[..]).map(|r| r.collapse),
+        Ok(RangeCollapse::End)
+    );
+    assert_eq!(
+        CursorRange::try_from(&[1u8, 3, 0][..]),
+        Err(AutomergeError::InvalidCursorFormat)
+    );
+}
+
+#[test]
+fn batch_cursor_resolution_matches_single() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "the quick brown fox").unwrap();
+    let mut cursors = vec![Cursor::Start, Cursor::End];
+    for i in 0..19 {
+        cursors.push(doc.get_cursor(&text, i, None).unwrap());
+        cursors.push(
+            doc.get_cursor_moving(&text, i, None, MoveCursor::Before)
+                .unwrap(),
+        );
+    }
+    let ranges = [
+        doc.get_cursor_range(&text, 4..9, None).unwrap(),
+        doc.get_cursor_range(&text, 10..10, None).unwrap(),
+        doc.get_cursor_range(&text, 16..19, None).unwrap(),
+    ];
+    let heads = doc.get_heads();
+
+    let mut other = doc.fork();
+    doc.splice_text(&text, 4, 6, "slow ").unwrap();
+    other.splice_text(&text, 16, 3, "cat").unwrap();
+    other.splice_text(&text, 0, 0, ">> ").unwrap();
+    doc.merge(&mut other).unwrap();
+    assert_eq!(doc.text(&text).unwrap(), ">> the slow brown cat");
+
+    for at in [None, Some(heads.as_slice())] {
+        let single = cursors
+            .iter()
+            .map(|c| doc.get_cursor_position(&text, c, at).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            doc.get_cursor_positions(&text, &cursors, at).unwrap(),
+            single
+        );
+        let single = ranges
+            .iter()
+            .map(|r| doc.get_cursor_range_position(&text, r, at).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            doc.get_cursor_range_positions(&text, &ranges, at).unwrap(),
+            single
+        );
+    }
+    assert_eq!(
+        doc.get_cursor_range_positions(&text, &ranges, None)
+            .unwrap(),
+        vec![12..12, 12..12, 21..21]
+    );
+
+    // A cursor from another document fails the whole batch
+    let mut stranger = AutoCommit::new();
+    let other_text = stranger.put_object(ROOT, "text", ObjType::Text).unwrap();
+    stranger.splice_text(&other_text, 0, 0, "x").unwrap();
+    cursors.push(stranger.get_cursor(&other_text, 0, None).unwrap());
+    assert!(doc.get_cursor_positions(&text, &cursors, None).is_err());
+}
+
+#[test]
+fn transform_index_along_history() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "hello world").unwrap();
+    let old = doc.get_heads();
+    doc.splice_text(&text, 6, 0, "big ").unwrap();
+    doc.splice_text(&text, 0, 6, "").unwrap();
+    assert_eq!(doc.text(&text).unwrap(), "big world");
+    let new = doc.get_heads();
+
+    // "big " was inserted at 6, so it's after a position which sticks to "hello " and before one
+    // which sticks to "world"
+    assert_eq!(
+        doc.transform_index(&text, 6, &old, &new, MoveCursor::After)
+            .unwrap(),
+        4
+    );
+    assert_eq!(
+        doc.transform_index(&text, 6, &old, &new, MoveCursor::Before)
+            .unwrap(),
+        0
+    );
+
+    // Going back in time works too, text which didn't exist yet sticks to its neighbours
+    assert_eq!(
+        doc.transform_indices(&text, &[0, 2, 4, 9], &new, &old, MoveCursor::After)
+            .unwrap(),
+        vec![6, 6, 6, 11]
+    );
+    assert_eq!(
+        doc.transform_indices(&text, &[0, 2, 4, 9], &new, &old, MoveCursor::Before)
+            .unwrap(),
+        vec![0, 6, 6, 11]
+    );
+    assert_eq!(
+        doc.transform_index(&text, 12, &old, &new, MoveCursor::After),
+        Err(AutomergeError::InvalidIndex(12))
+    );
+}
+
+#[test]
+fn transform_index_between_concurrent_heads() {
+    let mut doc = AutoCommit::new();
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "abc").unwrap();
+    let mut other = doc.fork();
+    doc.splice_text(&text, 1, 0, "X").unwrap();
+    other.splice_text(&text, 0, 0, "YY").unwrap();
+    let ours = doc.get_heads();
+    let theirs = other.get_heads();
+    doc.merge(&mut other).unwrap();
+
+    // Their "YYa|bc" is our "a|Xbc" or "aX|bc" depending on the bias
+    assert_eq!(
+        doc.transform_indices(&text, &[0, 3, 5], &theirs, &ours, MoveCursor::After)
+            .unwrap(),
+        vec![0, 2, 4]
+    );
+    assert_eq!(
+        doc.transform_indices(&text, &[0, 3, 5], &theirs, &ours, MoveCursor::Before)
+            .unwrap(),
+        vec![0, 1, 4]
+    );
+    let heads = doc.get_heads();
+    assert_eq!(
+        doc.transform_index(&text, 3, &theirs, &heads, MoveCursor::Before)
+            .unwrap(),
+        3
+    );
+}
+
+#[test]
+fn transform_index_counts_wide_characters() {
+    let mut doc = AutoCommit::new_with_encoding(TextEncoding::Utf16CodeUnit);
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "a😀b").unwrap();
+    let old = doc.get_heads();
+    doc.splice_text(&text, 0, 0, "🎉").unwrap();
+    let new = doc.get_heads();
+    assert_eq!(
+        doc.transform_index(&text, 3, &old, &new, MoveCursor::Before)
+            .unwrap(),
+        5
+    );
+    assert_eq!(
+        doc.transform_index(&text, 3, &old, &new, MoveCursor::After)
+            .unwrap(),
+        5
+    );
+}
+
+#[test]
+fn cursor_range_ends_after_wide_character() {
+    let mut doc = AutoCommit::new_with_encoding(TextEncoding::Utf16CodeUnit);
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "a😀b").unwrap();
+    let range = doc.get_cursor_range(&text, 1..3, None).unwrap();
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        1..3
+    );
+    doc.splice_text(&text, 0, 1, "").unwrap();
+    assert_eq!(
+        doc.get_cursor_range_position(&text, &range, None).unwrap(),
+        0..2
+    );
+}
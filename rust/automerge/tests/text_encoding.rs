@@ -637,3 +637,140 @@ fn patch_mark() {
     }
     .run_fallible()
 }
+
+#[test]
+fn convert_loaded_document() {
+    // A document saved by one peer and loaded by another with a different encoding, then
+    // converted back, sees the same indexes as the first peer
+    let mut doc = AutoCommit::new_with_encoding(Encoding::Utf16CodeUnit.into());
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "he👩‍👩‍👧‍👦llo").unwrap();
+    doc.mark(
+        &text,
+        Mark::new("bold".to_string(), true, 2, 13),
+        ExpandMark::None,
+    )
+    .unwrap();
+    let cursor = doc.get_cursor(&text, 13, None).unwrap();
+
+    let mut loaded = AutoCommit::load_with_options(
+        &doc.save(),
+        automerge::LoadOptions::new().text_encoding(Encoding::UnicodeCodePoint.into()),
+    )
+    .unwrap();
+    assert_eq!(loaded.length(&text), 12);
+    assert_eq!(loaded.get_cursor_position(&text, &cursor, None).unwrap(), 9);
+
+    loaded.set_text_encoding(Encoding::Utf16CodeUnit.into());
+    assert_eq!(loaded.text_encoding(), Encoding::Utf16CodeUnit.into());
+    assert_eq!(loaded.length(&text), doc.length(&text));
+    assert_eq!(loaded.marks(&text).unwrap(), doc.marks(&text).unwrap());
+    assert_eq!(
+        loaded.get_cursor_position(&text, &cursor, None).unwrap(),
+        13
+    );
+    loaded.splice_text(&text, 13, 0, "!").unwrap();
+    assert_eq!(loaded.text(&text).unwrap(), "he👩‍👩‍👧‍👦!llo");
+}
+
+#[test]
+fn convert_with_recorded_patches() {
+    // Patches recorded before the conversion are reported in the new encoding
+    let mut doc = AutoCommit::new_with_encoding(Encoding::Utf8CodeUnit.into());
+    let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&text, 0, 0, "👩‍👩‍👧‍👦").unwrap();
+    doc.update_diff_cursor();
+    doc.splice_text(&text, 25, 0, "a").unwrap();
+
+    doc.set_text_encoding(Encoding::Utf16CodeUnit.into());
+    let patches = doc.diff_incremental();
+    assert_eq!(patches.len(), 1);
+    match &patches[0].action {
+        automerge::PatchAction::SpliceText { index, value, .. } => {
+            assert_eq!(*index, 11);
+            assert_eq!(value.make_string(), "a");
+        }
+        other => panic!("unexpected patch {:?}", other),
+    }
+
+    doc.splice_text(&text, 12, 0, "b").unwrap();
+    let patches = doc.diff_incremental();
+    assert!(matches!(
+        patches[0].action,
+        automerge::PatchAction::SpliceText { index: 12, .. }
+    ));
+}
+
+const ENCODINGS: [Encoding; 4] = [
+    Encoding::UnicodeCodePoint,
+    Encoding::Utf8CodeUnit,
+    Encoding::Utf16CodeUnit,
+    Encoding::GraphemeCluster,
+];
+
+/// Whether text inserted in `a` is stored the same way as text inserted in `b`, text inserted
+/// while counting grapheme clusters is stored a grapheme rather than a code point at a time
+fn stored_alike(a: Encoding, b: Encoding) -> bool {
+    (a == Encoding::GraphemeCluster) == (b == Encoding::GraphemeCluster)
+}
+
+/// A document containing `text` which measures text in `encoding`
+///
+/// The document is created in `created_with` and then converted to `encoding`
+fn doc_with_text(
+    text: &str,
+    created_with: Encoding,
+    encoding: Encoding,
+) -> (AutoCommit, automerge::ObjId) {
+    let mut doc = AutoCommit::new_with_encoding(created_with.into());
+    let obj = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+    doc.splice_text(&obj, 0, 0, text).unwrap();
+    doc.set_text_encoding(encoding.into());
+    (doc, obj)
+}
+
+#[test]
+fn converted_documents_index_like_new_ones() {
+    // A document converted to an encoding reports the same lengths, cursor positions and marks
+    // as one created with it
+    let text = "a👩‍👩‍👧‍👦bé";
+    for encoding in ENCODINGS {
+        let (mut expected, expected_obj) = doc_with_text(text, encoding, encoding);
+        let length = expected.length(&expected_obj);
+        for created_with in ENCODINGS.into_iter().filter(|e| stored_alike(*e, encoding)) {
+            let (mut doc, obj) = doc_with_text(text, created_with, encoding);
+            assert_eq!(
+                doc.length(&obj),
+                length,
+                "{} created as {}",
+                encoding,
+                created_with
+            );
+            for index in 0..length {
+                assert_eq!(
+                    doc.get_cursor(&obj, index, None)
+                        .and_then(|c| doc.get_cursor_position(&obj, &c, None)),
+                    expected
+                        .get_cursor(&expected_obj, index, None)
+                        .and_then(|c| expected.get_cursor_position(&expected_obj, &c, None)),
+                    "{} created as {} at {}",
+                    encoding,
+                    created_with,
+                    index
+                );
+            }
+            for (doc, obj) in [(&mut doc, &obj), (&mut expected, &expected_obj)] {
+                doc.mark(
+                    obj,
+                    Mark::new("bold".to_string(), true, 1, length - 1),
+                    ExpandMark::None,
+                )
+                .unwrap();
+            }
+            assert_eq!(
+                doc.marks(&obj).unwrap(),
+                expected.marks(&expected_obj).unwrap()
+            );
+        }
+    }
+}